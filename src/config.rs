@@ -1,5 +1,11 @@
+use anyhow::Context;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::lustre::layout::CompositeLayout;
+
+/// Default plugin directory, shared with kubelet through a hostPath volume
+pub const DEFAULT_PLUGIN_DIR: &str = "/var/lib/kubelet/plugins/lustre.csi.klustrefs.io";
 
 /// Main configuration for the klustrefs CSI driver
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -9,6 +15,21 @@ pub struct Config {
 
     /// Lustre-specific configuration
     pub lustre: LustreConfig,
+
+    /// Orphaned mount garbage collection on the node
+    pub orphan_gc: OrphanGcConfig,
+
+    /// Background health checks of Lustre mounts on the node
+    pub health: HealthConfig,
+
+    /// Dynamic provisioning on the controller
+    pub provisioning: ProvisioningConfig,
+
+    /// Persistent Client Cache backends on the node
+    pub pcc: PccConfig,
+
+    /// Scratch directories of ephemeral inline volumes on the node
+    pub scratch: Option<ScratchConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,6 +42,35 @@ pub struct DriverConfig {
 
     /// Node ID where this driver instance is running
     pub node_id: String,
+
+    /// Directory for driver-owned files on the node, such as the state store
+    pub plugin_dir: String,
+
+    /// Which CSI services this instance serves
+    pub mode: DriverMode,
+}
+
+/// CSI services served by one driver instance
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DriverMode {
+    /// Identity, Controller and Node services
+    #[default]
+    All,
+    /// Identity and Controller services, for the provisioner deployment
+    Controller,
+    /// Identity and Node services, for the node DaemonSet
+    Node,
+}
+
+impl DriverMode {
+    pub fn serves_controller(self) -> bool {
+        self != DriverMode::Node
+    }
+
+    pub fn serves_node(self) -> bool {
+        self != DriverMode::Controller
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,8 +78,240 @@ pub struct LustreConfig {
     /// Default mount options for Lustre filesystems
     pub default_mount_options: Vec<String>,
 
-    /// Mapping of filesystem names to MGS addresses
+    /// Catalog of filesystem names to MGS addresses; volumes can be
+    /// populated from these filesystems
     pub filesystem_mapping: HashMap<String, String>,
+
+    /// How mount points and bind mounts are handled on the host
+    pub mount_mode: MountMode,
+
+    /// Detach busy mounts lazily when a forced unmount fails
+    pub lazy_unmount: bool,
+}
+
+/// Strategy for running mount operations in the host mount namespace
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum MountMode {
+    /// Fork `nsenter -t 1 -m` for every host command
+    #[default]
+    Nsenter,
+    /// Enter the host namespace once via setns and use syscalls directly
+    Native,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrphanGcConfig {
+    /// Whether orphaned mounts are ignored, reported or removed
+    pub mode: GcMode,
+
+    /// Seconds between two scans of the host mount table
+    pub interval_secs: u64,
+
+    /// kubelet's pod directory on the host, as mount points show it
+    pub pods_dir: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthConfig {
+    /// Seconds between two health passes; 0 disables the monitor
+    pub interval_secs: u64,
+
+    /// Upper bound for every syscall or command of a single check
+    pub timeout_secs: u64,
+
+    /// Remount stale staging mounts in place and re-bind their publishes
+    pub remount: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProvisioningConfig {
+    /// Named composite layouts StorageClasses can refer to via `layoutTemplate`
+    pub layout_templates: BTreeMap<String, String>,
+
+    /// Background `lfs migrate` jobs
+    pub migration: MigrationConfig,
+
+    /// Background copies for volume clones
+    pub copy: CopyConfig,
+
+    /// Object store that snapshots can be exported to
+    pub s3: Option<S3Config>,
+
+    /// Recreate the character and block devices found in archives that are
+    /// unpacked or imported; off, as a device node opens up the node's
+    /// hardware to whoever can reach it
+    pub unpack_device_nodes: bool,
+
+    /// Directory on a Lustre filesystem for the controller's jobs, so they
+    /// follow the controller to any node; the plugin directory if `None`
+    pub job_store: Option<JobStoreConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct MigrationConfig {
+    /// Files of one volume migrated at the same time
+    pub parallelism: usize,
+
+    /// Files started per second and volume; 0 does not throttle
+    pub files_per_second: u32,
+}
+
+impl Default for MigrationConfig {
+    fn default() -> Self {
+        Self {
+            parallelism: 4,
+            files_per_second: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct CopyConfig {
+    /// Files of one volume copied at the same time
+    pub parallelism: usize,
+}
+
+impl Default for CopyConfig {
+    fn default() -> Self {
+        Self { parallelism: 8 }
+    }
+}
+
+/// An S3-compatible object store, addressed path-style.
+///
+/// Credentials are read from `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`
+/// so they can come from a Secret rather than the config file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct S3Config {
+    /// e.g. `https://s3.example.com` or `http://minio.backup:9000`
+    pub endpoint: String,
+
+    pub bucket: String,
+
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+
+    /// Key prefix of all exports in the bucket
+    #[serde(default)]
+    pub prefix: String,
+
+    /// Size at which an export starts a new tar object
+    #[serde(default = "default_s3_chunk_size")]
+    pub chunk_size: u64,
+
+    /// Size of the parts of a multipart upload, at least 5 MiB
+    #[serde(default = "default_s3_part_size")]
+    pub part_size: u64,
+}
+
+/// Smallest part S3 accepts in a multipart upload, except for the last one
+pub const S3_MIN_PART_SIZE: u64 = 5 << 20;
+
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn default_s3_chunk_size() -> u64 {
+    1 << 30
+}
+
+fn default_s3_part_size() -> u64 {
+    64 << 20
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PccConfig {
+    /// Backends volumes can attach to via `pccBackend`, by name
+    pub backends: BTreeMap<String, PccBackend>,
+}
+
+/// A node-local cache directory registered with `lctl pcc add`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct PccBackend {
+    /// Cache directory on the host, e.g. on a local NVMe filesystem
+    pub path: String,
+
+    /// HSM archive ID identifying the backend; unique per node
+    pub archive_id: u32,
+
+    /// Read-only PCC (Lustre 2.16+) instead of read-write
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+/// Where ephemeral inline volumes get their per-pod scratch directories
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ScratchConfig {
+    /// Name of a filesystem from the `filesystems` catalog
+    pub filesystem: String,
+
+    /// Base directory below the filesystem root, e.g. `k8s/scratch`
+    pub path: String,
+
+    /// Seconds a scratch directory is kept after its pod is gone
+    #[serde(default)]
+    pub grace_period_secs: u64,
+}
+
+/// Where the controller keeps its job checkpoints and snapshot records
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct JobStoreConfig {
+    /// Name of a filesystem from the `filesystems` catalog
+    pub filesystem: String,
+
+    /// Directory below the filesystem root, e.g. `k8s/.klustre-csi`
+    pub path: String,
+}
+
+/// Settings read from the optional JSON driver config file
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct ConfigFile {
+    /// Layout templates by name, e.g. `{"small-files": "1M=mdt,eof=c-1"}`
+    pub layout_templates: BTreeMap<String, String>,
+
+    /// e.g. `{"parallelism": 8, "filesPerSecond": 100}`
+    pub migration: Option<MigrationConfig>,
+
+    /// e.g. `{"parallelism": 16}`
+    pub copy: Option<CopyConfig>,
+
+    /// PCC backends by name, e.g. `{"nvme": {"path": "/mnt/nvme/pcc", "archiveId": 2}}`
+    pub pcc_backends: BTreeMap<String, PccBackend>,
+
+    /// e.g. `{"endpoint": "http://minio:9000", "bucket": "backups"}`
+    pub s3: Option<S3Config>,
+
+    /// MGS NIDs of other filesystems by name, e.g. `{"datasets": "10.0.0.5@tcp"}`
+    pub filesystems: HashMap<String, String>,
+
+    /// e.g. `{"filesystem": "scratch", "path": "k8s/ephemeral", "gracePeriodSecs": 600}`
+    pub scratch: Option<ScratchConfig>,
+
+    /// Unpack device nodes from archives, e.g. `true`
+    pub unpack_device_nodes: bool,
+
+    /// e.g. `{"filesystem": "home", "path": "k8s/.klustre-csi"}`
+    pub job_store: Option<JobStoreConfig>,
+}
+
+/// What the orphaned mount collector does with what it finds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum GcMode {
+    Disabled,
+    /// Log orphaned mounts without touching them
+    #[default]
+    DryRun,
+    /// Unmount orphaned mounts and remove their directories
+    Enforce,
 }
 
 impl Config {
@@ -40,11 +322,142 @@ impl Config {
                 name: driver_name,
                 version: env!("CARGO_PKG_VERSION").to_string(),
                 node_id,
+                plugin_dir: DEFAULT_PLUGIN_DIR.to_string(),
+                mode: DriverMode::default(),
             },
             lustre: LustreConfig {
                 default_mount_options: vec!["flock".to_string(), "user_xattr".to_string()],
                 filesystem_mapping: HashMap::new(),
+                mount_mode: MountMode::default(),
+                lazy_unmount: false,
             },
+            orphan_gc: OrphanGcConfig {
+                mode: GcMode::default(),
+                interval_secs: 300,
+                pods_dir: "/var/lib/kubelet/pods".to_string(),
+            },
+            health: HealthConfig {
+                interval_secs: 60,
+                timeout_secs: 10,
+                remount: false,
+            },
+            provisioning: ProvisioningConfig::default(),
+            pcc: PccConfig::default(),
+            scratch: None,
+        }
+    }
+
+    /// Merge a driver config file into this configuration.
+    ///
+    /// Templates are parsed here so a broken one stops the driver at startup
+    /// instead of failing every CreateVolume that uses it.
+    pub fn load_file(&mut self, path: &str) -> anyhow::Result<()> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path))?;
+        let file: ConfigFile = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse config file {}", path))?;
+
+        for (name, spec) in &file.layout_templates {
+            CompositeLayout::parse(spec)
+                .with_context(|| format!("Invalid layout template {:?}", name))?;
+        }
+
+        self.provisioning.layout_templates = file.layout_templates;
+        if let Some(migration) = file.migration {
+            if migration.parallelism == 0 {
+                anyhow::bail!("migration.parallelism must be at least 1");
+            }
+            self.provisioning.migration = migration;
+        }
+        if let Some(copy) = file.copy {
+            if copy.parallelism == 0 {
+                anyhow::bail!("copy.parallelism must be at least 1");
+            }
+            self.provisioning.copy = copy;
+        }
+
+        if let Some(s3) = &file.s3 {
+            if !s3.endpoint.starts_with("http://") && !s3.endpoint.starts_with("https://") {
+                anyhow::bail!("s3.endpoint must be an http:// or https:// URL");
+            }
+            if s3.bucket.is_empty() {
+                anyhow::bail!("s3.bucket is required");
+            }
+            if s3.part_size < S3_MIN_PART_SIZE {
+                anyhow::bail!("s3.partSize must be at least {} bytes", S3_MIN_PART_SIZE);
+            }
+            if s3.chunk_size < s3.part_size {
+                anyhow::bail!("s3.chunkSize must not be smaller than s3.partSize");
+            }
+        }
+        self.provisioning.s3 = file.s3;
+        self.provisioning.unpack_device_nodes = file.unpack_device_nodes;
+
+        for (name, backend) in &file.pcc_backends {
+            if !backend.path.starts_with('/') {
+                anyhow::bail!("PCC backend {:?} needs an absolute path", name);
+            }
+            if backend.archive_id == 0 {
+                anyhow::bail!("PCC backend {:?} needs an archiveId of at least 1", name);
+            }
+        }
+        self.pcc.backends = file.pcc_backends;
+
+        for (fsname, mgs) in &file.filesystems {
+            if fsname.is_empty() || fsname.contains('/') {
+                anyhow::bail!("{:?} is not a filesystem name", fsname);
+            }
+            if !mgs.contains('@') || mgs.contains(":/") {
+                anyhow::bail!(
+                    "Filesystem {:?} needs an MGS NID list, got {:?}",
+                    fsname,
+                    mgs
+                );
+            }
+        }
+        if let Some(scratch) = &file.scratch {
+            check_catalog_dir(
+                "scratch",
+                &scratch.filesystem,
+                &scratch.path,
+                &file.filesystems,
+            )?;
         }
+        if let Some(store) = &file.job_store {
+            check_catalog_dir(
+                "jobStore",
+                &store.filesystem,
+                &store.path,
+                &file.filesystems,
+            )?;
+        }
+        self.lustre.filesystem_mapping = file.filesystems;
+        self.scratch = file.scratch;
+        self.provisioning.job_store = file.job_store;
+        Ok(())
+    }
+}
+
+/// Check that a `section` of the config file names a directory below the
+/// root of a filesystem from the catalog
+fn check_catalog_dir(
+    section: &str,
+    filesystem: &str,
+    path: &str,
+    filesystems: &HashMap<String, String>,
+) -> anyhow::Result<()> {
+    if !filesystems.contains_key(filesystem) {
+        anyhow::bail!(
+            "{}.filesystem {:?} is not in the filesystems catalog",
+            section,
+            filesystem
+        );
+    }
+    if path.split('/').any(|c| c == "." || c == "..") || path.trim_matches('/').is_empty() {
+        anyhow::bail!(
+            "{}.path must be a directory below the filesystem root",
+            section
+        );
     }
+    Ok(())
 }
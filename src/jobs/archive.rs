@@ -0,0 +1,496 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use tar::EntryType;
+
+use super::tree::{self, LAYOUT_XATTR, is_copied_xattr};
+use crate::s3::sigv4::hex;
+use crate::utils::xattr;
+
+/// PAX record prefix GNU tar and bsdtar use for extended attributes
+const XATTR_PAX_PREFIX: &str = "SCHILY.xattr.";
+
+/// Tar archive with the default settings exports use
+pub fn builder<W: Write>(writer: W) -> tar::Builder<W> {
+    let mut builder = tar::Builder::new(writer);
+    builder.follow_symlinks(false);
+    builder
+}
+
+/// Append `relative` below `root` to `builder`, with its Lustre layout and
+/// extended attributes as PAX records. The root itself is `.`.
+///
+/// Returns the size of the file data appended.
+pub fn append<W: Write>(
+    builder: &mut tar::Builder<W>,
+    root: &Path,
+    relative: &Path,
+) -> Result<u64> {
+    let path = root.join(relative);
+    let meta = std::fs::symlink_metadata(&path)
+        .with_context(|| format!("Failed to stat {}", path.display()))?;
+    // Opened before anything is written, so a file removed meanwhile
+    // leaves no partial entry behind
+    let mut file = if meta.is_file() {
+        Some(File::open(&path).with_context(|| format!("Failed to open {}", path.display()))?)
+    } else {
+        None
+    };
+
+    let mut records = Vec::new();
+    for name in xattr::list(&path)? {
+        if (name == LAYOUT_XATTR || is_copied_xattr(&name))
+            && let Some(value) = xattr::get(&path, &name)?
+        {
+            records.push((format!("{}{}", XATTR_PAX_PREFIX, name), value));
+        }
+    }
+    if !records.is_empty() {
+        builder
+            .append_pax_extensions(
+                records
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), &value[..])),
+            )
+            .context("Failed to write extended attributes")?;
+    }
+
+    let name = if relative.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        relative
+    };
+    match &mut file {
+        Some(file) => builder.append_file(name, file),
+        None => builder.append_path_with_name(&path, name),
+    }
+    .with_context(|| format!("Failed to archive {}", path.display()))?;
+    Ok(if meta.is_file() { meta.len() } else { 0 })
+}
+
+/// Whether an entry can be archived at all; sockets cannot
+pub fn is_archivable(path: &Path) -> Result<bool> {
+    let meta = std::fs::symlink_metadata(path)
+        .with_context(|| format!("Failed to stat {}", path.display()))?;
+    Ok(!meta.file_type().is_socket())
+}
+
+/// Reader that sums up what passes through it
+pub struct HashingReader<R> {
+    inner: R,
+    size: u64,
+    hasher: Sha256,
+}
+
+impl<R> HashingReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            size: 0,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Bytes read so far
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Hex SHA-256 of the bytes read so far
+    pub fn sha256(&self) -> String {
+        hex(&self.hasher.clone().finalize())
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.hasher.update(&buf[..len]);
+        self.size += len as u64;
+        Ok(len)
+    }
+}
+
+/// Ownership, mode, extended attributes and time of an archived entry
+#[derive(Debug, Clone)]
+struct Attributes {
+    uid: u32,
+    gid: u32,
+    mode: u32,
+    mtime: i64,
+    xattrs: Vec<(String, Vec<u8>)>,
+}
+
+/// Unpacks archives written with [`append`], or by GNU tar or bsdtar, below
+/// a root directory.
+///
+/// Directories get their ownership, mode and times in [`Restorer::finish`],
+/// once everything below them is in place.
+///
+/// Archives are not trusted: entries cannot reach outside the root, also
+/// not through symlinks or hard links, set-ID bits and file capabilities
+/// are dropped, and device nodes are refused unless `device_nodes` is set.
+#[derive(Debug)]
+pub struct Restorer {
+    root: PathBuf,
+    device_nodes: bool,
+    dirs: Vec<(PathBuf, Attributes)>,
+}
+
+impl Restorer {
+    pub fn new(root: &Path, device_nodes: bool) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            device_nodes,
+            dirs: Vec::new(),
+        }
+    }
+
+    /// Path of `relative` below the root. No directory on the way may be a
+    /// symlink, which an earlier entry could have pointed anywhere.
+    fn destination(&self, relative: &Path) -> Result<PathBuf> {
+        let mut path = self.root.clone();
+        let mut components = relative.components().peekable();
+        while let Some(component) = components.next() {
+            path.push(component);
+            if components.peek().is_none() {
+                break;
+            }
+            match std::fs::symlink_metadata(&path) {
+                Ok(meta) if meta.file_type().is_symlink() => anyhow::bail!(
+                    "Archive entry {} leads through the symlink {}",
+                    relative.display(),
+                    path.display()
+                ),
+                Ok(_) => {}
+                // Nothing can exist below it either
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => break,
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Failed to stat {}", path.display()));
+                }
+            }
+        }
+        Ok(self.root.join(relative))
+    }
+
+    /// Unpack one archive, replacing entries an interrupted earlier run left;
+    /// returns the size of the file data unpacked
+    pub fn unpack<R: Read>(&mut self, reader: R) -> Result<u64> {
+        let mut bytes = 0;
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries().context("Failed to read archive")? {
+            let mut entry = entry.context("Failed to read archive")?;
+            let relative = entry_path(&entry.path()?)?;
+            let dst = self.destination(&relative)?;
+
+            let mut layout = None;
+            let mut xattrs = Vec::new();
+            if let Some(records) = entry.pax_extensions()? {
+                for record in records {
+                    let record = record?;
+                    let Some(name) = record
+                        .key()
+                        .ok()
+                        .and_then(|key| key.strip_prefix(XATTR_PAX_PREFIX))
+                    else {
+                        continue;
+                    };
+                    if name == LAYOUT_XATTR {
+                        layout = Some(record.value_bytes().to_vec());
+                    } else if is_copied_xattr(name) {
+                        xattrs.push((name.to_string(), record.value_bytes().to_vec()));
+                    }
+                }
+            }
+
+            let header = entry.header();
+            let entry_type = header.entry_type();
+            let attributes = Attributes {
+                uid: header.uid()? as u32,
+                gid: header.gid()? as u32,
+                mode: tree::copied_mode(header.mode()?, entry_type == EntryType::Directory),
+                mtime: header.mtime()? as i64,
+                xattrs,
+            };
+
+            match entry_type {
+                EntryType::Directory => {
+                    make_dir(&dst, layout.as_deref())?;
+                    self.dirs.push((relative, attributes));
+                    continue;
+                }
+                EntryType::Regular | EntryType::Continuous | EntryType::GNUSparse => {
+                    tree::remove_existing(&dst)?;
+                    let mut file = OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .mode(0o600)
+                        .open(&dst)
+                        .with_context(|| format!("Failed to create {}", dst.display()))?;
+                    if let Some(layout) = &layout {
+                        xattr::set(&dst, LAYOUT_XATTR, layout).with_context(|| {
+                            format!("Failed to set layout of {}", dst.display())
+                        })?;
+                    }
+                    bytes += std::io::copy(&mut entry, &mut file)
+                        .with_context(|| format!("Failed to write {}", dst.display()))?;
+                }
+                EntryType::Link => {
+                    let target = entry
+                        .link_name()?
+                        .with_context(|| format!("Link {} has no target", relative.display()))?;
+                    let target = self.destination(&entry_path(&target)?)?;
+                    tree::remove_existing(&dst)?;
+                    std::fs::hard_link(&target, &dst).with_context(|| {
+                        format!("Failed to link {} to {}", dst.display(), target.display())
+                    })?;
+                    // A hard link shares the metadata of its target
+                    continue;
+                }
+                // Archive-wide PAX records, e.g. the commit of git archive
+                EntryType::XGlobalHeader => continue,
+                EntryType::Symlink => {
+                    let target = entry
+                        .link_name()?
+                        .with_context(|| format!("Link {} has no target", relative.display()))?;
+                    tree::remove_existing(&dst)?;
+                    std::os::unix::fs::symlink(&target, &dst)
+                        .with_context(|| format!("Failed to create link {}", dst.display()))?;
+                }
+                EntryType::Fifo => {
+                    tree::remove_existing(&dst)?;
+                    tree::make_node(&dst, libc::S_IFIFO | attributes.mode, 0)?;
+                }
+                EntryType::Char | EntryType::Block if !self.device_nodes => anyhow::bail!(
+                    "{} is a device node, which is only unpacked with unpackDeviceNodes",
+                    relative.display()
+                ),
+                EntryType::Char | EntryType::Block => {
+                    let file_type = match entry_type {
+                        EntryType::Char => libc::S_IFCHR,
+                        _ => libc::S_IFBLK,
+                    };
+                    // Only device entries are sure to have these fields
+                    let device = (
+                        header.device_major()?.unwrap_or_default(),
+                        header.device_minor()?.unwrap_or_default(),
+                    );
+                    tree::remove_existing(&dst)?;
+                    tree::make_node(
+                        &dst,
+                        file_type | attributes.mode,
+                        libc::makedev(device.0, device.1),
+                    )?;
+                }
+                other => anyhow::bail!(
+                    "{} has unsupported entry type {:?}",
+                    relative.display(),
+                    other
+                ),
+            }
+            restore_metadata(&dst, &attributes, entry_type == EntryType::Symlink)?;
+        }
+        Ok(bytes)
+    }
+
+    /// Give the unpacked directories their metadata, deepest first and the
+    /// root last
+    pub fn finish(mut self) -> Result<()> {
+        self.dirs.sort_by(|(a, _), (b, _)| b.cmp(a));
+        for (relative, attributes) in &self.dirs {
+            restore_metadata(&self.root.join(relative), attributes, false)?;
+        }
+        Ok(())
+    }
+}
+
+/// Path of an archive entry relative to the root; entries may not leave it
+fn entry_path(path: &Path) -> Result<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            _ => anyhow::bail!("Archive entry {} is outside the volume", path.display()),
+        }
+    }
+    Ok(relative)
+}
+
+fn make_dir(dst: &Path, layout: Option<&[u8]>) -> Result<()> {
+    match std::fs::create_dir(dst) {
+        Ok(()) => {}
+        // Left by an interrupted run; anything else there, such as a
+        // symlink, would have the directory's metadata applied through it
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            let meta = std::fs::symlink_metadata(dst)
+                .with_context(|| format!("Failed to stat {}", dst.display()))?;
+            if !meta.is_dir() {
+                anyhow::bail!("{} exists and is not a directory", dst.display());
+            }
+            return Ok(());
+        }
+        Err(e) => return Err(e).with_context(|| format!("Failed to create {}", dst.display())),
+    }
+    if let Some(layout) = layout {
+        xattr::set(dst, LAYOUT_XATTR, layout)
+            .with_context(|| format!("Failed to set default layout of {}", dst.display()))?;
+    }
+    Ok(())
+}
+
+/// Same order as when copying: ownership, mode, extended attributes, time
+fn restore_metadata(dst: &Path, attributes: &Attributes, is_symlink: bool) -> Result<()> {
+    std::os::unix::fs::lchown(dst, Some(attributes.uid), Some(attributes.gid))
+        .with_context(|| format!("Failed to change owner of {}", dst.display()))?;
+    if !is_symlink {
+        std::fs::set_permissions(dst, std::fs::Permissions::from_mode(attributes.mode))
+            .with_context(|| format!("Failed to change mode of {}", dst.display()))?;
+    }
+    for (name, value) in &attributes.xattrs {
+        xattr::set(dst, name, value)
+            .with_context(|| format!("Failed to set {} on {}", name, dst.display()))?;
+    }
+    let mtime = (attributes.mtime, 0);
+    tree::set_times(dst, mtime, mtime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+
+    #[test]
+    fn test_archive_round_trip() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(src.path().join("data/nested")).unwrap();
+        std::fs::write(src.path().join("data/a.txt"), "alpha").unwrap();
+        std::fs::write(src.path().join("data/nested/b.txt"), "beta").unwrap();
+        std::fs::set_permissions(
+            src.path().join("data/nested"),
+            std::fs::Permissions::from_mode(0o750),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink("data/a.txt", src.path().join("link")).unwrap();
+        let long_name = format!("data/{}.txt", "x".repeat(150));
+        std::fs::write(src.path().join(&long_name), "long").unwrap();
+
+        let mut builder = builder(Vec::new());
+        let mut bytes = 0;
+        for relative in ["", "data", "data/nested"] {
+            bytes += append(&mut builder, src.path(), Path::new(relative)).unwrap();
+        }
+        for relative in ["data/a.txt", "data/nested/b.txt", "link", &long_name] {
+            bytes += append(&mut builder, src.path(), Path::new(relative)).unwrap();
+        }
+        let archive = builder.into_inner().unwrap();
+        assert_eq!(bytes, 13);
+
+        let mut restorer = Restorer::new(dst.path(), false);
+        assert_eq!(restorer.unpack(&archive[..]).unwrap(), 13);
+        // Unpacking again replaces what is there
+        restorer.unpack(&archive[..]).unwrap();
+        restorer.finish().unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(dst.path().join("data/nested/b.txt")).unwrap(),
+            "beta"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dst.path().join(&long_name)).unwrap(),
+            "long"
+        );
+        assert_eq!(
+            std::fs::read_link(dst.path().join("link")).unwrap(),
+            PathBuf::from("data/a.txt")
+        );
+        let meta = std::fs::metadata(dst.path().join("data/nested")).unwrap();
+        assert_eq!(meta.mode() & 0o7777, 0o750);
+        assert_eq!(
+            meta.mtime(),
+            std::fs::metadata(src.path().join("data/nested"))
+                .unwrap()
+                .mtime()
+        );
+
+        assert!(entry_path(Path::new("./data/a.txt")).is_ok());
+        assert!(entry_path(Path::new("../etc/passwd")).is_err());
+        assert!(entry_path(Path::new("/etc/passwd")).is_err());
+    }
+
+    /// Archive of `(path, type, mode, link target)` entries without data
+    fn crafted(entries: &[(&str, EntryType, u32, Option<&str>)]) -> Vec<u8> {
+        let mut builder = builder(Vec::new());
+        for (path, entry_type, mode, target) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(*entry_type);
+            header.set_mode(*mode);
+            header.set_size(0);
+            header.set_uid(unsafe { libc::getuid() } as u64);
+            header.set_gid(unsafe { libc::getgid() } as u64);
+            header.set_mtime(0);
+            if let Some(target) = target {
+                header.set_link_name(target).unwrap();
+            }
+            if entry_type.is_character_special() {
+                header.set_device_major(1).unwrap();
+                header.set_device_minor(3).unwrap();
+            }
+            builder.append_data(&mut header, path, &[][..]).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_untrusted_archives() {
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("secret"), "secret").unwrap();
+        let outside_path = outside.path().to_str().unwrap();
+
+        for entries in [
+            // Writing through a symlink the archive made itself
+            vec![
+                ("escape", EntryType::Symlink, 0o777, Some(outside_path)),
+                ("escape/planted", EntryType::Regular, 0o644, None),
+            ],
+            // Linking to a file outside through it
+            vec![
+                ("escape", EntryType::Symlink, 0o777, Some(outside_path)),
+                ("stolen", EntryType::Link, 0o644, Some("escape/secret")),
+            ],
+            // Applying a directory's metadata through it
+            vec![
+                ("escape", EntryType::Symlink, 0o777, Some(outside_path)),
+                ("escape", EntryType::Directory, 0o777, None),
+            ],
+            vec![("null", EntryType::Char, 0o666, None)],
+        ] {
+            let dst = tempfile::tempdir().unwrap();
+            let mut restorer = Restorer::new(dst.path(), false);
+            assert!(
+                restorer.unpack(&crafted(&entries)[..]).is_err(),
+                "{:?}",
+                entries
+            );
+        }
+        assert!(!outside.path().join("planted").exists());
+        assert_eq!(std::fs::read_dir(outside.path()).unwrap().count(), 1);
+
+        // Set-ID bits are dropped from files, but not from directories
+        let dst = tempfile::tempdir().unwrap();
+        let archive = crafted(&[
+            ("shared", EntryType::Directory, 0o2775, None),
+            ("shared/tool", EntryType::Regular, 0o6755, None),
+        ]);
+        let mut restorer = Restorer::new(dst.path(), false);
+        restorer.unpack(&archive[..]).unwrap();
+        restorer.finish().unwrap();
+        let mode = |path: &str| std::fs::metadata(dst.path().join(path)).unwrap().mode() & 0o7777;
+        assert_eq!(mode("shared/tool"), 0o755);
+        assert_eq!(mode("shared"), 0o2775);
+    }
+}
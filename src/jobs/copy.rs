@@ -0,0 +1,349 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use tracing::{error, info, warn};
+
+use super::fill::FillJobs;
+use super::progress::Progress;
+use super::store::{CopyJob, CopyMode, JobStatus, JobStore, now};
+use super::tree;
+use crate::config::CopyConfig;
+use crate::provision::{Provisioner, SnapshotMarker, VolumeSource};
+
+/// How often a running copy persists its checkpoint
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Copies whole volumes or directories into new volumes in the background.
+///
+/// Files are copied in parallel with their ownership, mode, extended
+/// attributes, ACLs, times and Lustre layout, or hard-linked. Like
+/// migrations, jobs are recorded in the [`JobStore`] with a checkpoint and
+/// resume after a controller restart.
+#[derive(Debug, Clone)]
+pub struct CopyManager {
+    provisioner: Provisioner,
+    store: JobStore,
+    config: CopyConfig,
+    /// Copies running in this process, destination to source volume ID
+    running: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl CopyManager {
+    pub fn new(provisioner: Provisioner, store: JobStore, config: CopyConfig) -> Self {
+        Self {
+            provisioner,
+            store,
+            config,
+            running: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Whether a running copy reads from or writes to a volume
+    pub fn is_busy(&self, volume_id: &str) -> bool {
+        self.running
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(destination, source)| destination == volume_id || source == volume_id)
+    }
+
+    /// Record a copy of `source` into `destination` and start it
+    pub async fn start(
+        &self,
+        source: &VolumeSource,
+        destination: &VolumeSource,
+        mode: CopyMode,
+    ) -> Result<()> {
+        let volume_id = destination.to_string();
+        if self.running.lock().unwrap().contains_key(&volume_id) {
+            anyhow::bail!("A copy into {} is already running", volume_id);
+        }
+
+        let job = CopyJob::new(source.to_string(), mode);
+        self.store
+            .update(|state| state.copies.insert(volume_id, job))
+            .await?;
+        self.spawn(destination.clone(), source.to_string());
+        Ok(())
+    }
+
+    /// Last recorded state of the copy into a volume, if it had one
+    pub async fn status(&self, volume_id: &str) -> Option<CopyJob> {
+        self.store.snapshot().await.copies.remove(volume_id)
+    }
+
+    /// Drop the record of the copy into a volume
+    pub async fn forget(&self, volume_id: &str) -> Result<()> {
+        self.store
+            .update(|state| state.copies.remove(volume_id))
+            .await?;
+        Ok(())
+    }
+
+    /// Restart the copies that were running when the controller stopped
+    pub async fn resume(&self) {
+        for (volume_id, job) in self.store.snapshot().await.copies {
+            if job.status != JobStatus::Running {
+                continue;
+            }
+            match VolumeSource::parse(&volume_id) {
+                Ok(destination) => {
+                    info!(
+                        "Resuming copy from {} into {} after {}/{} file(s)",
+                        job.source, volume_id, job.copied, job.total
+                    );
+                    self.spawn(destination, job.source);
+                }
+                Err(e) => warn!("Cannot resume copy into {}: {:#}", volume_id, e),
+            }
+        }
+    }
+
+    fn spawn(&self, destination: VolumeSource, source: String) {
+        let volume_id = destination.to_string();
+        {
+            let mut running = self.running.lock().unwrap();
+            if running.contains_key(&volume_id) {
+                return;
+            }
+            running.insert(volume_id.clone(), source);
+        }
+
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let result = manager.run(&destination).await;
+            match &result {
+                Ok(()) => info!("Copy into {} finished", volume_id),
+                Err(e) => error!("Copy into {} failed: {:#}", volume_id, e),
+            }
+
+            let recorded = manager
+                .store
+                .update(|state| {
+                    if let Some(job) = state.copies.get_mut(&volume_id) {
+                        job.finished_at = Some(now());
+                        match result {
+                            Ok(()) => job.status = JobStatus::Completed,
+                            Err(e) => {
+                                job.status = JobStatus::Failed;
+                                job.last_error = Some(format!("{:#}", e));
+                            }
+                        }
+                    }
+                })
+                .await;
+            if let Err(e) = recorded {
+                warn!("Failed to record copy into {}: {:#}", volume_id, e);
+            }
+            manager.running.lock().unwrap().remove(&volume_id);
+        });
+    }
+
+    async fn run(&self, destination: &VolumeSource) -> Result<()> {
+        let volume_id = destination.to_string();
+        let job = self
+            .status(&volume_id)
+            .await
+            .with_context(|| format!("No copy recorded for {}", volume_id))?;
+        let source = VolumeSource::parse(&job.source)?;
+        let src_root = self.provisioner.host_volume_path(&source).await?;
+        let dst_root = self.provisioner.host_volume_path(destination).await?;
+        // A restart can interrupt a copy between marking its snapshot and
+        // recording its completion; the frozen snapshot is done
+        if destination.is_snapshot()
+            && self
+                .provisioner
+                .snapshot_marker(destination)
+                .await?
+                .is_some()
+        {
+            return Ok(());
+        }
+
+        // Directories are made up front so entries can be copied in any
+        // order; making them again after a restart is harmless
+        let tree = {
+            let (src_root, dst_root) = (src_root.clone(), dst_root.clone());
+            tokio::task::spawn_blocking(move || -> Result<tree::Tree> {
+                let tree = tree::scan(&src_root)?;
+                for dir in &tree.dirs {
+                    tree::make_dir(&src_root.join(dir), &dst_root.join(dir))?;
+                }
+                Ok(tree)
+            })
+            .await
+            .context("Scan task panicked")??
+        };
+
+        let skipped = job.checkpoint.as_ref().map_or(0, |checkpoint| {
+            let checkpoint = PathBuf::from(checkpoint);
+            tree.entries.partition_point(|entry| *entry <= checkpoint)
+        });
+        let total = tree.entries.len() as u64;
+        let entries = tree.entries[skipped..].to_vec();
+
+        self.store
+            .update(|state| {
+                if let Some(job) = state.copies.get_mut(&volume_id) {
+                    job.total = total;
+                    job.copied = skipped as u64;
+                }
+            })
+            .await?;
+        let copy_entry = match job.mode {
+            CopyMode::Copy => tree::copy_entry,
+            CopyMode::Link => tree::link_entry,
+        };
+        info!(
+            "Copying {} of {} file(s) from {} into {} with {} task(s), mode {:?}",
+            entries.len(),
+            total,
+            source,
+            volume_id,
+            self.config.parallelism,
+            job.mode
+        );
+
+        let mut progress = Progress::new(entries.len());
+        let mut tasks = JoinSet::new();
+        let mut next = 0;
+        let mut last_checkpoint = Instant::now();
+        loop {
+            while tasks.len() < self.config.parallelism && next < entries.len() {
+                let src = src_root.join(&entries[next]);
+                let dst = dst_root.join(&entries[next]);
+                let index = next;
+                tasks.spawn_blocking(move || (index, copy_entry(&src, &dst)));
+                next += 1;
+            }
+
+            let Some(joined) = tasks.join_next().await else {
+                break;
+            };
+            let (index, result) = joined.context("Copy task panicked")?;
+            if let Err(e) = &result {
+                warn!("Failed to copy {}: {:#}", entries[index].display(), e);
+                progress.last_error = Some(format!("{}: {:#}", entries[index].display(), e));
+            }
+            progress.complete(index, result.is_ok());
+
+            if last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+                self.checkpoint(&volume_id, &job, skipped, &entries, &progress)
+                    .await;
+                last_checkpoint = Instant::now();
+            }
+        }
+
+        // Deepest first, so copying a directory's metadata is the last
+        // change to it and to its parent
+        let bytes = tokio::task::spawn_blocking(move || -> Result<u64> {
+            for dir in tree.dirs.iter().rev() {
+                tree::finish_dir(&src_root.join(dir), &dst_root.join(dir))?;
+            }
+            tree::finish_dir(&src_root, &dst_root)?;
+            tree::size(&dst_root)
+        })
+        .await
+        .context("Copy task panicked")??;
+
+        self.checkpoint(&volume_id, &job, skipped, &entries, &progress)
+            .await;
+        let failed = job.failed + progress.prefix_failed;
+        if failed > 0 {
+            anyhow::bail!(
+                "{} file(s) could not be copied, last: {}",
+                failed,
+                progress
+                    .last_error
+                    .or(job.last_error)
+                    .unwrap_or_else(|| "unknown error".to_string())
+            );
+        }
+
+        let created_at = self
+            .store
+            .update(|state| {
+                if let Some(job) = state.copies.get_mut(&volume_id) {
+                    job.bytes = Some(bytes);
+                }
+                state
+                    .snapshots
+                    .get(&volume_id)
+                    .map(|record| record.created_at)
+            })
+            .await?;
+
+        // Snapshots are only ready once marked on their own directory
+        if destination.is_snapshot() {
+            let marker = SnapshotMarker {
+                source_volume_id: job.source.clone(),
+                created_at: created_at.unwrap_or_else(now),
+                bytes,
+            };
+            self.provisioner
+                .complete_snapshot(destination, &marker)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Persist the copied prefix of `entries`, which follow `skipped`
+    /// entries copied before
+    async fn checkpoint(
+        &self,
+        volume_id: &str,
+        job: &CopyJob,
+        skipped: usize,
+        entries: &[PathBuf],
+        progress: &Progress,
+    ) {
+        let copied = (skipped + progress.prefix) as u64;
+        let failed = job.failed + progress.prefix_failed;
+        info!(
+            "Copy into {}: {} file(s) copied, {} failed",
+            volume_id, copied, failed
+        );
+
+        let recorded = self
+            .store
+            .update(|state| {
+                if let Some(record) = state.copies.get_mut(volume_id) {
+                    record.copied = copied;
+                    record.failed = failed;
+                    if progress.prefix > 0 {
+                        record.checkpoint =
+                            Some(entries[progress.prefix - 1].to_string_lossy().into_owned());
+                    }
+                    if progress.last_error.is_some() {
+                        record.last_error = progress.last_error.clone();
+                    }
+                }
+            })
+            .await;
+        if let Err(e) = recorded {
+            warn!("Failed to checkpoint copy into {}: {:#}", volume_id, e);
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl FillJobs for CopyManager {
+    type Job = CopyJob;
+    const KIND: &'static str = "copy";
+    const BUSY: &'static str = "cloned";
+
+    fn is_busy(&self, volume_id: &str) -> bool {
+        CopyManager::is_busy(self, volume_id)
+    }
+
+    async fn status(&self, volume_id: &str) -> Option<CopyJob> {
+        CopyManager::status(self, volume_id).await
+    }
+
+    async fn forget(&self, volume_id: &str) -> Result<()> {
+        CopyManager::forget(self, volume_id).await
+    }
+}
@@ -0,0 +1,968 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Handle;
+use tracing::{error, info, warn};
+
+use super::archive::{self, HashingReader, Restorer};
+use super::fill::FillJobs;
+use super::store::{
+    ArchiveChunk, ExportJob, ImportJob, JobStatus, JobStore, JobsState, PendingUpload,
+    UploadedPart, now,
+};
+use super::tree::{self, Tree};
+use crate::config::S3Config;
+use crate::provision::{Provisioner, VolumeSource};
+use crate::s3::client::is_no_such_upload;
+use crate::s3::sigv4::{hex, sha256_hex};
+use crate::s3::{S3Client, S3Location};
+
+/// Object written last, once every chunk of an export is in place
+const MANIFEST: &str = "manifest.json";
+const MANIFEST_VERSION: u32 = 1;
+
+/// Index of an export, stored next to its chunks
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub source_volume_id: String,
+    /// Seconds since the Unix epoch
+    pub created_at: u64,
+    /// Size of the exported files
+    pub bytes: u64,
+    /// Tar objects in the order they are unpacked
+    pub chunks: Vec<ArchiveChunk>,
+}
+
+/// Exports volumes as chunked tar objects to an S3-compatible store, and
+/// imports them into new volumes.
+///
+/// Each chunk is written as a multipart upload whose parts are recorded in
+/// the [`JobStore`], so an export interrupted by a controller restart
+/// continues with the part it was at; an import continues with the first
+/// chunk it had not verified yet.
+#[derive(Debug, Clone)]
+pub struct ExportManager {
+    provisioner: Provisioner,
+    store: JobStore,
+    config: Option<S3Config>,
+    /// Whether imports recreate device nodes
+    device_nodes: bool,
+    /// Jobs running in this process: the snapshot ID of an export to its
+    /// source volume ID, or the volume ID of an import to its snapshot ID
+    running: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl ExportManager {
+    pub fn new(
+        provisioner: Provisioner,
+        store: JobStore,
+        config: Option<S3Config>,
+        device_nodes: bool,
+    ) -> Self {
+        Self {
+            provisioner,
+            store,
+            config,
+            device_nodes,
+            running: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Whether an object store to export to is configured
+    pub fn is_configured(&self) -> bool {
+        self.config.is_some()
+    }
+
+    /// Location of the export called `name` in the configured bucket
+    pub fn location(&self, name: &str) -> Result<S3Location> {
+        let config = self.config()?;
+        if name.is_empty()
+            || name.starts_with('.')
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        {
+            anyhow::bail!("{:?} is not a valid export name", name);
+        }
+
+        let prefix = config.prefix.trim_matches('/');
+        S3Location::parse(&format!("s3://{}/{}/{}", config.bucket, prefix, name))
+    }
+
+    /// Whether a running export or import uses a volume or snapshot
+    pub fn is_busy(&self, id: &str) -> bool {
+        self.running
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(job, other)| job == id || other == id)
+    }
+
+    /// Record an export of `source` to `location` and start it
+    pub async fn start_export(&self, location: &S3Location, source: &VolumeSource) -> Result<()> {
+        self.client(location)?;
+        let snapshot_id = location.to_string();
+        let job = ExportJob::new(source.to_string());
+        self.store
+            .update(|state| state.exports.insert(snapshot_id, job))
+            .await?;
+        self.spawn_export(location.clone(), source.to_string());
+        Ok(())
+    }
+
+    /// Continue a failed export where it stopped
+    pub async fn retry_export(&self, location: &S3Location) -> Result<()> {
+        let snapshot_id = location.to_string();
+        let source = self
+            .store
+            .update(|state| {
+                let job = state.exports.get_mut(&snapshot_id)?;
+                job.status = JobStatus::Running;
+                job.last_error = None;
+                job.finished_at = None;
+                Some(job.source.clone())
+            })
+            .await?
+            .with_context(|| format!("No export recorded for {}", snapshot_id))?;
+        self.spawn_export(location.clone(), source);
+        Ok(())
+    }
+
+    /// Last recorded state of the export with this snapshot ID
+    pub async fn export_status(&self, snapshot_id: &str) -> Option<ExportJob> {
+        self.store.snapshot().await.exports.remove(snapshot_id)
+    }
+
+    /// Record an import of the export at `location` into `destination` and
+    /// start it
+    pub async fn start_import(
+        &self,
+        location: &S3Location,
+        destination: &VolumeSource,
+    ) -> Result<()> {
+        self.client(location)?;
+        let volume_id = destination.to_string();
+        let job = ImportJob::new(location.to_string());
+        self.store
+            .update(|state| state.imports.insert(volume_id, job))
+            .await?;
+        self.spawn_import(destination.clone(), location.clone());
+        Ok(())
+    }
+
+    /// Last recorded state of the import into a volume, if it had one
+    pub async fn import_status(&self, volume_id: &str) -> Option<ImportJob> {
+        self.store.snapshot().await.imports.remove(volume_id)
+    }
+
+    /// Drop the record of the import into a volume
+    pub async fn forget_import(&self, volume_id: &str) -> Result<()> {
+        self.store
+            .update(|state| state.imports.remove(volume_id))
+            .await?;
+        Ok(())
+    }
+
+    /// Manifest of the export at `location`, `None` while it is incomplete
+    pub async fn manifest(&self, location: &S3Location) -> Result<Option<Manifest>> {
+        let client = self.client(location)?;
+        let location = location.clone();
+        tokio::task::spawn_blocking(move || fetch_manifest(&client, &location))
+            .await
+            .context("Manifest task panicked")?
+    }
+
+    /// Delete the objects of the export at `location` and its record
+    pub async fn delete(&self, location: &S3Location) -> Result<()> {
+        let snapshot_id = location.to_string();
+        if self.is_busy(&snapshot_id) {
+            anyhow::bail!("Export {} is in use", snapshot_id);
+        }
+
+        let client = self.client(location)?;
+        let job = self.export_status(&snapshot_id).await;
+        let target = location.clone();
+        tokio::task::spawn_blocking(move || delete_export(&client, &target, job.as_ref()))
+            .await
+            .context("Delete task panicked")??;
+
+        self.store
+            .update(|state| state.exports.remove(&snapshot_id))
+            .await?;
+        info!("Deleted export {}", snapshot_id);
+        Ok(())
+    }
+
+    /// Restart the exports and imports that were running when the
+    /// controller stopped
+    pub async fn resume(&self) {
+        let state = self.store.snapshot().await;
+        for (snapshot_id, job) in state.exports {
+            if job.status != JobStatus::Running {
+                continue;
+            }
+            match S3Location::parse(&snapshot_id) {
+                Ok(location) => {
+                    info!(
+                        "Resuming export of {} to {} after {} object(s)",
+                        job.source,
+                        snapshot_id,
+                        job.chunks.len()
+                    );
+                    self.spawn_export(location, job.source);
+                }
+                Err(e) => warn!("Cannot resume export to {}: {:#}", snapshot_id, e),
+            }
+        }
+        for (volume_id, job) in state.imports {
+            if job.status != JobStatus::Running {
+                continue;
+            }
+            match (
+                VolumeSource::parse(&volume_id),
+                S3Location::parse(&job.source),
+            ) {
+                (Ok(destination), Ok(location)) => {
+                    info!(
+                        "Resuming import from {} into {} after {}/{} object(s)",
+                        job.source, volume_id, job.imported, job.total
+                    );
+                    self.spawn_import(destination, location);
+                }
+                (Err(e), _) | (_, Err(e)) => {
+                    warn!("Cannot resume import into {}: {:#}", volume_id, e)
+                }
+            }
+        }
+    }
+
+    fn config(&self) -> Result<&S3Config> {
+        self.config
+            .as_ref()
+            .context("No S3 object store is configured")
+    }
+
+    fn client(&self, location: &S3Location) -> Result<S3Client> {
+        let config = self.config()?;
+        if location.bucket != config.bucket {
+            anyhow::bail!(
+                "Bucket {} is not the configured object store",
+                location.bucket
+            );
+        }
+        S3Client::new(config)
+    }
+
+    /// Claim `job` for this process; false if it already runs
+    fn claim(&self, job: &str, other: String) -> bool {
+        let mut running = self.running.lock().unwrap();
+        if running.contains_key(job) {
+            return false;
+        }
+        running.insert(job.to_string(), other);
+        true
+    }
+
+    fn spawn_export(&self, location: S3Location, source: String) {
+        let snapshot_id = location.to_string();
+        if !self.claim(&snapshot_id, source) {
+            return;
+        }
+
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let result = manager.run_export(&location).await;
+            match &result {
+                Ok(()) => info!("Export to {} finished", snapshot_id),
+                Err(e) => error!("Export to {} failed: {:#}", snapshot_id, e),
+            }
+
+            let recorded = manager
+                .store
+                .update(|state| {
+                    if let Some(job) = state.exports.get_mut(&snapshot_id) {
+                        job.finished_at = Some(now());
+                        match result {
+                            Ok(()) => job.status = JobStatus::Completed,
+                            Err(e) => {
+                                job.status = JobStatus::Failed;
+                                job.last_error = Some(format!("{:#}", e));
+                            }
+                        }
+                    }
+                })
+                .await;
+            if let Err(e) = recorded {
+                warn!("Failed to record export to {}: {:#}", snapshot_id, e);
+            }
+            manager.running.lock().unwrap().remove(&snapshot_id);
+        });
+    }
+
+    fn spawn_import(&self, destination: VolumeSource, location: S3Location) {
+        let volume_id = destination.to_string();
+        if !self.claim(&volume_id, location.to_string()) {
+            return;
+        }
+
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let result = manager.run_import(&destination, &location).await;
+            match &result {
+                Ok(()) => info!("Import into {} finished", volume_id),
+                Err(e) => error!("Import into {} failed: {:#}", volume_id, e),
+            }
+
+            let recorded = manager
+                .store
+                .update(|state| {
+                    if let Some(job) = state.imports.get_mut(&volume_id) {
+                        job.finished_at = Some(now());
+                        match result {
+                            Ok(()) => job.status = JobStatus::Completed,
+                            Err(e) => {
+                                job.status = JobStatus::Failed;
+                                job.last_error = Some(format!("{:#}", e));
+                            }
+                        }
+                    }
+                })
+                .await;
+            if let Err(e) = recorded {
+                warn!("Failed to record import into {}: {:#}", volume_id, e);
+            }
+            manager.running.lock().unwrap().remove(&volume_id);
+        });
+    }
+
+    async fn run_export(&self, location: &S3Location) -> Result<()> {
+        let snapshot_id = location.to_string();
+        let mut job = self
+            .export_status(&snapshot_id)
+            .await
+            .with_context(|| format!("No export recorded for {}", snapshot_id))?;
+        let source = VolumeSource::parse(&job.source)?;
+        let root = self.provisioner.host_volume_path(&source).await?;
+        let client = self.client(location)?;
+        let config = self.config()?.clone();
+
+        let location = location.clone();
+        let store = self.store.clone();
+        let handle = Handle::current();
+        tokio::task::spawn_blocking(move || {
+            let tree = tree::scan(&root)?;
+            info!(
+                "Exporting {} file(s) and {} directories of {} to {}",
+                tree.entries.len(),
+                tree.dirs.len(),
+                source,
+                snapshot_id
+            );
+            let mut persist = |job: &ExportJob| {
+                persist_blocking(&handle, &store, |state| {
+                    state.exports.insert(snapshot_id.clone(), job.clone());
+                })
+            };
+            export_tree(
+                &client,
+                &location,
+                &config,
+                &root,
+                &tree,
+                &mut job,
+                &mut persist,
+            )
+        })
+        .await
+        .context("Export task panicked")?
+    }
+
+    async fn run_import(&self, destination: &VolumeSource, location: &S3Location) -> Result<()> {
+        let volume_id = destination.to_string();
+        let mut job = self
+            .import_status(&volume_id)
+            .await
+            .with_context(|| format!("No import recorded for {}", volume_id))?;
+        let root = self.provisioner.host_volume_path(destination).await?;
+        let client = self.client(location)?;
+
+        let location = location.clone();
+        let store = self.store.clone();
+        let device_nodes = self.device_nodes;
+        let handle = Handle::current();
+        tokio::task::spawn_blocking(move || {
+            let manifest = fetch_manifest(&client, &location)?
+                .with_context(|| format!("There is no complete export at {}", location))?;
+            info!(
+                "Importing {} object(s) from {} into {}",
+                manifest.chunks.len(),
+                location,
+                volume_id
+            );
+            let mut persist = |job: &ImportJob| {
+                persist_blocking(&handle, &store, |state| {
+                    state.imports.insert(volume_id.clone(), job.clone());
+                })
+            };
+            let bytes = import_tree(
+                &client,
+                &location,
+                &manifest,
+                &root,
+                device_nodes,
+                &mut job,
+                &mut persist,
+            )?;
+            job.bytes = Some(bytes);
+            persist(&job)
+        })
+        .await
+        .context("Import task panicked")?
+    }
+}
+
+/// Update the job store from a blocking task
+fn persist_blocking(
+    handle: &Handle,
+    store: &JobStore,
+    f: impl FnOnce(&mut JobsState),
+) -> Result<()> {
+    handle.block_on(store.update(f))
+}
+
+/// Export the tree below `root`: its directories as the first chunk, the
+/// other entries in chunks of about `chunk_size`, then the manifest.
+///
+/// `job` is continued where it stopped and handed to `persist` after every
+/// uploaded part and chunk.
+pub fn export_tree(
+    client: &S3Client,
+    location: &S3Location,
+    config: &S3Config,
+    root: &Path,
+    tree: &Tree,
+    job: &mut ExportJob,
+    persist: &mut dyn FnMut(&ExportJob) -> Result<()>,
+) -> Result<()> {
+    let transfer = Transfer {
+        client,
+        location,
+        config,
+        root,
+    };
+    job.total = tree.entries.len() as u64;
+    let mut next = job.checkpoint.as_ref().map_or(0, |checkpoint| {
+        let checkpoint = PathBuf::from(checkpoint);
+        tree.entries.partition_point(|entry| *entry <= checkpoint)
+    });
+
+    if job.chunks.is_empty() {
+        let mut dirs = vec![PathBuf::new()];
+        dirs.extend(tree.dirs.iter().cloned());
+        let (chunk, _) = write_chunk_retrying(&transfer, &dirs, None, job, persist)?;
+        job.chunks.push(chunk);
+        persist(job)?;
+    }
+
+    while next < tree.entries.len() {
+        let (chunk, consumed) = write_chunk_retrying(
+            &transfer,
+            &tree.entries[next..],
+            Some(config.chunk_size),
+            job,
+            persist,
+        )?;
+        next += consumed;
+        info!(
+            "Uploaded {} to {}: {}/{} file(s) exported",
+            chunk.name,
+            location,
+            next,
+            tree.entries.len()
+        );
+        job.chunks.push(chunk);
+        job.exported = next as u64;
+        job.checkpoint = Some(tree.entries[next - 1].to_string_lossy().into_owned());
+        persist(job)?;
+    }
+
+    let manifest = Manifest {
+        version: MANIFEST_VERSION,
+        source_volume_id: job.source.clone(),
+        created_at: now(),
+        bytes: job.chunks.iter().map(|chunk| chunk.bytes).sum(),
+        chunks: job.chunks.clone(),
+    };
+    let body = serde_json::to_vec_pretty(&manifest).context("Failed to serialize manifest")?;
+    client.put_object(&location.key(MANIFEST), &body)?;
+    job.bytes = Some(manifest.bytes);
+    persist(job)
+}
+
+/// Where the chunks of an export are uploaded to and archived from
+struct Transfer<'a> {
+    client: &'a S3Client,
+    location: &'a S3Location,
+    config: &'a S3Config,
+    /// Root of the exported tree
+    root: &'a Path,
+}
+
+/// [`write_chunk`], starting the chunk over if the store no longer knows
+/// the upload a resumed export recorded, e.g. because it was completed
+/// just before a restart
+fn write_chunk_retrying(
+    transfer: &Transfer,
+    entries: &[PathBuf],
+    limit: Option<u64>,
+    job: &mut ExportJob,
+    persist: &mut dyn FnMut(&ExportJob) -> Result<()>,
+) -> Result<(ArchiveChunk, usize)> {
+    let resumed = job.upload.is_some();
+    match write_chunk(transfer, entries, limit, job, persist) {
+        Err(e) if resumed && is_no_such_upload(&e) => {
+            warn!(
+                "Upload to {} is gone, starting the chunk over",
+                transfer.location
+            );
+            job.upload = None;
+            write_chunk(transfer, entries, limit, job, persist)
+        }
+        result => result,
+    }
+}
+
+/// Archive `entries` into the next chunk of `job` until it reaches `limit`
+/// bytes, and return the chunk and how many entries it took
+fn write_chunk(
+    transfer: &Transfer,
+    entries: &[PathBuf],
+    limit: Option<u64>,
+    job: &mut ExportJob,
+    persist: &mut dyn FnMut(&ExportJob) -> Result<()>,
+) -> Result<(ArchiveChunk, usize)> {
+    let Transfer {
+        client,
+        location,
+        config,
+        root,
+    } = *transfer;
+    let name = format!("chunk-{:05}.tar", job.chunks.len());
+    let key = location.key(&name);
+    let upload = match job.upload.take() {
+        Some(upload) if upload.name == name => upload,
+        stale => {
+            if let Some(stale) = stale
+                && let Err(e) =
+                    client.abort_multipart_upload(&location.key(&stale.name), &stale.upload_id)
+            {
+                warn!("Failed to abort upload of {}: {:#}", stale.name, e);
+            }
+            PendingUpload {
+                name: name.clone(),
+                upload_id: client.create_multipart_upload(&key)?,
+                parts: Vec::new(),
+            }
+        }
+    };
+    job.upload = Some(upload.clone());
+    persist(job)?;
+
+    let mut on_part = |upload: &PendingUpload| {
+        job.upload = Some(upload.clone());
+        persist(job)
+    };
+    let writer = ChunkWriter::new(client, key, upload, config.part_size, &mut on_part);
+    let mut builder = archive::builder(writer);
+    let mut consumed = 0;
+    let mut archived = 0;
+    let mut bytes = 0;
+    for entry in entries {
+        if limit.is_some_and(|limit| consumed > 0 && builder.get_ref().size >= limit) {
+            break;
+        }
+        consumed += 1;
+
+        // Entries removed since the scan are left out
+        let path = root.join(entry);
+        let result = archive::is_archivable(&path).and_then(|archivable| {
+            if archivable {
+                archive::append(&mut builder, root, entry).map(Some)
+            } else {
+                Ok(None)
+            }
+        });
+        match result {
+            Ok(Some(size)) => {
+                archived += 1;
+                bytes += size;
+            }
+            Ok(None) => warn!("Skipping socket {}", entry.display()),
+            Err(e) if is_not_found(&e) => warn!("Skipping removed {}", entry.display()),
+            Err(e) => return Err(e),
+        }
+    }
+    let (size, sha256) = builder
+        .into_inner()
+        .context("Failed to finish archive")?
+        .finish()?;
+
+    job.upload = None;
+    Ok((
+        ArchiveChunk {
+            name,
+            size,
+            sha256,
+            entries: archived,
+            bytes,
+        },
+        consumed,
+    ))
+}
+
+fn is_not_found(e: &anyhow::Error) -> bool {
+    e.chain()
+        .filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+        .any(|e| e.kind() == std::io::ErrorKind::NotFound)
+}
+
+/// Tar output of one chunk, sent as a multipart upload in parts of exactly
+/// `part_size` bytes. Parts a resumed upload already has with the same
+/// checksum are not sent again.
+struct ChunkWriter<'a> {
+    client: &'a S3Client,
+    key: String,
+    upload: PendingUpload,
+    part_size: usize,
+    buffer: Vec<u8>,
+    /// Parts written so far
+    parts: u32,
+    size: u64,
+    hasher: Sha256,
+    on_part: &'a mut dyn FnMut(&PendingUpload) -> Result<()>,
+}
+
+impl<'a> ChunkWriter<'a> {
+    fn new(
+        client: &'a S3Client,
+        key: String,
+        upload: PendingUpload,
+        part_size: u64,
+        on_part: &'a mut dyn FnMut(&PendingUpload) -> Result<()>,
+    ) -> Self {
+        Self {
+            client,
+            key,
+            upload,
+            part_size: part_size as usize,
+            buffer: Vec::with_capacity(part_size as usize),
+            parts: 0,
+            size: 0,
+            hasher: Sha256::new(),
+            on_part,
+        }
+    }
+
+    fn send_part(&mut self) -> Result<()> {
+        let number = self.parts + 1;
+        let sha256 = sha256_hex(&self.buffer);
+        let unchanged = self
+            .upload
+            .parts
+            .iter()
+            .any(|part| part.number == number && part.sha256 == sha256);
+        if !unchanged {
+            let etag =
+                self.client
+                    .upload_part(&self.key, &self.upload.upload_id, number, &self.buffer)?;
+            self.upload.parts.retain(|part| part.number != number);
+            self.upload.parts.push(UploadedPart {
+                number,
+                etag,
+                sha256,
+            });
+            self.upload.parts.sort_by_key(|part| part.number);
+            (self.on_part)(&self.upload)?;
+        }
+        self.parts = number;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Send the last part, complete the upload and return the size and
+    /// checksum of the object
+    fn finish(mut self) -> Result<(u64, String)> {
+        if !self.buffer.is_empty() || self.parts == 0 {
+            self.send_part()?;
+        }
+        let parts: Vec<(u32, &str)> = self
+            .upload
+            .parts
+            .iter()
+            .filter(|part| part.number <= self.parts)
+            .map(|part| (part.number, part.etag.as_str()))
+            .collect();
+        self.client
+            .complete_multipart_upload(&self.key, &self.upload.upload_id, &parts)?;
+        Ok((self.size, hex(&self.hasher.finalize())))
+    }
+}
+
+impl Write for ChunkWriter<'_> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let data = &data[..data.len().min(self.part_size - self.buffer.len())];
+        self.buffer.extend_from_slice(data);
+        self.hasher.update(data);
+        self.size += data.len() as u64;
+        if self.buffer.len() == self.part_size {
+            self.send_part().map_err(std::io::Error::other)?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Unpack the chunks of an export below `root`, each checked against the
+/// size and checksum in the manifest; device nodes only if `device_nodes`.
+///
+/// `job` is continued after the chunks it already verified and handed to
+/// `persist` after every chunk. Returns the size of the imported files.
+pub fn import_tree(
+    client: &S3Client,
+    location: &S3Location,
+    manifest: &Manifest,
+    root: &Path,
+    device_nodes: bool,
+    job: &mut ImportJob,
+    persist: &mut dyn FnMut(&ImportJob) -> Result<()>,
+) -> Result<u64> {
+    job.total = manifest.chunks.len() as u64;
+    let mut restorer = Restorer::new(root, device_nodes);
+    for (index, chunk) in manifest.chunks.iter().enumerate() {
+        // The directories are unpacked on every run, for their metadata
+        if index > 0 && (index as u64) < job.imported {
+            continue;
+        }
+
+        let key = location.key(&chunk.name);
+        let mut reader = HashingReader::new(
+            client
+                .get_object(&key)?
+                .with_context(|| format!("{} is missing", key))?,
+        );
+        restorer
+            .unpack(&mut reader)
+            .with_context(|| format!("Failed to unpack {}", key))?;
+        std::io::copy(&mut reader, &mut std::io::sink())
+            .with_context(|| format!("Failed to download {}", key))?;
+        if reader.size() != chunk.size || reader.sha256() != chunk.sha256 {
+            anyhow::bail!("{} does not match the checksum in the manifest", key);
+        }
+
+        job.imported = job.imported.max(index as u64 + 1);
+        persist(job)?;
+    }
+
+    restorer.finish()?;
+    Ok(manifest.bytes)
+}
+
+/// Manifest of the export at `location`, `None` if it has none (yet)
+pub fn fetch_manifest(client: &S3Client, location: &S3Location) -> Result<Option<Manifest>> {
+    let key = location.key(MANIFEST);
+    let Some(mut reader) = client.get_object(&key)? else {
+        return Ok(None);
+    };
+    let mut body = Vec::new();
+    reader
+        .read_to_end(&mut body)
+        .with_context(|| format!("Failed to download {}", key))?;
+
+    let manifest: Manifest =
+        serde_json::from_slice(&body).with_context(|| format!("Failed to parse {}", key))?;
+    if manifest.version > MANIFEST_VERSION {
+        anyhow::bail!(
+            "{} has version {}, this driver reads up to {}",
+            key,
+            manifest.version,
+            MANIFEST_VERSION
+        );
+    }
+    Ok(Some(manifest))
+}
+
+/// Remove the objects of an export, the manifest first so a partly deleted
+/// export is never taken for a complete one
+fn delete_export(client: &S3Client, location: &S3Location, job: Option<&ExportJob>) -> Result<()> {
+    let mut names: Vec<String> = fetch_manifest(client, location)?
+        .map(|manifest| {
+            manifest
+                .chunks
+                .into_iter()
+                .map(|chunk| chunk.name)
+                .collect()
+        })
+        .unwrap_or_default();
+    if let Some(job) = job {
+        names.extend(job.chunks.iter().map(|chunk| chunk.name.clone()));
+        if let Some(upload) = &job.upload {
+            client.abort_multipart_upload(&location.key(&upload.name), &upload.upload_id)?;
+        }
+    }
+    names.sort_unstable();
+    names.dedup();
+
+    client.delete_object(&location.key(MANIFEST))?;
+    for name in names {
+        client.delete_object(&location.key(&name))?;
+    }
+    Ok(())
+}
+
+#[tonic::async_trait]
+impl FillJobs for ExportManager {
+    type Job = ImportJob;
+    const KIND: &'static str = "import";
+    const BUSY: &'static str = "exported or imported";
+
+    fn is_busy(&self, id: &str) -> bool {
+        ExportManager::is_busy(self, id)
+    }
+
+    async fn status(&self, volume_id: &str) -> Option<ImportJob> {
+        self.import_status(volume_id).await
+    }
+
+    async fn forget(&self, volume_id: &str) -> Result<()> {
+        self.forget_import(volume_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::s3::testing::StandIn;
+
+    #[test]
+    fn test_export_and_import() {
+        let store = StandIn::start();
+        let client = store.client();
+        let config = store.config();
+        let location = S3Location::parse("s3://backups/klustre/snapshot-1").unwrap();
+
+        let src = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(src.path().join("data/nested")).unwrap();
+        for i in 0..8 {
+            std::fs::write(
+                src.path().join(format!("data/file-{}", i)),
+                vec![b'a' + i as u8; 1500],
+            )
+            .unwrap();
+        }
+        std::fs::write(src.path().join("data/nested/small"), "small").unwrap();
+        let tree = tree::scan(src.path()).unwrap();
+
+        // The first export stops after a few parts, as if the controller
+        // restarted; the second one continues with the recorded upload
+        let mut job = ExportJob::new("10.0.0.1@tcp:/lustre/k8s/pvc-1".to_string());
+        let mut saved = job.clone();
+        let mut updates = 0;
+        let interrupted = export_tree(
+            &client,
+            &location,
+            &config,
+            src.path(),
+            &tree,
+            &mut job,
+            &mut |job| {
+                updates += 1;
+                saved = job.clone();
+                if saved.chunks.len() == 2
+                    && saved
+                        .upload
+                        .as_ref()
+                        .is_some_and(|upload| !upload.parts.is_empty())
+                {
+                    anyhow::bail!("Controller stopped");
+                }
+                Ok(())
+            },
+        );
+        assert!(interrupted.is_err());
+        assert!(saved.upload.is_some());
+        assert!(fetch_manifest(&client, &location).unwrap().is_none());
+
+        let mut job = saved;
+        export_tree(
+            &client,
+            &location,
+            &config,
+            src.path(),
+            &tree,
+            &mut job,
+            &mut |_| Ok(()),
+        )
+        .unwrap();
+        assert!(updates > 2);
+        assert_eq!(job.exported, 9);
+        assert_eq!(job.bytes, Some(8 * 1500 + 5));
+        assert_eq!(store.pending_uploads(), 0);
+
+        let manifest = fetch_manifest(&client, &location).unwrap().unwrap();
+        assert_eq!(manifest.chunks, job.chunks);
+        assert!(manifest.chunks.len() > 2);
+        let first = store.object("klustre/snapshot-1/chunk-00001.tar").unwrap();
+        assert_eq!(sha256_hex(&first), manifest.chunks[1].sha256);
+
+        let dst = tempfile::tempdir().unwrap();
+        let mut import = ImportJob::new(location.to_string());
+        let bytes = import_tree(
+            &client,
+            &location,
+            &manifest,
+            dst.path(),
+            false,
+            &mut import,
+            &mut |_| Ok(()),
+        )
+        .unwrap();
+        assert_eq!(bytes, 8 * 1500 + 5);
+        assert_eq!(import.imported, manifest.chunks.len() as u64);
+        assert_eq!(
+            std::fs::read(dst.path().join("data/file-7")).unwrap(),
+            vec![b'h'; 1500]
+        );
+        assert_eq!(
+            std::fs::read_to_string(dst.path().join("data/nested/small")).unwrap(),
+            "small"
+        );
+
+        // A corrupted chunk fails the import
+        let mut broken = manifest.clone();
+        broken.chunks[1].sha256 = sha256_hex(b"something else");
+        let mut import = ImportJob::new(location.to_string());
+        assert!(
+            import_tree(
+                &client,
+                &location,
+                &broken,
+                dst.path(),
+                false,
+                &mut import,
+                &mut |_| Ok(())
+            )
+            .is_err()
+        );
+
+        delete_export(&client, &location, Some(&job)).unwrap();
+        assert!(store.keys().is_empty());
+    }
+}
@@ -0,0 +1,38 @@
+use anyhow::Result;
+
+use super::store::JobStatus;
+
+/// Background jobs that fill new volumes, one per volume: clones, imports
+/// and unpacks. CreateVolume starts them and polls them until they are
+/// done; other calls must leave a volume alone while one is busy with it.
+#[tonic::async_trait]
+pub trait FillJobs: Send + Sync {
+    type Job: FillJob + Send;
+
+    /// Name of the job, as in "Failed to start copy"
+    const KIND: &'static str;
+
+    /// What a volume in use by a job undergoes, as in "Volume x is being
+    /// cloned"
+    const BUSY: &'static str;
+
+    /// Whether a running job reads from or writes to a volume
+    fn is_busy(&self, volume_id: &str) -> bool;
+
+    /// Last recorded state of the job filling a volume, if it had one
+    async fn status(&self, volume_id: &str) -> Option<Self::Job>;
+
+    /// Drop the record of the job filling a volume
+    async fn forget(&self, volume_id: &str) -> Result<()>;
+}
+
+/// Recorded state of one [`FillJobs`] job
+pub trait FillJob {
+    /// What the volume is filled from
+    fn source(&self) -> &str;
+
+    fn status(&self) -> JobStatus;
+
+    /// One-line progress summary
+    fn describe(&self) -> String;
+}
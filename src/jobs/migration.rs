@@ -0,0 +1,256 @@
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use tracing::{error, info, warn};
+
+use super::progress::Progress;
+use super::store::{JobStatus, JobStore, MigrationJob, now};
+use crate::config::MigrationConfig;
+use crate::lustre::layout::VolumeLayout;
+use crate::provision::{Provisioner, VolumeSource};
+
+/// How often a running migration persists its checkpoint
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Runs `lfs migrate` over whole volumes in the background.
+///
+/// Jobs are recorded in the [`JobStore`] with a checkpoint, so a migration
+/// interrupted by a controller restart continues where it stopped instead of
+/// starting over.
+#[derive(Debug, Clone)]
+pub struct MigrationManager {
+    provisioner: Provisioner,
+    store: JobStore,
+    config: MigrationConfig,
+    /// Volumes with a migration task running in this process
+    running: Arc<Mutex<HashSet<String>>>,
+}
+
+impl MigrationManager {
+    pub fn new(provisioner: Provisioner, store: JobStore, config: MigrationConfig) -> Self {
+        Self {
+            provisioner,
+            store,
+            config,
+            running: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    pub fn is_running(&self, volume_id: &str) -> bool {
+        self.running.lock().unwrap().contains(volume_id)
+    }
+
+    /// Record a migration of `volume` to `layout` and start it
+    pub async fn start(&self, volume: &VolumeSource, layout: &VolumeLayout) -> Result<()> {
+        let volume_id = volume.to_string();
+        if self.is_running(&volume_id) {
+            anyhow::bail!("A migration of {} is already running", volume_id);
+        }
+
+        let job = MigrationJob::new(layout.setstripe_args());
+        self.store
+            .update(|state| state.migrations.insert(volume_id, job))
+            .await?;
+        self.spawn(volume.clone());
+        Ok(())
+    }
+
+    /// Last recorded state of the migration of a volume, if it had one
+    pub async fn status(&self, volume_id: &str) -> Option<MigrationJob> {
+        self.store.snapshot().await.migrations.remove(volume_id)
+    }
+
+    /// Drop the record of a volume's migration, e.g. once it is deleted
+    pub async fn forget(&self, volume_id: &str) -> Result<()> {
+        self.store
+            .update(|state| state.migrations.remove(volume_id))
+            .await?;
+        Ok(())
+    }
+
+    /// Restart the migrations that were running when the controller stopped
+    pub async fn resume(&self) {
+        for (volume_id, job) in self.store.snapshot().await.migrations {
+            if job.status != JobStatus::Running {
+                continue;
+            }
+            match VolumeSource::parse(&volume_id) {
+                Ok(volume) => {
+                    info!(
+                        "Resuming migration of {} after {}/{} file(s)",
+                        volume_id, job.processed, job.total
+                    );
+                    self.spawn(volume);
+                }
+                Err(e) => warn!("Cannot resume migration of {}: {:#}", volume_id, e),
+            }
+        }
+    }
+
+    fn spawn(&self, volume: VolumeSource) {
+        let volume_id = volume.to_string();
+        if !self.running.lock().unwrap().insert(volume_id.clone()) {
+            return;
+        }
+
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let result = manager.run(&volume).await;
+            match &result {
+                Ok(()) => info!("Migration of {} finished", volume_id),
+                Err(e) => error!("Migration of {} failed: {:#}", volume_id, e),
+            }
+
+            let recorded = manager
+                .store
+                .update(|state| {
+                    if let Some(job) = state.migrations.get_mut(&volume_id) {
+                        job.finished_at = Some(now());
+                        match result {
+                            Ok(()) => job.status = JobStatus::Completed,
+                            Err(e) => {
+                                job.status = JobStatus::Failed;
+                                job.last_error = Some(format!("{:#}", e));
+                            }
+                        }
+                    }
+                })
+                .await;
+            if let Err(e) = recorded {
+                warn!("Failed to record migration of {}: {:#}", volume_id, e);
+            }
+            manager.running.lock().unwrap().remove(&volume_id);
+        });
+    }
+
+    async fn run(&self, volume: &VolumeSource) -> Result<()> {
+        let volume_id = volume.to_string();
+        let job = self
+            .status(&volume_id)
+            .await
+            .with_context(|| format!("No migration recorded for {}", volume_id))?;
+
+        // Sorted, so the processed files always form a prefix that one path
+        // can describe
+        let mut files = self.provisioner.list_files(volume).await?;
+        files.sort_unstable();
+        let skipped = job
+            .checkpoint
+            .as_ref()
+            .map_or(0, |checkpoint| files.partition_point(|f| f <= checkpoint));
+        let total = files.len() as u64;
+        let files = files.split_off(skipped);
+
+        self.store
+            .update(|state| {
+                if let Some(job) = state.migrations.get_mut(&volume_id) {
+                    job.total = total;
+                    job.processed = skipped as u64;
+                }
+            })
+            .await?;
+        info!(
+            "Migrating {} of {} file(s) of {} with {} task(s)",
+            files.len(),
+            total,
+            volume_id,
+            self.config.parallelism
+        );
+
+        let mut throttle = (self.config.files_per_second > 0).then(|| {
+            let mut interval = tokio::time::interval(Duration::from_secs_f64(
+                1.0 / self.config.files_per_second as f64,
+            ));
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            interval
+        });
+
+        let mut progress = Progress::new(files.len());
+        let mut tasks = JoinSet::new();
+        let mut next = 0;
+        let mut last_checkpoint = Instant::now();
+        loop {
+            while tasks.len() < self.config.parallelism && next < files.len() {
+                if let Some(throttle) = throttle.as_mut() {
+                    throttle.tick().await;
+                }
+                let provisioner = self.provisioner.clone();
+                let file = files[next].clone();
+                let args = job.layout_args.clone();
+                let index = next;
+                tasks.spawn(async move { (index, provisioner.migrate_file(&file, &args).await) });
+                next += 1;
+            }
+
+            let Some(joined) = tasks.join_next().await else {
+                break;
+            };
+            let (index, result) = joined.context("Migration task panicked")?;
+            if let Err(e) = &result {
+                warn!("Failed to migrate {}: {:#}", files[index], e);
+                progress.last_error = Some(format!("{}: {:#}", files[index], e));
+            }
+            progress.complete(index, result.is_ok());
+
+            if last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+                self.checkpoint(&volume_id, &job, skipped, &files, &progress)
+                    .await;
+                last_checkpoint = Instant::now();
+            }
+        }
+
+        self.checkpoint(&volume_id, &job, skipped, &files, &progress)
+            .await;
+        let failed = job.failed + progress.prefix_failed;
+        if failed > 0 {
+            anyhow::bail!(
+                "{} file(s) could not be migrated, last: {}",
+                failed,
+                progress
+                    .last_error
+                    .or(job.last_error)
+                    .unwrap_or_else(|| "unknown error".to_string())
+            );
+        }
+        Ok(())
+    }
+
+    /// Persist the processed prefix of `files`, which follow `skipped` files
+    /// processed before
+    async fn checkpoint(
+        &self,
+        volume_id: &str,
+        job: &MigrationJob,
+        skipped: usize,
+        files: &[String],
+        progress: &Progress,
+    ) {
+        let processed = (skipped + progress.prefix) as u64;
+        let failed = job.failed + progress.prefix_failed;
+        info!(
+            "Migration of {}: {} file(s) processed, {} failed",
+            volume_id, processed, failed
+        );
+
+        let recorded = self
+            .store
+            .update(|state| {
+                if let Some(record) = state.migrations.get_mut(volume_id) {
+                    record.processed = processed;
+                    record.failed = failed;
+                    if progress.prefix > 0 {
+                        record.checkpoint = Some(files[progress.prefix - 1].clone());
+                    }
+                    if progress.last_error.is_some() {
+                        record.last_error = progress.last_error.clone();
+                    }
+                }
+            })
+            .await;
+        if let Err(e) = recorded {
+            warn!("Failed to checkpoint migration of {}: {:#}", volume_id, e);
+        }
+    }
+}
@@ -0,0 +1,17 @@
+mod archive;
+pub mod copy;
+pub mod export;
+pub mod fill;
+pub mod migration;
+mod progress;
+pub mod store;
+pub mod tree;
+pub mod unpack;
+
+// Re-export
+pub use copy::CopyManager;
+pub use export::ExportManager;
+pub use fill::{FillJob, FillJobs};
+pub use migration::MigrationManager;
+pub use store::JobStore;
+pub use unpack::UnpackManager;
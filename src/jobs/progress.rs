@@ -0,0 +1,48 @@
+/// Tracks which files of an ordered list are done when they finish out of
+/// order, and how far the fully processed prefix reaches
+#[derive(Debug, Default)]
+pub(super) struct Progress {
+    /// Outcome per file, `None` while it is pending or running
+    done: Vec<Option<bool>>,
+    /// Number of leading files that are all done
+    pub prefix: usize,
+    /// Failures within the prefix
+    pub prefix_failed: u64,
+    pub last_error: Option<String>,
+}
+
+impl Progress {
+    pub fn new(len: usize) -> Self {
+        Self {
+            done: vec![None; len],
+            ..Default::default()
+        }
+    }
+
+    pub fn complete(&mut self, index: usize, succeeded: bool) {
+        self.done[index] = Some(succeeded);
+        while let Some(Some(succeeded)) = self.done.get(self.prefix) {
+            if !succeeded {
+                self.prefix_failed += 1;
+            }
+            self.prefix += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress() {
+        let mut progress = Progress::new(4);
+        progress.complete(1, true);
+        assert_eq!(progress.prefix, 0);
+        progress.complete(2, false);
+        progress.complete(0, true);
+        assert_eq!((progress.prefix, progress.prefix_failed), (3, 1));
+        progress.complete(3, true);
+        assert_eq!((progress.prefix, progress.prefix_failed), (4, 1));
+    }
+}
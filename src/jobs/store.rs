@@ -0,0 +1,550 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use tracing::info;
+
+use super::fill::FillJob;
+use crate::state::store::replace_file;
+
+/// File name of the job store inside its directory
+pub const JOBS_FILE: &str = "controller-jobs.json";
+
+/// Long-running controller jobs, kept so they resume after a restart, and
+/// the snapshots made by them
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JobsState {
+    /// Layout migrations, keyed by volume ID
+    pub migrations: BTreeMap<String, MigrationJob>,
+    /// Copies of one volume into another, keyed by destination volume ID
+    pub copies: BTreeMap<String, CopyJob>,
+    /// Snapshots, keyed by snapshot ID; each is filled by the copy with the
+    /// same key
+    pub snapshots: BTreeMap<String, SnapshotRecord>,
+    /// Group snapshots, keyed by group snapshot ID
+    pub group_snapshots: BTreeMap<String, GroupSnapshotRecord>,
+    /// Exports to the object store, keyed by their snapshot ID
+    pub exports: BTreeMap<String, ExportJob>,
+    /// Imports from the object store, keyed by destination volume ID
+    pub imports: BTreeMap<String, ImportJob>,
+    /// Archives unpacked into new volumes, keyed by destination volume ID
+    pub unpacks: BTreeMap<String, UnpackJob>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+/// Rewrite of every file of a volume with a new layout
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MigrationJob {
+    /// `lfs migrate` layout arguments
+    pub layout_args: Vec<String>,
+    pub status: JobStatus,
+    /// Files found when the volume was last scanned
+    pub total: u64,
+    /// Files up to and including the checkpoint
+    pub processed: u64,
+    /// Files up to the checkpoint that failed to migrate
+    pub failed: u64,
+    /// Last file, in sorted order, of the processed prefix; a resumed job
+    /// skips everything up to it
+    #[serde(default)]
+    pub checkpoint: Option<String>,
+    #[serde(default)]
+    pub last_error: Option<String>,
+    /// Seconds since the Unix epoch
+    pub started_at: u64,
+    #[serde(default)]
+    pub finished_at: Option<u64>,
+}
+
+impl MigrationJob {
+    pub fn new(layout_args: Vec<String>) -> Self {
+        Self {
+            layout_args,
+            status: JobStatus::Running,
+            total: 0,
+            processed: 0,
+            failed: 0,
+            checkpoint: None,
+            last_error: None,
+            started_at: now(),
+            finished_at: None,
+        }
+    }
+
+    /// One-line progress summary
+    pub fn describe(&self) -> String {
+        match self.status {
+            JobStatus::Running => format!(
+                "Migration in progress: {}/{} file(s) processed, {} failed",
+                self.processed, self.total, self.failed
+            ),
+            JobStatus::Completed => format!(
+                "Migration completed: {} file(s) processed, {} failed",
+                self.processed, self.failed
+            ),
+            JobStatus::Failed => format!(
+                "Migration failed after {}/{} file(s): {}",
+                self.processed,
+                self.total,
+                self.last_error.as_deref().unwrap_or("unknown error")
+            ),
+        }
+    }
+}
+
+/// How a copy fills the destination with the source's files
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CopyMode {
+    #[default]
+    Copy,
+    /// Hard-link regular files, which then share their data and metadata
+    /// with the source; both have to be on the same filesystem
+    Link,
+}
+
+/// Copy of every file of a source volume or directory into a new volume
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CopyJob {
+    /// Volume ID of the source
+    pub source: String,
+    #[serde(default)]
+    pub mode: CopyMode,
+    pub status: JobStatus,
+    /// Files, symlinks and other non-directories found in the source
+    pub total: u64,
+    /// Entries up to and including the checkpoint
+    pub copied: u64,
+    /// Entries up to the checkpoint that failed to copy
+    pub failed: u64,
+    /// Last entry, in sorted order and relative to the source, of the copied
+    /// prefix; a resumed job skips everything up to it
+    #[serde(default)]
+    pub checkpoint: Option<String>,
+    #[serde(default)]
+    pub last_error: Option<String>,
+    /// Size of the files in the destination once the copy completed
+    #[serde(default)]
+    pub bytes: Option<u64>,
+    /// Seconds since the Unix epoch
+    pub started_at: u64,
+    #[serde(default)]
+    pub finished_at: Option<u64>,
+}
+
+impl CopyJob {
+    pub fn new(source: String, mode: CopyMode) -> Self {
+        Self {
+            source,
+            mode,
+            status: JobStatus::Running,
+            total: 0,
+            copied: 0,
+            failed: 0,
+            checkpoint: None,
+            last_error: None,
+            bytes: None,
+            started_at: now(),
+            finished_at: None,
+        }
+    }
+
+    /// One-line progress summary
+    pub fn describe(&self) -> String {
+        let (what, done) = match self.mode {
+            CopyMode::Copy => ("Copy", "copied"),
+            CopyMode::Link => ("Link", "linked"),
+        };
+        match self.status {
+            JobStatus::Running => format!(
+                "{} from {} in progress: {}/{} file(s) {}, {} failed",
+                what, self.source, self.copied, self.total, done, self.failed
+            ),
+            JobStatus::Completed => format!(
+                "{} from {} completed: {} file(s) {}",
+                what, self.source, self.copied, done
+            ),
+            JobStatus::Failed => format!(
+                "{} from {} failed after {}/{} file(s): {}",
+                what,
+                self.source,
+                self.copied,
+                self.total,
+                self.last_error.as_deref().unwrap_or("unknown error")
+            ),
+        }
+    }
+}
+
+impl FillJob for CopyJob {
+    fn source(&self) -> &str {
+        &self.source
+    }
+
+    fn status(&self) -> JobStatus {
+        self.status
+    }
+
+    fn describe(&self) -> String {
+        CopyJob::describe(self)
+    }
+}
+
+/// Export of a volume as tar objects to the object store
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportJob {
+    /// Volume ID of the source
+    pub source: String,
+    pub status: JobStatus,
+    /// Files, symlinks and other non-directories found in the source
+    pub total: u64,
+    /// Entries in the uploaded chunks
+    pub exported: u64,
+    /// Last entry, in sorted order, of the uploaded chunks; a resumed
+    /// export starts its next chunk after it
+    #[serde(default)]
+    pub checkpoint: Option<String>,
+    /// Chunks uploaded so far; the first one holds the directories
+    #[serde(default)]
+    pub chunks: Vec<ArchiveChunk>,
+    /// Multipart upload of the chunk being written
+    #[serde(default)]
+    pub upload: Option<PendingUpload>,
+    #[serde(default)]
+    pub last_error: Option<String>,
+    /// Size of the exported files once the export completed
+    #[serde(default)]
+    pub bytes: Option<u64>,
+    /// Seconds since the Unix epoch
+    pub started_at: u64,
+    #[serde(default)]
+    pub finished_at: Option<u64>,
+}
+
+/// One tar object of an export
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveChunk {
+    /// Object name below the export's key prefix
+    pub name: String,
+    /// Size of the object
+    pub size: u64,
+    /// Hex SHA-256 of the object
+    pub sha256: String,
+    /// Entries archived in the object
+    pub entries: u64,
+    /// Size of the file data archived in the object
+    pub bytes: u64,
+}
+
+/// A multipart upload that can be continued after a restart
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingUpload {
+    pub name: String,
+    pub upload_id: String,
+    pub parts: Vec<UploadedPart>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadedPart {
+    /// From 1
+    pub number: u32,
+    pub etag: String,
+    /// Hex SHA-256 of the part, to tell whether a resumed export produced
+    /// the same bytes again
+    pub sha256: String,
+}
+
+impl ExportJob {
+    pub fn new(source: String) -> Self {
+        Self {
+            source,
+            status: JobStatus::Running,
+            total: 0,
+            exported: 0,
+            checkpoint: None,
+            chunks: Vec::new(),
+            upload: None,
+            last_error: None,
+            bytes: None,
+            started_at: now(),
+            finished_at: None,
+        }
+    }
+
+    /// One-line progress summary
+    pub fn describe(&self) -> String {
+        match self.status {
+            JobStatus::Running => format!(
+                "Export of {} in progress: {}/{} file(s) in {} object(s)",
+                self.source,
+                self.exported,
+                self.total,
+                self.chunks.len()
+            ),
+            JobStatus::Completed => format!(
+                "Export of {} completed: {} file(s) in {} object(s)",
+                self.source,
+                self.exported,
+                self.chunks.len()
+            ),
+            JobStatus::Failed => format!(
+                "Export of {} failed after {}/{} file(s): {}",
+                self.source,
+                self.exported,
+                self.total,
+                self.last_error.as_deref().unwrap_or("unknown error")
+            ),
+        }
+    }
+}
+
+/// Unpacking of an export into a new volume
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImportJob {
+    /// Snapshot ID of the export
+    pub source: String,
+    pub status: JobStatus,
+    /// Chunks of the export
+    pub total: u64,
+    /// Chunks unpacked and verified; a resumed import starts after them
+    pub imported: u64,
+    #[serde(default)]
+    pub last_error: Option<String>,
+    /// Size of the imported files once the import completed
+    #[serde(default)]
+    pub bytes: Option<u64>,
+    /// Seconds since the Unix epoch
+    pub started_at: u64,
+    #[serde(default)]
+    pub finished_at: Option<u64>,
+}
+
+impl ImportJob {
+    pub fn new(source: String) -> Self {
+        Self {
+            source,
+            status: JobStatus::Running,
+            total: 0,
+            imported: 0,
+            last_error: None,
+            bytes: None,
+            started_at: now(),
+            finished_at: None,
+        }
+    }
+
+    /// One-line progress summary
+    pub fn describe(&self) -> String {
+        match self.status {
+            JobStatus::Running => format!(
+                "Import from {} in progress: {}/{} object(s) unpacked",
+                self.source, self.imported, self.total
+            ),
+            JobStatus::Completed => format!(
+                "Import from {} completed: {} object(s) unpacked",
+                self.source, self.imported
+            ),
+            JobStatus::Failed => format!(
+                "Import from {} failed after {}/{} object(s): {}",
+                self.source,
+                self.imported,
+                self.total,
+                self.last_error.as_deref().unwrap_or("unknown error")
+            ),
+        }
+    }
+}
+
+impl FillJob for ImportJob {
+    fn source(&self) -> &str {
+        &self.source
+    }
+
+    fn status(&self) -> JobStatus {
+        self.status
+    }
+
+    fn describe(&self) -> String {
+        ImportJob::describe(self)
+    }
+}
+
+/// Unpacking of a tar archive on Lustre into a new volume
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UnpackJob {
+    /// Lustre source of the archive, `mgs@net:/fsname/path`
+    pub source: String,
+    pub status: JobStatus,
+    /// Size of the archive as stored
+    pub total: u64,
+    /// Bytes of the archive read so far; an interrupted unpack starts over
+    pub read: u64,
+    /// Hex SHA-256 the archive has to have
+    #[serde(default)]
+    pub expected_sha256: Option<String>,
+    /// Hex SHA-256 of the archive once it was read completely
+    #[serde(default)]
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub last_error: Option<String>,
+    /// Size of the unpacked files once the unpack completed
+    #[serde(default)]
+    pub bytes: Option<u64>,
+    /// Seconds since the Unix epoch
+    pub started_at: u64,
+    #[serde(default)]
+    pub finished_at: Option<u64>,
+}
+
+impl UnpackJob {
+    pub fn new(source: String, expected_sha256: Option<String>) -> Self {
+        Self {
+            source,
+            status: JobStatus::Running,
+            total: 0,
+            read: 0,
+            expected_sha256,
+            sha256: None,
+            last_error: None,
+            bytes: None,
+            started_at: now(),
+            finished_at: None,
+        }
+    }
+
+    /// One-line progress summary
+    pub fn describe(&self) -> String {
+        match self.status {
+            JobStatus::Running => format!(
+                "Unpacking {} in progress: {}/{} byte(s) read",
+                self.source, self.read, self.total
+            ),
+            JobStatus::Completed => format!(
+                "Unpacking {} completed: {} byte(s) of files, archive SHA-256 {}",
+                self.source,
+                self.bytes.unwrap_or_default(),
+                self.sha256.as_deref().unwrap_or("unknown")
+            ),
+            JobStatus::Failed => format!(
+                "Unpacking {} failed after {}/{} byte(s): {}",
+                self.source,
+                self.read,
+                self.total,
+                self.last_error.as_deref().unwrap_or("unknown error")
+            ),
+        }
+    }
+}
+
+impl FillJob for UnpackJob {
+    fn source(&self) -> &str {
+        &self.source
+    }
+
+    fn status(&self) -> JobStatus {
+        self.status
+    }
+
+    fn describe(&self) -> String {
+        UnpackJob::describe(self)
+    }
+}
+
+/// A snapshot of a volume, copied on Lustre or exported
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotRecord {
+    /// Name the snapshot was created with
+    pub name: String,
+    pub source_volume_id: String,
+    /// Seconds since the Unix epoch
+    pub created_at: u64,
+    /// Group snapshot this snapshot was taken for
+    #[serde(default)]
+    pub group_snapshot_id: Option<String>,
+}
+
+/// Snapshots of several volumes taken together
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupSnapshotRecord {
+    /// Member snapshot IDs, in the order of their sorted source volume IDs
+    pub snapshot_ids: Vec<String>,
+    /// Seconds since the Unix epoch
+    pub created_at: u64,
+}
+
+/// Seconds since the Unix epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// JSON-backed store for [`JobsState`] in a directory on a Lustre filesystem,
+/// or the plugin directory if none is configured.
+///
+/// Writes go to a synced temporary file that is renamed into place, like
+/// the node state store.
+#[derive(Debug, Clone)]
+pub struct JobStore {
+    path: PathBuf,
+    state: Arc<Mutex<JobsState>>,
+}
+
+impl JobStore {
+    /// Load the store from `dir`, starting empty if there are no jobs yet
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create state directory {}", dir.display()))?;
+
+        let path = dir.join(JOBS_FILE);
+        let state = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .with_context(|| format!("Failed to parse {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => JobsState::default(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+
+        info!("Loaded controller jobs from {}", path.display());
+        Ok(Self {
+            path,
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+
+    /// Copy of the current state
+    pub async fn snapshot(&self) -> JobsState {
+        self.state.lock().await.clone()
+    }
+
+    /// Apply `f` to the state and persist the result
+    pub async fn update<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut JobsState) -> T,
+    {
+        let mut state = self.state.lock().await;
+        let result = f(&mut state);
+        self.persist(&state).await?;
+        Ok(result)
+    }
+
+    async fn persist(&self, state: &JobsState) -> Result<()> {
+        let bytes =
+            serde_json::to_vec_pretty(state).context("Failed to serialize controller jobs")?;
+        replace_file(&self.path, bytes).await
+    }
+}
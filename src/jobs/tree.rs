@@ -0,0 +1,403 @@
+use anyhow::{Context, Result};
+use std::fs::{File, Metadata, OpenOptions};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use crate::utils::xattr;
+
+/// Extended attribute holding a Lustre layout; set on a file before it has
+/// data, it gives the file that layout, and on a directory its default
+pub(super) const LAYOUT_XATTR: &str = "lustre.lov";
+
+/// File capabilities of an executable
+const CAPABILITY_XATTR: &str = "security.capability";
+
+/// Inode flag that keeps even root from changing, renaming or removing a
+/// file, or from adding and removing entries of a directory
+const FS_IMMUTABLE_FL: libc::c_int = 0x10;
+
+/// Contents of a directory tree, relative to its root
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tree {
+    /// Directories below the root, parents before their children
+    pub dirs: Vec<PathBuf>,
+    /// Everything else, sorted
+    pub entries: Vec<PathBuf>,
+}
+
+/// List the tree below `root` without following symlinks
+pub fn scan(root: &Path) -> Result<Tree> {
+    let mut tree = Tree::default();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(dir) = pending.pop() {
+        let path = root.join(&dir);
+        let listing = std::fs::read_dir(&path)
+            .with_context(|| format!("Failed to list {}", path.display()))?;
+        for entry in listing {
+            let entry = entry.with_context(|| format!("Failed to list {}", path.display()))?;
+            let relative = dir.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                tree.dirs.push(relative.clone());
+                pending.push(relative);
+            } else {
+                tree.entries.push(relative);
+            }
+        }
+    }
+
+    // Sorting by path puts every directory after its parent
+    tree.dirs.sort_unstable();
+    tree.entries.sort_unstable();
+    Ok(tree)
+}
+
+/// Total size of the regular files below `root`
+pub fn size(root: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in scan(root)?.entries {
+        let path = root.join(entry);
+        let meta = std::fs::symlink_metadata(&path)
+            .with_context(|| format!("Failed to stat {}", path.display()))?;
+        if meta.is_file() {
+            size += meta.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Create directory `dst` for `src` with the same default layout; ownership
+/// and mode follow in [`finish_dir`] once its contents are copied
+pub fn make_dir(src: &Path, dst: &Path) -> Result<()> {
+    match std::fs::create_dir(dst) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to create {}", dst.display())),
+    }
+    if let Some(layout) = xattr::get(src, LAYOUT_XATTR)? {
+        xattr::set(dst, LAYOUT_XATTR, &layout)
+            .with_context(|| format!("Failed to set default layout of {}", dst.display()))?;
+    }
+    Ok(())
+}
+
+/// Give directory `dst` the ownership, mode, extended attributes and times
+/// of `src`
+pub fn finish_dir(src: &Path, dst: &Path) -> Result<()> {
+    let meta = std::fs::symlink_metadata(src)
+        .with_context(|| format!("Failed to stat {}", src.display()))?;
+    copy_metadata(src, dst, &meta)
+}
+
+/// Copy one non-directory entry from `src` to `dst`, replacing whatever an
+/// interrupted earlier copy left there
+pub fn copy_entry(src: &Path, dst: &Path) -> Result<()> {
+    let meta = std::fs::symlink_metadata(src)
+        .with_context(|| format!("Failed to stat {}", src.display()))?;
+    remove_existing(dst)?;
+
+    let file_type = meta.file_type();
+    if file_type.is_symlink() {
+        let target = std::fs::read_link(src)
+            .with_context(|| format!("Failed to read link {}", src.display()))?;
+        std::os::unix::fs::symlink(&target, dst)
+            .with_context(|| format!("Failed to create link {}", dst.display()))?;
+    } else if file_type.is_file() {
+        copy_data(src, dst)?;
+    } else {
+        make_node(dst, meta.mode(), meta.rdev())?;
+    }
+    copy_metadata(src, dst, &meta)
+}
+
+/// Hard-link regular file `src` at `dst`; anything else is copied
+pub fn link_entry(src: &Path, dst: &Path) -> Result<()> {
+    let meta = std::fs::symlink_metadata(src)
+        .with_context(|| format!("Failed to stat {}", src.display()))?;
+    if !meta.is_file() {
+        return copy_entry(src, dst);
+    }
+    remove_existing(dst)?;
+    std::fs::hard_link(src, dst)
+        .with_context(|| format!("Failed to link {} to {}", dst.display(), src.display()))
+}
+
+fn copy_data(src: &Path, dst: &Path) -> Result<()> {
+    let mut reader =
+        File::open(src).with_context(|| format!("Failed to open {}", src.display()))?;
+    let mut writer = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(dst)
+        .with_context(|| format!("Failed to create {}", dst.display()))?;
+
+    if let Some(layout) = xattr::get(src, LAYOUT_XATTR)? {
+        xattr::set(dst, LAYOUT_XATTR, &layout)
+            .with_context(|| format!("Failed to set layout of {}", dst.display()))?;
+    }
+    std::io::copy(&mut reader, &mut writer)
+        .with_context(|| format!("Failed to copy {} to {}", src.display(), dst.display()))?;
+    Ok(())
+}
+
+/// Remove what an interrupted earlier copy left at `dst`
+pub(super) fn remove_existing(dst: &Path) -> Result<()> {
+    match std::fs::remove_file(dst) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("Failed to replace {}", dst.display())),
+    }
+}
+
+/// Remove everything below `dir`, keeping `dir` itself with its layout and
+/// attributes
+pub(super) fn empty_dir(dir: &Path) -> Result<()> {
+    for entry in
+        std::fs::read_dir(dir).with_context(|| format!("Failed to list {}", dir.display()))?
+    {
+        let path = entry?.path();
+        let meta = std::fs::symlink_metadata(&path)
+            .with_context(|| format!("Failed to stat {}", path.display()))?;
+        if meta.is_dir() {
+            std::fs::remove_dir_all(&path)
+        } else {
+            std::fs::remove_file(&path)
+        }
+        .with_context(|| format!("Failed to remove {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Recreate a FIFO, socket or device node; `mode` includes the file type
+pub(super) fn make_node(dst: &Path, mode: u32, rdev: u64) -> Result<()> {
+    let path =
+        std::ffi::CString::new(dst.as_os_str().as_bytes()).context("Path contains a NUL byte")?;
+    let rc = unsafe { libc::mknod(path.as_ptr(), mode, rdev) };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to create {}", dst.display()));
+    }
+    Ok(())
+}
+
+/// Extended attributes copied along with a file. Lustre's own `trusted.*`
+/// attributes describe the source file (FID, links, HSM state) and stay
+/// behind; the layout is copied before the data instead. File capabilities
+/// are privileges, which a copy does not pass on.
+pub(super) fn is_copied_xattr(name: &str) -> bool {
+    (name.starts_with("user.")
+        || name.starts_with("security.")
+        || name.starts_with("system.posix_acl_"))
+        && name != CAPABILITY_XATTR
+}
+
+/// Permission bits of a copy: set-user-ID and set-group-ID are privileges
+/// and are dropped, except set-group-ID on directories, which only passes
+/// the group on to new files
+pub(super) fn copied_mode(mode: u32, is_dir: bool) -> u32 {
+    if is_dir { mode & 0o7777 } else { mode & 0o1777 }
+}
+
+/// Ownership, then mode, then extended attributes and ACLs, then times:
+/// changing the owner clears set-ID bits and file capabilities, and the
+/// times would move again with any later change
+fn copy_metadata(src: &Path, dst: &Path, meta: &Metadata) -> Result<()> {
+    std::os::unix::fs::lchown(dst, Some(meta.uid()), Some(meta.gid()))
+        .with_context(|| format!("Failed to change owner of {}", dst.display()))?;
+
+    let is_symlink = meta.file_type().is_symlink();
+    if !is_symlink {
+        let mode = copied_mode(meta.mode(), meta.is_dir());
+        std::fs::set_permissions(dst, std::fs::Permissions::from_mode(mode))
+            .with_context(|| format!("Failed to change mode of {}", dst.display()))?;
+    }
+
+    for name in xattr::list(src)?
+        .into_iter()
+        .filter(|name| is_copied_xattr(name))
+    {
+        if let Some(value) = xattr::get(src, &name)? {
+            xattr::set(dst, &name, &value)
+                .with_context(|| format!("Failed to set {} on {}", name, dst.display()))?;
+        }
+    }
+
+    set_times(
+        dst,
+        (meta.atime(), meta.atime_nsec()),
+        (meta.mtime(), meta.mtime_nsec()),
+    )
+}
+
+/// Set the access and modification times of `dst`, as seconds and
+/// nanoseconds, without following a symlink
+pub(super) fn set_times(dst: &Path, atime: (i64, i64), mtime: (i64, i64)) -> Result<()> {
+    let path =
+        std::ffi::CString::new(dst.as_os_str().as_bytes()).context("Path contains a NUL byte")?;
+    let times = [
+        libc::timespec {
+            tv_sec: atime.0,
+            tv_nsec: atime.1,
+        },
+        libc::timespec {
+            tv_sec: mtime.0,
+            tv_nsec: mtime.1,
+        },
+    ];
+    let rc = unsafe {
+        libc::utimensat(
+            libc::AT_FDCWD,
+            path.as_ptr(),
+            times.as_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to set times of {}", dst.display()));
+    }
+    Ok(())
+}
+
+/// Set or clear the immutable flag of `path`. Only regular files and
+/// directories are opened for it; symlinks cannot carry inode flags, and
+/// device nodes, FIFOs and sockets are left alone.
+pub fn set_immutable(path: &Path, immutable: bool) -> Result<()> {
+    let meta = std::fs::symlink_metadata(path)
+        .with_context(|| format!("Failed to stat {}", path.display()))?;
+    if !meta.is_file() && !meta.is_dir() {
+        return Ok(());
+    }
+
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let fd = std::os::fd::AsRawFd::as_raw_fd(&file);
+    let mut flags: libc::c_int = 0;
+    if unsafe { libc::ioctl(fd, libc::FS_IOC_GETFLAGS, &mut flags) } != 0 {
+        let e = std::io::Error::last_os_error();
+        // Nothing to clear on a filesystem without inode flags
+        if !immutable && matches!(e.raw_os_error(), Some(libc::ENOTTY | libc::EOPNOTSUPP)) {
+            return Ok(());
+        }
+        return Err(e).with_context(|| format!("Failed to read flags of {}", path.display()));
+    }
+    let changed = if immutable {
+        flags | FS_IMMUTABLE_FL
+    } else {
+        flags & !FS_IMMUTABLE_FL
+    };
+    if changed != flags && unsafe { libc::ioctl(fd, libc::FS_IOC_SETFLAGS, &changed) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to change flags of {}", path.display()));
+    }
+    Ok(())
+}
+
+/// Set or clear the immutable flag of everything below `root`, but not of
+/// `root` itself
+pub fn set_immutable_below(root: &Path, immutable: bool) -> Result<()> {
+    let tree = scan(root)?;
+    for path in tree.dirs.iter().chain(&tree.entries) {
+        set_immutable(&root.join(path), immutable)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy_tree() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(src.path().join("data/nested")).unwrap();
+        std::fs::write(src.path().join("data/a.txt"), "alpha").unwrap();
+        std::fs::write(src.path().join("data/nested/b.txt"), "beta").unwrap();
+        std::fs::set_permissions(
+            src.path().join("data/a.txt"),
+            std::fs::Permissions::from_mode(0o640),
+        )
+        .unwrap();
+        std::fs::set_permissions(
+            src.path().join("data/nested/b.txt"),
+            std::fs::Permissions::from_mode(0o6755),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink("data/a.txt", src.path().join("link")).unwrap();
+
+        let tree = scan(src.path()).unwrap();
+        assert_eq!(
+            tree.dirs,
+            vec![PathBuf::from("data"), PathBuf::from("data/nested")]
+        );
+        assert_eq!(
+            tree.entries,
+            vec![
+                PathBuf::from("data/a.txt"),
+                PathBuf::from("data/nested/b.txt"),
+                PathBuf::from("link")
+            ]
+        );
+
+        for dir in &tree.dirs {
+            make_dir(&src.path().join(dir), &dst.path().join(dir)).unwrap();
+        }
+        for entry in &tree.entries {
+            copy_entry(&src.path().join(entry), &dst.path().join(entry)).unwrap();
+        }
+        // Copying again replaces what is there
+        copy_entry(&src.path().join("link"), &dst.path().join("link")).unwrap();
+        for dir in tree.dirs.iter().rev() {
+            finish_dir(&src.path().join(dir), &dst.path().join(dir)).unwrap();
+        }
+
+        let copied = dst.path().join("data/a.txt");
+        assert_eq!(std::fs::read_to_string(&copied).unwrap(), "alpha");
+        let meta = std::fs::metadata(&copied).unwrap();
+        assert_eq!(meta.mode() & 0o7777, 0o640);
+        assert_eq!(
+            meta.mtime(),
+            std::fs::metadata(src.path().join("data/a.txt"))
+                .unwrap()
+                .mtime()
+        );
+        assert_eq!(size(dst.path()).unwrap(), 9);
+        // Set-ID bits do not survive a copy
+        let privileged = std::fs::metadata(dst.path().join("data/nested/b.txt")).unwrap();
+        assert_eq!(privileged.mode() & 0o7777, 0o755);
+        assert_eq!(copied_mode(0o2775, true), 0o2775);
+        assert!(is_copied_xattr("security.selinux"));
+        assert!(!is_copied_xattr("security.capability"));
+        assert_eq!(
+            std::fs::read_link(dst.path().join("link")).unwrap(),
+            PathBuf::from("data/a.txt")
+        );
+
+        let linked = tempfile::tempdir_in(src.path()).unwrap();
+        for entry in ["data/a.txt", "link"] {
+            link_entry(
+                &src.path().join(entry),
+                &linked.path().join(entry.replace('/', "-")),
+            )
+            .unwrap();
+        }
+        assert_eq!(
+            std::fs::metadata(linked.path().join("data-a.txt"))
+                .unwrap()
+                .ino(),
+            std::fs::metadata(src.path().join("data/a.txt"))
+                .unwrap()
+                .ino()
+        );
+        assert!(
+            std::fs::symlink_metadata(linked.path().join("link"))
+                .unwrap()
+                .is_symlink()
+        );
+    }
+}
@@ -0,0 +1,422 @@
+use anyhow::{Context, Result};
+use flate2::bufread::GzDecoder;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+use super::archive::{HashingReader, Restorer};
+use super::fill::FillJobs;
+use super::store::{JobStatus, JobStore, UnpackJob, now};
+use super::tree;
+use crate::provision::{Provisioner, VolumeSource};
+
+/// How often a running unpack persists its progress
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// First bytes of a gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Unpacks tar archives on Lustre into new volumes in the background.
+///
+/// The archive's SHA-256 is computed while it is read and recorded with the
+/// job; an expected one is checked before anything is unpacked. A failed
+/// unpack leaves the volume empty, and one that was interrupted by a
+/// controller restart starts over, replacing what it had unpacked before.
+#[derive(Debug, Clone)]
+pub struct UnpackManager {
+    provisioner: Provisioner,
+    store: JobStore,
+    /// Whether archives may recreate device nodes
+    device_nodes: bool,
+    /// Volumes unpacked into by this process
+    running: Arc<Mutex<HashSet<String>>>,
+}
+
+impl UnpackManager {
+    pub fn new(provisioner: Provisioner, store: JobStore, device_nodes: bool) -> Self {
+        Self {
+            provisioner,
+            store,
+            device_nodes,
+            running: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// Whether an archive is being unpacked into a volume
+    pub fn is_busy(&self, volume_id: &str) -> bool {
+        self.running.lock().unwrap().contains(volume_id)
+    }
+
+    /// Record unpacking `archive` into `destination` and start it
+    pub async fn start(
+        &self,
+        archive: &VolumeSource,
+        destination: &VolumeSource,
+        expected_sha256: Option<String>,
+    ) -> Result<()> {
+        let volume_id = destination.to_string();
+        if self.is_busy(&volume_id) {
+            anyhow::bail!("An archive is already being unpacked into {}", volume_id);
+        }
+
+        let job = UnpackJob::new(archive.to_string(), expected_sha256);
+        self.store
+            .update(|state| state.unpacks.insert(volume_id, job))
+            .await?;
+        self.spawn(destination.clone());
+        Ok(())
+    }
+
+    /// Last recorded state of the unpack into a volume, if it had one
+    pub async fn status(&self, volume_id: &str) -> Option<UnpackJob> {
+        self.store.snapshot().await.unpacks.remove(volume_id)
+    }
+
+    /// Drop the record of the unpack into a volume
+    pub async fn forget(&self, volume_id: &str) -> Result<()> {
+        self.store
+            .update(|state| state.unpacks.remove(volume_id))
+            .await?;
+        Ok(())
+    }
+
+    /// Restart the unpacks that were running when the controller stopped
+    pub async fn resume(&self) {
+        for (volume_id, job) in self.store.snapshot().await.unpacks {
+            if job.status != JobStatus::Running {
+                continue;
+            }
+            match VolumeSource::parse(&volume_id) {
+                Ok(destination) => {
+                    info!("Restarting unpack of {} into {}", job.source, volume_id);
+                    self.spawn(destination);
+                }
+                Err(e) => warn!("Cannot resume unpack into {}: {:#}", volume_id, e),
+            }
+        }
+    }
+
+    fn spawn(&self, destination: VolumeSource) {
+        let volume_id = destination.to_string();
+        if !self.running.lock().unwrap().insert(volume_id.clone()) {
+            return;
+        }
+
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let result = manager.run(&destination).await;
+            match &result {
+                Ok(()) => info!("Unpack into {} finished", volume_id),
+                Err(e) => error!("Unpack into {} failed: {:#}", volume_id, e),
+            }
+
+            let recorded = manager
+                .store
+                .update(|state| {
+                    if let Some(job) = state.unpacks.get_mut(&volume_id) {
+                        job.finished_at = Some(now());
+                        match result {
+                            Ok(()) => job.status = JobStatus::Completed,
+                            Err(e) => {
+                                job.status = JobStatus::Failed;
+                                job.last_error = Some(format!("{:#}", e));
+                            }
+                        }
+                    }
+                })
+                .await;
+            if let Err(e) = recorded {
+                warn!("Failed to record unpack into {}: {:#}", volume_id, e);
+            }
+            manager.running.lock().unwrap().remove(&volume_id);
+        });
+    }
+
+    async fn run(&self, destination: &VolumeSource) -> Result<()> {
+        let volume_id = destination.to_string();
+        let job = self
+            .status(&volume_id)
+            .await
+            .with_context(|| format!("No unpack recorded for {}", volume_id))?;
+        let archive = VolumeSource::parse(&job.source)?;
+        let archive_path = self.provisioner.host_volume_path(&archive).await?;
+        let root = self.provisioner.host_volume_path(destination).await?;
+        let size = tokio::fs::metadata(&archive_path)
+            .await
+            .with_context(|| format!("Failed to stat archive {}", archive))?
+            .len();
+        // An archive with an expected checksum is read twice
+        let total = if job.expected_sha256.is_some() {
+            size * 2
+        } else {
+            size
+        };
+
+        self.record(&volume_id, |job| {
+            job.total = total;
+            job.read = 0;
+        })
+        .await?;
+        info!(
+            "Unpacking {} ({} byte(s)) into {}",
+            archive, size, volume_id
+        );
+
+        let read = Arc::new(AtomicU64::new(0));
+        let mut task = {
+            let read = read.clone();
+            let expected_sha256 = job.expected_sha256.clone();
+            let device_nodes = self.device_nodes;
+            tokio::task::spawn_blocking(move || {
+                unpack_archive(
+                    &archive_path,
+                    &root,
+                    expected_sha256.as_deref(),
+                    device_nodes,
+                    &read,
+                )
+            })
+        };
+        let mut checkpoint = tokio::time::interval(CHECKPOINT_INTERVAL);
+        checkpoint.tick().await;
+        let (bytes, sha256) = loop {
+            tokio::select! {
+                joined = &mut task => break joined.context("Unpack task panicked")??,
+                _ = checkpoint.tick() => {
+                    let read = read.load(Ordering::Relaxed);
+                    info!("Unpack into {}: {}/{} byte(s) read", volume_id, read, total);
+                    if let Err(e) = self.record(&volume_id, |job| job.read = read).await {
+                        warn!("Failed to checkpoint unpack into {}: {:#}", volume_id, e);
+                    }
+                }
+            }
+        };
+
+        info!("Archive {} has SHA-256 {}", archive, sha256);
+        let read = read.load(Ordering::Relaxed);
+        self.record(&volume_id, |job| {
+            job.read = read;
+            job.sha256 = Some(sha256);
+            job.bytes = Some(bytes);
+        })
+        .await
+    }
+
+    async fn record(&self, volume_id: &str, f: impl FnOnce(&mut UnpackJob)) -> Result<()> {
+        self.store
+            .update(|state| {
+                if let Some(job) = state.unpacks.get_mut(volume_id) {
+                    f(job);
+                }
+            })
+            .await
+    }
+}
+
+/// Reader that publishes how much was read from it
+struct CountingReader<'a, R> {
+    inner: R,
+    read: &'a AtomicU64,
+}
+
+impl<R: Read> Read for CountingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.read.fetch_add(len as u64, Ordering::Relaxed);
+        Ok(len)
+    }
+}
+
+/// Unpack the tar archive at `archive`, gzip-compressed or not, below `root`
+/// and count the bytes read from it in `read`; device nodes are only
+/// unpacked if `device_nodes`.
+///
+/// With `expected_sha256`, the archive is hashed before anything is
+/// unpacked, and again while it is, in case it changed in between. On any
+/// failure `root` is emptied, so no volume is left with part of an archive
+/// or with an archive other than the one asked for.
+///
+/// Returns the size of the unpacked files and the hex SHA-256 of the
+/// archive as stored.
+pub fn unpack_archive(
+    archive: &Path,
+    root: &Path,
+    expected_sha256: Option<&str>,
+    device_nodes: bool,
+    read: &AtomicU64,
+) -> Result<(u64, String)> {
+    let result = (|| {
+        if let Some(expected) = expected_sha256 {
+            check_sha256(archive, &hash_archive(archive, read)?, expected)?;
+        }
+        let (bytes, sha256) = extract_archive(archive, root, device_nodes, read)?;
+        if let Some(expected) = expected_sha256 {
+            check_sha256(archive, &sha256, expected)?;
+        }
+        Ok((bytes, sha256))
+    })();
+    if result.is_err()
+        && let Err(e) = tree::empty_dir(root)
+    {
+        warn!(
+            "Failed to remove what was unpacked into {}: {:#}",
+            root.display(),
+            e
+        );
+    }
+    result
+}
+
+fn check_sha256(archive: &Path, sha256: &str, expected: &str) -> Result<()> {
+    if sha256 != expected {
+        anyhow::bail!(
+            "Archive {} has SHA-256 {}, expected {}",
+            archive.display(),
+            sha256,
+            expected
+        );
+    }
+    Ok(())
+}
+
+/// Hex SHA-256 of the archive at `archive`, counting the bytes in `read`
+fn hash_archive(archive: &Path, read: &AtomicU64) -> Result<String> {
+    let file = File::open(archive)
+        .with_context(|| format!("Failed to open archive {}", archive.display()))?;
+    let mut reader = HashingReader::new(CountingReader { inner: file, read });
+    std::io::copy(&mut reader, &mut std::io::sink())
+        .with_context(|| format!("Failed to read archive {}", archive.display()))?;
+    Ok(reader.sha256())
+}
+
+fn extract_archive(
+    archive: &Path,
+    root: &Path,
+    device_nodes: bool,
+    read: &AtomicU64,
+) -> Result<(u64, String)> {
+    let file = File::open(archive)
+        .with_context(|| format!("Failed to open archive {}", archive.display()))?;
+    let mut reader = BufReader::new(HashingReader::new(CountingReader { inner: file, read }));
+    let compressed = reader
+        .fill_buf()
+        .with_context(|| format!("Failed to read archive {}", archive.display()))?
+        .starts_with(&GZIP_MAGIC);
+
+    let mut restorer = Restorer::new(root, device_nodes);
+    let bytes = if compressed {
+        restorer.unpack(GzDecoder::new(&mut reader))
+    } else {
+        restorer.unpack(&mut reader)
+    }
+    .with_context(|| format!("Failed to unpack {}", archive.display()))?;
+    restorer.finish()?;
+
+    // Padding after the end of the archive counts toward its checksum
+    std::io::copy(&mut reader, &mut std::io::sink())
+        .with_context(|| format!("Failed to read archive {}", archive.display()))?;
+    Ok((bytes, reader.get_ref().sha256()))
+}
+
+#[tonic::async_trait]
+impl FillJobs for UnpackManager {
+    type Job = UnpackJob;
+    const KIND: &'static str = "unpack";
+    const BUSY: &'static str = "populated";
+
+    fn is_busy(&self, volume_id: &str) -> bool {
+        UnpackManager::is_busy(self, volume_id)
+    }
+
+    async fn status(&self, volume_id: &str) -> Option<UnpackJob> {
+        UnpackManager::status(self, volume_id).await
+    }
+
+    async fn forget(&self, volume_id: &str) -> Result<()> {
+        UnpackManager::forget(self, volume_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobs::archive;
+    use crate::s3::sigv4::sha256_hex;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_unpack_archive() {
+        let src = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(src.path().join("data")).unwrap();
+        std::fs::write(src.path().join("data/a.txt"), "alpha").unwrap();
+
+        let mut builder = archive::builder(Vec::new());
+        for relative in ["", "data", "data/a.txt"] {
+            archive::append(&mut builder, src.path(), Path::new(relative)).unwrap();
+        }
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Link);
+        header.set_size(0);
+        header.set_mode(0o644);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(0);
+        builder
+            .append_link(&mut header, "data/b.txt", "data/a.txt")
+            .unwrap();
+        let plain = builder.into_inner().unwrap();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(&plain).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        for contents in [plain, compressed] {
+            let dir = tempfile::tempdir().unwrap();
+            let archive_path = dir.path().join("reference.tar");
+            std::fs::write(&archive_path, &contents).unwrap();
+            let root = dir.path().join("volume");
+            std::fs::create_dir(&root).unwrap();
+
+            let read = AtomicU64::new(0);
+            let (bytes, sha256) = unpack_archive(&archive_path, &root, None, false, &read).unwrap();
+            assert_eq!(bytes, 5);
+            assert_eq!(sha256, sha256_hex(&contents));
+            assert_eq!(read.load(Ordering::Relaxed), contents.len() as u64);
+            assert_eq!(
+                std::fs::read_to_string(root.join("data/b.txt")).unwrap(),
+                "alpha"
+            );
+
+            // The checksum is known before anything is unpacked
+            let read = AtomicU64::new(0);
+            let expected = sha256_hex(&contents);
+            unpack_archive(&archive_path, &root, Some(&expected), false, &read).unwrap();
+            assert_eq!(read.load(Ordering::Relaxed), 2 * contents.len() as u64);
+
+            // A wrong one leaves the volume empty
+            let other = sha256_hex(b"other");
+            assert!(unpack_archive(&archive_path, &root, Some(&other), false, &read).is_err());
+            assert_eq!(std::fs::read_dir(&root).unwrap().count(), 0);
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let read = AtomicU64::new(0);
+        assert!(
+            unpack_archive(
+                &PathBuf::from("/nonexistent.tar"),
+                dir.path(),
+                None,
+                false,
+                &read
+            )
+            .is_err()
+        );
+    }
+}
@@ -1,15 +1,42 @@
 #![allow(dead_code)]
 use anyhow::{Context, Result};
+use std::collections::HashSet;
 use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tracing::{debug, info, warn};
 
+use super::dirstripe::DirStripe;
+use super::health::{
+    ImportState, MountHealth, classify_imports, host_path, instance_of, parse_import, probe_mount,
+};
+use super::hsm::{HsmState, parse_hsm_state};
+use super::layout::{FsTopology, VolumeLayout, parse_pool_names, parse_pool_osts, parse_targets};
+use super::params::LustreParams;
+use super::quota::{ProjectQuota, ProjectUsage};
+use super::tuning::ClientTuning;
+use crate::config::PccBackend;
+
+/// Client device types whose imports reflect the health of a mount
+const IMPORT_DEVICE_TYPES: &[&str] = &["mdc", "osc"];
+
+/// Files passed to one `lfs` call that takes a list of files
+const FILE_BATCH: usize = 256;
+
+/// How often HSM state is polled while waiting for a restore
+const HSM_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
 /// Lustre client utilities and health checks
-#[derive(Debug, Clone)]
-pub struct LustreClient;
+#[derive(Debug, Clone, Default)]
+pub struct LustreClient {
+    /// Mount points with a health probe still running
+    probes: Arc<Mutex<HashSet<String>>>,
+    params: LustreParams,
+}
 
 impl LustreClient {
     pub fn new() -> Self {
-        Self
+        Self::default()
     }
 
     /// Check if Lustre client kernel module is loaded
@@ -103,35 +130,582 @@ impl LustreClient {
         })
     }
 
-    /// Check if a Lustre mount is healthy
-    pub fn check_mount_health(&self, mount_point: &str) -> Result<bool> {
+    /// Check if a Lustre mount is healthy.
+    ///
+    /// Every step is bounded by `timeout`, so a hung mount is reported as
+    /// such instead of hanging the caller too.
+    pub async fn check_mount_health(&self, mount_point: &str, timeout: Duration) -> MountHealth {
         debug!("Checking mount health: {}", mount_point);
 
-        // Try to stat the mount point
-        let output = Command::new("stat")
-            .arg(mount_point)
-            .output()
-            .context("Failed to stat mount point")?;
+        let health = probe_mount(mount_point, timeout, &self.probes).await;
+        if !health.is_healthy() {
+            warn!(
+                "Mount point {} is unhealthy: {}",
+                mount_point,
+                health.message()
+            );
+            return health;
+        }
 
-        if !output.status.success() {
-            warn!("Mount point {} is not accessible", mount_point);
-            return Ok(false);
+        let imports = match self.client_instance(mount_point, timeout).await {
+            Ok(instance) => self.read_imports(&instance).await,
+            Err(e) => {
+                debug!("Cannot resolve client instance of {}: {:#}", mount_point, e);
+                Vec::new()
+            }
+        };
+
+        if let Some(health) = classify_imports(&imports) {
+            warn!(
+                "Mount point {} is unhealthy: {}",
+                mount_point,
+                health.message()
+            );
+            return health;
         }
 
-        // Try to list directory (basic read test)
-        let output = Command::new("ls")
-            .arg("-la")
-            .arg(mount_point)
-            .output()
-            .context("Failed to list mount point")?;
+        debug!("Mount point {} is healthy", mount_point);
+        MountHealth::Healthy
+    }
+
+    /// Superblock instance backing a mount point, via `lfs getname`
+    pub async fn client_instance(&self, mount_point: &str, timeout: Duration) -> Result<String> {
+        let mut cmd = tokio::process::Command::new("lfs");
+        cmd.arg("getname")
+            .arg(host_path(mount_point))
+            .kill_on_drop(true);
+
+        let output = tokio::time::timeout(timeout, cmd.output())
+            .await
+            .context("lfs getname timed out")?
+            .context("Failed to execute lfs getname")?;
 
         if !output.status.success() {
-            warn!("Cannot list mount point {}", mount_point);
-            return Ok(false);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("lfs getname failed: {}", stderr);
+        }
+
+        // "lustre-ffff8803c9bd1000 /mnt/lustre"
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        stdout
+            .split_whitespace()
+            .next()
+            .and_then(instance_of)
+            .map(String::from)
+            .with_context(|| format!("Unexpected lfs getname output: {}", stdout.trim()))
+    }
+
+    /// Lustre parameter access for this client
+    pub fn params(&self) -> &LustreParams {
+        &self.params
+    }
+
+    /// Apply per-volume tuning to the llite and OSC devices of a mount.
+    ///
+    /// Settings live on the client instance, so they must be applied again
+    /// whenever the mount is recreated.
+    pub async fn apply_tuning(
+        &self,
+        mount_point: &str,
+        tuning: &ClientTuning,
+        timeout: Duration,
+    ) -> Result<()> {
+        if tuning.is_empty() {
+            return Ok(());
+        }
+
+        let instance = self.client_instance(mount_point, timeout).await?;
+        for (pattern, value) in tuning.params_for(&instance) {
+            let written = self.params.set(&pattern, &value).await?;
+            if written.is_empty() {
+                anyhow::bail!("No Lustre devices match {}", pattern);
+            }
+            info!(
+                "Set {}={} on {} device(s) of {}",
+                pattern,
+                value,
+                written.len(),
+                mount_point
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Run `lfs` with a time limit and return its standard output
+    async fn lfs(&self, args: &[String], timeout: Duration) -> Result<String> {
+        run_tool("lfs", args, timeout).await
+    }
+
+    /// Run `lctl` with a time limit and return its standard output
+    async fn lctl(&self, args: &[String], timeout: Duration) -> Result<String> {
+        run_tool("lctl", args, timeout).await
+    }
+
+    /// Client version as (major, minor), via `lfs --version`
+    pub async fn client_version(&self, timeout: Duration) -> Result<(u32, u32)> {
+        let output = self.lfs(&["--version".to_string()], timeout).await?;
+        parse_version(&output)
+            .with_context(|| format!("Unexpected lfs --version output: {}", output.trim()))
+    }
+
+    /// Active OSTs, MDTs and OST pools of the filesystem mounted at `mount_point`
+    pub async fn topology(
+        &self,
+        mount_point: &str,
+        fsname: &str,
+        timeout: Duration,
+    ) -> Result<FsTopology> {
+        let path = host_path(mount_point).to_string_lossy().into_owned();
+
+        let osts = parse_targets(
+            &self
+                .lfs(&["osts".to_string(), path.clone()], timeout)
+                .await?,
+        );
+        let mdts = parse_targets(
+            &self
+                .lfs(&["mdts".to_string(), path.clone()], timeout)
+                .await?,
+        );
+        let mut pools = std::collections::BTreeMap::new();
+        let names = self.lfs(&["pool_list".to_string(), path], timeout).await?;
+        for pool in parse_pool_names(&names) {
+            let members = self
+                .lfs(
+                    &["pool_list".to_string(), format!("{}.{}", fsname, pool)],
+                    timeout,
+                )
+                .await?;
+            pools.insert(pool, parse_pool_osts(&members));
+        }
+
+        Ok(FsTopology { osts, mdts, pools })
+    }
+
+    /// Create `dir` with `lfs mkdir` on the given MDT placement
+    pub async fn make_dir(
+        &self,
+        dir: &str,
+        dir_stripe: &DirStripe,
+        mdt_index: Option<i64>,
+        timeout: Duration,
+    ) -> Result<()> {
+        let mut args = vec!["mkdir".to_string()];
+        args.extend(dir_stripe.mkdir_args(mdt_index));
+        args.push(host_path(dir).to_string_lossy().into_owned());
+        self.lfs(&args, timeout).await?;
+
+        info!("Created directory {} with {:?}", dir, args);
+        Ok(())
+    }
+
+    /// Files below `dir` with a mirror that is not in sync, via `lfs find`.
+    ///
+    /// Paths are returned as seen by the plugin, below the host root.
+    pub async fn find_stale_mirrors(&self, dir: &str, timeout: Duration) -> Result<Vec<String>> {
+        let output = self
+            .lfs(
+                &[
+                    "find".to_string(),
+                    host_path(dir).to_string_lossy().into_owned(),
+                    "--type".to_string(),
+                    "f".to_string(),
+                    "--mirror-state=^ro".to_string(),
+                ],
+                timeout,
+            )
+            .await?;
+        Ok(output.lines().map(String::from).collect())
+    }
+
+    /// Regular files below `dir`, via `lfs find`.
+    ///
+    /// Paths are returned as seen by the plugin, below the host root.
+    pub async fn find_files(&self, dir: &str, timeout: Duration) -> Result<Vec<String>> {
+        let output = self
+            .lfs(
+                &[
+                    "find".to_string(),
+                    host_path(dir).to_string_lossy().into_owned(),
+                    "--type".to_string(),
+                    "f".to_string(),
+                ],
+                timeout,
+            )
+            .await?;
+        Ok(output.lines().map(String::from).collect())
+    }
+
+    /// Rewrite one file with a new layout, given as `lfs setstripe`
+    /// arguments, via `lfs migrate`
+    pub async fn migrate(
+        &self,
+        file: &str,
+        layout_args: &[String],
+        timeout: Duration,
+    ) -> Result<()> {
+        let mut args = vec!["migrate".to_string()];
+        args.extend_from_slice(layout_args);
+        args.push(file.to_string());
+        self.lfs(&args, timeout).await?;
+        Ok(())
+    }
+
+    /// Account `dir`, and everything below it if `recursive`, to
+    /// `project_id`, and make new files inherit it (`lfs project -p <id> -s`)
+    pub async fn set_project(
+        &self,
+        dir: &str,
+        project_id: u32,
+        recursive: bool,
+        timeout: Duration,
+    ) -> Result<()> {
+        let mut args = vec![
+            "project".to_string(),
+            "-p".to_string(),
+            project_id.to_string(),
+            "-s".to_string(),
+        ];
+        if recursive {
+            args.push("-r".to_string());
         }
+        args.push(host_path(dir).to_string_lossy().into_owned());
+        self.lfs(&args, timeout).await?;
+        Ok(())
+    }
 
-        info!("Mount point {} is healthy", mount_point);
-        Ok(true)
+    /// Usage and limits of a project on the filesystem mounted at
+    /// `mount_point`, via `lfs quota -q -p <id>`
+    pub async fn project_usage(
+        &self,
+        mount_point: &str,
+        project_id: u32,
+        timeout: Duration,
+    ) -> Result<ProjectUsage> {
+        let output = self
+            .lfs(
+                &[
+                    "quota".to_string(),
+                    "-q".to_string(),
+                    "-p".to_string(),
+                    project_id.to_string(),
+                    host_path(mount_point).to_string_lossy().into_owned(),
+                ],
+                timeout,
+            )
+            .await?;
+        ProjectUsage::parse(&output)
+    }
+
+    /// Set the limits of a project quota on the filesystem mounted at
+    /// `mount_point`
+    pub async fn set_project_quota(
+        &self,
+        mount_point: &str,
+        project_id: u32,
+        quota: &ProjectQuota,
+        timeout: Duration,
+    ) -> Result<()> {
+        let mut args = vec!["setquota".to_string()];
+        args.extend(quota.setquota_args(project_id));
+        args.push(host_path(mount_point).to_string_lossy().into_owned());
+        self.lfs(&args, timeout).await?;
+
+        info!("Set quota of project {} to {:?}", project_id, quota);
+        Ok(())
+    }
+
+    /// HSM state of each file, via `lfs hsm_state`
+    pub async fn hsm_state(
+        &self,
+        files: &[String],
+        timeout: Duration,
+    ) -> Result<Vec<(String, HsmState)>> {
+        let mut states = Vec::with_capacity(files.len());
+        for batch in files.chunks(FILE_BATCH) {
+            let mut args = vec!["hsm_state".to_string()];
+            args.extend_from_slice(batch);
+            states.extend(parse_hsm_state(&self.lfs(&args, timeout).await?));
+        }
+        Ok(states)
+    }
+
+    /// Ask the copytool to copy files to the archive (`lfs hsm_archive`)
+    pub async fn hsm_archive(
+        &self,
+        files: &[String],
+        archive_id: Option<u32>,
+        timeout: Duration,
+    ) -> Result<()> {
+        let mut options = Vec::new();
+        if let Some(archive_id) = archive_id {
+            options.extend(["--archive".to_string(), archive_id.to_string()]);
+        }
+        let mut args = vec!["hsm_archive".to_string()];
+        args.extend(options);
+        self.batched(&args, files, timeout).await
+    }
+
+    /// Drop the Lustre copy of archived files (`lfs hsm_release`)
+    pub async fn hsm_release(&self, files: &[String], timeout: Duration) -> Result<()> {
+        self.batched(&["hsm_release".to_string()], files, timeout)
+            .await
+    }
+
+    /// Ask the copytool to bring released files back (`lfs hsm_restore`)
+    pub async fn hsm_restore(&self, files: &[String], timeout: Duration) -> Result<()> {
+        self.batched(&["hsm_restore".to_string()], files, timeout)
+            .await
+    }
+
+    /// Run `lfs <args> <files>` over `files` in batches
+    async fn batched(&self, args: &[String], files: &[String], timeout: Duration) -> Result<()> {
+        for batch in files.chunks(FILE_BATCH) {
+            let mut args = args.to_vec();
+            args.extend_from_slice(batch);
+            self.lfs(&args, timeout).await?;
+        }
+        debug!("Ran lfs {} on {} file(s)", args.join(" "), files.len());
+        Ok(())
+    }
+
+    /// Register a PCC backend with the client mount at `mount_point`
+    /// (`lctl pcc add`); a backend that is already registered is kept
+    pub async fn pcc_add(
+        &self,
+        mount_point: &str,
+        backend: &PccBackend,
+        timeout: Duration,
+    ) -> Result<()> {
+        let backend_path = host_path(&backend.path).to_string_lossy().into_owned();
+        let listed = self
+            .lctl(
+                &[
+                    "pcc".to_string(),
+                    "list".to_string(),
+                    host_path(mount_point).to_string_lossy().into_owned(),
+                ],
+                timeout,
+            )
+            .await?;
+        if parse_pcc_paths(&listed).contains(&backend_path.as_str()) {
+            debug!("PCC backend {} is already on {}", backend.path, mount_point);
+            return Ok(());
+        }
+
+        let id_param = if backend.read_only { "roid" } else { "rwid" };
+        let result = self
+            .lctl(
+                &[
+                    "pcc".to_string(),
+                    "add".to_string(),
+                    host_path(mount_point).to_string_lossy().into_owned(),
+                    backend_path,
+                    "--param".to_string(),
+                    format!("{}={}", id_param, backend.archive_id),
+                ],
+                timeout,
+            )
+            .await;
+
+        result?;
+        info!("Added PCC backend {} to {}", backend.path, mount_point);
+        Ok(())
+    }
+
+    /// Unregister a PCC backend from a client mount (`lctl pcc del`)
+    pub async fn pcc_del(
+        &self,
+        mount_point: &str,
+        backend: &PccBackend,
+        timeout: Duration,
+    ) -> Result<()> {
+        self.lctl(
+            &[
+                "pcc".to_string(),
+                "del".to_string(),
+                host_path(mount_point).to_string_lossy().into_owned(),
+                host_path(&backend.path).to_string_lossy().into_owned(),
+            ],
+            timeout,
+        )
+        .await?;
+
+        info!("Removed PCC backend {} from {}", backend.path, mount_point);
+        Ok(())
+    }
+
+    /// Attach every regular file below `dir` to a PCC backend, which copies
+    /// their data into the cache.
+    ///
+    /// A batch that fails is logged and skipped; returns the number of files
+    /// attached.
+    pub async fn pcc_attach_dir(
+        &self,
+        dir: &str,
+        backend: &PccBackend,
+        timeout: Duration,
+    ) -> Result<usize> {
+        let files = self.find_files(dir, timeout).await?;
+        let mut args = vec![
+            "pcc".to_string(),
+            "attach".to_string(),
+            "-i".to_string(),
+            backend.archive_id.to_string(),
+        ];
+        if backend.read_only {
+            args.push("-r".to_string());
+        }
+
+        let mut attached = 0;
+        for batch in files.chunks(FILE_BATCH) {
+            match self.batched(&args, batch, timeout).await {
+                Ok(()) => attached += batch.len(),
+                Err(e) => warn!("Failed to attach files below {} to PCC: {:#}", dir, e),
+            }
+        }
+        Ok(attached)
+    }
+
+    /// Detach every file below `dir` from PCC (`lfs pcc detach`); files that
+    /// are not attached are left alone by Lustre
+    pub async fn pcc_detach_dir(&self, dir: &str, timeout: Duration) -> Result<()> {
+        let files = self.find_files(dir, timeout).await?;
+        self.batched(&["pcc".to_string(), "detach".to_string()], &files, timeout)
+            .await
+    }
+
+    /// Restore the released files below `dir`, or only `files` relative to
+    /// it, and wait until they are online.
+    ///
+    /// Returns false if some were still released when `timeout` ran out.
+    pub async fn restore_online(
+        &self,
+        dir: &str,
+        files: Option<&[String]>,
+        timeout: Duration,
+    ) -> Result<bool> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let files = match files {
+            Some(files) => files
+                .iter()
+                .map(|file| host_path(dir).join(file).to_string_lossy().into_owned())
+                .collect(),
+            None => self.find_files(dir, timeout).await?,
+        };
+
+        let mut requested = false;
+        loop {
+            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+            let released: Vec<String> = self
+                .hsm_state(&files, remaining)
+                .await?
+                .into_iter()
+                .filter(|(_, state)| state.released)
+                .map(|(file, _)| file)
+                .collect();
+            if released.is_empty() {
+                return Ok(true);
+            }
+
+            if !requested {
+                info!(
+                    "Restoring {} released file(s) below {}",
+                    released.len(),
+                    dir
+                );
+                self.hsm_restore(&released, remaining).await?;
+                requested = true;
+            }
+            if tokio::time::Instant::now() + HSM_POLL_INTERVAL > deadline {
+                warn!(
+                    "{} file(s) below {} are still released after {:?}",
+                    released.len(),
+                    dir,
+                    timeout
+                );
+                return Ok(false);
+            }
+            tokio::time::sleep(HSM_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Ask the OSTs to read the whole of `file`, a host path, into their
+    /// cache ahead of use (`lfs ladvise -a willread`)
+    pub async fn ladvise_willread(&self, file: &str, timeout: Duration) -> Result<()> {
+        let size = tokio::fs::metadata(file)
+            .await
+            .with_context(|| format!("Failed to stat {}", file))?
+            .len();
+        if size == 0 {
+            return Ok(());
+        }
+
+        self.lfs(
+            &[
+                "ladvise".to_string(),
+                "-a".to_string(),
+                "willread".to_string(),
+                "-s".to_string(),
+                "0".to_string(),
+                "-e".to_string(),
+                size.to_string(),
+                file.to_string(),
+            ],
+            timeout,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Copy up-to-date data over the stale mirrors of one file
+    pub async fn mirror_resync(&self, file: &str, timeout: Duration) -> Result<()> {
+        self.lfs(
+            &["mirror".to_string(), "resync".to_string(), file.to_string()],
+            timeout,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Set the default layout new files in `dir` inherit
+    pub async fn set_default_layout(
+        &self,
+        dir: &str,
+        layout: &VolumeLayout,
+        timeout: Duration,
+    ) -> Result<()> {
+        if layout.is_empty() {
+            return Ok(());
+        }
+
+        let mut args = vec!["setstripe".to_string()];
+        args.extend(layout.setstripe_args());
+        args.push(host_path(dir).to_string_lossy().into_owned());
+        self.lfs(&args, timeout).await?;
+
+        info!("Set default layout of {} to {:?}", dir, layout);
+        Ok(())
+    }
+
+    /// MDC and OSC import states of one client instance
+    pub async fn read_imports(&self, instance: &str) -> Vec<ImportState> {
+        let mut imports = Vec::new();
+
+        for device_type in IMPORT_DEVICE_TYPES {
+            let pattern = format!("{}.*-{}.import", device_type, instance);
+            match self.params.get(&pattern).await {
+                Ok(params) => imports.extend(
+                    params
+                        .iter()
+                        .filter_map(|param| parse_import(param.device()?, param.as_text()?)),
+                ),
+                Err(e) => debug!("Failed to read {}: {:#}", pattern, e),
+            }
+        }
+
+        imports
     }
 
     /// Get Lustre version
@@ -208,6 +782,55 @@ impl LustreFilesystemInfo {
     }
 }
 
+/// Major and minor version from output such as `lfs 2.15.3`
+fn parse_version(output: &str) -> Option<(u32, u32)> {
+    let version = output
+        .split_whitespace()
+        .find(|token| token.starts_with(|c: char| c.is_ascii_digit()))?;
+    let mut parts = version.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+/// Backend paths listed by `lctl pcc list`
+///
+/// ```text
+/// mount: /mnt/lustre
+/// pcc_backends:
+///   - pcc_path: /mnt/pcc
+///     rwid: 2
+/// ```
+fn parse_pcc_paths(output: &str) -> Vec<&str> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim_start().trim_start_matches("- ");
+            line.strip_prefix("pcc_path:").map(str::trim)
+        })
+        .collect()
+}
+
+/// Run a Lustre utility with a time limit and return its standard output
+async fn run_tool(tool: &str, args: &[String], timeout: Duration) -> Result<String> {
+    let mut cmd = tokio::process::Command::new(tool);
+    cmd.args(args).kill_on_drop(true);
+    debug!("Executing {} {}", tool, args.join(" "));
+
+    let subcommand = args.first().map(String::as_str).unwrap_or_default();
+    let output = tokio::time::timeout(timeout, cmd.output())
+        .await
+        .with_context(|| format!("{} {} timed out", tool, subcommand))?
+        .with_context(|| format!("Failed to execute {} {}", tool, subcommand))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("{} {} failed: {}", tool, subcommand, stderr.trim());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
 /// Parse size string like "96.0G", "1.0M", etc. to bytes
 fn parse_size_string(size_str: &str) -> Option<u64> {
     let size_str = size_str.trim();
@@ -246,4 +869,24 @@ mod tests {
         assert_eq!(parse_size_string("96.0G"), Some(96 * 1024 * 1024 * 1024));
         assert_eq!(parse_size_string("3.0M"), Some(3 * 1024 * 1024));
     }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("lfs 2.15.3\n"), Some((2, 15)));
+        assert_eq!(parse_version("lfs 2.10.8_1_g1a2b3c"), Some((2, 10)));
+        assert_eq!(parse_version("lfs unknown"), None);
+    }
+
+    #[test]
+    fn test_parse_pcc_paths() {
+        let output = "mount: /mnt/lustre\n\
+                      pcc_backends:\n  \
+                      - pcc_path: /mnt/pcc\n    \
+                      roid: 0\n    \
+                      rwid: 2\n  \
+                      - pcc_path: /mnt/pcc-ro\n    \
+                      roid: 3\n";
+        assert_eq!(parse_pcc_paths(output), vec!["/mnt/pcc", "/mnt/pcc-ro"]);
+        assert!(parse_pcc_paths("mount: /mnt/lustre\npcc_backends: []\n").is_empty());
+    }
 }
@@ -0,0 +1,246 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+
+const MDT_INDEX_KEY: &str = "mdtIndex";
+const MDT_PLACEMENT_KEY: &str = "mdtPlacement";
+const MDT_COUNT_KEY: &str = "mdtCount";
+const DIR_HASH_TYPE_KEY: &str = "dirHashType";
+
+/// Hash types for striped directories, with the client version each needs
+const HASH_TYPES: &[(&str, (u32, u32))] = &[
+    ("fnv_1a_64", (2, 8)),
+    ("all_char", (2, 8)),
+    ("crush", (2, 14)),
+];
+
+/// Client version needed to create striped directories
+const STRIPED_DIR_MIN_VERSION: (u32, u32) = (2, 8);
+/// Client version that lets the MDS place directories by free space
+const SPACE_BALANCED_MIN_VERSION: (u32, u32) = (2, 13);
+
+/// Which MDT a volume directory is created on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MdtPlacement {
+    Index(u32),
+    /// The provisioner cycles through the active MDTs
+    RoundRobin,
+    /// The MDS picks an MDT by free space (`lfs mkdir -i -1`)
+    SpaceBalanced,
+}
+
+/// MDT placement and striping of a volume directory on a DNE filesystem.
+///
+/// An empty `DirStripe` creates a plain directory, which Lustre puts on the
+/// MDT of its parent.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DirStripe {
+    pub placement: Option<MdtPlacement>,
+    /// Number of MDTs a striped directory spans; -1 spans all of them
+    pub count: Option<i64>,
+    pub hash_type: Option<String>,
+}
+
+impl DirStripe {
+    /// Read `mdtIndex`, `mdtPlacement`, `mdtCount` and `dirHashType`
+    pub fn from_parameters(parameters: &HashMap<String, String>) -> Result<Self> {
+        let index = parameters
+            .get(MDT_INDEX_KEY)
+            .map(|v| {
+                v.trim()
+                    .parse::<u32>()
+                    .with_context(|| format!("{} must be an MDT index, got {:?}", MDT_INDEX_KEY, v))
+            })
+            .transpose()?;
+        let placement = match (index, parameters.get(MDT_PLACEMENT_KEY)) {
+            (Some(_), Some(_)) => anyhow::bail!(
+                "{} and {} are mutually exclusive",
+                MDT_INDEX_KEY,
+                MDT_PLACEMENT_KEY
+            ),
+            (Some(index), None) => Some(MdtPlacement::Index(index)),
+            (None, Some(placement)) => Some(match placement.trim() {
+                "roundRobin" => MdtPlacement::RoundRobin,
+                "spaceBalanced" => MdtPlacement::SpaceBalanced,
+                other => anyhow::bail!(
+                    "{} must be roundRobin or spaceBalanced, got {:?}",
+                    MDT_PLACEMENT_KEY,
+                    other
+                ),
+            }),
+            (None, None) => None,
+        };
+
+        let count = parameters
+            .get(MDT_COUNT_KEY)
+            .map(|v| {
+                v.trim()
+                    .parse::<i64>()
+                    .ok()
+                    .filter(|count| *count == -1 || *count >= 1)
+                    .with_context(|| {
+                        format!("{} must be -1 or at least 1, got {:?}", MDT_COUNT_KEY, v)
+                    })
+            })
+            .transpose()?;
+
+        let hash_type = parameters
+            .get(DIR_HASH_TYPE_KEY)
+            .map(|v| v.trim().to_string());
+        if let Some(hash_type) = &hash_type {
+            if !HASH_TYPES.iter().any(|(name, _)| name == hash_type) {
+                anyhow::bail!("Unknown {} {:?}", DIR_HASH_TYPE_KEY, hash_type);
+            }
+            if count.is_none_or(|count| count == 1) {
+                anyhow::bail!(
+                    "{} needs a striped directory ({} other than 1)",
+                    DIR_HASH_TYPE_KEY,
+                    MDT_COUNT_KEY
+                );
+            }
+        }
+
+        Ok(Self {
+            placement,
+            count,
+            hash_type,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Check MDT index and count against the active MDTs
+    pub fn validate(&self, mdts: &[u32]) -> Result<()> {
+        if let Some(MdtPlacement::Index(index)) = self.placement
+            && !mdts.contains(&index)
+        {
+            anyhow::bail!("MDT {} is not an active MDT of the filesystem", index);
+        }
+        if let Some(count) = self.count
+            && count > mdts.len() as i64
+        {
+            anyhow::bail!(
+                "{} {} exceeds the {} active MDT(s)",
+                MDT_COUNT_KEY,
+                count,
+                mdts.len()
+            );
+        }
+        Ok(())
+    }
+
+    /// Oldest Lustre client that can create this directory, as (major, minor)
+    pub fn min_client_version(&self) -> Option<(u32, u32)> {
+        let mut required = None;
+        if self.placement == Some(MdtPlacement::SpaceBalanced) {
+            required = required.max(Some(SPACE_BALANCED_MIN_VERSION));
+        }
+        if self.count.is_some_and(|count| count != 1) {
+            required = required.max(Some(STRIPED_DIR_MIN_VERSION));
+        }
+        if let Some(hash_type) = &self.hash_type {
+            let version = HASH_TYPES
+                .iter()
+                .find(|(name, _)| name == hash_type)
+                .map(|(_, version)| *version);
+            required = required.max(version);
+        }
+        required
+    }
+
+    /// Arguments for `lfs mkdir`, with round-robin placement already
+    /// resolved to `mdt_index`
+    pub fn mkdir_args(&self, mdt_index: Option<i64>) -> Vec<String> {
+        let mut args = Vec::new();
+        let index = match self.placement {
+            Some(MdtPlacement::Index(index)) => Some(index as i64),
+            Some(MdtPlacement::SpaceBalanced) => Some(-1),
+            Some(MdtPlacement::RoundRobin) => mdt_index,
+            None => None,
+        };
+        if let Some(index) = index {
+            args.extend(["-i".to_string(), index.to_string()]);
+        }
+        if let Some(count) = self.count {
+            args.extend(["-c".to_string(), count.to_string()]);
+        }
+        if let Some(hash_type) = &self.hash_type {
+            args.extend(["-H".to_string(), hash_type.clone()]);
+        }
+        args
+    }
+
+    /// Record placement and striping in a volume context
+    pub fn to_volume_context(&self, volume_context: &mut HashMap<String, String>) {
+        match self.placement {
+            Some(MdtPlacement::Index(index)) => {
+                volume_context.insert(MDT_INDEX_KEY.to_string(), index.to_string());
+            }
+            Some(MdtPlacement::RoundRobin) => {
+                volume_context.insert(MDT_PLACEMENT_KEY.to_string(), "roundRobin".to_string());
+            }
+            Some(MdtPlacement::SpaceBalanced) => {
+                volume_context.insert(MDT_PLACEMENT_KEY.to_string(), "spaceBalanced".to_string());
+            }
+            None => {}
+        }
+        if let Some(count) = self.count {
+            volume_context.insert(MDT_COUNT_KEY.to_string(), count.to_string());
+        }
+        if let Some(hash_type) = &self.hash_type {
+            volume_context.insert(DIR_HASH_TYPE_KEY.to_string(), hash_type.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::parameters;
+
+    #[test]
+    fn test_dir_stripe_args() {
+        let stripe = DirStripe::from_parameters(&parameters(&[
+            ("mdtPlacement", "roundRobin"),
+            ("mdtCount", "2"),
+            ("dirHashType", "crush"),
+        ]))
+        .unwrap();
+        assert_eq!(stripe.min_client_version(), Some((2, 14)));
+        assert_eq!(
+            stripe.mkdir_args(Some(3)),
+            vec!["-i", "3", "-c", "2", "-H", "crush"]
+        );
+
+        let pinned = DirStripe::from_parameters(&parameters(&[("mdtIndex", "1")])).unwrap();
+        assert_eq!(pinned.min_client_version(), None);
+    }
+
+    #[test]
+    fn test_dir_stripe_validation() {
+        let stripe = DirStripe::from_parameters(&parameters(&[("mdtCount", "2")])).unwrap();
+        assert!(stripe.validate(&[0, 1]).is_ok());
+        assert!(stripe.validate(&[0]).is_err());
+
+        let pinned = DirStripe::from_parameters(&parameters(&[("mdtIndex", "1")])).unwrap();
+        assert!(pinned.validate(&[0, 1]).is_ok());
+        assert!(pinned.validate(&[0, 2]).is_err());
+    }
+
+    #[test]
+    fn test_invalid_dir_stripe() {
+        for bad in [
+            &[("mdtIndex", "1"), ("mdtPlacement", "spaceBalanced")][..],
+            &[("mdtPlacement", "random")],
+            &[("mdtCount", "0")],
+            &[("dirHashType", "fnv_1a_64")],
+        ] {
+            assert!(
+                DirStripe::from_parameters(&parameters(bad)).is_err(),
+                "{:?}",
+                bad
+            );
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Root of the host filesystem as seen through the host init process.
+///
+/// Paths under it resolve in the host mount namespace, so staging mounts
+/// that are not shared with the plugin container can still be probed.
+pub const HOST_ROOT: &str = "/proc/1/root";
+
+/// Import states a healthy, connected client reports
+const CONNECTED_STATES: &[&str] = &["FULL", "IDLE"];
+
+/// Result of probing one Lustre client mount
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MountHealth {
+    Healthy,
+    /// A syscall did not return within the probe timeout
+    Hung,
+    /// The mount answers with an error such as ESTALE, ENOTCONN or EIO
+    Stale(String),
+    /// The client was evicted by at least one target
+    Evicted(Vec<String>),
+    /// At least one target connection is not fully up
+    Degraded(Vec<String>),
+}
+
+impl MountHealth {
+    pub fn is_healthy(&self) -> bool {
+        matches!(self, MountHealth::Healthy)
+    }
+
+    /// Human-readable summary for a CSI `VolumeCondition`
+    pub fn message(&self) -> String {
+        match self {
+            MountHealth::Healthy => "Lustre mount is healthy".to_string(),
+            MountHealth::Hung => "Lustre mount is not responding".to_string(),
+            MountHealth::Stale(err) => format!("Lustre mount is stale: {}", err),
+            MountHealth::Evicted(targets) => {
+                format!("Lustre client was evicted by {}", targets.join(", "))
+            }
+            MountHealth::Degraded(targets) => {
+                format!("Lustre targets not connected: {}", targets.join(", "))
+            }
+        }
+    }
+}
+
+/// Connection state of one MDC/OSC import
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportState {
+    /// Device name, e.g. `lustre-OST0000-osc-ffff8803c9bd1000`
+    pub device: String,
+    /// Target UUID, e.g. `lustre-OST0000_UUID`
+    pub target: String,
+    /// Import state, e.g. `FULL`, `DISCONN`, `EVICTED`
+    pub state: String,
+}
+
+impl ImportState {
+    pub fn is_connected(&self) -> bool {
+        CONNECTED_STATES.contains(&self.state.as_str())
+    }
+
+    pub fn is_evicted(&self) -> bool {
+        self.state == "EVICTED"
+    }
+}
+
+/// Classify a set of imports; `None` means they are all connected
+pub fn classify_imports(imports: &[ImportState]) -> Option<MountHealth> {
+    let evicted: Vec<String> = imports
+        .iter()
+        .filter(|i| i.is_evicted())
+        .map(|i| i.target.clone())
+        .collect();
+    if !evicted.is_empty() {
+        return Some(MountHealth::Evicted(evicted));
+    }
+
+    let degraded: Vec<String> = imports
+        .iter()
+        .filter(|i| !i.is_connected())
+        .map(|i| format!("{} ({})", i.target, i.state))
+        .collect();
+    if !degraded.is_empty() {
+        return Some(MountHealth::Degraded(degraded));
+    }
+
+    None
+}
+
+/// Parse the YAML-ish `import` parameter of one device.
+///
+/// ```text
+/// import:
+///     name: lustre-OST0000-osc-ffff8803c9bd1000
+///     target: lustre-OST0000_UUID
+///     state: FULL
+/// ```
+pub fn parse_import(device: &str, text: &str) -> Option<ImportState> {
+    let mut target = None;
+    let mut state = None;
+
+    for line in text.lines() {
+        let Some((key, value)) = line.trim().split_once(':') else {
+            continue;
+        };
+        match key.trim() {
+            "target" if target.is_none() => target = Some(value.trim().to_string()),
+            "state" if state.is_none() => state = Some(value.trim().to_string()),
+            _ => {}
+        }
+    }
+
+    Some(ImportState {
+        device: device.to_string(),
+        target: target?,
+        state: state?,
+    })
+}
+
+/// Superblock instance of a client device or `lfs getname` name.
+///
+/// `lustre-ffff8803c9bd1000` and `lustre-OST0000-osc-ffff8803c9bd1000` both
+/// yield `ffff8803c9bd1000`.
+pub fn instance_of(name: &str) -> Option<&str> {
+    name.rsplit_once('-')
+        .map(|(_, instance)| instance)
+        .filter(|instance| !instance.is_empty())
+}
+
+/// Path of `path` in the host mount namespace
+pub fn host_path(path: &str) -> PathBuf {
+    Path::new(HOST_ROOT).join(path.trim_start_matches('/'))
+}
+
+/// Probe a mount point with `stat`, `statvfs` and `readdir`, bounded in time.
+///
+/// The syscalls run on a blocking thread that may stay stuck on a hung
+/// mount; `in_flight` ensures at most one such thread exists per path.
+pub async fn probe_mount(
+    path: &str,
+    timeout: Duration,
+    in_flight: &Arc<Mutex<HashSet<String>>>,
+) -> MountHealth {
+    if !in_flight.lock().unwrap().insert(path.to_string()) {
+        // The previous probe of this path never returned
+        return MountHealth::Hung;
+    }
+
+    let probe_path = host_path(path);
+    let key = path.to_string();
+    let in_flight_done = in_flight.clone();
+    let handle = tokio::task::spawn_blocking(move || {
+        let result = probe_syscalls(&probe_path);
+        in_flight_done.lock().unwrap().remove(&key);
+        result
+    });
+
+    match tokio::time::timeout(timeout, handle).await {
+        Ok(Ok(Ok(()))) => MountHealth::Healthy,
+        Ok(Ok(Err(e))) => MountHealth::Stale(e.to_string()),
+        Ok(Err(e)) => MountHealth::Stale(format!("probe task failed: {}", e)),
+        Err(_) => MountHealth::Hung,
+    }
+}
+
+fn probe_syscalls(path: &Path) -> std::io::Result<()> {
+    std::fs::metadata(path)?;
+    statvfs(path)?;
+    if let Some(entry) = std::fs::read_dir(path)?.next() {
+        entry?;
+    }
+    Ok(())
+}
+
+/// Filesystem usage as reported by `statvfs(2)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsUsage {
+    pub total_bytes: u64,
+    pub available_bytes: u64,
+    pub used_bytes: u64,
+    pub total_inodes: u64,
+    pub free_inodes: u64,
+    pub used_inodes: u64,
+}
+
+pub fn statvfs(path: &Path) -> std::io::Result<FsUsage> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let mut buf: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut buf) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let frsize = buf.f_frsize as u64;
+    let total_bytes = buf.f_blocks as u64 * frsize;
+    let free_bytes = buf.f_bfree as u64 * frsize;
+    let available_bytes = buf.f_bavail as u64 * frsize;
+    let total_inodes = buf.f_files as u64;
+    let free_inodes = buf.f_ffree as u64;
+
+    Ok(FsUsage {
+        total_bytes,
+        available_bytes,
+        used_bytes: total_bytes.saturating_sub(free_bytes),
+        total_inodes,
+        free_inodes,
+        used_inodes: total_inodes.saturating_sub(free_inodes),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_import() {
+        let text = "\
+import:
+    name: lustre-OST0001-osc-ffff8803c9bd1000
+    target: lustre-OST0001_UUID
+    state: EVICTED
+    connect_flags: [ write_grant, server_lock ]
+    import_flags: [ replayable, pingable ]
+    connection:
+       failover_nids: [ 10.0.0.2@tcp ]
+       current_connection: 10.0.0.2@tcp
+";
+        let import = parse_import("lustre-OST0001-osc-ffff8803c9bd1000", text).unwrap();
+        assert_eq!(import.target, "lustre-OST0001_UUID");
+        assert!(import.is_evicted());
+        assert_eq!(
+            classify_imports(&[import]),
+            Some(MountHealth::Evicted(vec!["lustre-OST0001_UUID".into()]))
+        );
+
+        assert_eq!(
+            instance_of("lustre-OST0001-osc-ffff8803c9bd1000"),
+            Some("ffff8803c9bd1000")
+        );
+        assert_eq!(
+            instance_of("lustre-ffff8803c9bd1000"),
+            Some("ffff8803c9bd1000")
+        );
+        assert!(parse_import("x", "import:\n    name: x\n").is_none());
+    }
+}
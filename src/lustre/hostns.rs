@@ -0,0 +1,195 @@
+use anyhow::{Context, Result};
+use std::ffi::CString;
+use std::fs::File;
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use tokio::sync::oneshot;
+use tracing::{debug, error, info};
+
+/// Mount namespace of the host init process
+pub const HOST_MOUNT_NAMESPACE: &str = "/proc/1/ns/mnt";
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Runs filesystem and mount syscalls inside the host mount namespace.
+///
+/// `setns(CLONE_NEWNS)` changes the namespace of the calling thread only, so
+/// all work is funnelled through one dedicated OS thread that has entered the
+/// host namespace once at startup. Tokio worker threads are never moved.
+#[derive(Clone)]
+pub struct HostNamespace {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl std::fmt::Debug for HostNamespace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HostNamespace").finish_non_exhaustive()
+    }
+}
+
+impl HostNamespace {
+    /// Spawn the worker thread and enter the mount namespace at `ns_path`
+    pub fn enter(ns_path: &str) -> Result<Self> {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let (ready_tx, ready_rx) = mpsc::channel::<Result<()>>();
+        let ns_path = ns_path.to_string();
+
+        thread::Builder::new()
+            .name("host-mntns".to_string())
+            .spawn(move || {
+                let entered = enter_mount_namespace(&ns_path);
+                let ok = entered.is_ok();
+                let _ = ready_tx.send(entered);
+                if !ok {
+                    return;
+                }
+
+                while let Ok(job) = queue.recv() {
+                    job();
+                }
+                debug!("Host mount namespace worker exiting");
+            })
+            .context("Failed to spawn host mount namespace thread")?;
+
+        ready_rx
+            .recv()
+            .context("Host mount namespace thread exited during startup")??;
+
+        info!("Entered host mount namespace via setns");
+        Ok(Self { jobs })
+    }
+
+    /// Run a closure on the namespace thread and wait for its result
+    pub async fn run<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce() -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        self.jobs
+            .send(Box::new(move || {
+                let _ = tx.send(f());
+            }))
+            .map_err(|_| anyhow::anyhow!("Host mount namespace thread is not running"))?;
+
+        rx.await
+            .context("Host mount namespace thread dropped the request")?
+    }
+
+    /// Check whether `path` is a directory on the host
+    pub async fn is_dir(&self, path: &str) -> Result<bool> {
+        let path = path.to_string();
+        self.run(move || Ok(Path::new(&path).is_dir())).await
+    }
+
+    /// Create `path` and any missing parents on the host
+    pub async fn create_dir_all(&self, path: &str) -> Result<()> {
+        let path = path.to_string();
+        self.run(move || {
+            std::fs::create_dir_all(&path)
+                .with_context(|| format!("Failed to create directory {}", path))
+        })
+        .await
+    }
+
+    /// Remove the empty directory `path` on the host; a missing path is fine
+    pub async fn remove_dir(&self, path: &str) -> Result<()> {
+        let path = path.to_string();
+        self.run(move || match std::fs::remove_dir(&path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e).with_context(|| format!("Failed to remove directory {}", path)),
+        })
+        .await
+    }
+
+    /// Bind mount `source` onto `target`, optionally read-only
+    pub async fn bind_mount(&self, source: &str, target: &str, read_only: bool) -> Result<()> {
+        let source = source.to_string();
+        let target = target.to_string();
+        self.run(move || {
+            let src = c_path(&source)?;
+            let tgt = c_path(&target)?;
+
+            sys_mount(&src, &tgt, libc::MS_BIND)
+                .with_context(|| format!("Failed to bind mount {} -> {}", source, target))?;
+
+            // MS_RDONLY is ignored on the initial bind and needs a remount
+            if read_only
+                && let Err(e) = sys_mount(
+                    &src,
+                    &tgt,
+                    libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY,
+                )
+            {
+                let _ = sys_umount(&tgt, 0);
+                return Err(e).with_context(|| format!("Failed to remount {} read-only", target));
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Unmount `target` with `umount2(2)` flags such as `MNT_FORCE`
+    pub async fn unmount(&self, target: &str, flags: libc::c_int) -> Result<()> {
+        let target = target.to_string();
+        self.run(move || {
+            let tgt = c_path(&target)?;
+            sys_umount(&tgt, flags).with_context(|| format!("Failed to unmount {}", target))
+        })
+        .await
+    }
+}
+
+fn enter_mount_namespace(ns_path: &str) -> Result<()> {
+    let ns = File::open(ns_path)
+        .with_context(|| format!("Failed to open mount namespace {}", ns_path))?;
+
+    // setns(CLONE_NEWNS) is refused while the thread shares fs attributes
+    // with the rest of the process.
+    if unsafe { libc::unshare(libc::CLONE_FS) } != 0 {
+        let err = std::io::Error::last_os_error();
+        error!("unshare(CLONE_FS) failed: {}", err);
+        return Err(err).context("Failed to unshare filesystem attributes");
+    }
+
+    if unsafe { libc::setns(ns.as_raw_fd(), libc::CLONE_NEWNS) } != 0 {
+        let err = std::io::Error::last_os_error();
+        error!("setns({}) failed: {}", ns_path, err);
+        return Err(err).context("Failed to enter host mount namespace");
+    }
+
+    Ok(())
+}
+
+fn c_path(path: &str) -> Result<CString> {
+    CString::new(Path::new(path).as_os_str().as_bytes())
+        .with_context(|| format!("Path contains a NUL byte: {}", path))
+}
+
+fn sys_mount(source: &CString, target: &CString, flags: libc::c_ulong) -> std::io::Result<()> {
+    let rc = unsafe {
+        libc::mount(
+            source.as_ptr(),
+            target.as_ptr(),
+            std::ptr::null(),
+            flags,
+            std::ptr::null(),
+        )
+    };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn sys_umount(target: &CString, flags: libc::c_int) -> std::io::Result<()> {
+    if unsafe { libc::umount2(target.as_ptr(), flags) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,274 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::utils::xattr;
+
+const ON_DELETE_KEY: &str = "hsmOnDelete";
+const ARCHIVE_ID_KEY: &str = "hsmArchiveId";
+const RESTORE_KEY: &str = "hsmRestore";
+const RESTORE_FILES_KEY: &str = "hsmRestoreFiles";
+const RESTORE_TIMEOUT_KEY: &str = "hsmRestoreTimeout";
+
+/// Extended attribute recording the delete policy on a volume directory,
+/// since DeleteVolume only gets the volume ID
+const DELETE_POLICY_XATTR: &str = "trusted.klustre_csi.on_delete";
+
+/// How long a publish waits for files to come back online by default
+const DEFAULT_RESTORE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// HSM flags of one file, as listed by `lfs hsm_state`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HsmState {
+    pub exists: bool,
+    pub archived: bool,
+    pub released: bool,
+    /// Changed since it was archived
+    pub dirty: bool,
+    /// The archive copy is gone
+    pub lost: bool,
+    /// Marked never to be archived
+    pub no_archive: bool,
+}
+
+impl HsmState {
+    /// Whether the archive holds an up-to-date copy of the file
+    pub fn is_archived(&self) -> bool {
+        self.exists && self.archived && !self.dirty
+    }
+
+    /// Whether an archive request for the file can ever succeed
+    pub fn can_archive(&self) -> bool {
+        !self.lost && !self.no_archive
+    }
+}
+
+/// What DeleteVolume does with a volume directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeletePolicy {
+    /// Remove the directory and its data
+    #[default]
+    Delete,
+    /// Archive every file to the HSM tier, then release the data from
+    /// Lustre and keep the directory
+    Archive { archive_id: Option<u32> },
+}
+
+impl DeletePolicy {
+    /// Read `hsmOnDelete` (`delete` or `archive`) and `hsmArchiveId`
+    pub fn from_parameters(parameters: &HashMap<String, String>) -> Result<Self> {
+        let archive_id = parameters
+            .get(ARCHIVE_ID_KEY)
+            .map(|v| {
+                v.trim().parse::<u32>().with_context(|| {
+                    format!("{} must be an archive number, got {:?}", ARCHIVE_ID_KEY, v)
+                })
+            })
+            .transpose()?;
+
+        match parameters.get(ON_DELETE_KEY).map(|v| v.trim()) {
+            None | Some("delete") if archive_id.is_some() => {
+                anyhow::bail!("{} needs {}: archive", ARCHIVE_ID_KEY, ON_DELETE_KEY)
+            }
+            None | Some("delete") => Ok(Self::Delete),
+            Some("archive") => Ok(Self::Archive { archive_id }),
+            Some(other) => anyhow::bail!(
+                "{} must be delete or archive, got {:?}",
+                ON_DELETE_KEY,
+                other
+            ),
+        }
+    }
+
+    pub fn to_volume_context(self, volume_context: &mut HashMap<String, String>) {
+        if let Self::Archive { archive_id } = self {
+            volume_context.insert(ON_DELETE_KEY.to_string(), "archive".to_string());
+            if let Some(archive_id) = archive_id {
+                volume_context.insert(ARCHIVE_ID_KEY.to_string(), archive_id.to_string());
+            }
+        }
+    }
+
+    /// Record the policy on a volume directory
+    pub fn store(self, dir: &Path) -> Result<()> {
+        let value = match self {
+            Self::Delete => return Ok(()),
+            Self::Archive { archive_id: None } => "archive".to_string(),
+            Self::Archive {
+                archive_id: Some(id),
+            } => format!("archive:{}", id),
+        };
+        xattr::set(dir, DELETE_POLICY_XATTR, value.as_bytes())
+            .with_context(|| format!("Failed to record delete policy on {}", dir.display()))
+    }
+
+    /// Policy recorded on a volume directory; directories without one are
+    /// deleted
+    pub fn load(dir: &Path) -> Result<Self> {
+        let Some(value) = xattr::get(dir, DELETE_POLICY_XATTR)
+            .with_context(|| format!("Failed to read delete policy of {}", dir.display()))?
+        else {
+            return Ok(Self::Delete);
+        };
+        let value = String::from_utf8_lossy(&value);
+        match value.split_once(':') {
+            None if value == "archive" => Ok(Self::Archive { archive_id: None }),
+            Some(("archive", id)) => Ok(Self::Archive {
+                archive_id: Some(id.parse().with_context(|| {
+                    format!("Invalid archive number in delete policy {:?}", value)
+                })?),
+            }),
+            _ => anyhow::bail!("Unknown delete policy {:?} on {}", value, dir.display()),
+        }
+    }
+}
+
+/// Files to bring back online from the HSM tier before a volume is published
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HsmRestore {
+    /// Paths relative to the volume root; `None` restores the whole volume
+    pub files: Option<Vec<String>>,
+    pub timeout: Duration,
+}
+
+impl HsmRestore {
+    /// Read `hsmRestore: "true"`, or `hsmRestoreFiles` (comma-separated
+    /// paths relative to the volume), and `hsmRestoreTimeout` in seconds
+    pub fn from_volume_context(volume_context: &HashMap<String, String>) -> Result<Option<Self>> {
+        let files = volume_context.get(RESTORE_FILES_KEY).map(|list| {
+            list.split(',')
+                .map(str::trim)
+                .filter(|file| !file.is_empty())
+                .map(String::from)
+                .collect::<Vec<_>>()
+        });
+        if let Some(files) = &files {
+            if files.is_empty() {
+                anyhow::bail!("{} lists no files", RESTORE_FILES_KEY);
+            }
+            if let Some(file) = files
+                .iter()
+                .find(|file| file.starts_with('/') || file.split('/').any(|part| part == ".."))
+            {
+                anyhow::bail!(
+                    "{} must hold paths inside the volume, got {:?}",
+                    RESTORE_FILES_KEY,
+                    file
+                );
+            }
+        }
+
+        let enabled = match volume_context.get(RESTORE_KEY).map(|v| v.trim()) {
+            None => files.is_some(),
+            Some("true") => true,
+            Some("false") if files.is_some() => {
+                anyhow::bail!("{} is set but {} is false", RESTORE_FILES_KEY, RESTORE_KEY)
+            }
+            Some("false") => false,
+            Some(other) => anyhow::bail!("{} must be true or false, got {:?}", RESTORE_KEY, other),
+        };
+
+        let timeout = volume_context
+            .get(RESTORE_TIMEOUT_KEY)
+            .map(|v| {
+                v.trim()
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|secs| *secs > 0)
+                    .map(Duration::from_secs)
+                    .with_context(|| {
+                        format!(
+                            "{} must be a number of seconds, got {:?}",
+                            RESTORE_TIMEOUT_KEY, v
+                        )
+                    })
+            })
+            .transpose()?
+            .unwrap_or(DEFAULT_RESTORE_TIMEOUT);
+
+        Ok(enabled.then_some(Self { files, timeout }))
+    }
+}
+
+/// Parse `lfs hsm_state` output into the state of each file
+///
+/// ```text
+/// /mnt/lustre/a: (0x0000000d) released exists archived, archive_id:1
+/// /mnt/lustre/b: (0x00000000)
+/// ```
+pub fn parse_hsm_state(output: &str) -> Vec<(String, HsmState)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (path, flags) = line.rsplit_once(": (0x")?;
+            let (_, flags) = flags.split_once(')')?;
+            let flags = flags.split(',').next().unwrap_or_default();
+
+            let mut state = HsmState::default();
+            for flag in flags.split_whitespace() {
+                match flag {
+                    "exists" => state.exists = true,
+                    "archived" => state.archived = true,
+                    "released" => state.released = true,
+                    "dirty" => state.dirty = true,
+                    "lost" => state.lost = true,
+                    "noarchive" => state.no_archive = true,
+                    _ => {}
+                }
+            }
+            Some((path.to_string(), state))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hsm_state() {
+        let output = "/mnt/lustre/a: (0x0000000d) released exists archived, archive_id:1\n\
+                      /mnt/lustre/b: (0x00000000)\n\
+                      /mnt/lustre/c: (0x0000000b) exists dirty archived, archive_id:2\n\
+                      /mnt/lustre/d: (0x00000010) noarchive\n\
+                      /mnt/lustre/e: (0x0000000f) released exists archived lost, archive_id:1\n";
+        let states = parse_hsm_state(output);
+        assert_eq!(states.len(), 5);
+        assert_eq!(states[0].0, "/mnt/lustre/a");
+        assert!(states[0].1.released && states[0].1.is_archived());
+        assert_eq!(states[1].1, HsmState::default());
+        assert!(states[2].1.dirty && !states[2].1.is_archived());
+        assert!(states[2].1.can_archive());
+        assert!(!states[3].1.can_archive() && !states[4].1.can_archive());
+
+        let context: HashMap<String, String> = [
+            ("hsmRestoreFiles".to_string(), "data/a, data/b".to_string()),
+            ("hsmRestoreTimeout".to_string(), "60".to_string()),
+        ]
+        .into();
+        let restore = HsmRestore::from_volume_context(&context).unwrap().unwrap();
+        assert_eq!(restore.files.unwrap(), vec!["data/a", "data/b"]);
+        assert_eq!(restore.timeout, Duration::from_secs(60));
+
+        let escape: HashMap<String, String> =
+            [("hsmRestoreFiles".to_string(), "../other".to_string())].into();
+        assert!(HsmRestore::from_volume_context(&escape).is_err());
+        assert_eq!(
+            HsmRestore::from_volume_context(&HashMap::new()).unwrap(),
+            None
+        );
+
+        let archive: HashMap<String, String> = [
+            ("hsmOnDelete".to_string(), "archive".to_string()),
+            ("hsmArchiveId".to_string(), "3".to_string()),
+        ]
+        .into();
+        assert_eq!(
+            DeletePolicy::from_parameters(&archive).unwrap(),
+            DeletePolicy::Archive {
+                archive_id: Some(3)
+            }
+        );
+    }
+}
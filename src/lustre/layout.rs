@@ -0,0 +1,788 @@
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashMap};
+
+/// Stripe sizes must be a multiple of this
+const STRIPE_SIZE_ALIGNMENT: u64 = 64 * 1024;
+
+/// Largest stripe size Lustre accepts
+const MAX_STRIPE_SIZE: u64 = 4 * 1024 * 1024 * 1024 - STRIPE_SIZE_ALIGNMENT;
+
+/// Default file layout of a volume directory, as set by `lfs setstripe`.
+///
+/// Unset fields inherit the filesystem default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StripeLayout {
+    /// Number of OSTs per file; -1 stripes over all available OSTs
+    pub count: Option<i64>,
+    /// Stripe size in bytes
+    pub size: Option<u64>,
+    /// Index of the first OST; -1 lets the MDS choose
+    pub offset: Option<i64>,
+    /// OST pool new files are allocated from
+    pub pool: Option<String>,
+}
+
+impl StripeLayout {
+    /// Read `stripeCount`, `stripeSize`, `stripeOffset` and `ostPool`
+    pub fn from_parameters(parameters: &HashMap<String, String>) -> Result<Self> {
+        let count = parameters
+            .get("stripeCount")
+            .map(|v| parse_index("stripeCount", v))
+            .transpose()?;
+        let size = parameters
+            .get("stripeSize")
+            .map(|v| parse_stripe_size(v))
+            .transpose()?;
+        let offset = parameters
+            .get("stripeOffset")
+            .map(|v| parse_index("stripeOffset", v))
+            .transpose()?;
+        let pool = parameters
+            .get("ostPool")
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty());
+
+        if let Some(pool) = &pool
+            && !is_pool_name(pool)
+        {
+            anyhow::bail!("ostPool {:?} is not a valid pool name", pool);
+        }
+
+        Ok(Self {
+            count,
+            size,
+            offset,
+            pool,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Check the layout against the OSTs and pools of the target filesystem
+    pub fn validate(&self, topology: &FsTopology) -> Result<()> {
+        check_osts(topology, self.count, self.offset, self.pool.as_deref())
+    }
+
+    /// Arguments for `lfs setstripe` on the volume directory
+    pub fn setstripe_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(count) = self.count {
+            args.extend(["-c".to_string(), count.to_string()]);
+        }
+        if let Some(size) = self.size {
+            args.extend(["-S".to_string(), size.to_string()]);
+        }
+        if let Some(offset) = self.offset {
+            args.extend(["-i".to_string(), offset.to_string()]);
+        }
+        if let Some(pool) = &self.pool {
+            args.extend(["-p".to_string(), pool.clone()]);
+        }
+        args
+    }
+
+    /// Record the layout in a volume context, with sizes in bytes
+    pub fn to_volume_context(&self, volume_context: &mut HashMap<String, String>) {
+        if let Some(count) = self.count {
+            volume_context.insert("stripeCount".to_string(), count.to_string());
+        }
+        if let Some(size) = self.size {
+            volume_context.insert("stripeSize".to_string(), size.to_string());
+        }
+        if let Some(offset) = self.offset {
+            volume_context.insert("stripeOffset".to_string(), offset.to_string());
+        }
+        if let Some(pool) = &self.pool {
+            volume_context.insert("ostPool".to_string(), pool.clone());
+        }
+    }
+}
+
+/// Layout keys of a volume context or StorageClass
+const LAYOUT_KEY: &str = "layout";
+const LAYOUT_TEMPLATE_KEY: &str = "layoutTemplate";
+const STRIPE_KEYS: &[&str] = &["stripeCount", "stripeSize", "stripeOffset", "ostPool"];
+const MIRROR_COUNT_KEY: &str = "mirrorCount";
+const MIRROR_POOLS_KEY: &str = "mirrorPools";
+
+/// Most mirrors a Lustre file can have
+const MAX_MIRRORS: usize = 16;
+
+/// Client version needed for composite (PFL) layouts
+const PFL_MIN_VERSION: (u32, u32) = (2, 10);
+/// Client version needed for Data-on-MDT components
+const DOM_MIN_VERSION: (u32, u32) = (2, 11);
+/// Client version needed for File Level Redundancy
+const FLR_MIN_VERSION: (u32, u32) = (2, 11);
+
+/// Largest Data-on-MDT component Lustre accepts
+const MAX_DOM_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Default layout of a volume directory, plain or composite
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VolumeLayout {
+    Striped(StripeLayout),
+    Composite {
+        /// Driver config template the layout came from, if any
+        template: Option<String>,
+        layout: CompositeLayout,
+    },
+    /// File Level Redundancy: files get one replica per mirror
+    Mirrored(MirrorLayout),
+}
+
+impl VolumeLayout {
+    /// Read either the stripe parameters, mirror parameters, a compact
+    /// `layout` string or a `layoutTemplate` name resolved against `templates`
+    pub fn from_parameters(
+        parameters: &HashMap<String, String>,
+        templates: &BTreeMap<String, String>,
+    ) -> Result<Self> {
+        if let Some(mirrors) = MirrorLayout::from_parameters(parameters)? {
+            if parameters.contains_key(LAYOUT_KEY) || parameters.contains_key(LAYOUT_TEMPLATE_KEY) {
+                anyhow::bail!("Mirrors cannot be combined with a composite layout");
+            }
+            return Ok(Self::Mirrored(mirrors));
+        }
+
+        let (template, spec) = match (
+            parameters.get(LAYOUT_KEY),
+            parameters.get(LAYOUT_TEMPLATE_KEY),
+        ) {
+            (None, None) => return StripeLayout::from_parameters(parameters).map(Self::Striped),
+            (Some(_), Some(_)) => {
+                anyhow::bail!(
+                    "{} and {} are mutually exclusive",
+                    LAYOUT_KEY,
+                    LAYOUT_TEMPLATE_KEY
+                )
+            }
+            (Some(spec), None) => (None, spec),
+            (None, Some(name)) => {
+                let spec = templates
+                    .get(name)
+                    .with_context(|| format!("Unknown layout template {:?}", name))?;
+                (Some(name.clone()), spec)
+            }
+        };
+
+        if let Some(key) = STRIPE_KEYS.iter().find(|k| parameters.contains_key(**k)) {
+            anyhow::bail!("{} cannot be combined with a composite layout", key);
+        }
+
+        let layout = CompositeLayout::parse(spec)
+            .with_context(|| format!("Invalid composite layout {:?}", spec))?;
+        Ok(Self::Composite { template, layout })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Self::Striped(layout) if layout.is_empty())
+    }
+
+    /// Whether `key` is one of the parameters a layout is read from
+    pub fn is_layout_key(key: &str) -> bool {
+        [
+            LAYOUT_KEY,
+            LAYOUT_TEMPLATE_KEY,
+            MIRROR_COUNT_KEY,
+            MIRROR_POOLS_KEY,
+        ]
+        .iter()
+        .chain(STRIPE_KEYS)
+        .any(|k| *k == key)
+    }
+
+    pub fn validate(&self, topology: &FsTopology) -> Result<()> {
+        match self {
+            Self::Striped(layout) => layout.validate(topology),
+            Self::Composite { layout, .. } => layout.validate(topology),
+            Self::Mirrored(layout) => layout.validate(topology),
+        }
+    }
+
+    /// Oldest Lustre client that can set this layout, as (major, minor)
+    pub fn min_client_version(&self) -> Option<(u32, u32)> {
+        match self {
+            Self::Striped(_) => None,
+            Self::Composite { layout, .. } if layout.has_dom() => Some(DOM_MIN_VERSION),
+            Self::Composite { .. } => Some(PFL_MIN_VERSION),
+            Self::Mirrored(_) => Some(FLR_MIN_VERSION),
+        }
+    }
+
+    pub fn setstripe_args(&self) -> Vec<String> {
+        match self {
+            Self::Striped(layout) => layout.setstripe_args(),
+            Self::Composite { layout, .. } => layout.setstripe_args(),
+            Self::Mirrored(layout) => layout.setstripe_args(),
+        }
+    }
+
+    pub fn to_volume_context(&self, volume_context: &mut HashMap<String, String>) {
+        match self {
+            Self::Striped(layout) => layout.to_volume_context(volume_context),
+            Self::Composite { template, layout } => {
+                volume_context.insert(LAYOUT_KEY.to_string(), layout.to_string());
+                if let Some(template) = template {
+                    volume_context.insert(LAYOUT_TEMPLATE_KEY.to_string(), template.clone());
+                }
+            }
+            Self::Mirrored(layout) => layout.to_volume_context(volume_context),
+        }
+    }
+}
+
+/// Mirrored default layout, as set by `lfs setstripe -N ... -N ...`.
+///
+/// Every mirror shares the stripe count and size; pools can differ so
+/// replicas land on separate failure domains.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirrorLayout {
+    pub mirrors: Vec<StripeLayout>,
+}
+
+impl MirrorLayout {
+    /// Read `mirrorCount` and `mirrorPools`; `None` if neither is set
+    pub fn from_parameters(parameters: &HashMap<String, String>) -> Result<Option<Self>> {
+        let count = parameters
+            .get(MIRROR_COUNT_KEY)
+            .map(|v| {
+                v.trim()
+                    .parse::<usize>()
+                    .with_context(|| format!("{} must be a number, got {:?}", MIRROR_COUNT_KEY, v))
+            })
+            .transpose()?;
+        let pools: Option<Vec<String>> = parameters
+            .get(MIRROR_POOLS_KEY)
+            .map(|v| v.split(',').map(|p| p.trim().to_string()).collect());
+
+        let count = match (count, &pools) {
+            (None, None) => return Ok(None),
+            (Some(count), Some(pools)) if count != pools.len() => anyhow::bail!(
+                "{} is {} but {} names {} pool(s)",
+                MIRROR_COUNT_KEY,
+                count,
+                MIRROR_POOLS_KEY,
+                pools.len()
+            ),
+            (Some(count), _) => count,
+            (None, Some(pools)) => pools.len(),
+        };
+        if !(2..=MAX_MIRRORS).contains(&count) {
+            anyhow::bail!(
+                "Mirrored layouts need 2 to {} mirrors, got {}",
+                MAX_MIRRORS,
+                count
+            );
+        }
+        if let Some(pool) = pools.iter().flatten().find(|p| !is_pool_name(p)) {
+            anyhow::bail!("{} has an invalid pool name {:?}", MIRROR_POOLS_KEY, pool);
+        }
+        if parameters.contains_key("stripeOffset") {
+            anyhow::bail!("stripeOffset would put every mirror on the same OST");
+        }
+        if pools.is_some() && parameters.contains_key("ostPool") {
+            anyhow::bail!("ostPool and {} are mutually exclusive", MIRROR_POOLS_KEY);
+        }
+
+        let base = StripeLayout::from_parameters(parameters)?;
+        let mirrors = (0..count)
+            .map(|i| StripeLayout {
+                pool: pools
+                    .as_ref()
+                    .map(|pools| pools[i].clone())
+                    .or_else(|| base.pool.clone()),
+                ..base.clone()
+            })
+            .collect();
+
+        Ok(Some(Self { mirrors }))
+    }
+
+    pub fn validate(&self, topology: &FsTopology) -> Result<()> {
+        for mirror in &self.mirrors {
+            check_osts(topology, mirror.count, None, mirror.pool.as_deref())?;
+        }
+        Ok(())
+    }
+
+    pub fn setstripe_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        for mirror in &self.mirrors {
+            args.push("-N".to_string());
+            args.extend(mirror.setstripe_args());
+        }
+        args
+    }
+
+    pub fn to_volume_context(&self, volume_context: &mut HashMap<String, String>) {
+        volume_context.insert(MIRROR_COUNT_KEY.to_string(), self.mirrors.len().to_string());
+        let pools: Vec<&str> = self
+            .mirrors
+            .iter()
+            .filter_map(|m| m.pool.as_deref())
+            .collect();
+        if pools.len() == self.mirrors.len() {
+            volume_context.insert(MIRROR_POOLS_KEY.to_string(), pools.join(","));
+        }
+        if let Some(first) = self.mirrors.first() {
+            StripeLayout {
+                pool: None,
+                ..first.clone()
+            }
+            .to_volume_context(volume_context);
+        }
+    }
+}
+
+/// Progressive File Layout written as comma-separated `<end>=<spec>`
+/// components, e.g. `1M=mdt,256M=c1,eof=c-1 S4M`.
+///
+/// `<end>` is where the component's extent ends, a size or `eof`. `<spec>`
+/// is `mdt` for a Data-on-MDT component, or space-separated `c<count>`,
+/// `S<size>` and `p<pool>` options for an OST component.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompositeLayout {
+    pub components: Vec<LayoutComponent>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayoutComponent {
+    /// Extent end in bytes; `None` extends to the end of the file
+    pub end: Option<u64>,
+    pub kind: ComponentKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ComponentKind {
+    /// Data stored on the MDT together with the inode
+    Mdt,
+    Ost {
+        count: Option<i64>,
+        size: Option<u64>,
+        pool: Option<String>,
+    },
+}
+
+impl CompositeLayout {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut components = Vec::new();
+
+        for component in spec.split(',').map(str::trim) {
+            let (end, kind) = component
+                .split_once('=')
+                .with_context(|| format!("Component {:?} is not <end>=<spec>", component))?;
+            let end = match end.trim() {
+                "eof" | "-1" => None,
+                end => Some(parse_size("component end", end)?),
+            };
+            let kind = match kind.trim() {
+                "mdt" => ComponentKind::Mdt,
+                options => parse_ost_component(options)?,
+            };
+            components.push(LayoutComponent { end, kind });
+        }
+
+        let layout = Self { components };
+        layout.check()?;
+        Ok(layout)
+    }
+
+    /// Structural rules `lfs setstripe` would otherwise reject late
+    fn check(&self) -> Result<()> {
+        let mut start = 0;
+        for (i, component) in self.components.iter().enumerate() {
+            let last = i + 1 == self.components.len();
+            match component.end {
+                None if !last => anyhow::bail!("Only the last component can extend to eof"),
+                Some(_) if last => anyhow::bail!("The last component must extend to eof"),
+                Some(end) if end <= start => {
+                    anyhow::bail!("Component ends must be increasing, got {}", end)
+                }
+                Some(end) if !end.is_multiple_of(STRIPE_SIZE_ALIGNMENT) => {
+                    anyhow::bail!("Component end {} is not a multiple of 64KiB", end)
+                }
+                Some(end) => start = end,
+                None => {}
+            }
+
+            if component.kind == ComponentKind::Mdt {
+                if i != 0 {
+                    anyhow::bail!("Only the first component can be on the MDT");
+                }
+                if component.end.is_none_or(|end| end > MAX_DOM_SIZE) {
+                    anyhow::bail!("A Data-on-MDT component must end at 1GiB or earlier");
+                }
+            }
+        }
+
+        if self.components.len() < 2 {
+            anyhow::bail!("A composite layout needs at least two components");
+        }
+        Ok(())
+    }
+
+    pub fn has_dom(&self) -> bool {
+        self.components.iter().any(|c| c.kind == ComponentKind::Mdt)
+    }
+
+    pub fn validate(&self, topology: &FsTopology) -> Result<()> {
+        for component in &self.components {
+            if let ComponentKind::Ost { count, pool, .. } = &component.kind {
+                check_osts(topology, *count, None, pool.as_deref())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn setstripe_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        for component in &self.components {
+            let end = component
+                .end
+                .map_or("-1".to_string(), |end| end.to_string());
+            args.extend(["-E".to_string(), end]);
+            match &component.kind {
+                ComponentKind::Mdt => args.extend(["-L".to_string(), "mdt".to_string()]),
+                ComponentKind::Ost { count, size, pool } => {
+                    args.extend(
+                        StripeLayout {
+                            count: *count,
+                            size: *size,
+                            offset: None,
+                            pool: pool.clone(),
+                        }
+                        .setstripe_args(),
+                    );
+                }
+            }
+        }
+        args
+    }
+}
+
+impl std::fmt::Display for CompositeLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let components: Vec<String> = self
+            .components
+            .iter()
+            .map(|component| {
+                let end = component.end.map_or("eof".to_string(), format_size);
+                let spec = match &component.kind {
+                    ComponentKind::Mdt => "mdt".to_string(),
+                    ComponentKind::Ost { count, size, pool } => {
+                        let mut options = Vec::new();
+                        if let Some(count) = count {
+                            options.push(format!("c{}", count));
+                        }
+                        if let Some(size) = size {
+                            options.push(format!("S{}", format_size(*size)));
+                        }
+                        if let Some(pool) = pool {
+                            options.push(format!("p{}", pool));
+                        }
+                        options.join(" ")
+                    }
+                };
+                format!("{}={}", end, spec)
+            })
+            .collect();
+        write!(f, "{}", components.join(","))
+    }
+}
+
+fn parse_ost_component(options: &str) -> Result<ComponentKind> {
+    let mut count = None;
+    let mut size = None;
+    let mut pool = None;
+
+    for option in options.split_whitespace() {
+        let mut chars = option.chars();
+        let kind = chars.next();
+        match (kind, chars.as_str()) {
+            (Some('c'), value) => count = Some(parse_index("stripe count", value)?),
+            (Some('S'), value) => size = Some(parse_stripe_size(value)?),
+            (Some('p'), value) if is_pool_name(value) => pool = Some(value.to_string()),
+            _ => anyhow::bail!("Unknown component option {:?}", option),
+        }
+    }
+
+    Ok(ComponentKind::Ost { count, size, pool })
+}
+
+/// Check stripe count, offset and pool against the OSTs they select from
+fn check_osts(
+    topology: &FsTopology,
+    count: Option<i64>,
+    offset: Option<i64>,
+    pool: Option<&str>,
+) -> Result<()> {
+    let osts = match pool {
+        Some(pool) => topology
+            .pools
+            .get(pool)
+            .with_context(|| format!("OST pool {:?} does not exist", pool))?,
+        None => &topology.osts,
+    };
+    let scope = match pool {
+        Some(pool) => format!("pool {}", pool),
+        None => "the filesystem".to_string(),
+    };
+
+    if let Some(count) = count
+        && count > osts.len() as i64
+    {
+        anyhow::bail!(
+            "stripe count {} exceeds the {} OST(s) of {}",
+            count,
+            osts.len(),
+            scope
+        );
+    }
+
+    if let Some(offset) = offset
+        && offset >= 0
+        && !osts.contains(&(offset as u32))
+    {
+        anyhow::bail!("stripe offset {} is not an OST of {}", offset, scope);
+    }
+
+    Ok(())
+}
+
+/// Active targets and OST pools of a filesystem, by target index
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FsTopology {
+    pub osts: Vec<u32>,
+    pub mdts: Vec<u32>,
+    pub pools: BTreeMap<String, Vec<u32>>,
+}
+
+fn parse_index(key: &str, value: &str) -> Result<i64> {
+    let index: i64 = value
+        .trim()
+        .parse()
+        .with_context(|| format!("{} must be an integer, got {:?}", key, value))?;
+    if index < -1 {
+        anyhow::bail!("{} must be -1 or greater, got {}", key, index);
+    }
+    Ok(index)
+}
+
+fn is_pool_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Parse a size such as `1048576`, `4M` or `64k`
+pub(crate) fn parse_size(key: &str, value: &str) -> Result<u64> {
+    let value = value.trim();
+    let (number, multiplier) = match value.char_indices().last() {
+        Some((idx, unit)) if unit.is_ascii_alphabetic() => {
+            let multiplier = match unit.to_ascii_uppercase() {
+                'K' => 1024,
+                'M' => 1024 * 1024,
+                'G' => 1024 * 1024 * 1024,
+                _ => anyhow::bail!("{} {:?} has an unknown unit", key, value),
+            };
+            (&value[..idx], multiplier)
+        }
+        _ => (value, 1),
+    };
+
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .with_context(|| format!("{} {:?} is not a valid size", key, value))
+}
+
+/// Format a size with the largest unit that divides it
+fn format_size(size: u64) -> String {
+    [('G', 1 << 30), ('M', 1 << 20), ('K', 1 << 10)]
+        .into_iter()
+        .find(|(_, unit)| size.is_multiple_of(*unit))
+        .map_or(size.to_string(), |(suffix, unit)| {
+            format!("{}{}", size / unit, suffix)
+        })
+}
+
+fn parse_stripe_size(value: &str) -> Result<u64> {
+    let size = parse_size("stripeSize", value)?;
+    if size == 0 || !size.is_multiple_of(STRIPE_SIZE_ALIGNMENT) || size > MAX_STRIPE_SIZE {
+        anyhow::bail!(
+            "stripeSize {:?} must be a non-zero multiple of 64KiB below 4GiB",
+            value
+        );
+    }
+    Ok(size)
+}
+
+/// Indices of the active targets listed by `lfs osts` or `lfs mdts`
+///
+/// ```text
+/// OBDS:
+/// 0: lustre-OST0000_UUID ACTIVE
+/// 1: lustre-OST0001_UUID INACTIVE
+/// ```
+pub fn parse_targets(output: &str) -> Vec<u32> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (index, rest) = line.trim().split_once(':')?;
+            let active = rest.split_whitespace().nth(1) == Some("ACTIVE");
+            active.then(|| index.trim().parse().ok()).flatten()
+        })
+        .collect()
+}
+
+/// Pool names listed by `lfs pool_list <fsname>`
+///
+/// ```text
+/// Pools from lustre:
+/// lustre.flash
+/// ```
+pub fn parse_pool_names(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter(|line| !line.starts_with("Pools from"))
+        .filter_map(|line| line.trim().split_once('.'))
+        .map(|(_, pool)| pool.to_string())
+        .collect()
+}
+
+/// OST indices listed by `lfs pool_list <fsname>.<pool>`
+///
+/// ```text
+/// Pool: lustre.flash
+/// lustre-OST0000_UUID
+/// ```
+pub fn parse_pool_osts(output: &str) -> Vec<u32> {
+    output.lines().filter_map(ost_index).collect()
+}
+
+/// Index of an OST UUID such as `lustre-OST000a_UUID`
+fn ost_index(uuid: &str) -> Option<u32> {
+    let (_, rest) = uuid.trim().rsplit_once("-OST")?;
+    let hex = rest.split('_').next()?;
+    u32::from_str_radix(hex, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::parameters;
+
+    #[test]
+    fn test_stripe_layout() {
+        let parameters = parameters(&[
+            ("stripeCount", "2"),
+            ("stripeSize", "4M"),
+            ("ostPool", "flash"),
+        ]);
+
+        let layout = StripeLayout::from_parameters(&parameters).unwrap();
+        assert_eq!(layout.size, Some(4 * 1024 * 1024));
+        assert_eq!(
+            layout.setstripe_args(),
+            vec!["-c", "2", "-S", "4194304", "-p", "flash"]
+        );
+
+        let osts = parse_targets(
+            "OBDS:\n0: lustre-OST0000_UUID ACTIVE\n1: lustre-OST0001_UUID ACTIVE\n10: lustre-OST000a_UUID INACTIVE\n",
+        );
+        assert_eq!(osts, vec![0, 1]);
+        assert_eq!(
+            parse_pool_names("Pools from lustre:\nlustre.flash\nlustre.archive\n"),
+            vec!["flash", "archive"]
+        );
+        assert_eq!(
+            parse_pool_osts("Pool: lustre.flash\nlustre-OST000a_UUID\n"),
+            vec![10]
+        );
+
+        let mut topology = FsTopology {
+            osts,
+            ..Default::default()
+        };
+        assert!(layout.validate(&topology).is_err());
+        topology.pools.insert("flash".to_string(), vec![0]);
+        assert!(layout.validate(&topology).is_err());
+        topology.pools.insert("flash".to_string(), vec![0, 1]);
+        assert!(layout.validate(&topology).is_ok());
+
+        let bad: HashMap<String, String> = [("stripeSize".to_string(), "100k".to_string())].into();
+        assert!(StripeLayout::from_parameters(&bad).is_err());
+    }
+
+    #[test]
+    fn test_composite_layout() {
+        let templates: BTreeMap<String, String> = [(
+            "small-files".to_string(),
+            "1M=mdt, 256M=c1, eof=c-1 S4M".to_string(),
+        )]
+        .into();
+        let parameters: HashMap<String, String> =
+            [("layoutTemplate".to_string(), "small-files".to_string())].into();
+
+        let layout = VolumeLayout::from_parameters(&parameters, &templates).unwrap();
+        assert_eq!(layout.min_client_version(), Some((2, 11)));
+        assert_eq!(
+            layout.setstripe_args().join(" "),
+            "-E 1048576 -L mdt -E 268435456 -c 1 -E -1 -c -1 -S 4194304"
+        );
+
+        let mut context = HashMap::new();
+        layout.to_volume_context(&mut context);
+        assert_eq!(context["layout"], "1M=mdt,256M=c1,eof=c-1 S4M");
+        assert_eq!(context["layoutTemplate"], "small-files");
+
+        for spec in [
+            "eof=c1",
+            "256M=c1,1M=c2,eof=c4",
+            "1M=c1,2M=mdt,eof=c4",
+            "1M=mdt,eof=x4",
+            "1M=c1,4M=c2",
+            "eof=é4",
+            "1M=c1,eof=€",
+        ] {
+            assert!(CompositeLayout::parse(spec).is_err(), "{}", spec);
+        }
+
+        let mixed: HashMap<String, String> = [
+            ("layout".to_string(), "1M=mdt,eof=c4".to_string()),
+            ("stripeCount".to_string(), "2".to_string()),
+        ]
+        .into();
+        assert!(VolumeLayout::from_parameters(&mixed, &templates).is_err());
+
+        let mirrored: HashMap<String, String> = [
+            ("mirrorPools".to_string(), "flash, archive".to_string()),
+            ("stripeCount".to_string(), "2".to_string()),
+        ]
+        .into();
+        let layout = VolumeLayout::from_parameters(&mirrored, &templates).unwrap();
+        assert_eq!(layout.min_client_version(), Some((2, 11)));
+        assert_eq!(
+            layout.setstripe_args().join(" "),
+            "-N -c 2 -p flash -N -c 2 -p archive"
+        );
+        let topology = FsTopology {
+            osts: vec![0, 1, 2, 3],
+            pools: [
+                ("flash".to_string(), vec![0, 1]),
+                ("archive".to_string(), vec![2]),
+            ]
+            .into(),
+            ..Default::default()
+        };
+        assert!(layout.validate(&topology).is_err());
+
+        let single: HashMap<String, String> = [("mirrorCount".to_string(), "1".to_string())].into();
+        assert!(VolumeLayout::from_parameters(&single, &templates).is_err());
+    }
+}
@@ -1,6 +1,17 @@
 pub mod client;
+pub mod dirstripe;
+pub mod health;
+pub mod hostns;
+pub mod hsm;
+pub mod layout;
 pub mod mount;
+pub mod mountinfo;
+pub mod params;
+pub mod prefetch;
+pub mod quota;
+pub mod tuning;
 
 // Re-export
 pub use client::LustreClient;
-pub use mount::MountManager;
+pub use mount::{MountConflict, MountManager};
+pub use tuning::ClientTuning;
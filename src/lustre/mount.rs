@@ -1,17 +1,118 @@
 use anyhow::{Context, Result};
+use std::path::PathBuf;
 use std::process::Command;
+use std::time::Duration;
 use tracing::{debug, info, warn};
 
+use super::health::host_path;
+use super::hostns::{HOST_MOUNT_NAMESPACE, HostNamespace};
+use super::mountinfo::{HOST_MOUNTINFO, MountEntry, MountTableCache};
+use crate::config::{LustreConfig, MountMode};
+
+/// Plain unmount attempts before escalating to a forced unmount
+const UNMOUNT_ATTEMPTS: u32 = 3;
+
+/// Delay between plain unmount attempts, doubled after each one
+const UNMOUNT_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Escalation levels tried by [`MountManager::unmount`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UnmountStep {
+    Normal,
+    /// `umount -f`; aborts in-flight RPCs of an evicted Lustre client
+    Force,
+    /// `umount -l`; detaches now, cleans up once the mount is no longer busy
+    Lazy,
+}
+
+/// A target is already mounted, but not with what the caller asked for
+#[derive(Debug)]
+pub struct MountConflict {
+    pub target: String,
+    pub expected: String,
+    pub found: String,
+}
+
+impl std::fmt::Display for MountConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} is already mounted with {}, expected {}",
+            self.target, self.found, self.expected
+        )
+    }
+}
+
+impl std::error::Error for MountConflict {}
+
 /// Manages Lustre filesystem mount operations
 #[derive(Debug, Clone)]
-pub struct MountManager;
+pub struct MountManager {
+    /// Worker inside the host mount namespace; `None` shells out to nsenter
+    host_ns: Option<HostNamespace>,
+    mount_table: MountTableCache,
+    /// Fall back to a lazy unmount when a forced unmount did not help
+    lazy_unmount: bool,
+    /// Directory standing in for the host, see [`MountManager::fake`]
+    #[cfg(test)]
+    fake: Option<fake::FakeHost>,
+}
 
 impl MountManager {
-    pub fn new() -> Self {
-        Self
+    pub fn new(config: &LustreConfig) -> Self {
+        let host_ns = match config.mount_mode {
+            MountMode::Nsenter => None,
+            MountMode::Native => match HostNamespace::enter(HOST_MOUNT_NAMESPACE) {
+                Ok(ns) => Some(ns),
+                Err(e) => {
+                    warn!(
+                        "Native host mounts unavailable, falling back to nsenter: {:#}",
+                        e
+                    );
+                    None
+                }
+            },
+        };
+
+        Self {
+            host_ns,
+            mount_table: MountTableCache::new(HOST_MOUNTINFO),
+            lazy_unmount: config.lazy_unmount,
+            #[cfg(test)]
+            fake: None,
+        }
+    }
+
+    /// Mounts recorded in a mount table below `root` instead of made on the
+    /// host, with `root` standing in for the host's `/`
+    #[cfg(test)]
+    pub fn fake(root: &std::path::Path) -> Self {
+        let fake = fake::FakeHost::new(root);
+        Self {
+            host_ns: None,
+            mount_table: MountTableCache::new(fake.mountinfo()),
+            lazy_unmount: false,
+            fake: Some(fake),
+        }
     }
 
-    /// Mount a Lustre filesystem using nsenter to execute on host
+    /// Where the plugin finds `path` of the host
+    pub fn host_path(&self, path: &str) -> PathBuf {
+        #[cfg(test)]
+        if let Some(fake) = &self.fake {
+            return fake.path(path);
+        }
+        host_path(path)
+    }
+
+    /// Cached view of the host mount table
+    pub fn mount_table(&self) -> &MountTableCache {
+        &self.mount_table
+    }
+
+    /// Mount a Lustre filesystem on the host. The mount point is created
+    /// through the host namespace worker, or nsenter without one;
+    /// `mount.lustre` itself always runs through nsenter.
     pub async fn mount(
         &self,
         source: &str,       // e.g., "192.168.1.10@tcp0:/lustre"
@@ -20,12 +121,29 @@ impl MountManager {
     ) -> Result<()> {
         info!("Mounting Lustre: {} -> {}", source, target);
 
-        // Create target directory on host using nsenter
+        // Create target directory on host
         self.ensure_mount_point(target).await?;
 
-        // Check if already mounted
-        if self.is_mounted(target).await? {
-            info!("Target {} is already mounted", target);
+        // Check if already mounted, and with what
+        if let Some(entry) = self.mount_table.lookup(target).await? {
+            if !entry.matches_source(source) {
+                return Err(MountConflict {
+                    target: target.to_string(),
+                    expected: source.to_string(),
+                    found: describe(&entry),
+                }
+                .into());
+            }
+
+            let missing: Vec<&String> = options.iter().filter(|o| !entry.has_option(o)).collect();
+            if !missing.is_empty() {
+                warn!(
+                    "Target {} is mounted from {} without requested options {:?}",
+                    target, source, missing
+                );
+            }
+
+            info!("Target {} is already mounted from {}", target, source);
             return Ok(());
         }
 
@@ -33,14 +151,16 @@ impl MountManager {
         let mount_opts = options.to_vec();
         let opts_str = mount_opts.join(",");
 
-        // Use nsenter to execute mount.lustre on the host
-        // -t 1: target PID 1 (init/systemd on host)
-        // -m: enter mount namespace
-        let mut cmd = Command::new("nsenter");
-        cmd.arg("-t")
-            .arg("1")
-            .arg("-m")
-            .arg("/usr/sbin/mount.lustre");
+        #[cfg(test)]
+        if let Some(fake) = &self.fake {
+            fake.mount(source, "/", None, target, &opts_str)?;
+            self.mount_table.invalidate();
+            return Ok(());
+        }
+
+        // mount.lustre always runs through nsenter, even in native mode, as
+        // the Lustre client mount helper does more than a plain mount(2).
+        let mut cmd = nsenter("/usr/sbin/mount.lustre");
 
         if !opts_str.is_empty() {
             cmd.arg("-o").arg(&opts_str);
@@ -52,6 +172,7 @@ impl MountManager {
 
         // Execute mount
         let output = cmd.output().context("Failed to execute mount command")?;
+        self.mount_table.invalidate();
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
@@ -68,7 +189,106 @@ impl MountManager {
         Ok(())
     }
 
-    /// Unmount a Lustre filesystem using nsenter
+    /// Bind mount an already mounted path (e.g. a staging mount), or a
+    /// directory below one, onto target
+    pub async fn bind_mount(&self, source: &str, target: &str, read_only: bool) -> Result<()> {
+        info!("Bind mounting: {} -> {}", source, target);
+
+        self.ensure_mount_point(target).await?;
+
+        if let Some(entry) = self.mount_table.lookup(target).await? {
+            let expected = self.mount_of(source).await?;
+
+            if entry.device != expected.device
+                || entry.root != expected.root
+                || entry.is_read_only() != read_only
+            {
+                return Err(MountConflict {
+                    target: target.to_string(),
+                    expected: format!(
+                        "{} ({})",
+                        describe(&expected),
+                        if read_only { "ro" } else { "rw" }
+                    ),
+                    found: describe(&entry),
+                }
+                .into());
+            }
+
+            info!("Target {} is already bind mounted from {}", target, source);
+            return Ok(());
+        }
+
+        let result = self.bind_mount_inner(source, target, read_only).await;
+        self.mount_table.invalidate();
+        result?;
+
+        info!("Successfully bind mounted {} at {}", source, target);
+        Ok(())
+    }
+
+    /// The mount `path` is on, with the root a bind mount of `path` gets
+    async fn mount_of(&self, path: &str) -> Result<MountEntry> {
+        self.mount_table
+            .get()
+            .await?
+            .containing(path)
+            .with_context(|| format!("Bind mount source {} is not mounted", path))
+    }
+
+    async fn bind_mount_inner(&self, source: &str, target: &str, read_only: bool) -> Result<()> {
+        #[cfg(test)]
+        if let Some(fake) = &self.fake {
+            let entry = self.mount_of(source).await?;
+            let options = if read_only { "ro" } else { "rw" };
+            return fake.mount(
+                &entry.source,
+                &entry.root,
+                Some(&entry.device),
+                target,
+                options,
+            );
+        }
+
+        if let Some(ns) = &self.host_ns {
+            ns.bind_mount(source, target, read_only).await?;
+        } else {
+            let output = nsenter("mount")
+                .arg("--bind")
+                .arg(source)
+                .arg(target)
+                .output()
+                .context("Failed to execute bind mount command")?;
+
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                anyhow::bail!("Bind mount failed: {}", stderr);
+            }
+
+            if read_only {
+                let output = nsenter("mount")
+                    .arg("-o")
+                    .arg("remount,bind,ro")
+                    .arg(target)
+                    .output()
+                    .context("Failed to execute read-only remount command")?;
+
+                if !output.status.success() {
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    let _ = nsenter("umount").arg(target).output();
+                    anyhow::bail!("Read-only remount failed: {}", stderr);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Unmount a Lustre filesystem or bind mount on the host.
+    ///
+    /// Retries a plain unmount, then escalates to a forced and (if enabled)
+    /// a lazy unmount. Success is judged by the mount table, not the exit
+    /// code, so stacked mounts and racing unmounts are handled too.
     pub async fn unmount(&self, target: &str) -> Result<()> {
         info!("Unmounting: {}", target);
 
@@ -78,12 +298,75 @@ impl MountManager {
             return Ok(());
         }
 
-        // Execute unmount using nsenter
-        let output = Command::new("nsenter")
-            .arg("-t")
-            .arg("1")
-            .arg("-m")
-            .arg("umount")
+        let mut steps = vec![UnmountStep::Normal; UNMOUNT_ATTEMPTS as usize];
+        steps.push(UnmountStep::Force);
+        if self.lazy_unmount {
+            steps.push(UnmountStep::Lazy);
+        }
+
+        let mut backoff = UNMOUNT_BACKOFF;
+        let mut last_error = None;
+        for step in steps {
+            debug!("Unmounting {} ({:?})", target, step);
+
+            let result = self.unmount_once(target, step).await;
+            self.mount_table.invalidate();
+            if let Err(e) = result {
+                warn!("Unmount of {} ({:?}) failed: {:#}", target, step, e);
+                last_error = Some(e);
+            }
+
+            if !self.is_mounted(target).await? {
+                info!("Successfully unmounted {} ({:?})", target, step);
+                return Ok(());
+            }
+
+            if step == UnmountStep::Normal {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+        }
+
+        match last_error {
+            Some(e) => Err(e.context(format!("{} is still mounted", target))),
+            None => anyhow::bail!("{} is still mounted after unmounting", target),
+        }
+    }
+
+    /// Lazily detach `target` in one step, without escalation or retries
+    pub async fn detach(&self, target: &str) -> Result<()> {
+        let result = self.unmount_once(target, UnmountStep::Lazy).await;
+        self.mount_table.invalidate();
+        result
+    }
+
+    async fn unmount_once(&self, target: &str, step: UnmountStep) -> Result<()> {
+        #[cfg(test)]
+        if let Some(fake) = &self.fake {
+            return fake.unmount(target);
+        }
+
+        if let Some(ns) = &self.host_ns {
+            let flags = match step {
+                UnmountStep::Normal => 0,
+                UnmountStep::Force => libc::MNT_FORCE,
+                UnmountStep::Lazy => libc::MNT_DETACH,
+            };
+            return ns.unmount(target, flags).await;
+        }
+
+        let mut cmd = nsenter("umount");
+        match step {
+            UnmountStep::Normal => {}
+            UnmountStep::Force => {
+                cmd.arg("-f");
+            }
+            UnmountStep::Lazy => {
+                cmd.arg("-l");
+            }
+        }
+
+        let output = cmd
             .arg(target)
             .output()
             .context("Failed to execute umount command")?;
@@ -93,35 +376,70 @@ impl MountManager {
             anyhow::bail!("Unmount failed: {}", stderr);
         }
 
-        info!("Successfully unmounted {}", target);
         Ok(())
     }
 
-    /// Check if a path is already mounted on the host
-    async fn is_mounted(&self, target: &str) -> Result<bool> {
-        let output = Command::new("nsenter")
-            .arg("-t")
-            .arg("1")
-            .arg("-m")
-            .arg("findmnt")
-            .arg("-o")
-            .arg("TARGET")
-            .arg("-n")
+    /// Remove an unmounted mount point directory on the host.
+    ///
+    /// Only an empty directory is removed, so data is never deleted if the
+    /// path turns out not to be a stale mount point.
+    pub async fn remove_mount_point(&self, target: &str) -> Result<()> {
+        if self.is_mounted(target).await? {
+            anyhow::bail!("Refusing to remove {}: still mounted", target);
+        }
+
+        #[cfg(test)]
+        if let Some(fake) = &self.fake {
+            return fake.remove_dir(target);
+        }
+
+        if let Some(ns) = &self.host_ns {
+            return ns.remove_dir(target).await;
+        }
+
+        let output = nsenter("rmdir")
             .arg(target)
             .output()
-            .context("Failed to check mount status")?;
+            .context("Failed to execute rmdir command")?;
 
-        Ok(output.status.success() && !output.stdout.is_empty())
+        // A missing directory is already the desired state
+        let check_output = nsenter("test")
+            .arg("-e")
+            .arg(target)
+            .output()
+            .context("Failed to check if mount point exists")?;
+
+        if check_output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("Failed to remove mount point {}: {}", target, stderr);
+        }
+
+        debug!("Removed mount point {}", target);
+        Ok(())
+    }
+
+    /// Check if a path is already mounted on the host
+    pub async fn is_mounted(&self, target: &str) -> Result<bool> {
+        Ok(self.mount_table.lookup(target).await?.is_some())
     }
 
     /// Ensure mount point directory exists on the host
     async fn ensure_mount_point(&self, target: &str) -> Result<()> {
+        #[cfg(test)]
+        if let Some(fake) = &self.fake {
+            return Ok(std::fs::create_dir_all(fake.path(target))?);
+        }
+
+        if let Some(ns) = &self.host_ns {
+            if !ns.is_dir(target).await? {
+                debug!("Creating mount point on host: {}", target);
+                ns.create_dir_all(target).await?;
+            }
+            return Ok(());
+        }
+
         // Check if directory exists on host using nsenter
-        let check_output = Command::new("nsenter")
-            .arg("-t")
-            .arg("1")
-            .arg("-m")
-            .arg("test")
+        let check_output = nsenter("test")
             .arg("-d")
             .arg(target)
             .output()
@@ -131,11 +449,7 @@ impl MountManager {
             debug!("Creating mount point on host: {}", target);
 
             // Create directory on host using nsenter
-            let mkdir_output = Command::new("nsenter")
-                .arg("-t")
-                .arg("1")
-                .arg("-m")
-                .arg("mkdir")
+            let mkdir_output = nsenter("mkdir")
                 .arg("-p")
                 .arg(target)
                 .output()
@@ -150,3 +464,94 @@ impl MountManager {
         Ok(())
     }
 }
+
+/// Short description of a mount for error messages
+fn describe(entry: &MountEntry) -> String {
+    format!(
+        "{} {} (root {}, {})",
+        entry.fs_type,
+        entry.source,
+        entry.root,
+        entry.mount_options.join(",")
+    )
+}
+
+/// Build a command that runs `program` in the host mount namespace
+// -t 1: target PID 1 (init/systemd on host)
+// -m: enter mount namespace
+fn nsenter(program: &str) -> Command {
+    let mut cmd = Command::new("nsenter");
+    cmd.arg("-t").arg("1").arg("-m").arg(program);
+    cmd
+}
+
+#[cfg(test)]
+mod fake {
+    use anyhow::Result;
+    use std::path::{Path, PathBuf};
+
+    /// A directory standing in for the host, with its own mountinfo file
+    #[derive(Debug, Clone)]
+    pub struct FakeHost {
+        root: PathBuf,
+    }
+
+    impl FakeHost {
+        pub fn new(root: &Path) -> Self {
+            std::fs::write(root.join("mountinfo"), "").unwrap();
+            Self {
+                root: root.to_path_buf(),
+            }
+        }
+
+        pub fn mountinfo(&self) -> PathBuf {
+            self.root.join("mountinfo")
+        }
+
+        pub fn path(&self, path: &str) -> PathBuf {
+            self.root.join(path.trim_start_matches('/'))
+        }
+
+        /// Add a mount; a bind mount shares the device of its source
+        pub fn mount(
+            &self,
+            source: &str,
+            root: &str,
+            device: Option<&str>,
+            target: &str,
+            options: &str,
+        ) -> Result<()> {
+            let mut table = std::fs::read_to_string(self.mountinfo())?;
+            let id = 100 + table.lines().count();
+            let device = device.map_or_else(|| format!("0:{}", id), String::from);
+            let options = if options.is_empty() { "rw" } else { options };
+            table.push_str(&format!(
+                "{} 1 {} {} {} {} - lustre {} rw\n",
+                id, device, root, target, options, source
+            ));
+            Ok(std::fs::write(self.mountinfo(), table)?)
+        }
+
+        /// Remove the topmost mount at `target`
+        pub fn unmount(&self, target: &str) -> Result<()> {
+            let table = std::fs::read_to_string(self.mountinfo())?;
+            let mut lines: Vec<&str> = table.lines().collect();
+            let Some(top) = lines
+                .iter()
+                .rposition(|line| line.split(' ').nth(4) == Some(target))
+            else {
+                anyhow::bail!("{} is not mounted", target);
+            };
+            lines.remove(top);
+            let table: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+            Ok(std::fs::write(self.mountinfo(), table)?)
+        }
+
+        pub fn remove_dir(&self, target: &str) -> Result<()> {
+            match std::fs::remove_dir(self.path(target)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            }
+        }
+    }
+}
@@ -0,0 +1,372 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Mount table of the host init process
+pub const HOST_MOUNTINFO: &str = "/proc/1/mountinfo";
+
+/// How long a parsed mount table is reused before re-reading it
+const DEFAULT_TTL: Duration = Duration::from_secs(2);
+
+/// One line of `/proc/<pid>/mountinfo`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountEntry {
+    pub mount_id: u32,
+    pub parent_id: u32,
+    /// `major:minor` of the backing device
+    pub device: String,
+    /// Path inside the filesystem that forms the root of this mount
+    pub root: String,
+    pub mount_point: String,
+    /// Per-mount options (e.g. `rw`, `nosuid`)
+    pub mount_options: Vec<String>,
+    pub fs_type: String,
+    pub source: String,
+    /// Per-superblock options (e.g. `flock`, `user_xattr` for Lustre)
+    pub super_options: Vec<String>,
+}
+
+impl MountEntry {
+    pub fn is_read_only(&self) -> bool {
+        self.mount_options.iter().any(|o| o == "ro")
+    }
+
+    /// Whether `option` is set at either the mount or superblock level
+    pub fn has_option(&self, option: &str) -> bool {
+        self.mount_options
+            .iter()
+            .chain(self.super_options.iter())
+            .any(|o| o == option)
+    }
+
+    /// Whether this entry is mounted from the given Lustre source
+    pub fn matches_source(&self, source: &str) -> bool {
+        same_lustre_source(&self.source, source)
+    }
+}
+
+/// Parsed snapshot of a mountinfo file
+#[derive(Debug, Clone, Default)]
+pub struct MountTable {
+    entries: Vec<MountEntry>,
+}
+
+impl MountTable {
+    /// Parse the contents of a mountinfo file
+    pub fn parse(content: &str) -> Result<Self> {
+        let entries = content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(parse_line)
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { entries })
+    }
+
+    pub fn entries(&self) -> &[MountEntry] {
+        &self.entries
+    }
+
+    /// Topmost mount at `mount_point`, if any
+    pub fn find(&self, mount_point: &str) -> Option<&MountEntry> {
+        let mount_point = normalize_path(mount_point);
+        self.entries
+            .iter()
+            .rev()
+            .find(|e| e.mount_point == mount_point)
+    }
+
+    /// The mount `path` is on, with the root a bind mount of `path` gets
+    pub fn containing(&self, path: &str) -> Option<MountEntry> {
+        let path = Path::new(path);
+        let mut mount_point = path;
+        loop {
+            if let Some(entry) = self.find(&mount_point.to_string_lossy()) {
+                let mut entry = entry.clone();
+                let below = path.strip_prefix(mount_point).unwrap_or(Path::new(""));
+                if !below.as_os_str().is_empty() {
+                    entry.root = Path::new(&entry.root)
+                        .join(below)
+                        .to_string_lossy()
+                        .into_owned();
+                }
+                return Some(entry);
+            }
+            mount_point = mount_point.parent()?;
+        }
+    }
+}
+
+type CachedTable = Option<(Instant, Arc<MountTable>)>;
+
+/// Mount table cache shared by all mount operations.
+///
+/// Reading mountinfo is cheap compared to forking `findmnt`, but during pod
+/// start storms many checks land within the same second, so a parsed table is
+/// reused for a short TTL. Callers that change the mount table invalidate it.
+#[derive(Debug, Clone)]
+pub struct MountTableCache {
+    path: PathBuf,
+    ttl: Duration,
+    cached: Arc<Mutex<CachedTable>>,
+}
+
+impl MountTableCache {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            ttl: DEFAULT_TTL,
+            cached: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Current mount table, re-read from disk if the cached copy expired
+    pub async fn get(&self) -> Result<Arc<MountTable>> {
+        if let Some((read_at, table)) = self.cached.lock().unwrap().as_ref()
+            && read_at.elapsed() < self.ttl
+        {
+            return Ok(table.clone());
+        }
+
+        let content = tokio::fs::read_to_string(&self.path)
+            .await
+            .with_context(|| format!("Failed to read {}", self.path.display()))?;
+        let table = Arc::new(MountTable::parse(&content)?);
+        debug!(
+            "Read {} mount entries from {}",
+            table.entries().len(),
+            self.path.display()
+        );
+
+        *self.cached.lock().unwrap() = Some((Instant::now(), table.clone()));
+        Ok(table)
+    }
+
+    /// Drop the cached table so the next lookup re-reads it
+    pub fn invalidate(&self) {
+        *self.cached.lock().unwrap() = None;
+    }
+
+    /// Topmost mount at `mount_point`, if any
+    pub async fn lookup(&self, mount_point: &str) -> Result<Option<MountEntry>> {
+        Ok(self.get().await?.find(mount_point).cloned())
+    }
+}
+
+fn parse_line(line: &str) -> Result<MountEntry> {
+    // 36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw,errors=continue
+    let (head, tail) = line
+        .split_once(" - ")
+        .with_context(|| format!("Malformed mountinfo line: {}", line))?;
+
+    let mut fields = head.split(' ');
+    let mut next = |name: &str| {
+        fields
+            .next()
+            .with_context(|| format!("Missing {} in mountinfo line: {}", name, line))
+    };
+
+    let mount_id = next("mount ID")?.parse().context("Invalid mount ID")?;
+    let parent_id = next("parent ID")?.parse().context("Invalid parent ID")?;
+    let device = next("device")?.to_string();
+    let root = unescape(next("root")?);
+    let mount_point = unescape(next("mount point")?);
+    let mount_options = split_options(next("mount options")?);
+
+    let mut fields = tail.split(' ');
+    let fs_type = fields
+        .next()
+        .with_context(|| format!("Missing filesystem type in mountinfo line: {}", line))?
+        .to_string();
+    let source = unescape(fields.next().unwrap_or_default());
+    let super_options = split_options(fields.next().unwrap_or_default());
+
+    Ok(MountEntry {
+        mount_id,
+        parent_id,
+        device,
+        root,
+        mount_point,
+        mount_options,
+        fs_type,
+        source,
+        super_options,
+    })
+}
+
+fn split_options(options: &str) -> Vec<String> {
+    options
+        .split(',')
+        .filter(|o| !o.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Decode the octal escapes the kernel uses for space, tab, newline and `\`
+fn unescape(field: &str) -> String {
+    if !field.contains('\\') {
+        return field.to_string();
+    }
+
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && let Some(digits) = bytes.get(i + 1..i + 4)
+            && let Ok(digits) = std::str::from_utf8(digits)
+            && let Ok(code) = u8::from_str_radix(digits, 8)
+        {
+            out.push(code);
+            i += 4;
+            continue;
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn normalize_path(path: &str) -> &str {
+    match path.trim_end_matches('/') {
+        "" => "/",
+        trimmed => trimmed,
+    }
+}
+
+/// Compare two Lustre sources, ignoring how the MGS NIDs were spelled.
+///
+/// The kernel reports `tcp0` as `tcp` and may reorder failover NIDs, so a
+/// plain string comparison reports false conflicts.
+pub fn same_lustre_source(a: &str, b: &str) -> bool {
+    let (Some((nids_a, fs_a)), Some((nids_b, fs_b))) = (a.split_once(":/"), b.split_once(":/"))
+    else {
+        return a == b;
+    };
+
+    if normalize_path(fs_a) != normalize_path(fs_b) {
+        return false;
+    }
+
+    let mut nids_a = split_nids(nids_a);
+    let mut nids_b = split_nids(nids_b);
+    nids_a.sort();
+    nids_b.sort();
+    nids_a == nids_b
+}
+
+fn split_nids(nids: &str) -> Vec<String> {
+    nids.split([':', ','])
+        .filter(|nid| !nid.is_empty())
+        .map(|nid| match nid.split_once('@') {
+            Some((addr, net)) => {
+                // Network number 0 is implied: tcp0 == tcp, but o2ib10 != o2ib1
+                let name = net.trim_end_matches(|c: char| c.is_ascii_digit());
+                let number = &net[name.len()..];
+                let net = if number.trim_start_matches('0').is_empty() {
+                    name
+                } else {
+                    net
+                };
+                format!("{}@{}", addr, net)
+            }
+            None => nid.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+318 22 0:57 / /var/lib/kubelet/plugins/kubernetes.io/csi/lustre.csi.klustrefs.io/abc/globalmount rw,relatime shared:170 - lustre 10.0.0.1@tcp:/lustre-fs rw,flock,user_xattr,lazystatfs
+402 22 0:57 / /var/lib/kubelet/pods/uid-1/volumes/kubernetes.io~csi/pv-1/mount ro,relatime shared:170 - lustre 10.0.0.1@tcp:/lustre-fs rw,flock,user_xattr,lazystatfs
+410 22 0:60 / /mnt/with\\040space rw - tmpfs tmpfs rw
+";
+
+    #[test]
+    fn test_parse_mountinfo() {
+        let table = MountTable::parse(SAMPLE).unwrap();
+        assert_eq!(table.entries().len(), 4);
+
+        let staged = table
+            .find("/var/lib/kubelet/plugins/kubernetes.io/csi/lustre.csi.klustrefs.io/abc/globalmount/")
+            .unwrap();
+        assert_eq!(staged.fs_type, "lustre");
+        assert_eq!(staged.mount_id, 318);
+        assert_eq!(staged.device, "0:57");
+        assert_eq!(staged.source, "10.0.0.1@tcp:/lustre-fs");
+        assert!(staged.has_option("flock"));
+        assert!(!staged.is_read_only());
+
+        let published = table
+            .find("/var/lib/kubelet/pods/uid-1/volumes/kubernetes.io~csi/pv-1/mount")
+            .unwrap();
+        assert!(published.is_read_only());
+
+        let spaced = table.find("/mnt/with space").unwrap();
+        assert_eq!(spaced.fs_type, "tmpfs");
+
+        assert!(table.find("/not/mounted").is_none());
+        assert!(MountTable::parse("garbage").is_err());
+    }
+
+    #[test]
+    fn test_containing_mount() {
+        let staging =
+            "/var/lib/kubelet/plugins/kubernetes.io/csi/lustre.csi.klustrefs.io/abc/globalmount";
+        let table = MountTable::parse(&format!(
+            "{}511 22 0:57 /k8s/pv-2 /mnt/pv-2 rw - lustre 10.0.0.1@tcp:/lustre-fs rw\n",
+            SAMPLE
+        ))
+        .unwrap();
+
+        let staged = table.containing(staging).unwrap();
+        assert_eq!(staged.mount_id, 318);
+        assert_eq!(staged.root, "/");
+
+        let sub_path = table
+            .containing(&format!("{}/team-a/web-0", staging))
+            .unwrap();
+        assert_eq!(sub_path.mount_id, 318);
+        assert_eq!(sub_path.root, "/team-a/web-0");
+
+        let nested = table.containing("/mnt/pv-2/web-0").unwrap();
+        assert_eq!(nested.mount_id, 511);
+        assert_eq!(nested.root, "/k8s/pv-2/web-0");
+
+        let root = table.containing("/not/mounted").unwrap();
+        assert_eq!(root.fs_type, "ext4");
+        assert_eq!(root.root, "/not/mounted");
+        assert!(MountTable::default().containing("/not/mounted").is_none());
+    }
+
+    #[test]
+    fn test_same_lustre_source() {
+        assert!(same_lustre_source(
+            "10.0.0.1@tcp0:/lustre-fs",
+            "10.0.0.1@tcp:/lustre-fs"
+        ));
+        assert!(same_lustre_source(
+            "10.0.0.1@tcp:10.0.0.2@tcp:/fs/sub",
+            "10.0.0.2@tcp0:10.0.0.1@tcp0:/fs/sub/"
+        ));
+        assert!(!same_lustre_source(
+            "10.0.0.1@tcp:/lustre-fs",
+            "10.0.0.1@tcp:/other-fs"
+        ));
+        assert!(!same_lustre_source(
+            "10.0.0.1@tcp:/lustre-fs",
+            "10.0.0.9@tcp:/lustre-fs"
+        ));
+        assert!(!same_lustre_source(
+            "10.0.0.1@o2ib10:/lustre-fs",
+            "10.0.0.1@o2ib1:/lustre-fs"
+        ));
+    }
+}
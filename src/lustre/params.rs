@@ -0,0 +1,463 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::debug;
+
+/// Where the kernel exposes Lustre parameters, in the order `lctl` uses
+pub const PARAM_ROOTS: &[&str] = &[
+    "/sys/fs/lustre",
+    "/sys/kernel/debug/lustre",
+    "/proc/fs/lustre",
+];
+
+/// Upper bound for an `lctl` fallback call
+const LCTL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Value of one Lustre parameter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamValue {
+    /// A single integer, e.g. `max_rpcs_in_flight`
+    Integer(i64),
+    /// `key: value` lines, e.g. `max_cached_mb` or `stats`-like output
+    Map(BTreeMap<String, String>),
+    /// Anything else, trimmed
+    Text(String),
+}
+
+impl ParamValue {
+    pub fn parse(raw: &str) -> Self {
+        let raw = raw.trim();
+        if let Ok(value) = raw.parse() {
+            return ParamValue::Integer(value);
+        }
+
+        if raw.contains('\n') {
+            let map: Option<BTreeMap<String, String>> = raw
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| {
+                    // Nested YAML (indented lines) does not fit a flat map
+                    if line.starts_with(char::is_whitespace) {
+                        return None;
+                    }
+                    line.split_once(':')
+                        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+                })
+                .collect();
+            if let Some(map) = map {
+                return ParamValue::Map(map);
+            }
+        }
+
+        ParamValue::Text(raw.to_string())
+    }
+}
+
+/// One resolved parameter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Param {
+    /// Full name with wildcards resolved, e.g. `llite.lustre-ffff88.max_cached_mb`
+    pub name: String,
+    /// File backing the parameter; `None` when it came from `lctl`
+    pub path: Option<PathBuf>,
+    pub value: ParamValue,
+}
+
+impl Param {
+    /// Last component of the name, e.g. `max_cached_mb`
+    #[cfg(test)]
+    pub fn leaf(&self) -> &str {
+        self.name.rsplit('.').next().unwrap_or(&self.name)
+    }
+
+    /// Device component of a `type.device.param` name
+    pub fn device(&self) -> Option<&str> {
+        let mut parts = self.name.splitn(3, '.');
+        parts.next()?;
+        let device = parts.next()?;
+        parts.next()?;
+        Some(device)
+    }
+
+    /// Integer value; map-valued parameters use the entry named like the leaf
+    ///
+    /// `max_cached_mb` reads as `users: 2\nmax_cached_mb: 128\n...`.
+    #[cfg(test)]
+    pub fn as_i64(&self) -> Option<i64> {
+        match &self.value {
+            ParamValue::Integer(v) => Some(*v),
+            ParamValue::Map(map) => map.get(self.leaf())?.parse().ok(),
+            ParamValue::Text(_) => None,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn as_u64(&self) -> Option<u64> {
+        self.as_i64().and_then(|v| u64::try_from(v).ok())
+    }
+
+    /// `0`/`1` flags such as `checksums`
+    #[cfg(test)]
+    pub fn as_bool(&self) -> Option<bool> {
+        match self.as_i64()? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+
+    /// Raw text of a parameter that is neither an integer nor a map
+    pub fn as_text(&self) -> Option<&str> {
+        match &self.value {
+            ParamValue::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+/// Typed `lctl get_param` / `lctl set_param` over sysfs, debugfs and procfs.
+///
+/// Parameter names are dot-separated path components below one of the roots,
+/// with `*` and `?` wildcards per component. When no file matches, `lctl` is
+/// used as a fallback, which also covers parameters only it knows how to map.
+#[derive(Debug, Clone)]
+pub struct LustreParams {
+    roots: Vec<PathBuf>,
+    lctl_fallback: bool,
+}
+
+impl Default for LustreParams {
+    fn default() -> Self {
+        Self {
+            roots: PARAM_ROOTS.iter().map(PathBuf::from).collect(),
+            lctl_fallback: true,
+        }
+    }
+}
+
+impl LustreParams {
+    /// Parameters below custom roots only, e.g. a fake tree in tests
+    #[cfg(test)]
+    pub fn with_roots(roots: Vec<PathBuf>) -> Self {
+        Self {
+            roots,
+            lctl_fallback: false,
+        }
+    }
+
+    /// Read every parameter matching `pattern`, e.g. `osc.*.max_dirty_mb`
+    pub async fn get(&self, pattern: &str) -> Result<Vec<Param>> {
+        let files = self.find(pattern).await;
+        if files.is_empty() {
+            return self.lctl_get(pattern).await;
+        }
+
+        let mut params = Vec::with_capacity(files.len());
+        for (name, path) in files {
+            let raw = tokio::fs::read_to_string(&path)
+                .await
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            params.push(Param {
+                name,
+                path: Some(path),
+                value: ParamValue::parse(&raw),
+            });
+        }
+
+        Ok(params)
+    }
+
+    /// Read a single parameter, failing if it does not exist or is ambiguous
+    #[cfg(test)]
+    pub async fn get_one(&self, name: &str) -> Result<Param> {
+        let mut params = self.get(name).await?;
+        match params.len() {
+            1 => Ok(params.remove(0)),
+            0 => anyhow::bail!("Lustre parameter {} not found", name),
+            n => anyhow::bail!("Lustre parameter {} matches {} instances", name, n),
+        }
+    }
+
+    /// Write `value` to every parameter matching `pattern`.
+    ///
+    /// Returns the names that were written.
+    pub async fn set(&self, pattern: &str, value: &str) -> Result<Vec<String>> {
+        let files = self.find(pattern).await;
+        if files.is_empty() {
+            return self.lctl_set(pattern, value).await;
+        }
+
+        let mut written = Vec::with_capacity(files.len());
+        for (name, path) in files {
+            tokio::fs::write(&path, value)
+                .await
+                .with_context(|| format!("Failed to set {}={}", name, value))?;
+            debug!("Set Lustre parameter {}={}", name, value);
+            written.push(name);
+        }
+
+        Ok(written)
+    }
+
+    /// Resolve `pattern` to parameter files; the first root providing a name wins
+    async fn find(&self, pattern: &str) -> Vec<(String, PathBuf)> {
+        let components: Vec<&str> = pattern.split('.').collect();
+        let mut found: BTreeMap<String, PathBuf> = BTreeMap::new();
+
+        for root in &self.roots {
+            let mut candidates = vec![(Vec::<String>::new(), root.clone())];
+            for component in &components {
+                let mut next = Vec::new();
+                for (names, dir) in candidates {
+                    for (name, path) in expand(&dir, component).await {
+                        let mut names = names.clone();
+                        names.push(name);
+                        next.push((names, path));
+                    }
+                }
+                candidates = next;
+            }
+
+            for (names, path) in candidates {
+                if is_file(&path).await {
+                    found.entry(names.join(".")).or_insert(path);
+                }
+            }
+        }
+
+        found.into_iter().collect()
+    }
+
+    async fn lctl_get(&self, pattern: &str) -> Result<Vec<Param>> {
+        if !self.lctl_fallback {
+            return Ok(Vec::new());
+        }
+
+        let stdout = lctl(&["get_param", pattern]).await?;
+        Ok(parse_lctl_output(&stdout))
+    }
+
+    async fn lctl_set(&self, pattern: &str, value: &str) -> Result<Vec<String>> {
+        if !self.lctl_fallback {
+            anyhow::bail!("Lustre parameter {} not found", pattern);
+        }
+
+        // "osc.lustre-OST0000-osc-ffff88.max_dirty_mb=512"
+        let stdout = lctl(&["set_param", &format!("{}={}", pattern, value)]).await?;
+        Ok(stdout
+            .lines()
+            .filter_map(|line| line.split_once('=').map(|(name, _)| name.to_string()))
+            .collect())
+    }
+}
+
+/// Entries of `dir` matching one name component
+async fn expand(dir: &Path, component: &str) -> Vec<(String, PathBuf)> {
+    if !component.contains(['*', '?']) {
+        let path = dir.join(component);
+        return if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            vec![(component.to_string(), path)]
+        } else {
+            Vec::new()
+        };
+    }
+
+    let mut matches = Vec::new();
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return matches;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if wildcard_match(component, &name) {
+            matches.push((name, entry.path()));
+        }
+    }
+    matches.sort();
+    matches
+}
+
+async fn is_file(path: &Path) -> bool {
+    tokio::fs::metadata(path)
+        .await
+        .map(|m| m.is_file())
+        .unwrap_or(false)
+}
+
+/// Shell-style matching with `*` (any run) and `?` (any one character)
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+async fn lctl(args: &[&str]) -> Result<String> {
+    let mut cmd = tokio::process::Command::new("lctl");
+    cmd.args(args).kill_on_drop(true);
+
+    let output = tokio::time::timeout(LCTL_TIMEOUT, cmd.output())
+        .await
+        .context("lctl timed out")?
+        .context("Failed to execute lctl")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("lctl {} failed: {}", args.join(" "), stderr.trim());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parse `lctl get_param` output; multi-line values start with `name=`
+fn parse_lctl_output(output: &str) -> Vec<Param> {
+    let mut params = Vec::new();
+    let mut current: Option<(String, String)> = None;
+
+    for line in output.lines() {
+        let starts_param = line
+            .split_once('=')
+            .filter(|(name, _)| name.contains('.') && !name.contains(char::is_whitespace));
+
+        if let Some((name, value)) = starts_param {
+            if let Some((name, raw)) = current.take() {
+                params.push(Param {
+                    name,
+                    path: None,
+                    value: ParamValue::parse(&raw),
+                });
+            }
+            current = Some((name.to_string(), value.to_string()));
+        } else if let Some((_, raw)) = current.as_mut() {
+            raw.push('\n');
+            raw.push_str(line);
+        }
+    }
+
+    if let Some((name, raw)) = current {
+        params.push(Param {
+            name,
+            path: None,
+            value: ParamValue::parse(&raw),
+        });
+    }
+
+    params
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(root: &Path, rel: &str, content: &str) {
+        let path = root.join(rel);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_params_against_fake_sysfs() {
+        let sys = tempfile::tempdir().unwrap();
+        let proc = tempfile::tempdir().unwrap();
+        write(
+            sys.path(),
+            "llite/lustre-ffff01/max_cached_mb",
+            "users: 2\nmax_cached_mb: 128\nused_mb: 0\n",
+        );
+        write(
+            sys.path(),
+            "osc/lustre-OST0000-osc-ffff01/max_dirty_mb",
+            "32\n",
+        );
+        write(
+            sys.path(),
+            "osc/lustre-OST0001-osc-ffff01/max_dirty_mb",
+            "32\n",
+        );
+        write(
+            sys.path(),
+            "osc/lustre-OST0000-osc-ffff02/max_dirty_mb",
+            "64\n",
+        );
+        write(proc.path(), "llite/lustre-ffff01/checksums", "1\n");
+
+        let params = LustreParams::with_roots(vec![sys.path().into(), proc.path().into()]);
+
+        let cached = params.get_one("llite.*.max_cached_mb").await.unwrap();
+        assert_eq!(cached.name, "llite.lustre-ffff01.max_cached_mb");
+        assert_eq!(cached.device(), Some("lustre-ffff01"));
+        assert_eq!(cached.as_u64(), Some(128));
+
+        let dirty = params.get("osc.*-ffff01.max_dirty_mb").await.unwrap();
+        assert_eq!(dirty.len(), 2);
+        assert!(dirty.iter().all(|p| p.as_u64() == Some(32)));
+
+        let checksums = params
+            .get_one("llite.lustre-ffff01.checksums")
+            .await
+            .unwrap();
+        assert_eq!(checksums.as_bool(), Some(true));
+
+        let written = params
+            .set("osc.*-ffff01.max_dirty_mb", "512")
+            .await
+            .unwrap();
+        assert_eq!(written.len(), 2);
+        let dirty = params.get("osc.*.max_dirty_mb").await.unwrap();
+        let values: Vec<_> = dirty.iter().map(|p| p.as_u64().unwrap()).collect();
+        assert_eq!(values, vec![512, 64, 512]);
+
+        assert!(params.get("llite.*.missing").await.unwrap().is_empty());
+        assert!(params.set("llite.*.missing", "1").await.is_err());
+        assert!(params.get_one("osc.*.max_dirty_mb").await.is_err());
+    }
+
+    #[test]
+    fn test_parse_lctl_output() {
+        let params = parse_lctl_output(
+            "\
+osc.lustre-OST0000-osc-ffff01.max_rpcs_in_flight=8
+llite.lustre-ffff01.max_cached_mb=
+users: 2
+max_cached_mb: 128
+",
+        );
+        assert_eq!(params.len(), 2);
+        assert_eq!(params[0].as_u64(), Some(8));
+        assert_eq!(params[1].leaf(), "max_cached_mb");
+        assert_eq!(params[1].as_u64(), Some(128));
+
+        assert!(wildcard_match(
+            "lustre-OST*-osc-ffff01",
+            "lustre-OST0002-osc-ffff01"
+        ));
+        assert!(!wildcard_match(
+            "lustre-OST*-osc-ffff01",
+            "lustre-MDT0000-mdc-ffff01"
+        ));
+        assert!(wildcard_match("OST000?", "OST0003"));
+    }
+}
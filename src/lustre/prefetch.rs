@@ -0,0 +1,128 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::Path;
+
+const FILES_KEY: &str = "prefetchFiles";
+const MANIFEST_KEY: &str = "prefetchManifest";
+
+/// Files to hint into the OST caches once a volume is published
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrefetchHints {
+    /// Paths relative to the volume root
+    pub files: Vec<String>,
+    /// File inside the volume listing one path per line, relative to the
+    /// volume root
+    pub manifest: Option<String>,
+}
+
+impl PrefetchHints {
+    /// Read `prefetchFiles` (comma-separated paths) and `prefetchManifest`
+    pub fn from_volume_context(volume_context: &HashMap<String, String>) -> Result<Option<Self>> {
+        let files: Vec<String> = volume_context
+            .get(FILES_KEY)
+            .map(|list| {
+                list.split(',')
+                    .map(str::trim)
+                    .filter(|file| !file.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let manifest = volume_context
+            .get(MANIFEST_KEY)
+            .map(|v| v.trim().to_string());
+
+        for path in files.iter().chain(&manifest) {
+            if !is_inside_volume(path) {
+                anyhow::bail!(
+                    "{} and {} must hold paths inside the volume, got {:?}",
+                    FILES_KEY,
+                    MANIFEST_KEY,
+                    path
+                );
+            }
+        }
+
+        if files.is_empty() && manifest.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Self { files, manifest }))
+    }
+
+    /// Host paths of the files to hint below the volume mounted at `root`,
+    /// reading the manifest if there is one
+    pub fn resolve(&self, root: &Path) -> Result<Vec<String>> {
+        let mut files = self.files.clone();
+        if let Some(manifest) = &self.manifest {
+            let path = root.join(manifest);
+            let content = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read prefetch manifest {}", path.display()))?;
+            files.extend(parse_manifest(&content)?);
+        }
+
+        files.sort_unstable();
+        files.dedup();
+        Ok(files
+            .iter()
+            .map(|file| root.join(file).to_string_lossy().into_owned())
+            .collect())
+    }
+}
+
+/// Parse a prefetch manifest: one path per line, relative to the volume
+/// root; blank lines and `#` comments are skipped
+pub fn parse_manifest(content: &str) -> Result<Vec<String>> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            if is_inside_volume(line) {
+                Ok(line.to_string())
+            } else {
+                anyhow::bail!("Prefetch manifest entry {:?} is outside the volume", line)
+            }
+        })
+        .collect()
+}
+
+fn is_inside_volume(path: &str) -> bool {
+    !path.is_empty() && !path.starts_with('/') && !path.split('/').any(|part| part == "..")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefetch_hints() {
+        let context: HashMap<String, String> = [
+            (
+                "prefetchFiles".to_string(),
+                "train/a.bin, train/b.bin".to_string(),
+            ),
+            ("prefetchManifest".to_string(), "MANIFEST".to_string()),
+        ]
+        .into();
+        let hints = PrefetchHints::from_volume_context(&context)
+            .unwrap()
+            .unwrap();
+        assert_eq!(hints.files, vec!["train/a.bin", "train/b.bin"]);
+        assert_eq!(hints.manifest.as_deref(), Some("MANIFEST"));
+
+        let escape: HashMap<String, String> =
+            [("prefetchManifest".to_string(), "../MANIFEST".to_string())].into();
+        assert!(PrefetchHints::from_volume_context(&escape).is_err());
+        assert_eq!(
+            PrefetchHints::from_volume_context(&HashMap::new()).unwrap(),
+            None
+        );
+
+        let manifest = "# training set\ntrain/a.bin\n\n  train/c.bin\n";
+        assert_eq!(
+            parse_manifest(manifest).unwrap(),
+            vec!["train/a.bin", "train/c.bin"]
+        );
+        assert!(parse_manifest("/etc/passwd\n").is_err());
+    }
+}
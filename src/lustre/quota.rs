@@ -0,0 +1,207 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+
+use super::layout::parse_size;
+
+const QUOTA_BYTES_KEY: &str = "quotaBytes";
+const QUOTA_INODES_KEY: &str = "quotaInodes";
+
+/// Hard limits of the project quota a volume directory is accounted to.
+///
+/// A limit of 0 removes it; a limit left out is not changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProjectQuota {
+    pub bytes: Option<u64>,
+    pub inodes: Option<u64>,
+}
+
+impl ProjectQuota {
+    /// Read `quotaBytes` (a size such as `100G`) and `quotaInodes`
+    pub fn from_parameters(parameters: &HashMap<String, String>) -> Result<Self> {
+        let bytes = parameters
+            .get(QUOTA_BYTES_KEY)
+            .map(|v| parse_size(QUOTA_BYTES_KEY, v))
+            .transpose()?;
+        let inodes = parameters
+            .get(QUOTA_INODES_KEY)
+            .map(|v| {
+                v.trim().parse::<u64>().with_context(|| {
+                    format!(
+                        "{} must be a number of inodes, got {:?}",
+                        QUOTA_INODES_KEY, v
+                    )
+                })
+            })
+            .transpose()?;
+        Ok(Self { bytes, inodes })
+    }
+
+    /// Limits that remove both the block and the inode limit
+    pub fn unlimited() -> Self {
+        Self {
+            bytes: Some(0),
+            inodes: Some(0),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Whether `key` is one of the quota parameters
+    pub fn is_quota_key(key: &str) -> bool {
+        key == QUOTA_BYTES_KEY || key == QUOTA_INODES_KEY
+    }
+
+    /// Arguments for `lfs setquota`, before the mount point
+    pub fn setquota_args(&self, project_id: u32) -> Vec<String> {
+        let mut args = vec!["-p".to_string(), project_id.to_string()];
+        if let Some(bytes) = self.bytes {
+            // Block limits are given in KiB; round up so the limit is never
+            // below the one asked for
+            args.extend(["-B".to_string(), bytes.div_ceil(1024).to_string()]);
+        }
+        if let Some(inodes) = self.inodes {
+            args.extend(["-I".to_string(), inodes.to_string()]);
+        }
+        args
+    }
+
+    pub fn to_volume_context(&self, volume_context: &mut HashMap<String, String>) {
+        if let Some(bytes) = self.bytes {
+            volume_context.insert(QUOTA_BYTES_KEY.to_string(), bytes.to_string());
+        }
+        if let Some(inodes) = self.inodes {
+            volume_context.insert(QUOTA_INODES_KEY.to_string(), inodes.to_string());
+        }
+    }
+}
+
+/// Extended attribute of a volume directory holding the project ID it was
+/// given, so the ID is allocated once and found again on delete
+pub const PROJECT_ID_XATTR: &str = "trusted.klustre_csi.project_id";
+
+/// Project ID to try first for a volume, derived from its volume ID so
+/// allocation rarely has to probe further.
+///
+/// This is a 32-bit FNV-1a hash, kept clear of project 0 (no project). It
+/// can collide, so an ID is only used once `lfs quota` shows it unused.
+pub fn project_id(volume_id: &str) -> u32 {
+    let hash = volume_id.bytes().fold(0x811c_9dc5u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    });
+    hash.max(1)
+}
+
+/// The project ID after `id`, skipping project 0
+pub fn next_project_id(id: u32) -> u32 {
+    id.wrapping_add(1).max(1)
+}
+
+/// Usage and hard limits of one project, from `lfs quota -q -p <id>`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProjectUsage {
+    pub bytes: u64,
+    /// Hard block limit in bytes, 0 if there is none
+    pub bytes_limit: u64,
+    pub inodes: u64,
+    /// Hard inode limit, 0 if there is none
+    pub inodes_limit: u64,
+    /// Whether a soft limit is set
+    soft_limits: bool,
+}
+
+impl ProjectUsage {
+    pub fn parse(output: &str) -> Result<Self> {
+        // The mount point comes first; long ones push the numbers onto the
+        // next line. Usage over a limit is marked with a trailing `*`.
+        let fields: Vec<&str> = output.split_whitespace().skip(1).collect();
+        if fields.len() < 8 {
+            anyhow::bail!("Unexpected lfs quota output: {:?}", output.trim());
+        }
+        // kbytes, quota, limit, grace, files, quota, limit, grace
+        let number = |index: usize| -> Result<u64> {
+            fields[index]
+                .trim_end_matches('*')
+                .parse()
+                .with_context(|| format!("Unexpected lfs quota output: {:?}", output.trim()))
+        };
+        Ok(Self {
+            bytes: number(0)? * 1024,
+            bytes_limit: number(2)? * 1024,
+            inodes: number(4)?,
+            inodes_limit: number(6)?,
+            soft_limits: number(1)? > 0 || number(5)? > 0,
+        })
+    }
+
+    /// Whether the project accounts any space or inodes, or has limits set
+    pub fn is_in_use(&self) -> bool {
+        self.bytes > 0
+            || self.bytes_limit > 0
+            || self.inodes > 0
+            || self.inodes_limit > 0
+            || self.soft_limits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::parameters;
+
+    #[test]
+    fn test_project_quota() {
+        let parameters = parameters(&[("quotaBytes", "10G"), ("quotaInodes", "1000")]);
+        let quota = ProjectQuota::from_parameters(&parameters).unwrap();
+        assert_eq!(
+            quota.setquota_args(42),
+            vec!["-p", "42", "-B", "10485760", "-I", "1000"]
+        );
+
+        let partial = ProjectQuota {
+            bytes: Some(1500),
+            inodes: None,
+        };
+        assert_eq!(partial.setquota_args(7), vec!["-p", "7", "-B", "2"]);
+
+        let bad: HashMap<String, String> = [("quotaInodes".to_string(), "-1".to_string())].into();
+        assert!(ProjectQuota::from_parameters(&bad).is_err());
+
+        let id = project_id("10.0.0.1@tcp:/lustre/k8s/pvc-1");
+        assert_ne!(id, 0);
+        assert_eq!(id, project_id("10.0.0.1@tcp:/lustre/k8s/pvc-1"));
+        assert_ne!(id, project_id("10.0.0.1@tcp:/lustre/k8s/pvc-2"));
+        assert_eq!(next_project_id(u32::MAX), 1);
+        assert_eq!(
+            ProjectQuota::unlimited().setquota_args(3),
+            vec!["-p", "3", "-B", "0", "-I", "0"]
+        );
+    }
+
+    #[test]
+    fn test_project_usage() {
+        let unused =
+            "      /mnt/lustre       0       0       0       -       0       0       0       -\n";
+        assert!(!ProjectUsage::parse(unused).unwrap().is_in_use());
+
+        let used = ProjectUsage::parse("/mnt/lustre 4 0 0 - 1 0 0 -\n").unwrap();
+        assert!(used.is_in_use());
+        assert_eq!((used.bytes, used.inodes), (4096, 1));
+
+        let limited = "/var/lib/kubelet/plugins/klustre/controller/lustre-10.0.0.1@tcp\n\
+                       0 0 1048576 - 0 0 0 -\n";
+        let limited = ProjectUsage::parse(limited).unwrap();
+        assert!(limited.is_in_use());
+        assert_eq!(limited.bytes_limit, 1 << 30);
+
+        let soft = ProjectUsage::parse("/mnt/lustre 0 100 0 - 0 0 0 -").unwrap();
+        assert!(soft.is_in_use());
+
+        let over = ProjectUsage::parse("/mnt/lustre 2048* 0 1024 6d23h 2 0 0 -\n").unwrap();
+        assert_eq!(over.bytes, 2048 * 1024);
+
+        assert!(ProjectUsage::parse("").is_err());
+        assert!(ProjectUsage::parse("/mnt/lustre x 0 0 - 0 0 0 -").is_err());
+    }
+}
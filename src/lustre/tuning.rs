@@ -0,0 +1,219 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+
+/// Kind of value a tunable accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ValueKind {
+    /// Non-negative integer
+    Count,
+    /// `true`/`false`, written as `1`/`0`
+    Flag,
+}
+
+/// A client parameter that can be set per volume
+struct Tunable {
+    /// Key in the StorageClass parameters / PV volume attributes
+    key: &'static str,
+    /// Client device type the parameter lives on
+    device_type: &'static str,
+    /// Parameter name below each device
+    param: &'static str,
+    kind: ValueKind,
+}
+
+const TUNABLES: &[Tunable] = &[
+    Tunable {
+        key: "maxCachedMb",
+        device_type: "llite",
+        param: "max_cached_mb",
+        kind: ValueKind::Count,
+    },
+    Tunable {
+        key: "maxReadAheadMb",
+        device_type: "llite",
+        param: "max_read_ahead_mb",
+        kind: ValueKind::Count,
+    },
+    Tunable {
+        key: "maxReadAheadPerFileMb",
+        device_type: "llite",
+        param: "max_read_ahead_per_file_mb",
+        kind: ValueKind::Count,
+    },
+    Tunable {
+        key: "statAheadMax",
+        device_type: "llite",
+        param: "statahead_max",
+        kind: ValueKind::Count,
+    },
+    Tunable {
+        key: "checksums",
+        device_type: "llite",
+        param: "checksums",
+        kind: ValueKind::Flag,
+    },
+    Tunable {
+        key: "maxDirtyMb",
+        device_type: "osc",
+        param: "max_dirty_mb",
+        kind: ValueKind::Count,
+    },
+    Tunable {
+        key: "maxRpcsInFlight",
+        device_type: "osc",
+        param: "max_rpcs_in_flight",
+        kind: ValueKind::Count,
+    },
+];
+
+/// Client tuning requested by a volume, keyed by volume context key.
+///
+/// Values are normalized to what gets written to the parameter file, so two
+/// requests can be compared directly.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientTuning {
+    settings: BTreeMap<String, String>,
+}
+
+impl ClientTuning {
+    /// Collect and validate tuning keys from a volume context
+    pub fn from_volume_context(volume_context: &HashMap<String, String>) -> Result<Self> {
+        let mut settings = BTreeMap::new();
+
+        for tunable in TUNABLES {
+            let Some(raw) = volume_context.get(tunable.key) else {
+                continue;
+            };
+            let value = match tunable.kind {
+                ValueKind::Count => {
+                    raw.trim()
+                        .parse::<u64>()
+                        .map(|v| v.to_string())
+                        .map_err(|_| {
+                            anyhow::anyhow!(
+                                "{} must be a non-negative integer, got {:?}",
+                                tunable.key,
+                                raw
+                            )
+                        })?
+                }
+                ValueKind::Flag => match raw.trim() {
+                    "true" | "1" => "1".to_string(),
+                    "false" | "0" => "0".to_string(),
+                    _ => anyhow::bail!("{} must be true or false, got {:?}", tunable.key, raw),
+                },
+            };
+            settings.insert(tunable.key.to_string(), value);
+        }
+
+        Ok(Self { settings })
+    }
+
+    /// Rebuild from settings stored in the node state
+    pub fn from_settings(settings: BTreeMap<String, String>) -> Self {
+        Self { settings }
+    }
+
+    pub fn settings(&self) -> &BTreeMap<String, String> {
+        &self.settings
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.settings.is_empty()
+    }
+
+    /// Parameter patterns and values for the devices of one client instance.
+    ///
+    /// Devices of a mount share its superblock suffix, e.g. the llite device
+    /// `lustre-ffff8803c9bd1000` and the OSC `lustre-OST0000-osc-ffff8803c9bd1000`.
+    pub fn params_for(&self, instance: &str) -> Vec<(String, String)> {
+        TUNABLES
+            .iter()
+            .filter_map(|tunable| {
+                let value = self.settings.get(tunable.key)?;
+                let pattern = format!("{}.*-{}.{}", tunable.device_type, instance, tunable.param);
+                Some((pattern, value.clone()))
+            })
+            .collect()
+    }
+
+    /// Keys whose values differ between two requests, as `key: a != b`
+    pub fn conflicts_with(&self, other: &ClientTuning) -> Vec<String> {
+        let keys: std::collections::BTreeSet<&String> =
+            self.settings.keys().chain(other.settings.keys()).collect();
+
+        keys.into_iter()
+            .filter_map(|key| {
+                let ours = self.settings.get(key);
+                let theirs = other.settings.get(key);
+                (ours != theirs).then(|| {
+                    format!(
+                        "{}: {} != {}",
+                        key,
+                        ours.map(String::as_str).unwrap_or("unset"),
+                        theirs.map(String::as_str).unwrap_or("unset")
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Keys both requests set, to different values
+    pub fn conflicts_in_common(&self, other: &ClientTuning) -> Vec<String> {
+        self.settings
+            .iter()
+            .filter_map(|(key, ours)| {
+                let theirs = other.settings.get(key)?;
+                (ours != theirs).then(|| format!("{}: {} != {}", key, ours, theirs))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::parameters;
+
+    #[test]
+    fn test_client_tuning() {
+        let context = parameters(&[
+            ("source", "10.0.0.1@tcp:/lustre-fs"),
+            ("maxCachedMb", "4096"),
+            ("checksums", "false"),
+            ("maxDirtyMb", "512"),
+        ]);
+
+        let tuning = ClientTuning::from_volume_context(&context).unwrap();
+        assert_eq!(
+            tuning.params_for("ffff01"),
+            vec![
+                (
+                    "llite.*-ffff01.max_cached_mb".to_string(),
+                    "4096".to_string()
+                ),
+                ("llite.*-ffff01.checksums".to_string(), "0".to_string()),
+                ("osc.*-ffff01.max_dirty_mb".to_string(), "512".to_string()),
+            ]
+        );
+
+        let other =
+            ClientTuning::from_settings([("maxCachedMb".to_string(), "1024".to_string())].into());
+        assert_eq!(
+            tuning.conflicts_with(&other),
+            vec![
+                "checksums: 0 != unset",
+                "maxCachedMb: 4096 != 1024",
+                "maxDirtyMb: 512 != unset"
+            ]
+        );
+        assert!(tuning.conflicts_with(&tuning.clone()).is_empty());
+        assert_eq!(
+            tuning.conflicts_in_common(&other),
+            vec!["maxCachedMb: 4096 != 1024"]
+        );
+
+        let bad: HashMap<String, String> = [("statAheadMax".to_string(), "-1".to_string())].into();
+        assert!(ClientTuning::from_volume_context(&bad).is_err());
+    }
+}
@@ -5,9 +5,14 @@ use tracing::{error, info};
 use tracing_subscriber::layer::Layer;
 mod config;
 mod csi_types;
+mod jobs;
 mod lustre;
+mod provision;
+mod s3;
 mod server;
 mod services;
+mod state;
+mod tasks;
 mod utils;
 
 #[derive(Parser, Debug)]
@@ -29,6 +34,38 @@ struct Args {
     )]
     endpoint: String,
 
+    /// CSI services to serve: all, controller or node
+    #[arg(long, value_enum, default_value = "all", env = "DRIVER_MODE")]
+    mode: config::DriverMode,
+
+    /// Directory for driver-owned state on the node
+    #[arg(long, default_value = config::DEFAULT_PLUGIN_DIR, env = "PLUGIN_DIR")]
+    plugin_dir: String,
+
+    /// Orphaned Lustre mount collection: disabled, dry-run or enforce
+    #[arg(long, value_enum, default_value = "dry-run", env = "ORPHAN_GC")]
+    orphan_gc: config::GcMode,
+
+    /// Seconds between orphaned mount scans
+    #[arg(long, default_value_t = 300, env = "ORPHAN_GC_INTERVAL")]
+    orphan_gc_interval: u64,
+
+    /// Seconds between mount health checks (0 disables them)
+    #[arg(long, default_value_t = 60, env = "HEALTH_CHECK_INTERVAL")]
+    health_check_interval: u64,
+
+    /// Seconds before a mount health check counts as hung
+    #[arg(long, default_value_t = 10, env = "HEALTH_CHECK_TIMEOUT")]
+    health_check_timeout: u64,
+
+    /// Remount stale staging mounts in place
+    #[arg(long, default_value_t = false, env = "REMOUNT_STALE")]
+    remount_stale: bool,
+
+    /// JSON driver config file, e.g. with layout templates
+    #[arg(long, env = "CONFIG_FILE")]
+    config_file: Option<String>,
+
     /// Log level (trace, debug, info, warn, error)
     #[arg(long, default_value = "info", env = "LOG_LEVEL")]
     log_level: String,
@@ -36,9 +73,30 @@ struct Args {
     #[arg(long, default_value = "plain", env = "LOG_FORMAT")]
     log_format: String,
 
+    /// Host mount strategy: nsenter per command, or native setns + syscalls
+    #[arg(long, value_enum, default_value = "nsenter", env = "MOUNT_MODE")]
+    mount_mode: config::MountMode,
+
+    /// Lazily detach mounts that stay busy after a forced unmount
+    #[arg(long, default_value_t = false, env = "LAZY_UNMOUNT")]
+    lazy_unmount: bool,
+
     /// read RUST_LOG if present
     #[arg(long, default_value = "", env = "RUST_LOG")]
     _ignored_rust_log: String,
+
+    /// Run a one-off admin command instead of the CSI server
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Resync the stale mirrors of every file in a provisioned volume
+    MirrorResync {
+        /// Volume ID, e.g. 10.0.0.1@tcp:/lustre/k8s/pvc-1234
+        volume_id: String,
+    },
 }
 
 #[tokio::main]
@@ -57,13 +115,32 @@ async fn main() -> Result<()> {
     info!("Driver name: {}", args.driver_name);
     info!("Node ID: {}", args.node_id);
     info!("Endpoint: {}", args.endpoint);
+    info!("Driver mode: {:?}", args.mode);
+    info!("Mount mode: {:?}", args.mount_mode);
 
     // Create configuration
-    let config = config::Config::new(args.driver_name.clone(), args.node_id.clone());
+    let mut config = config::Config::new(args.driver_name.clone(), args.node_id.clone());
+    config.driver.plugin_dir = args.plugin_dir.clone();
+    config.driver.mode = args.mode;
+    config.lustre.mount_mode = args.mount_mode;
+    config.lustre.lazy_unmount = args.lazy_unmount;
+    config.orphan_gc.mode = args.orphan_gc;
+    config.orphan_gc.interval_secs = args.orphan_gc_interval;
+    config.health.interval_secs = args.health_check_interval;
+    config.health.timeout_secs = args.health_check_timeout;
+    config.health.remount = args.remount_stale;
+    if let Some(path) = &args.config_file {
+        info!("Loading config file: {}", path);
+        config.load_file(path)?;
+    }
+
+    if let Some(command) = &args.command {
+        return run_command(command, &config).await;
+    }
 
     // Start the CSI gRPC server
     info!("Initializing CSI gRPC server...");
-    let server = server::CSIServer::new(config)?;
+    let server = server::CSIServer::new(config).await?;
 
     info!("Server starting on {}", args.endpoint);
     if let Err(e) = server.start(&args.endpoint).await {
@@ -74,6 +151,30 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// Run an admin command against the Lustre client of this instance
+async fn run_command(command: &Command, config: &config::Config) -> Result<()> {
+    match command {
+        Command::MirrorResync { volume_id } => {
+            let volume = provision::VolumeSource::parse(volume_id)?;
+            let provisioner = provision::Provisioner::new(config, lustre::LustreClient::new());
+            let report = provisioner.resync_mirrors(&volume).await?;
+
+            for (file, e) in &report.failed {
+                error!("{}: {}", file, e);
+            }
+            if !report.failed.is_empty() {
+                anyhow::bail!(
+                    "{} of {} file(s) failed to resync",
+                    report.failed.len(),
+                    report.total
+                );
+            }
+            info!("Resynced {} file(s) of {}", report.resynced, volume);
+            Ok(())
+        }
+    }
+}
+
 pub fn setup_tracing(log_level: &str, log_format: &str) -> Result<()> {
     use std::io;
     use tracing_error::ErrorLayer;
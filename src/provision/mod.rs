@@ -0,0 +1,12 @@
+pub mod options;
+pub mod populate;
+pub mod provisioner;
+pub mod scratch;
+pub mod volume;
+
+// Re-export
+pub use options::{VolumeChanges, VolumeOptions};
+pub use populate::PopulateSource;
+pub use provisioner::{ArchiveBlocked, DeleteOutcome, Provisioner, SnapshotMarker, VolumeConflict};
+pub use scratch::ScratchSpace;
+pub use volume::VolumeSource;
@@ -0,0 +1,116 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::lustre::dirstripe::DirStripe;
+use crate::lustre::hsm::DeletePolicy;
+use crate::lustre::layout::{FsTopology, VolumeLayout};
+use crate::lustre::quota::ProjectQuota;
+
+/// Mutable parameter that rewrites existing files with a changed layout
+const MIGRATE_KEY: &str = "migrate";
+
+/// How a provisioned volume directory is laid out, from StorageClass parameters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VolumeOptions {
+    /// Default layout of files created in the directory
+    pub layout: VolumeLayout,
+    /// MDT placement and striping of the directory itself
+    pub dir_stripe: DirStripe,
+    /// Limits of the volume's project quota
+    pub quota: ProjectQuota,
+    /// Whether DeleteVolume removes or archives the volume
+    pub on_delete: DeletePolicy,
+}
+
+impl VolumeOptions {
+    pub fn from_parameters(
+        parameters: &HashMap<String, String>,
+        layout_templates: &BTreeMap<String, String>,
+    ) -> Result<Self> {
+        Ok(Self {
+            layout: VolumeLayout::from_parameters(parameters, layout_templates)?,
+            dir_stripe: DirStripe::from_parameters(parameters)?,
+            quota: ProjectQuota::from_parameters(parameters)?,
+            on_delete: DeletePolicy::from_parameters(parameters)?,
+        })
+    }
+
+    /// Whether validation or placement needs the filesystem's targets
+    pub fn needs_topology(&self) -> bool {
+        !self.layout.is_empty() || !self.dir_stripe.is_empty()
+    }
+
+    pub fn validate(&self, topology: &FsTopology) -> Result<()> {
+        self.layout.validate(topology)?;
+        self.dir_stripe.validate(&topology.mdts)
+    }
+
+    /// Oldest Lustre client that can apply these options, as (major, minor)
+    pub fn min_client_version(&self) -> Option<(u32, u32)> {
+        self.layout
+            .min_client_version()
+            .max(self.dir_stripe.min_client_version())
+    }
+
+    pub fn to_volume_context(&self, volume_context: &mut HashMap<String, String>) {
+        self.layout.to_volume_context(volume_context);
+        self.dir_stripe.to_volume_context(volume_context);
+        self.quota.to_volume_context(volume_context);
+        self.on_delete.to_volume_context(volume_context);
+    }
+}
+
+/// Changes to an existing volume, from VolumeAttributesClass parameters.
+///
+/// Only the default layout and the quota can change; the directory stays
+/// where it was created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VolumeChanges {
+    /// New default layout, if any layout parameter was given
+    pub layout: Option<VolumeLayout>,
+    pub quota: ProjectQuota,
+    /// Rewrite existing files with the new layout
+    pub migrate: bool,
+}
+
+impl VolumeChanges {
+    pub fn from_parameters(
+        parameters: &HashMap<String, String>,
+        layout_templates: &BTreeMap<String, String>,
+    ) -> Result<Self> {
+        if let Some(key) = parameters.keys().find(|key| {
+            key.as_str() != MIGRATE_KEY
+                && !VolumeLayout::is_layout_key(key)
+                && !ProjectQuota::is_quota_key(key)
+        }) {
+            anyhow::bail!("{} cannot be changed on an existing volume", key);
+        }
+
+        let layout = if parameters
+            .keys()
+            .any(|key| VolumeLayout::is_layout_key(key))
+        {
+            Some(VolumeLayout::from_parameters(parameters, layout_templates)?)
+        } else {
+            None
+        };
+        let migrate = match parameters.get(MIGRATE_KEY).map(|v| v.trim()) {
+            None | Some("false") => false,
+            Some("true") => true,
+            Some(other) => anyhow::bail!("{} must be true or false, got {:?}", MIGRATE_KEY, other),
+        };
+        if migrate && layout.is_none() {
+            anyhow::bail!("{} needs a new layout to migrate to", MIGRATE_KEY);
+        }
+
+        Ok(Self {
+            layout,
+            quota: ProjectQuota::from_parameters(parameters)?,
+            migrate,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layout.is_none() && self.quota.is_empty()
+    }
+}
@@ -0,0 +1,221 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+
+use super::volume::VolumeSource;
+
+const FROM_KEY: &str = "populateFrom";
+const ARCHIVE_KEY: &str = "populateArchive";
+const MODE_KEY: &str = "populateMode";
+const SHA256_KEY: &str = "populateSha256";
+
+/// What a new volume is seeded with before it is reported ready
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PopulateSource {
+    /// A directory, copied or, with `link`, hard-linked
+    Directory { path: FsPath, link: bool },
+    /// A tar archive, optionally gzip-compressed, and its expected SHA-256
+    Archive {
+        path: FsPath,
+        sha256: Option<String>,
+    },
+}
+
+/// A path on a filesystem known by name, written `fsname:/path`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsPath {
+    pub fsname: String,
+    /// Below the filesystem root, without leading slash
+    pub path: String,
+}
+
+impl FsPath {
+    fn parse(key: &str, value: &str) -> Result<Self> {
+        let (fsname, path) = value.trim().split_once(":/").with_context(|| {
+            format!("{} must be of the form fsname:/path, got {:?}", key, value)
+        })?;
+        let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        if fsname.is_empty() || fsname.contains('@') {
+            anyhow::bail!(
+                "{} must name a filesystem, not an MGS, got {:?}",
+                key,
+                value
+            );
+        }
+        if components.is_empty() || components.iter().any(|c| *c == "." || *c == "..") {
+            anyhow::bail!(
+                "{} needs a path below the filesystem root, got {:?}",
+                key,
+                value
+            );
+        }
+
+        Ok(Self {
+            fsname: fsname.to_string(),
+            path: components.join("/"),
+        })
+    }
+
+    /// The path on the filesystem of `parent` if it has this name, or else
+    /// on one from the `filesystems` catalog
+    pub fn resolve(
+        &self,
+        parent: &VolumeSource,
+        filesystems: &HashMap<String, String>,
+    ) -> Result<VolumeSource> {
+        let mgs = if self.fsname == parent.fsname {
+            &parent.mgs
+        } else {
+            filesystems.get(&self.fsname).with_context(|| {
+                format!(
+                    "Filesystem {} is neither the StorageClass's nor in the filesystems catalog",
+                    self.fsname
+                )
+            })?
+        };
+        VolumeSource::parse(&format!("{}:/{}/{}", mgs, self.fsname, self.path))
+    }
+}
+
+impl PopulateSource {
+    /// Read `populateFrom` or `populateArchive`, with `populateMode` (`copy`
+    /// or `link`) for directories and `populateSha256` for archives
+    pub fn from_parameters(parameters: &HashMap<String, String>) -> Result<Option<Self>> {
+        let from = parameters.get(FROM_KEY);
+        let archive = parameters.get(ARCHIVE_KEY);
+        let mode = parameters.get(MODE_KEY).map(|v| v.trim());
+        let sha256 = parameters
+            .get(SHA256_KEY)
+            .map(|v| v.trim().to_ascii_lowercase());
+
+        match (from, archive) {
+            (Some(_), Some(_)) => {
+                anyhow::bail!("{} and {} cannot be used together", FROM_KEY, ARCHIVE_KEY)
+            }
+            (Some(from), None) => {
+                if sha256.is_some() {
+                    anyhow::bail!("{} needs {}", SHA256_KEY, ARCHIVE_KEY);
+                }
+                let link = match mode {
+                    None | Some("copy") => false,
+                    Some("link") => true,
+                    Some(other) => {
+                        anyhow::bail!("{} must be copy or link, got {:?}", MODE_KEY, other)
+                    }
+                };
+                Ok(Some(Self::Directory {
+                    path: FsPath::parse(FROM_KEY, from)?,
+                    link,
+                }))
+            }
+            (None, Some(archive)) => {
+                if mode.is_some() {
+                    anyhow::bail!("{} needs {}", MODE_KEY, FROM_KEY);
+                }
+                if let Some(sha256) = &sha256
+                    && (sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()))
+                {
+                    anyhow::bail!("{} must be a hex SHA-256, got {:?}", SHA256_KEY, sha256);
+                }
+                Ok(Some(Self::Archive {
+                    path: FsPath::parse(ARCHIVE_KEY, archive)?,
+                    sha256,
+                }))
+            }
+            (None, None) if mode.is_some() => anyhow::bail!("{} needs {}", MODE_KEY, FROM_KEY),
+            (None, None) if sha256.is_some() => {
+                anyhow::bail!("{} needs {}", SHA256_KEY, ARCHIVE_KEY)
+            }
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::parameters;
+
+    fn parent() -> VolumeSource {
+        VolumeSource::parse("10.0.0.1@tcp:/lustre/k8s").unwrap()
+    }
+
+    fn filesystems() -> HashMap<String, String> {
+        parameters(&[("datasets", "10.0.0.5@tcp")])
+    }
+
+    #[test]
+    fn test_populate_from_directory() {
+        let source = PopulateSource::from_parameters(&parameters(&[
+            ("populateFrom", "lustre:/reference/imagenet/"),
+            ("populateMode", "link"),
+        ]))
+        .unwrap()
+        .unwrap();
+        let PopulateSource::Directory { path, link } = source else {
+            panic!("not a directory: {:?}", source);
+        };
+        assert!(link);
+        assert_eq!(
+            path.resolve(&parent(), &filesystems()).unwrap().to_string(),
+            "10.0.0.1@tcp:/lustre/reference/imagenet"
+        );
+    }
+
+    #[test]
+    fn test_populate_from_archive() {
+        let source = PopulateSource::from_parameters(&parameters(&[
+            ("populateArchive", "datasets:/archives/ref.tar.gz"),
+            ("populateSha256", &"AB".repeat(32)),
+        ]))
+        .unwrap()
+        .unwrap();
+        let PopulateSource::Archive { path, sha256 } = source else {
+            panic!("not an archive: {:?}", source);
+        };
+        assert_eq!(sha256, Some("ab".repeat(32)));
+        assert_eq!(
+            path.resolve(&parent(), &filesystems()).unwrap().to_string(),
+            "10.0.0.5@tcp:/datasets/archives/ref.tar.gz"
+        );
+    }
+
+    #[test]
+    fn test_populate_from_unknown_filesystem() {
+        let unknown = FsPath::parse(FROM_KEY, "scratch:/data").unwrap();
+        assert!(unknown.resolve(&parent(), &filesystems()).is_err());
+    }
+
+    #[test]
+    fn test_invalid_populate_parameters() {
+        assert_eq!(
+            PopulateSource::from_parameters(&parameters(&[])).unwrap(),
+            None
+        );
+        for bad in [
+            &[
+                ("populateFrom", "lustre:/a"),
+                ("populateArchive", "lustre:/b.tar"),
+            ][..],
+            &[("populateFrom", "lustre:/")],
+            &[("populateFrom", "lustre:/a/../b")],
+            &[("populateFrom", "10.0.0.1@tcp:/lustre/a")],
+            &[("populateFrom", "lustre:/a"), ("populateMode", "move")],
+            &[("populateFrom", "lustre:/a"), ("populateSha256", "ab")],
+            &[
+                ("populateArchive", "lustre:/b.tar"),
+                ("populateSha256", "ab"),
+            ],
+            &[
+                ("populateArchive", "lustre:/b.tar"),
+                ("populateMode", "link"),
+            ],
+            &[("populateMode", "copy")],
+        ] {
+            assert!(
+                PopulateSource::from_parameters(&parameters(bad)).is_err(),
+                "{:?}",
+                bad
+            );
+        }
+    }
+}
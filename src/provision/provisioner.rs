@@ -0,0 +1,706 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+use super::options::{VolumeChanges, VolumeOptions};
+use super::volume::VolumeSource;
+use crate::config::Config;
+use crate::jobs::store::now;
+use crate::jobs::tree;
+use crate::lustre::dirstripe::MdtPlacement;
+use crate::lustre::health::host_path;
+use crate::lustre::hsm::DeletePolicy;
+use crate::lustre::layout::FsTopology;
+use crate::lustre::quota::{
+    PROJECT_ID_XATTR, ProjectQuota, ProjectUsage, next_project_id, project_id,
+};
+use crate::lustre::{LustreClient, MountManager};
+use crate::utils::xattr;
+
+/// Upper bound for a single `lfs` call made while provisioning
+const LFS_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Upper bound for scanning a volume or resyncing one file, both of which
+/// scale with the data involved
+const SCAN_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// How long DeleteVolume waits for the copytool to archive a volume
+/// before reporting the files it has not archived as a failure
+const ARCHIVE_DEADLINE: Duration = Duration::from_secs(24 * 3600);
+
+/// When archiving of a volume started, in seconds since the Unix epoch
+const ARCHIVE_STARTED_XATTR: &str = "trusted.klustre_csi.archive_started";
+
+/// [`SnapshotMarker`] of a snapshot directory whose copy is complete
+const SNAPSHOT_MARKER_XATTR: &str = "trusted.klustre_csi.snapshot";
+
+/// Files listed in an [`ArchiveBlocked`] message
+const BLOCKED_FILES_SHOWN: usize = 10;
+
+/// Project IDs tried before giving up on finding an unused one
+const PROJECT_ID_ATTEMPTS: usize = 64;
+
+/// Request a volume directory was created for, to tell retries from
+/// conflicting requests for the same name
+const CREATE_REQUEST_XATTR: &str = "trusted.klustre_csi.create_request";
+
+/// A volume exists, but was created for a different request
+#[derive(Debug)]
+pub struct VolumeConflict {
+    pub volume: String,
+}
+
+impl std::fmt::Display for VolumeConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Volume {} already exists with different parameters",
+            self.volume
+        )
+    }
+}
+
+impl std::error::Error for VolumeConflict {}
+
+/// Files of a volume keep DeleteVolume from archiving it: they cannot be
+/// archived at all, or the copytool did not archive them in time
+#[derive(Debug)]
+pub struct ArchiveBlocked {
+    pub volume: String,
+    pub reason: String,
+    pub files: Vec<String>,
+}
+
+impl std::fmt::Display for ArchiveBlocked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} file(s) of {} {}: {}",
+            self.files.len(),
+            self.volume,
+            self.reason,
+            self.files[..self.files.len().min(BLOCKED_FILES_SHOWN)].join(", ")
+        )?;
+        if self.files.len() > BLOCKED_FILES_SHOWN {
+            write!(f, " and {} more", self.files.len() - BLOCKED_FILES_SHOWN)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ArchiveBlocked {}
+
+/// Written onto a snapshot directory once its copy is complete, so the
+/// snapshot can be restored and listed without the controller's job records
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotMarker {
+    pub source_volume_id: String,
+    /// Seconds since the Unix epoch
+    pub created_at: u64,
+    /// Size of the copied files
+    pub bytes: u64,
+}
+
+/// Outcome of resynchronizing the mirrors of one volume
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResyncReport {
+    /// Files found with a stale mirror
+    pub total: usize,
+    pub resynced: usize,
+    /// Files whose resync failed, with the error
+    pub failed: Vec<(String, String)>,
+}
+
+/// What deleting a volume did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeleteOutcome {
+    Deleted,
+    /// Files are still being archived; nothing was released yet
+    Archiving {
+        pending: usize,
+    },
+    /// Every file is archived and released; the directory is kept
+    Released {
+        files: usize,
+    },
+}
+
+/// Creates and deletes volume directories on Lustre filesystems.
+///
+/// The controller keeps one client mount per filesystem under
+/// `<plugin_dir>/controller`, made on first use and shared by all volumes.
+#[derive(Debug, Clone)]
+pub struct Provisioner {
+    mount_manager: MountManager,
+    lustre_client: LustreClient,
+    mounts_dir: String,
+    mount_options: Vec<String>,
+    /// Serializes filesystem mounts so concurrent requests mount only once
+    mounting: Arc<Mutex<()>>,
+    /// Round-robin position for `mdtPlacement: roundRobin`
+    next_mdt: Arc<AtomicUsize>,
+    /// Serializes project ID allocation so two volumes never pick the same
+    /// unused ID
+    allocating: Arc<Mutex<()>>,
+}
+
+impl Provisioner {
+    pub fn new(config: &Config, lustre_client: LustreClient) -> Self {
+        let mounts_dir = Path::new(&config.driver.plugin_dir)
+            .join("controller")
+            .to_string_lossy()
+            .into_owned();
+        info!("Provisioner mounts filesystems under {}", mounts_dir);
+
+        Self {
+            mount_manager: MountManager::new(&config.lustre),
+            lustre_client,
+            mounts_dir,
+            mount_options: config.lustre.default_mount_options.clone(),
+            mounting: Arc::new(Mutex::new(())),
+            next_mdt: Arc::new(AtomicUsize::new(0)),
+            allocating: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Mount point of the filesystem holding `volume`, mounting it if needed
+    async fn filesystem_root(&self, volume: &VolumeSource) -> Result<String> {
+        let root = format!("{}/{}", self.mounts_dir, volume.mount_name());
+
+        let _mounting = self.mounting.lock().await;
+        self.mount_manager
+            .mount(&volume.filesystem(), &root, &self.mount_options)
+            .await
+            .with_context(|| format!("Failed to mount {}", volume.filesystem()))?;
+
+        Ok(root)
+    }
+
+    /// Path of `volume` below the controller's filesystem mount
+    async fn volume_path(&self, volume: &VolumeSource) -> Result<String> {
+        let root = self.filesystem_root(volume).await?;
+        Ok(format!("{}/{}", root, volume.subdir))
+    }
+
+    /// Host path of `volume` below the controller's filesystem mount, for
+    /// direct file access
+    pub async fn host_volume_path(&self, volume: &VolumeSource) -> Result<PathBuf> {
+        Ok(host_path(&self.volume_path(volume).await?))
+    }
+
+    /// OSTs and pools of the filesystem holding `volume`
+    pub async fn topology(&self, volume: &VolumeSource) -> Result<FsTopology> {
+        let root = self.filesystem_root(volume).await?;
+        let topology = self
+            .lustre_client
+            .topology(&root, &volume.fsname, LFS_TIMEOUT)
+            .await?;
+        debug!("Topology of {}: {:?}", volume.fsname, topology);
+        Ok(topology)
+    }
+
+    /// Version of the Lustre client the controller runs
+    pub async fn client_version(&self) -> Result<(u32, u32)> {
+        self.lustre_client.client_version(LFS_TIMEOUT).await
+    }
+
+    /// Create the directory for a volume called `name` below `parent`.
+    ///
+    /// `topology` is needed for round-robin MDT placement. Creating an
+    /// existing volume again for the same `request` succeeds, so retried
+    /// requests are harmless; a different one fails with [`VolumeConflict`].
+    pub async fn create_volume(
+        &self,
+        parent: &VolumeSource,
+        name: &str,
+        options: &VolumeOptions,
+        topology: Option<&FsTopology>,
+        request: &str,
+    ) -> Result<VolumeSource> {
+        let volume = parent.child(name)?;
+        let path = self.volume_path(&volume).await?;
+        let host = host_path(&path);
+
+        if tokio::fs::try_exists(&host).await?
+            && let Some(created_for) = xattr::get(&host, CREATE_REQUEST_XATTR)?
+            && created_for != request.as_bytes()
+        {
+            return Err(VolumeConflict {
+                volume: volume.to_string(),
+            }
+            .into());
+        }
+
+        if options.dir_stripe.is_empty() {
+            tokio::fs::create_dir_all(&host)
+                .await
+                .with_context(|| format!("Failed to create volume directory {}", path))?;
+        } else if !tokio::fs::try_exists(&host).await? {
+            if let Some(parent_dir) = host.parent() {
+                tokio::fs::create_dir_all(parent_dir)
+                    .await
+                    .with_context(|| format!("Failed to create parent of {}", path))?;
+            }
+            let mdt_index = self.next_mdt_index(options, topology);
+            self.lustre_client
+                .make_dir(&path, &options.dir_stripe, mdt_index, LFS_TIMEOUT)
+                .await?;
+        }
+
+        self.lustre_client
+            .set_default_layout(&path, &options.layout, LFS_TIMEOUT)
+            .await?;
+        self.set_quota(&volume, &options.quota).await?;
+        options.on_delete.store(&host)?;
+        xattr::set(&host, CREATE_REQUEST_XATTR, request.as_bytes())
+            .with_context(|| format!("Failed to record the request for {}", volume))?;
+
+        info!("Created volume directory {}", volume);
+        Ok(volume)
+    }
+
+    /// MDT for the next round-robin placed volume
+    fn next_mdt_index(
+        &self,
+        options: &VolumeOptions,
+        topology: Option<&FsTopology>,
+    ) -> Option<i64> {
+        if options.dir_stripe.placement != Some(MdtPlacement::RoundRobin) {
+            return None;
+        }
+        let mdts = &topology?.mdts;
+        if mdts.is_empty() {
+            return None;
+        }
+        let next = self.next_mdt.fetch_add(1, Ordering::Relaxed);
+        Some(mdts[next % mdts.len()] as i64)
+    }
+
+    /// Resync every file of a volume that has a stale mirror.
+    ///
+    /// Progress is logged in steps of about a tenth of the files; a failing
+    /// file is recorded and the rest are still processed.
+    pub async fn resync_mirrors(&self, volume: &VolumeSource) -> Result<ResyncReport> {
+        let path = self.volume_path(volume).await?;
+        let files = self
+            .lustre_client
+            .find_stale_mirrors(&path, SCAN_TIMEOUT)
+            .await?;
+
+        let mut report = ResyncReport {
+            total: files.len(),
+            ..Default::default()
+        };
+        info!(
+            "Found {} file(s) with stale mirrors in {}",
+            report.total, volume
+        );
+
+        let step = (report.total / 10).max(1);
+        for (done, file) in files.iter().enumerate() {
+            match self.lustre_client.mirror_resync(file, SCAN_TIMEOUT).await {
+                Ok(()) => {
+                    debug!("Resynced {}", file);
+                    report.resynced += 1;
+                }
+                Err(e) => {
+                    warn!("Failed to resync {}: {:#}", file, e);
+                    report.failed.push((file.clone(), format!("{:#}", e)));
+                }
+            }
+
+            let done = done + 1;
+            if done % step == 0 || done == report.total {
+                info!(
+                    "Mirror resync of {}: {}/{} file(s) processed, {} failed",
+                    volume,
+                    done,
+                    report.total,
+                    report.failed.len()
+                );
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Regular files of a volume, as paths below the host root
+    pub async fn list_files(&self, volume: &VolumeSource) -> Result<Vec<String>> {
+        let path = self.volume_path(volume).await?;
+        self.lustre_client.find_files(&path, SCAN_TIMEOUT).await
+    }
+
+    /// Rewrite one file of a volume with new layout arguments
+    pub async fn migrate_file(&self, file: &str, layout_args: &[String]) -> Result<()> {
+        self.lustre_client
+            .migrate(file, layout_args, SCAN_TIMEOUT)
+            .await
+    }
+
+    /// Whether the directory of `volume` exists
+    pub async fn volume_exists(&self, volume: &VolumeSource) -> Result<bool> {
+        let path = self.volume_path(volume).await?;
+        Ok(tokio::fs::try_exists(host_path(&path)).await?)
+    }
+
+    /// Create the empty directory of a snapshot. The snapshot area is
+    /// read-only (0555) and completed snapshots are immutable, see
+    /// [`Self::complete_snapshot`]; modes alone would not stop root in a pod
+    /// that mounts the filesystem root.
+    pub async fn create_snapshot_dir(&self, snapshot: &VolumeSource) -> Result<()> {
+        let host = self.host_volume_path(snapshot).await?;
+        let area = host.parent().context("Snapshot has no snapshot area")?;
+
+        tokio::fs::create_dir_all(&host)
+            .await
+            .with_context(|| format!("Failed to create snapshot directory {}", snapshot))?;
+        tokio::fs::set_permissions(area, std::fs::Permissions::from_mode(0o555))
+            .await
+            .with_context(|| format!("Failed to make {} read-only", area.display()))?;
+
+        info!("Created snapshot directory {}", snapshot);
+        Ok(())
+    }
+
+    /// Mark a snapshot whose copy finished as complete and make its tree
+    /// immutable, so that not even root can change it.
+    ///
+    /// Everything below the directory is frozen before the marker is
+    /// written and the directory itself after it, so a marked snapshot is
+    /// always a frozen one; on failure both are undone and the copy can run
+    /// again.
+    pub async fn complete_snapshot(
+        &self,
+        snapshot: &VolumeSource,
+        marker: &SnapshotMarker,
+    ) -> Result<()> {
+        let host = self.host_volume_path(snapshot).await?;
+        let value = serde_json::to_vec(marker)?;
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            let frozen = tree::set_immutable_below(&host, true)
+                .and_then(|()| xattr::set(&host, SNAPSHOT_MARKER_XATTR, &value))
+                .and_then(|()| tree::set_immutable(&host, true));
+            if frozen.is_err() {
+                let thawed = tree::set_immutable(&host, false)
+                    .and_then(|()| xattr::remove(&host, SNAPSHOT_MARKER_XATTR))
+                    .and_then(|()| tree::set_immutable_below(&host, false));
+                if let Err(e) = thawed {
+                    warn!("Failed to unfreeze {}: {:#}", host.display(), e);
+                }
+            }
+            frozen
+        })
+        .await
+        .context("Snapshot task panicked")?
+        .with_context(|| format!("Failed to complete snapshot {}", snapshot))?;
+
+        info!("Snapshot {} is complete and immutable", snapshot);
+        Ok(())
+    }
+
+    /// Marker of a completed snapshot, `None` while it is being copied or
+    /// if its directory is gone
+    pub async fn snapshot_marker(&self, snapshot: &VolumeSource) -> Result<Option<SnapshotMarker>> {
+        let host = self.host_volume_path(snapshot).await?;
+        if !tokio::fs::try_exists(&host).await? {
+            return Ok(None);
+        }
+        let value = tokio::task::spawn_blocking(move || xattr::get(&host, SNAPSHOT_MARKER_XATTR))
+            .await
+            .context("Snapshot task panicked")??;
+        value
+            .map(|value| {
+                serde_json::from_slice(&value)
+                    .with_context(|| format!("Invalid marker on snapshot {}", snapshot))
+            })
+            .transpose()
+    }
+
+    /// Remove a snapshot directory and everything in it, lifting the
+    /// immutable flags of a completed snapshot first
+    pub async fn delete_snapshot(&self, snapshot: &VolumeSource) -> Result<()> {
+        if !snapshot.is_snapshot() {
+            anyhow::bail!("{} is not a snapshot", snapshot);
+        }
+
+        let host = self.host_volume_path(snapshot).await?;
+        if !tokio::fs::try_exists(&host).await? {
+            warn!("Snapshot directory {} is already gone", snapshot);
+            return Ok(());
+        }
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            tree::set_immutable(&host, false)?;
+            tree::set_immutable_below(&host, false)?;
+            std::fs::remove_dir_all(&host)?;
+            Ok(())
+        })
+        .await
+        .context("Snapshot task panicked")?
+        .with_context(|| format!("Failed to delete {}", snapshot))?;
+
+        info!("Deleted snapshot directory {}", snapshot);
+        Ok(())
+    }
+
+    /// Apply a new default layout and/or quota to an existing volume.
+    ///
+    /// Files already in the volume keep their layout.
+    pub async fn modify_volume(
+        &self,
+        volume: &VolumeSource,
+        changes: &VolumeChanges,
+    ) -> Result<()> {
+        let path = self.volume_path(volume).await?;
+        if let Some(layout) = &changes.layout {
+            self.lustre_client
+                .set_default_layout(&path, layout, LFS_TIMEOUT)
+                .await?;
+        }
+        self.set_quota(volume, &changes.quota).await?;
+
+        info!("Modified volume {}", volume);
+        Ok(())
+    }
+
+    /// Account a volume to its own project and set the project's limits
+    async fn set_quota(&self, volume: &VolumeSource, quota: &ProjectQuota) -> Result<()> {
+        if quota.is_empty() {
+            return Ok(());
+        }
+
+        let root = self.filesystem_root(volume).await?;
+        let path = format!("{}/{}", root, volume.subdir);
+        let project_id = self.volume_project(volume, &root, &path).await?;
+
+        // Tagging is recursive, so it scales with the files in the volume
+        self.lustre_client
+            .set_project(&path, project_id, true, SCAN_TIMEOUT)
+            .await?;
+        self.lustre_client
+            .set_project_quota(&root, project_id, quota, LFS_TIMEOUT)
+            .await
+    }
+
+    /// Project ID of a volume, allocating an unused one on first use
+    async fn volume_project(&self, volume: &VolumeSource, root: &str, path: &str) -> Result<u32> {
+        let host = host_path(path);
+        if let Some(id) = stored_project_id(&host)? {
+            return Ok(id);
+        }
+
+        let _allocating = self.allocating.lock().await;
+        let mut candidate = project_id(&volume.to_string());
+        for _ in 0..PROJECT_ID_ATTEMPTS {
+            if !self
+                .lustre_client
+                .project_usage(root, candidate, LFS_TIMEOUT)
+                .await?
+                .is_in_use()
+            {
+                // Tagging the directory itself makes the project show as
+                // used before the next allocation looks at it
+                self.lustre_client
+                    .set_project(path, candidate, false, LFS_TIMEOUT)
+                    .await?;
+                xattr::set(&host, PROJECT_ID_XATTR, candidate.to_string().as_bytes())
+                    .with_context(|| format!("Failed to record the project of {}", volume))?;
+                info!("Allocated project {} to {}", candidate, volume);
+                return Ok(candidate);
+            }
+            debug!("Project {} is in use, trying the next one", candidate);
+            candidate = next_project_id(candidate);
+        }
+        anyhow::bail!(
+            "No unused project ID found for {} after {} attempts",
+            volume,
+            PROJECT_ID_ATTEMPTS
+        )
+    }
+
+    /// Usage and limits of a volume's project, `None` if it has none
+    pub async fn volume_quota(&self, volume: &VolumeSource) -> Result<Option<ProjectUsage>> {
+        let root = self.filesystem_root(volume).await?;
+        let path = format!("{}/{}", root, volume.subdir);
+        let Some(project_id) = stored_project_id(&host_path(&path))? else {
+            return Ok(None);
+        };
+        let usage = self
+            .lustre_client
+            .project_usage(&root, project_id, LFS_TIMEOUT)
+            .await?;
+        Ok(Some(usage))
+    }
+
+    /// The request a volume was created for, as recorded by
+    /// [`Self::create_volume`]
+    pub async fn created_for(&self, volume: &VolumeSource) -> Result<Option<String>> {
+        let path = self.volume_path(volume).await?;
+        let request = xattr::get(&host_path(&path), CREATE_REQUEST_XATTR)?;
+        Ok(request.map(|request| String::from_utf8_lossy(&request).into_owned()))
+    }
+
+    /// Remove the limits of a volume's project, if it was given one, so the
+    /// ID can be allocated again
+    async fn reset_quota(&self, volume: &VolumeSource, project_id: Option<u32>) -> Result<()> {
+        let Some(project_id) = project_id else {
+            return Ok(());
+        };
+        let root = self.filesystem_root(volume).await?;
+        self.lustre_client
+            .set_project_quota(&root, project_id, &ProjectQuota::unlimited(), LFS_TIMEOUT)
+            .await
+    }
+
+    /// Remove a volume directory and everything in it, or archive and
+    /// release its files if it was created with `hsmOnDelete: archive`
+    pub async fn delete_volume(&self, volume: &VolumeSource) -> Result<DeleteOutcome> {
+        if volume.subdir.is_empty() {
+            anyhow::bail!("Refusing to delete the root of {}", volume.fsname);
+        }
+
+        let path = self.volume_path(volume).await?;
+        let host = host_path(&path);
+        if !tokio::fs::try_exists(&host).await? {
+            warn!("Volume directory {} is already gone", volume);
+            return Ok(DeleteOutcome::Deleted);
+        }
+
+        let project_id = stored_project_id(&host)?;
+        if let DeletePolicy::Archive { archive_id } = DeletePolicy::load(&host)? {
+            let outcome = self.archive_volume(volume, &path, archive_id).await?;
+            if let DeleteOutcome::Released { .. } = outcome {
+                self.reset_quota(volume, project_id).await?;
+            }
+            return Ok(outcome);
+        }
+
+        // Before removing the directory, which is what holds the project ID
+        self.reset_quota(volume, project_id).await?;
+        match tokio::fs::remove_dir_all(&host).await {
+            Ok(()) => info!("Deleted volume directory {}", volume),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                warn!("Volume directory {} is already gone", volume)
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to delete {}", path));
+            }
+        }
+
+        Ok(DeleteOutcome::Deleted)
+    }
+
+    /// Archive every file of a volume, then release them once all are.
+    ///
+    /// Archiving is done by the copytool in its own time, so this only
+    /// queues requests and reports what is still pending; the caller is
+    /// expected to come back later. Files that can never be archived, or
+    /// are still not archived after [`ARCHIVE_DEADLINE`], fail with
+    /// [`ArchiveBlocked`].
+    async fn archive_volume(
+        &self,
+        volume: &VolumeSource,
+        path: &str,
+        archive_id: Option<u32>,
+    ) -> Result<DeleteOutcome> {
+        let files = self.lustre_client.find_files(path, SCAN_TIMEOUT).await?;
+        let states = self.lustre_client.hsm_state(&files, SCAN_TIMEOUT).await?;
+
+        let unarchived = |can_archive: bool| -> Vec<String> {
+            states
+                .iter()
+                .filter(|(_, state)| !state.is_archived() && state.can_archive() == can_archive)
+                .map(|(file, _)| file.clone())
+                .collect()
+        };
+        let blocked = unarchived(false);
+        if !blocked.is_empty() {
+            return Err(ArchiveBlocked {
+                volume: volume.to_string(),
+                reason: "are lost or marked noarchive".to_string(),
+                files: blocked,
+            }
+            .into());
+        }
+
+        let pending = unarchived(true);
+        if !pending.is_empty() {
+            // Files with an archive request in flight are refused again;
+            // they are picked up by the next attempt if still pending
+            if let Err(e) = self
+                .lustre_client
+                .hsm_archive(&pending, archive_id, SCAN_TIMEOUT)
+                .await
+            {
+                warn!("Failed to queue archiving of {}: {:#}", volume, e);
+            }
+
+            // Requests keep being queued past the deadline, so a fixed
+            // copytool still gets the volume archived
+            let started = archive_started(&host_path(path))?;
+            if now().saturating_sub(started) > ARCHIVE_DEADLINE.as_secs() {
+                return Err(ArchiveBlocked {
+                    volume: volume.to_string(),
+                    reason: format!("are still not archived after {:?}", ARCHIVE_DEADLINE),
+                    files: pending,
+                }
+                .into());
+            }
+            info!(
+                "Waiting for {} of {} file(s) of {} to be archived",
+                pending.len(),
+                states.len(),
+                volume
+            );
+            return Ok(DeleteOutcome::Archiving {
+                pending: pending.len(),
+            });
+        }
+
+        let online: Vec<String> = states
+            .iter()
+            .filter(|(_, state)| !state.released)
+            .map(|(file, _)| file.clone())
+            .collect();
+        self.lustre_client
+            .hsm_release(&online, SCAN_TIMEOUT)
+            .await?;
+
+        info!("Released {} archived file(s) of {}", online.len(), volume);
+        Ok(DeleteOutcome::Released {
+            files: states.len(),
+        })
+    }
+}
+
+/// Project ID recorded on a volume directory by an earlier allocation
+fn stored_project_id(dir: &Path) -> Result<Option<u32>> {
+    let Some(value) = xattr::get(dir, PROJECT_ID_XATTR)? else {
+        return Ok(None);
+    };
+    let value = String::from_utf8_lossy(&value);
+    let id = value
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid project ID {:?} on {}", value, dir.display()))?;
+    Ok(Some(id))
+}
+
+/// When archiving of a volume directory started, recording now if this is
+/// the first attempt
+fn archive_started(dir: &Path) -> Result<u64> {
+    if let Some(value) = xattr::get(dir, ARCHIVE_STARTED_XATTR)?
+        && let Ok(started) = String::from_utf8_lossy(&value).trim().parse()
+    {
+        return Ok(started);
+    }
+    let started = now();
+    xattr::set(dir, ARCHIVE_STARTED_XATTR, started.to_string().as_bytes())
+        .with_context(|| format!("Failed to record archive start on {}", dir.display()))?;
+    Ok(started)
+}
@@ -0,0 +1,179 @@
+use anyhow::{Context, Result};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use tracing::info;
+
+use super::volume::VolumeSource;
+use crate::config::Config;
+use crate::lustre::MountManager;
+
+/// Per-pod scratch directories of ephemeral inline volumes, below a base
+/// directory on a catalog filesystem.
+///
+/// The node mounts the filesystem once under `<plugin_dir>/scratch` to make
+/// and remove the directories; pods get their own directory mounted.
+#[derive(Debug, Clone)]
+pub struct ScratchSpace {
+    mount_manager: MountManager,
+    base: VolumeSource,
+    mount_point: String,
+    mount_options: Vec<String>,
+    /// How long a directory is kept after its volume was unpublished
+    pub grace_period: Duration,
+    /// Host path of the node's mount of the filesystem, once mounted
+    root: Arc<OnceCell<PathBuf>>,
+}
+
+impl ScratchSpace {
+    /// Scratch space of the node, `None` if none is configured
+    pub fn new(config: &Config, mount_manager: MountManager) -> Result<Option<Self>> {
+        let Some(scratch) = &config.scratch else {
+            return Ok(None);
+        };
+        let mgs = config
+            .lustre
+            .filesystem_mapping
+            .get(&scratch.filesystem)
+            .with_context(|| {
+                format!(
+                    "Scratch filesystem {} is not in the filesystems catalog",
+                    scratch.filesystem
+                )
+            })?;
+        let base =
+            VolumeSource::parse(&format!("{}:/{}/{}", mgs, scratch.filesystem, scratch.path))?;
+        let mount_point = Path::new(&config.driver.plugin_dir)
+            .join("scratch")
+            .join(base.mount_name())
+            .to_string_lossy()
+            .into_owned();
+        info!("Ephemeral volumes get scratch directories below {}", base);
+
+        Ok(Some(Self {
+            mount_manager,
+            base,
+            mount_point,
+            mount_options: config.lustre.default_mount_options.clone(),
+            grace_period: Duration::from_secs(scratch.grace_period_secs),
+            root: Arc::new(OnceCell::new()),
+        }))
+    }
+
+    /// Scratch directory of the ephemeral volume `volume_id`
+    pub fn dir(&self, volume_id: &str) -> Result<VolumeSource> {
+        self.base.child(volume_id)
+    }
+
+    /// Whether `dir` is one of the scratch directories, and nothing else
+    /// that could be removed
+    pub fn contains(&self, dir: &VolumeSource) -> bool {
+        dir.filesystem() == self.base.filesystem()
+            && dir
+                .subdir
+                .rsplit_once('/')
+                .is_some_and(|(parent, _)| parent == self.base.subdir)
+    }
+
+    /// Create the scratch directory of `volume_id`, writable by any user as
+    /// pods may not run as root. The sticky bit keeps users from removing
+    /// each other's files.
+    pub async fn create(&self, volume_id: &str) -> Result<VolumeSource> {
+        let dir = self.dir(volume_id)?;
+        let path = self.host_path(&dir).await?;
+        tokio::fs::create_dir_all(&path)
+            .await
+            .with_context(|| format!("Failed to create scratch directory {}", dir))?;
+        tokio::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o1777))
+            .await
+            .with_context(|| format!("Failed to open up scratch directory {}", dir))?;
+        Ok(dir)
+    }
+
+    /// Remove a scratch directory and everything in it
+    pub async fn delete(&self, dir: &VolumeSource) -> Result<()> {
+        if !self.contains(dir) {
+            anyhow::bail!("{} is not a scratch directory below {}", dir, self.base);
+        }
+        let path = self.host_path(dir).await?;
+        match tokio::fs::remove_dir_all(&path).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("Failed to remove {}", dir)),
+        }
+        info!("Deleted scratch directory {}", dir);
+        Ok(())
+    }
+
+    /// Host path of `dir` below the node's mount of the filesystem, mounting
+    /// it on first use
+    async fn host_path(&self, dir: &VolumeSource) -> Result<PathBuf> {
+        let root = self
+            .root
+            .get_or_try_init(|| async {
+                self.mount_manager
+                    .mount(
+                        &self.base.filesystem(),
+                        &self.mount_point,
+                        &self.mount_options,
+                    )
+                    .await
+                    .with_context(|| format!("Failed to mount {}", self.base.filesystem()))?;
+                anyhow::Ok(self.mount_manager.host_path(&self.mount_point))
+            })
+            .await?;
+        Ok(root.join(&dir.subdir))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ScratchConfig;
+
+    #[test]
+    fn test_scratch_dirs() {
+        let mut config = Config::new("lustre.csi.klustrefs.io".into(), "node-1".into());
+        let mount_manager = MountManager::new(&config.lustre);
+        assert!(
+            ScratchSpace::new(&config, mount_manager.clone())
+                .unwrap()
+                .is_none()
+        );
+
+        config.scratch = Some(ScratchConfig {
+            filesystem: "scratch".to_string(),
+            path: "k8s/ephemeral".to_string(),
+            grace_period_secs: 60,
+        });
+        assert!(ScratchSpace::new(&config, mount_manager.clone()).is_err());
+
+        config
+            .lustre
+            .filesystem_mapping
+            .insert("scratch".to_string(), "10.0.0.9@tcp".to_string());
+        let scratch = ScratchSpace::new(&config, mount_manager).unwrap().unwrap();
+        let dir = scratch.dir("csi-4f2a").unwrap();
+        assert_eq!(
+            dir.to_string(),
+            "10.0.0.9@tcp:/scratch/k8s/ephemeral/csi-4f2a"
+        );
+        assert!(scratch.contains(&dir));
+        assert!(scratch.dir("../etc").is_err());
+
+        for other in [
+            "10.0.0.9@tcp:/scratch/k8s/ephemeral",
+            "10.0.0.9@tcp:/scratch/k8s/other/csi-4f2a",
+            "10.0.0.9@tcp:/scratch/k8s/ephemeral/csi-4f2a/nested",
+            "10.0.0.1@tcp:/scratch/k8s/ephemeral/csi-4f2a",
+        ] {
+            assert!(
+                !scratch.contains(&VolumeSource::parse(other).unwrap()),
+                "{}",
+                other
+            );
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use anyhow::{Context, Result};
+use std::fmt;
+
+/// Directory next to the volumes of a StorageClass that holds their
+/// snapshots
+const SNAPSHOT_AREA: &str = ".klustre-snapshots";
+
+/// A directory on a Lustre filesystem, written `mgs@net:/fsname[/subdir]`.
+///
+/// Provisioned volumes use this form as their volume ID and as the `source`
+/// in their volume context, so the node plugin mounts the volume directory
+/// directly and DeleteVolume needs nothing but the ID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VolumeSource {
+    /// MGS NID list, e.g. `10.0.0.1@tcp:10.0.0.2@tcp`
+    pub mgs: String,
+    pub fsname: String,
+    /// Path below the filesystem root without leading slash; empty for the root
+    pub subdir: String,
+}
+
+impl VolumeSource {
+    pub fn parse(source: &str) -> Result<Self> {
+        let (mgs, path) = source
+            .split_once(":/")
+            .with_context(|| format!("{:?} is not of the form mgs@net:/fsname", source))?;
+        if mgs.is_empty() || !mgs.contains('@') {
+            anyhow::bail!("{:?} has no MGS NID", source);
+        }
+
+        let mut components = path.split('/').filter(|c| !c.is_empty());
+        let fsname = components
+            .next()
+            .with_context(|| format!("{:?} has no filesystem name", source))?;
+        let components: Vec<&str> = components.collect();
+        if components.iter().any(|c| *c == "." || *c == "..") {
+            anyhow::bail!("{:?} contains relative path components", source);
+        }
+
+        Ok(Self {
+            mgs: mgs.to_string(),
+            fsname: fsname.to_string(),
+            subdir: components.join("/"),
+        })
+    }
+
+    /// Source of the filesystem root
+    pub fn filesystem(&self) -> String {
+        format!("{}:/{}", self.mgs, self.fsname)
+    }
+
+    /// Directory name for a mount of the filesystem, unique per MGS and
+    /// filesystem name
+    pub fn mount_name(&self) -> String {
+        let mgs: String = self
+            .mgs
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | '@') {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        format!("{}-{}", self.fsname, mgs)
+    }
+
+    /// A volume directory called `name` below this one
+    pub fn child(&self, name: &str) -> Result<Self> {
+        if name.is_empty()
+            || name == "."
+            || name == ".."
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        {
+            anyhow::bail!("{:?} is not a valid volume directory name", name);
+        }
+
+        let subdir = if self.subdir.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", self.subdir, name)
+        };
+        Ok(Self {
+            subdir,
+            ..self.clone()
+        })
+    }
+
+    /// Snapshot called `name` of this volume, in the snapshot area of the
+    /// directory the volume was created in
+    pub fn snapshot(&self, name: &str) -> Result<Self> {
+        if self.subdir.is_empty() {
+            anyhow::bail!("The root of {} cannot be snapshotted", self.fsname);
+        }
+        let parent = match self.subdir.rsplit_once('/') {
+            Some((parent, _)) => parent.to_string(),
+            None => String::new(),
+        };
+        Self {
+            subdir: parent,
+            ..self.clone()
+        }
+        .child(SNAPSHOT_AREA)?
+        .child(name)
+    }
+
+    /// Whether this is a snapshot rather than a volume
+    pub fn is_snapshot(&self) -> bool {
+        self.subdir
+            .rsplit('/')
+            .nth(1)
+            .is_some_and(|area| area == SNAPSHOT_AREA)
+    }
+}
+
+impl fmt::Display for VolumeSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.filesystem())?;
+        if !self.subdir.is_empty() {
+            write!(f, "/{}", self.subdir)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_volume_source() {
+        let parent = VolumeSource::parse("10.0.0.1@tcp:10.0.0.2@tcp:/lustre//k8s/").unwrap();
+        assert_eq!(parent.mgs, "10.0.0.1@tcp:10.0.0.2@tcp");
+        assert_eq!(parent.fsname, "lustre");
+        assert_eq!(parent.subdir, "k8s");
+        assert_eq!(parent.filesystem(), "10.0.0.1@tcp:10.0.0.2@tcp:/lustre");
+        assert_eq!(parent.mount_name(), "lustre-10.0.0.1@tcp_10.0.0.2@tcp");
+        let other = VolumeSource::parse("10.0.0.9@tcp:/lustre/k8s").unwrap();
+        assert_ne!(other.mount_name(), parent.mount_name());
+
+        let volume = parent.child("pvc-1234").unwrap();
+        assert_eq!(
+            volume.to_string(),
+            "10.0.0.1@tcp:10.0.0.2@tcp:/lustre/k8s/pvc-1234"
+        );
+        assert_eq!(VolumeSource::parse(&volume.to_string()).unwrap(), volume);
+
+        let snapshot = volume.snapshot("snapshot-1").unwrap();
+        assert_eq!(snapshot.subdir, "k8s/.klustre-snapshots/snapshot-1");
+        assert!(snapshot.is_snapshot() && !volume.is_snapshot());
+
+        assert!(parent.child("../etc").is_err());
+        assert!(VolumeSource::parse("10.0.0.1@tcp:/lustre/../x").is_err());
+        assert!(VolumeSource::parse("lustre").is_err());
+    }
+}
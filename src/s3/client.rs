@@ -0,0 +1,324 @@
+use anyhow::{Context, Result};
+use std::io::Read;
+use std::time::Duration;
+use time::OffsetDateTime;
+
+use super::sigv4::{self, Credentials, amz_date, canonical_query, sha256_hex, uri_encode};
+use crate::config::S3Config;
+
+const ACCESS_KEY_ENV: &str = "AWS_ACCESS_KEY_ID";
+const SECRET_KEY_ENV: &str = "AWS_SECRET_ACCESS_KEY";
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Bound on a stalled transfer; parts are sent and read in one piece
+const IO_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Error response of the object store
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Error {
+    pub status: u16,
+    /// S3 error code, e.g. `NoSuchUpload`
+    pub code: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for S3Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "S3 returned {} {}: {}",
+            self.status, self.code, self.message
+        )
+    }
+}
+
+impl std::error::Error for S3Error {}
+
+/// Whether `e` says a multipart upload was completed or aborted already
+pub fn is_no_such_upload(e: &anyhow::Error) -> bool {
+    e.chain()
+        .filter_map(|cause| cause.downcast_ref::<S3Error>())
+        .any(|e| e.code == "NoSuchUpload")
+}
+
+/// Blocking client for the few object operations exports need, on one
+/// bucket of an S3-compatible store.
+///
+/// Every request carries the SHA-256 of its body, which the store checks
+/// before accepting it.
+#[derive(Clone)]
+pub struct S3Client {
+    agent: ureq::Agent,
+    /// Scheme and authority, e.g. `http://minio:9000`
+    endpoint: String,
+    host: String,
+    bucket: String,
+    region: String,
+    credentials: Credentials,
+}
+
+impl std::fmt::Debug for S3Client {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3Client")
+            .field("endpoint", &self.endpoint)
+            .field("bucket", &self.bucket)
+            .finish_non_exhaustive()
+    }
+}
+
+impl S3Client {
+    /// Client for the configured store, with credentials from the
+    /// environment
+    pub fn new(config: &S3Config) -> Result<Self> {
+        let var = |name: &str| std::env::var(name).with_context(|| format!("{} is not set", name));
+        let credentials = Credentials {
+            access_key_id: var(ACCESS_KEY_ENV)?,
+            secret_access_key: var(SECRET_KEY_ENV)?,
+        };
+        Self::with_credentials(config, credentials)
+    }
+
+    pub fn with_credentials(config: &S3Config, credentials: Credentials) -> Result<Self> {
+        let endpoint = config.endpoint.trim_end_matches('/');
+        let host = endpoint
+            .split_once("://")
+            .map(|(_, host)| host)
+            .filter(|host| !host.is_empty() && !host.contains('/'))
+            .with_context(|| format!("{:?} is not a URL without a path", config.endpoint))?;
+
+        Ok(Self {
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(CONNECT_TIMEOUT)
+                .timeout_read(IO_TIMEOUT)
+                .timeout_write(IO_TIMEOUT)
+                .build(),
+            endpoint: endpoint.to_string(),
+            host: host.to_string(),
+            bucket: config.bucket.clone(),
+            region: config.region.clone(),
+            credentials,
+        })
+    }
+
+    pub fn put_object(&self, key: &str, body: &[u8]) -> Result<()> {
+        self.send("PUT", key, &[], body)
+            .with_context(|| format!("Failed to upload {}", key))?;
+        Ok(())
+    }
+
+    /// Stream an object, `None` if there is no such key
+    pub fn get_object(&self, key: &str) -> Result<Option<Box<dyn Read + Send + Sync>>> {
+        let response = self
+            .send("GET", key, &[], &[])
+            .with_context(|| format!("Failed to download {}", key))?;
+        Ok(response.map(|response| response.into_reader()))
+    }
+
+    /// Delete an object; deleting a missing key succeeds
+    pub fn delete_object(&self, key: &str) -> Result<()> {
+        self.send("DELETE", key, &[], &[])
+            .with_context(|| format!("Failed to delete {}", key))?;
+        Ok(())
+    }
+
+    /// Start a multipart upload and return its upload ID
+    pub fn create_multipart_upload(&self, key: &str) -> Result<String> {
+        let body = self
+            .send("POST", key, &[("uploads", "")], &[])
+            .and_then(|response| read_body(response, key))
+            .with_context(|| format!("Failed to start upload of {}", key))?;
+        xml_value(&body, "UploadId")
+            .map(String::from)
+            .with_context(|| format!("No UploadId in response for {}", key))
+    }
+
+    /// Upload part `number` (from 1) and return its ETag; uploading a part
+    /// number again replaces the part
+    pub fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        number: u32,
+        body: &[u8],
+    ) -> Result<String> {
+        let number = number.to_string();
+        let response = self
+            .send(
+                "PUT",
+                key,
+                &[("partNumber", &number), ("uploadId", upload_id)],
+                body,
+            )
+            .with_context(|| format!("Failed to upload part {} of {}", number, key))?
+            .with_context(|| format!("Upload of {} is gone", key))?;
+        response
+            .header("ETag")
+            .map(String::from)
+            .with_context(|| format!("No ETag for part {} of {}", number, key))
+    }
+
+    /// Join the given `(number, ETag)` parts, in order, into the object
+    pub fn complete_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: &[(u32, &str)],
+    ) -> Result<()> {
+        let mut request = String::from("<CompleteMultipartUpload>");
+        for (number, etag) in parts {
+            request.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                number, etag
+            ));
+        }
+        request.push_str("</CompleteMultipartUpload>");
+
+        let body = self
+            .send("POST", key, &[("uploadId", upload_id)], request.as_bytes())
+            .and_then(|response| read_body(response, key))
+            .with_context(|| format!("Failed to complete upload of {}", key))?;
+        // S3 may report a failed completion in a 200 response
+        if body.contains("<Error>") {
+            return Err(error_from_body(200, &body))
+                .with_context(|| format!("Failed to complete upload of {}", key));
+        }
+        Ok(())
+    }
+
+    pub fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> Result<()> {
+        match self.send("DELETE", key, &[("uploadId", upload_id)], &[]) {
+            Ok(_) => Ok(()),
+            Err(e) if is_no_such_upload(&e) => Ok(()),
+            Err(e) => Err(e).with_context(|| format!("Failed to abort upload of {}", key)),
+        }
+    }
+
+    /// Send a signed request, `None` on `NoSuchKey`
+    fn send(
+        &self,
+        method: &str,
+        key: &str,
+        query: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<Option<ureq::Response>> {
+        let path = format!(
+            "/{}/{}",
+            uri_encode(&self.bucket, true),
+            uri_encode(key, false)
+        );
+        let payload_sha256 = sha256_hex(body);
+        let date = amz_date(OffsetDateTime::now_utc());
+        let headers = [
+            ("host", self.host.as_str()),
+            ("x-amz-content-sha256", payload_sha256.as_str()),
+            ("x-amz-date", date.as_str()),
+        ];
+        let authorization = sigv4::authorization(
+            &self.credentials,
+            &self.region,
+            &sigv4::Request {
+                method,
+                path: &path,
+                query,
+                headers: &headers,
+                payload_sha256: &payload_sha256,
+            },
+            &date,
+        );
+
+        let mut url = format!("{}{}", self.endpoint, path);
+        if !query.is_empty() {
+            url.push('?');
+            url.push_str(&canonical_query(query));
+        }
+        let mut request = self
+            .agent
+            .request(method, &url)
+            .set("Authorization", &authorization);
+        for (name, value) in headers {
+            request = request.set(name, value);
+        }
+        let result = if matches!(method, "GET" | "DELETE") {
+            request.call()
+        } else {
+            request.send_bytes(body)
+        };
+
+        match result {
+            Ok(response) => Ok(Some(response)),
+            Err(ureq::Error::Status(status, response)) => {
+                let body = response.into_string().unwrap_or_default();
+                let error = error_from_body(status, &body);
+                if error.code == "NoSuchKey" {
+                    return Ok(None);
+                }
+                Err(error.into())
+            }
+            Err(e) => Err(e).context("Object store is unreachable"),
+        }
+    }
+}
+
+fn read_body(response: Option<ureq::Response>, key: &str) -> Result<String> {
+    let response = response.with_context(|| format!("{} does not exist", key))?;
+    Ok(response.into_string()?)
+}
+
+fn error_from_body(status: u16, body: &str) -> S3Error {
+    S3Error {
+        status,
+        code: xml_value(body, "Code").unwrap_or("Unknown").to_string(),
+        message: xml_value(body, "Message").unwrap_or_default().to_string(),
+    }
+}
+
+/// Text of the first `<tag>` element of an XML response
+fn xml_value<'a>(body: &'a str, tag: &str) -> Option<&'a str> {
+    let start = body.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let end = body[start..].find(&format!("</{}>", tag))? + start;
+    Some(&body[start..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::s3::testing::StandIn;
+
+    #[test]
+    fn test_s3_client() {
+        let store = StandIn::start();
+        let client = store.client();
+
+        client.put_object("k8s/a b.txt", b"alpha").unwrap();
+        let mut data = String::new();
+        client
+            .get_object("k8s/a b.txt")
+            .unwrap()
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "alpha");
+        assert!(client.get_object("k8s/missing").unwrap().is_none());
+
+        let upload_id = client.create_multipart_upload("k8s/big").unwrap();
+        let second = client
+            .upload_part("k8s/big", &upload_id, 2, b"-two")
+            .unwrap();
+        let first = client
+            .upload_part("k8s/big", &upload_id, 1, b"one")
+            .unwrap();
+        client
+            .complete_multipart_upload("k8s/big", &upload_id, &[(1, &first), (2, &second)])
+            .unwrap();
+        assert_eq!(store.object("k8s/big").unwrap(), b"one-two");
+
+        let e = client
+            .upload_part("k8s/big", &upload_id, 3, b"three")
+            .unwrap_err();
+        assert!(is_no_such_upload(&e));
+
+        client.delete_object("k8s/big").unwrap();
+        client.delete_object("k8s/big").unwrap();
+        assert_eq!(store.keys(), vec!["k8s/a b.txt"]);
+    }
+}
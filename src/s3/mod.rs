@@ -0,0 +1,88 @@
+pub mod client;
+pub mod sigv4;
+#[cfg(test)]
+pub(crate) mod testing;
+
+use anyhow::{Context, Result};
+use std::fmt;
+
+// Re-export
+pub use client::S3Client;
+
+const SCHEME: &str = "s3://";
+
+/// Where an export lives in the object store, written `s3://bucket/prefix`.
+///
+/// Exports are snapshots, and this form is their snapshot ID; it cannot be
+/// mistaken for a volume ID, which always has an MGS NID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Location {
+    pub bucket: String,
+    /// Key prefix of the export's objects, without leading or trailing slash
+    pub prefix: String,
+}
+
+impl S3Location {
+    pub fn parse(location: &str) -> Result<Self> {
+        let rest = location
+            .strip_prefix(SCHEME)
+            .with_context(|| format!("{:?} is not of the form s3://bucket/prefix", location))?;
+        let (bucket, prefix) = rest
+            .split_once('/')
+            .with_context(|| format!("{:?} has no key prefix", location))?;
+        let prefix = prefix.trim_matches('/');
+        if bucket.is_empty() || prefix.is_empty() {
+            anyhow::bail!("{:?} needs a bucket and a key prefix", location);
+        }
+        if prefix
+            .split('/')
+            .any(|c| c.is_empty() || c == "." || c == "..")
+        {
+            anyhow::bail!("{:?} contains relative or empty path components", location);
+        }
+
+        Ok(Self {
+            bucket: bucket.to_string(),
+            prefix: prefix.to_string(),
+        })
+    }
+
+    /// Whether `id` is meant to be an object store location
+    pub fn is_location(id: &str) -> bool {
+        id.starts_with(SCHEME)
+    }
+
+    /// Key of the object `name` of this export
+    pub fn key(&self, name: &str) -> String {
+        format!("{}/{}", self.prefix, name)
+    }
+}
+
+impl fmt::Display for S3Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}/{}", SCHEME, self.bucket, self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_s3_location() {
+        let location = S3Location::parse("s3://backups/klustre/snapshot-1/").unwrap();
+        assert_eq!(location.bucket, "backups");
+        assert_eq!(location.prefix, "klustre/snapshot-1");
+        assert_eq!(location.to_string(), "s3://backups/klustre/snapshot-1");
+        assert_eq!(
+            location.key("manifest.json"),
+            "klustre/snapshot-1/manifest.json"
+        );
+
+        assert!(S3Location::is_location("s3://backups/x"));
+        assert!(!S3Location::is_location("10.0.0.1@tcp:/lustre/k8s"));
+        assert!(S3Location::parse("s3://backups").is_err());
+        assert!(S3Location::parse("s3://backups/../x").is_err());
+        assert!(S3Location::parse("10.0.0.1@tcp:/lustre/k8s").is_err());
+    }
+}
@@ -0,0 +1,265 @@
+//! A small in-memory stand-in for an S3-compatible store, enough for the
+//! operations [`S3Client`] uses. Like MinIO, it rejects bodies that do not
+//! match their `x-amz-content-sha256` header.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+
+use super::S3Client;
+use super::sigv4::{Credentials, sha256_hex};
+use crate::config::S3Config;
+
+pub const BUCKET: &str = "backups";
+
+#[derive(Default)]
+struct Objects {
+    objects: BTreeMap<String, Vec<u8>>,
+    /// Parts of unfinished multipart uploads, by upload ID
+    uploads: HashMap<String, BTreeMap<u32, Vec<u8>>>,
+    next_upload: u32,
+}
+
+pub struct StandIn {
+    endpoint: String,
+    objects: Arc<Mutex<Objects>>,
+}
+
+struct Reply {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    fn ok(body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status: 200,
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    fn no_content() -> Self {
+        Self {
+            status: 204,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    fn error(status: u16, code: &str) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: format!(
+                "<Error><Code>{}</Code><Message>{}</Message></Error>",
+                code, code
+            )
+            .into_bytes(),
+        }
+    }
+}
+
+impl StandIn {
+    /// Serve on a free local port until the test process exits
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let objects = Arc::new(Mutex::new(Objects::default()));
+
+        let shared = objects.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let objects = shared.clone();
+                std::thread::spawn(move || serve(stream, &objects));
+            }
+        });
+        Self { endpoint, objects }
+    }
+
+    pub fn config(&self) -> S3Config {
+        S3Config {
+            endpoint: self.endpoint.clone(),
+            bucket: BUCKET.to_string(),
+            region: "us-east-1".to_string(),
+            prefix: "klustre".to_string(),
+            chunk_size: 4096,
+            part_size: 1024,
+        }
+    }
+
+    pub fn client(&self) -> S3Client {
+        let credentials = Credentials {
+            access_key_id: "minio".to_string(),
+            secret_access_key: "minio123".to_string(),
+        };
+        S3Client::with_credentials(&self.config(), credentials).unwrap()
+    }
+
+    pub fn object(&self, key: &str) -> Option<Vec<u8>> {
+        self.objects.lock().unwrap().objects.get(key).cloned()
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.objects
+            .lock()
+            .unwrap()
+            .objects
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// Multipart uploads neither completed nor aborted
+    pub fn pending_uploads(&self) -> usize {
+        self.objects.lock().unwrap().uploads.len()
+    }
+}
+
+fn serve(stream: TcpStream, objects: &Mutex<Objects>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+        return;
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default().to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+    let length = headers
+        .get("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body).unwrap();
+
+    let reply = if !headers
+        .get("authorization")
+        .is_some_and(|value| value.starts_with("AWS4-HMAC-SHA256 Credential=minio/"))
+    {
+        Reply::error(403, "AccessDenied")
+    } else if headers.get("x-amz-content-sha256") != Some(&sha256_hex(&body)) {
+        Reply::error(400, "XAmzContentSHA256Mismatch")
+    } else {
+        handle(&method, &target, &body, &mut objects.lock().unwrap())
+    };
+
+    let mut stream = stream;
+    let mut response = format!(
+        "HTTP/1.1 {} Reply\r\nContent-Length: {}\r\nConnection: close\r\n",
+        reply.status,
+        reply.body.len()
+    );
+    for (name, value) in &reply.headers {
+        response.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    response.push_str("\r\n");
+    let _ = stream.write_all(response.as_bytes());
+    let _ = stream.write_all(&reply.body);
+}
+
+fn handle(method: &str, target: &str, body: &[u8], objects: &mut Objects) -> Reply {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query: HashMap<&str, String> = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (name, decode(value))
+        })
+        .collect();
+    let Some(key) = path.strip_prefix(&format!("/{}/", BUCKET)).map(decode) else {
+        return Reply::error(404, "NoSuchBucket");
+    };
+
+    match (method, query.get("uploadId")) {
+        ("PUT", Some(upload_id)) => {
+            let number = query["partNumber"].parse().unwrap();
+            let Some(parts) = objects.uploads.get_mut(upload_id) else {
+                return Reply::error(404, "NoSuchUpload");
+            };
+            parts.insert(number, body.to_vec());
+            let mut reply = Reply::ok("");
+            reply.headers.push((
+                "ETag".to_string(),
+                format!("\"{}\"", &sha256_hex(body)[..32]),
+            ));
+            reply
+        }
+        ("PUT", None) => {
+            objects.objects.insert(key, body.to_vec());
+            Reply::ok("")
+        }
+        ("POST", None) if query.contains_key("uploads") => {
+            objects.next_upload += 1;
+            let upload_id = format!("upload-{}", objects.next_upload);
+            objects.uploads.insert(upload_id.clone(), BTreeMap::new());
+            Reply::ok(format!(
+                "<InitiateMultipartUploadResult><UploadId>{}</UploadId></InitiateMultipartUploadResult>",
+                upload_id
+            ))
+        }
+        ("POST", Some(upload_id)) => {
+            let Some(parts) = objects.uploads.remove(upload_id) else {
+                return Reply::error(404, "NoSuchUpload");
+            };
+            let request = String::from_utf8_lossy(body);
+            let mut object = Vec::new();
+            for number in request.split("<PartNumber>").skip(1) {
+                let number: u32 = number.split('<').next().unwrap().parse().unwrap();
+                match parts.get(&number) {
+                    Some(part) => object.extend_from_slice(part),
+                    None => return Reply::error(400, "InvalidPart"),
+                }
+            }
+            objects.objects.insert(key, object);
+            Reply::ok("<CompleteMultipartUploadResult></CompleteMultipartUploadResult>")
+        }
+        ("GET", None) => match objects.objects.get(&key) {
+            Some(object) => Reply::ok(object.clone()),
+            None => Reply::error(404, "NoSuchKey"),
+        },
+        ("DELETE", Some(upload_id)) => match objects.uploads.remove(upload_id) {
+            Some(_) => Reply::no_content(),
+            None => Reply::error(404, "NoSuchUpload"),
+        },
+        ("DELETE", None) => {
+            objects.objects.remove(&key);
+            Reply::no_content()
+        }
+        _ => Reply::error(405, "MethodNotAllowed"),
+    }
+}
+
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && i + 3 <= bytes.len()
+            && let Ok(b) = u8::from_str_radix(&value[i + 1..i + 3], 16)
+        {
+            decoded.push(b);
+            i += 3;
+            continue;
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8(decoded).unwrap()
+}
@@ -8,25 +8,38 @@ use tracing::{error, info};
 
 use crate::config::Config;
 use crate::csi_types::{
-    controller_server::ControllerServer, identity_server::IdentityServer, node_server::NodeServer,
+    controller_server::ControllerServer, group_controller_server::GroupControllerServer,
+    identity_server::IdentityServer, node_server::NodeServer,
 };
-use crate::services::{ControllerService, IdentityService, NodeService};
+use crate::services::{ControllerService, GroupControllerService, IdentityService, NodeService};
 
 pub struct CSIServer {
     identity_service: IdentityService,
-    node_service: NodeService,
-    controller_service: ControllerService,
+    node_service: Option<NodeService>,
+    controller_service: Option<ControllerService>,
 }
 
 impl CSIServer {
-    pub fn new(config: Config) -> Result<Self> {
+    pub async fn new(config: Config) -> Result<Self> {
         info!("Creating CSI server with config: {:?}", config);
 
-        let identity_service =
-            IdentityService::new(config.driver.name.clone(), config.driver.version.clone());
+        let mode = config.driver.mode;
+        let identity_service = IdentityService::new(
+            config.driver.name.clone(),
+            config.driver.version.clone(),
+            mode.serves_controller(),
+        );
 
-        let node_service = NodeService::new(config.driver.node_id.clone());
-        let controller_service = ControllerService::new();
+        let node_service = if mode.serves_node() {
+            Some(NodeService::new(&config)?)
+        } else {
+            None
+        };
+        let controller_service = if mode.serves_controller() {
+            Some(ControllerService::new(&config).await?)
+        } else {
+            None
+        };
 
         Ok(Self {
             identity_service,
@@ -48,6 +61,17 @@ impl CSIServer {
             fs::create_dir_all(parent).await?;
         }
 
+        // Settle anything a previous instance left half done before serving
+        if let Some(node_service) = &self.node_service {
+            if let Err(e) = node_service.reconcile().await {
+                error!("Failed to reconcile node state: {:#}", e);
+            }
+            node_service.spawn_background_tasks();
+        }
+        if let Some(controller_service) = &self.controller_service {
+            controller_service.resume_jobs().await;
+        }
+
         info!("Binding to Unix socket: {}", socket_path.display());
         let uds = UnixListener::bind(socket_path)?;
         let uds_stream = UnixListenerStream::new(uds);
@@ -57,8 +81,11 @@ impl CSIServer {
 
         Server::builder()
             .add_service(IdentityServer::new(self.identity_service.clone()))
-            .add_service(NodeServer::new(self.node_service.clone()))
-            .add_service(ControllerServer::new(self.controller_service.clone()))
+            .add_optional_service(self.node_service.clone().map(NodeServer::new))
+            .add_optional_service(self.controller_service.clone().map(ControllerServer::new))
+            .add_optional_service(self.controller_service.clone().map(|controller| {
+                GroupControllerServer::new(GroupControllerService::new(controller))
+            }))
             .serve_with_incoming(uds_stream)
             .await
             .map_err(|e| {
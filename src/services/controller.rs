@@ -1,47 +1,1152 @@
+use crate::config::Config;
 use crate::csi_types::{
     ControllerExpandVolumeRequest, ControllerExpandVolumeResponse,
     ControllerGetCapabilitiesRequest, ControllerGetCapabilitiesResponse,
     ControllerGetVolumeRequest, ControllerGetVolumeResponse, ControllerModifyVolumeRequest,
     ControllerModifyVolumeResponse, ControllerPublishVolumeRequest,
-    ControllerPublishVolumeResponse, ControllerUnpublishVolumeRequest,
+    ControllerPublishVolumeResponse, ControllerServiceCapability, ControllerUnpublishVolumeRequest,
     ControllerUnpublishVolumeResponse, CreateSnapshotRequest, CreateSnapshotResponse,
     CreateVolumeRequest, CreateVolumeResponse, DeleteSnapshotRequest, DeleteSnapshotResponse,
     DeleteVolumeRequest, DeleteVolumeResponse, GetCapacityRequest, GetCapacityResponse,
     GetSnapshotRequest, GetSnapshotResponse, ListSnapshotsRequest, ListSnapshotsResponse,
-    ListVolumesRequest, ListVolumesResponse, ValidateVolumeCapabilitiesRequest,
-    ValidateVolumeCapabilitiesResponse, controller_server::Controller,
+    ListVolumesRequest, ListVolumesResponse, Snapshot, ValidateVolumeCapabilitiesRequest,
+    ValidateVolumeCapabilitiesResponse, Volume, VolumeCondition, VolumeContentSource,
+    controller_get_volume_response, controller_server::Controller, controller_service_capability,
+    list_snapshots_response, volume_content_source,
 };
+use crate::jobs::store::{CopyMode, JobStatus, JobsState, SnapshotRecord, now};
+use crate::jobs::{
+    CopyManager, ExportManager, FillJob, FillJobs, JobStore, MigrationManager, UnpackManager,
+};
+use crate::lustre::LustreClient;
+use crate::lustre::layout::FsTopology;
+use crate::provision::{
+    ArchiveBlocked, DeleteOutcome, PopulateSource, Provisioner, SnapshotMarker, VolumeChanges,
+    VolumeConflict, VolumeOptions, VolumeSource,
+};
+use crate::s3::S3Location;
+use crate::utils::locks::{OperationGuard, OperationLocks, volume_key};
+use crate::utils::pod::SubPathTemplate;
+use anyhow::Context;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
 use tonic::{Request, Response, Status};
-use tracing::{debug, info, instrument};
+use tracing::{debug, error, info, instrument, warn};
+
+/// Parameter prefix the external-provisioner reserves for itself
+const RESERVED_PARAMETER_PREFIX: &str = "csi.storage.k8s.io/";
+
+/// VolumeSnapshotClass parameter choosing where snapshots are kept
+const DESTINATION_PARAMETER: &str = "destination";
+
+/// Volume context key reporting the SHA-256 of the archive a volume was
+/// populated from
+const POPULATED_SHA256_KEY: &str = "populatedSha256";
+
+/// What a new volume is filled with
+enum ContentSource {
+    /// A volume or snapshot directory on the same filesystem, copied
+    Copy(VolumeSource),
+    /// A `populateFrom` directory, copied or hard-linked
+    Populate(VolumeSource, CopyMode),
+    /// An export, imported from the object store
+    Import(S3Location),
+    /// A `populateArchive` tar archive and the SHA-256 it has to have
+    Unpack(VolumeSource, Option<String>),
+}
 
-#[derive(Debug, Clone, Default)]
-pub struct ControllerService;
+#[derive(Debug, Clone)]
+pub struct ControllerService {
+    provisioner: Provisioner,
+    locks: OperationLocks,
+    layout_templates: BTreeMap<String, String>,
+    migrations: MigrationManager,
+    copies: CopyManager,
+    exports: ExportManager,
+    unpacks: UnpackManager,
+    jobs: JobStore,
+    /// Filesystems other than a StorageClass's own that volumes can be
+    /// populated from, by name
+    filesystems: HashMap<String, String>,
+}
 
 impl ControllerService {
-    pub fn new() -> Self {
+    pub async fn new(config: &Config) -> anyhow::Result<Self> {
         info!("Creating Controller service");
-        Self
+        let provisioner = Provisioner::new(config, LustreClient::new());
+        let jobs = JobStore::open(job_store_dir(config, &provisioner).await?)?;
+        Ok(Self {
+            migrations: MigrationManager::new(
+                provisioner.clone(),
+                jobs.clone(),
+                config.provisioning.migration.clone(),
+            ),
+            copies: CopyManager::new(
+                provisioner.clone(),
+                jobs.clone(),
+                config.provisioning.copy.clone(),
+            ),
+            exports: ExportManager::new(
+                provisioner.clone(),
+                jobs.clone(),
+                config.provisioning.s3.clone(),
+                config.provisioning.unpack_device_nodes,
+            ),
+            unpacks: UnpackManager::new(
+                provisioner.clone(),
+                jobs.clone(),
+                config.provisioning.unpack_device_nodes,
+            ),
+            jobs,
+            filesystems: config.lustre.filesystem_mapping.clone(),
+            provisioner,
+            locks: OperationLocks::new(),
+            layout_templates: config.provisioning.layout_templates.clone(),
+        })
+    }
+
+    /// Continue the background jobs a previous instance left running
+    pub async fn resume_jobs(&self) {
+        self.migrations.resume().await;
+        self.copies.resume().await;
+        self.exports.resume().await;
+        self.unpacks.resume().await;
+    }
+
+    /// Directory or archive on the StorageClass's filesystem, or on one from
+    /// the catalog, that `populate*` parameters seed new volumes from
+    fn populate_source(
+        &self,
+        populate: &PopulateSource,
+        parent: &VolumeSource,
+    ) -> Result<ContentSource, Status> {
+        let invalid = |e: anyhow::Error| {
+            Status::invalid_argument(format!("Invalid populate source: {:#}", e))
+        };
+        match populate {
+            PopulateSource::Directory { path, link } => {
+                let source = path.resolve(parent, &self.filesystems).map_err(invalid)?;
+                if !link {
+                    return Ok(ContentSource::Populate(source, CopyMode::Copy));
+                }
+                if source.filesystem() != parent.filesystem() {
+                    return Err(Status::invalid_argument(format!(
+                        "{} can only be hard-linked into volumes on {}",
+                        source,
+                        source.filesystem()
+                    )));
+                }
+                Ok(ContentSource::Populate(source, CopyMode::Link))
+            }
+            PopulateSource::Archive { path, sha256 } => Ok(ContentSource::Unpack(
+                path.resolve(parent, &self.filesystems).map_err(invalid)?,
+                sha256.clone(),
+            )),
+        }
+    }
+
+    /// Content a new volume is filled with; volumes and snapshot directories
+    /// have to be on the filesystem the new volume is created on
+    fn content_source(
+        content_source: Option<&VolumeContentSource>,
+        parent: &VolumeSource,
+    ) -> Result<Option<ContentSource>, Status> {
+        let Some(content_source) = content_source else {
+            return Ok(None);
+        };
+        let source = match &content_source.r#type {
+            Some(volume_content_source::Type::Volume(source)) => {
+                Self::parse_volume_id(&source.volume_id)?
+            }
+            Some(volume_content_source::Type::Snapshot(snapshot))
+                if S3Location::is_location(&snapshot.snapshot_id) =>
+            {
+                return match S3Location::parse(&snapshot.snapshot_id) {
+                    Ok(location) => Ok(Some(ContentSource::Import(location))),
+                    Err(e) => Err(Status::not_found(format!(
+                        "Snapshot {} was not made by this driver: {:#}",
+                        snapshot.snapshot_id, e
+                    ))),
+                };
+            }
+            Some(volume_content_source::Type::Snapshot(snapshot)) => {
+                match VolumeSource::parse(&snapshot.snapshot_id) {
+                    Ok(snapshot) if snapshot.is_snapshot() => snapshot,
+                    _ => {
+                        return Err(Status::not_found(format!(
+                            "Snapshot {} was not made by this driver",
+                            snapshot.snapshot_id
+                        )));
+                    }
+                }
+            }
+            None => return Err(Status::invalid_argument("volume_content_source is empty")),
+        };
+        if source.filesystem() != parent.filesystem() {
+            return Err(Status::invalid_argument(format!(
+                "Volume {} is not on {} and cannot be cloned there",
+                source,
+                parent.filesystem()
+            )));
+        }
+        Ok(Some(ContentSource::Copy(source)))
+    }
+
+    /// Reject restoring from a snapshot that has not been marked complete
+    async fn check_snapshot_ready(&self, snapshot: &VolumeSource) -> Result<(), Status> {
+        match self.provisioner.snapshot_marker(snapshot).await {
+            Ok(Some(_)) => Ok(()),
+            Ok(None) => Err(Status::unavailable(format!(
+                "Snapshot {} is not ready to use",
+                snapshot
+            ))),
+            Err(e) => {
+                error!("Failed to check snapshot {}: {:#}", snapshot, e);
+                Err(Status::internal(format!(
+                    "Failed to check snapshot: {:#}",
+                    e
+                )))
+            }
+        }
+    }
+
+    /// Completion marker of a copy-based snapshot; unreadable markers are
+    /// logged and count as missing
+    async fn snapshot_marker(&self, snapshot_id: &str) -> Option<SnapshotMarker> {
+        if S3Location::is_location(snapshot_id) {
+            return None;
+        }
+        let snapshot = VolumeSource::parse(snapshot_id).ok()?;
+        match self.provisioner.snapshot_marker(&snapshot).await {
+            Ok(marker) => marker,
+            Err(e) => {
+                warn!("Failed to read marker of snapshot {}: {:#}", snapshot_id, e);
+                None
+            }
+        }
+    }
+
+    /// CSI view of a recorded snapshot; exports are ready once `state`
+    /// records them completed, copies once their directory is marked
+    pub(crate) async fn snapshot(
+        &self,
+        snapshot_id: &str,
+        record: &SnapshotRecord,
+        state: &JobsState,
+    ) -> Snapshot {
+        let marker = match state.exports.get(snapshot_id) {
+            Some(_) => None,
+            None => self.snapshot_marker(snapshot_id).await,
+        };
+        snapshot(snapshot_id, record, state, marker.as_ref())
+    }
+
+    /// Recorded snapshots matching the ListSnapshots filters, in ID order
+    async fn list_snapshots(&self, snapshot_id: &str, source_volume_id: &str) -> Vec<Snapshot> {
+        let state = self.jobs.snapshot().await;
+        let mut snapshots = Vec::new();
+        for (id, record) in matching_snapshots(&state, snapshot_id, source_volume_id) {
+            snapshots.push(self.snapshot(id, record, &state).await);
+        }
+        snapshots
+    }
+
+    /// Reject restoring from an export that is still being uploaded; exports
+    /// this controller has no record of are checked when they are imported
+    async fn check_export_ready(&self, location: &S3Location) -> Result<(), Status> {
+        if !self.exports.is_configured() {
+            return Err(Status::failed_precondition(
+                "No S3 object store is configured",
+            ));
+        }
+        match self.exports.export_status(&location.to_string()).await {
+            Some(job) if job.status != JobStatus::Completed => Err(Status::unavailable(format!(
+                "Snapshot {} is not ready to use",
+                location
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Import the export at `location` into the new `volume` in the
+    /// background, reported like [`Self::clone_volume`]
+    async fn import_volume(
+        &self,
+        location: &S3Location,
+        volume: &VolumeSource,
+    ) -> Result<(), Status> {
+        let start = self.exports.start_import(location, volume);
+        fill_volume(&self.exports, &location.to_string(), volume, start).await?;
+        Ok(())
+    }
+
+    /// Copy `source` into the new `volume` in the background, reported like
+    /// [`fill_volume`]
+    async fn clone_volume(
+        &self,
+        source: &VolumeSource,
+        volume: &VolumeSource,
+        mode: CopyMode,
+    ) -> Result<(), Status> {
+        let start = self.copies.start(source, volume, mode);
+        fill_volume(&self.copies, &source.to_string(), volume, start).await?;
+        Ok(())
+    }
+
+    /// Unpack `archive` into the new `volume` in the background, reported
+    /// like [`Self::clone_volume`]; returns the archive's SHA-256 once done
+    async fn unpack_volume(
+        &self,
+        archive: &VolumeSource,
+        expected_sha256: Option<&String>,
+        volume: &VolumeSource,
+    ) -> Result<String, Status> {
+        let start = self
+            .unpacks
+            .start(archive, volume, expected_sha256.cloned());
+        let job = fill_volume(&self.unpacks, &archive.to_string(), volume, start).await?;
+        Ok(job.sha256.unwrap_or_default())
+    }
+
+    /// Reject changing a volume that a clone, import or unpack is still
+    /// reading from or writing to
+    fn check_volume_idle(&self, volume_id: &str) -> Result<(), Status> {
+        check_idle(&self.copies, volume_id)?;
+        check_idle(&self.exports, volume_id)?;
+        check_idle(&self.unpacks, volume_id)
     }
+
+    /// Parse a volume ID this driver handed out
+    pub(crate) fn parse_volume_id(volume_id: &str) -> Result<VolumeSource, Status> {
+        match VolumeSource::parse(volume_id) {
+            Ok(volume) if !volume.subdir.is_empty() && !volume.is_snapshot() => Ok(volume),
+            _ => Err(Status::not_found(format!(
+                "Volume {} was not provisioned by this driver",
+                volume_id
+            ))),
+        }
+    }
+
+    async fn check_volume_exists(&self, volume: &VolumeSource) -> Result<(), Status> {
+        let exists = self.provisioner.volume_exists(volume).await.map_err(|e| {
+            error!("Failed to look up volume {}: {:#}", volume, e);
+            Status::internal(format!("Failed to look up volume: {:#}", e))
+        })?;
+        if !exists {
+            return Err(Status::not_found(format!(
+                "Volume {} does not exist",
+                volume
+            )));
+        }
+        Ok(())
+    }
+
+    async fn check_populate_source(&self, source: &VolumeSource) -> Result<(), Status> {
+        let exists = self.provisioner.volume_exists(source).await.map_err(|e| {
+            error!("Failed to look up {}: {:#}", source, e);
+            Status::internal(format!("Failed to look up populate source: {:#}", e))
+        })?;
+        if !exists {
+            return Err(Status::not_found(format!(
+                "Populate source {} does not exist",
+                source
+            )));
+        }
+        Ok(())
+    }
+
+    /// Reject options the controller's Lustre client is too old to apply
+    async fn check_client_version(&self, required: Option<(u32, u32)>) -> Result<(), Status> {
+        let Some(required) = required else {
+            return Ok(());
+        };
+        let version = self.provisioner.client_version().await.map_err(|e| {
+            error!("Failed to read Lustre client version: {:#}", e);
+            Status::internal(format!("Failed to read Lustre client version: {:#}", e))
+        })?;
+        if version < required {
+            return Err(Status::invalid_argument(format!(
+                "Layout needs Lustre {}.{} or newer, the client is {}.{}",
+                required.0, required.1, version.0, version.1
+            )));
+        }
+        Ok(())
+    }
+
+    async fn topology(&self, volume: &VolumeSource) -> Result<FsTopology, Status> {
+        self.provisioner.topology(volume).await.map_err(|e| {
+            error!("Failed to read topology of {}: {:#}", volume.fsname, e);
+            Status::internal(format!("Failed to read filesystem topology: {:#}", e))
+        })
+    }
+
+    /// Store of the controller's jobs and snapshots
+    pub(crate) fn jobs(&self) -> &JobStore {
+        &self.jobs
+    }
+
+    /// Create the directory and record of snapshot `name` of `source`, or
+    /// find them from an earlier call.
+    ///
+    /// Returns the snapshot ID, its record and whether this call created it.
+    pub(crate) async fn prepare_snapshot(
+        &self,
+        name: &str,
+        source: &VolumeSource,
+        group_snapshot_id: Option<&str>,
+    ) -> Result<(String, SnapshotRecord, bool), Status> {
+        let snapshot_dir = source
+            .snapshot(name)
+            .map_err(|e| Status::invalid_argument(format!("Invalid snapshot: {:#}", e)))?;
+        let snapshot_id = snapshot_dir.to_string();
+        let source_volume_id = source.to_string();
+        if let Some(record) = self
+            .existing_snapshot(name, &source_volume_id, &snapshot_id)
+            .await?
+        {
+            return Ok((snapshot_id, record, false));
+        }
+
+        self.check_volume_exists(source).await?;
+        self.provisioner
+            .create_snapshot_dir(&snapshot_dir)
+            .await
+            .map_err(|e| {
+                error!("Failed to create snapshot {}: {:#}", name, e);
+                Status::internal(format!("Failed to create snapshot: {:#}", e))
+            })?;
+
+        let record = SnapshotRecord {
+            name: name.to_string(),
+            source_volume_id,
+            created_at: now(),
+            group_snapshot_id: group_snapshot_id.map(String::from),
+        };
+        self.jobs
+            .update(|state| state.snapshots.insert(snapshot_id.clone(), record.clone()))
+            .await
+            .map_err(|e| {
+                error!("Failed to record snapshot {}: {:#}", name, e);
+                Status::internal(format!("Failed to record snapshot: {:#}", e))
+            })?;
+        Ok((snapshot_id, record, true))
+    }
+
+    /// Record of an earlier snapshot called `name`, which has to have been
+    /// taken of the same volume to the same place; snapshot names are unique
+    /// across all volumes
+    async fn existing_snapshot(
+        &self,
+        name: &str,
+        source_volume_id: &str,
+        snapshot_id: &str,
+    ) -> Result<Option<SnapshotRecord>, Status> {
+        let state = self.jobs.snapshot().await;
+        let Some((id, record)) = state
+            .snapshots
+            .into_iter()
+            .find(|(_, record)| record.name == name)
+        else {
+            return Ok(None);
+        };
+        if record.source_volume_id != source_volume_id {
+            return Err(Status::already_exists(format!(
+                "Snapshot {} was taken of volume {}",
+                name, record.source_volume_id
+            )));
+        }
+        if id != snapshot_id {
+            return Err(Status::already_exists(format!(
+                "Snapshot {} was taken to {}",
+                name, id
+            )));
+        }
+        Ok(Some(record))
+    }
+
+    /// Record the export `name` of `source`, or find it from an earlier call
+    async fn prepare_export(
+        &self,
+        name: &str,
+        source: &VolumeSource,
+    ) -> Result<(String, SnapshotRecord), Status> {
+        if !self.exports.is_configured() {
+            return Err(Status::failed_precondition(
+                "No S3 object store is configured",
+            ));
+        }
+        let location = self
+            .exports
+            .location(name)
+            .map_err(|e| Status::invalid_argument(format!("Invalid snapshot: {:#}", e)))?;
+        let snapshot_id = location.to_string();
+        let source_volume_id = source.to_string();
+        if let Some(record) = self
+            .existing_snapshot(name, &source_volume_id, &snapshot_id)
+            .await?
+        {
+            return Ok((snapshot_id, record));
+        }
+
+        self.check_volume_exists(source).await?;
+        let record = SnapshotRecord {
+            name: name.to_string(),
+            source_volume_id,
+            created_at: now(),
+            group_snapshot_id: None,
+        };
+        self.jobs
+            .update(|state| state.snapshots.insert(snapshot_id.clone(), record.clone()))
+            .await
+            .map_err(|e| {
+                error!("Failed to record snapshot {}: {:#}", name, e);
+                Status::internal(format!("Failed to record snapshot: {:#}", e))
+            })?;
+        Ok((snapshot_id, record))
+    }
+
+    /// Start or check the export that fills a snapshot.
+    ///
+    /// A failed export is reported and then continued where it stopped, so
+    /// the snapshotter's retry finds it running again.
+    async fn export_progress(
+        &self,
+        snapshot_id: &str,
+        record: &SnapshotRecord,
+    ) -> Result<Snapshot, Status> {
+        let location = S3Location::parse(snapshot_id)
+            .map_err(|e| Status::internal(format!("Invalid snapshot ID: {:#}", e)))?;
+        match self.exports.export_status(snapshot_id).await {
+            None => {
+                let source = Self::parse_volume_id(&record.source_volume_id)?;
+                self.exports
+                    .start_export(&location, &source)
+                    .await
+                    .map_err(|e| {
+                        error!("Failed to start export to {}: {:#}", snapshot_id, e);
+                        Status::internal(format!("Failed to start export: {:#}", e))
+                    })?;
+            }
+            Some(job) if job.status == JobStatus::Failed => {
+                if let Err(e) = self.exports.retry_export(&location).await {
+                    warn!("Failed to restart export to {}: {:#}", snapshot_id, e);
+                }
+                return Err(Status::internal(job.describe()));
+            }
+            Some(_) => {}
+        }
+
+        let snapshot = self
+            .snapshot(snapshot_id, record, &self.jobs.snapshot().await)
+            .await;
+        if snapshot.ready_to_use {
+            info!("Snapshot {} is ready", snapshot_id);
+        }
+        Ok(snapshot)
+    }
+
+    /// Snapshot this controller has no record of, read from the marker of
+    /// a completed snapshot directory or the manifest of an export, such
+    /// as one made by another cluster
+    async fn unrecorded_snapshot(&self, snapshot_id: &str) -> Result<Option<Snapshot>, Status> {
+        let Ok(location) = S3Location::parse(snapshot_id) else {
+            return Ok(self
+                .snapshot_marker(snapshot_id)
+                .await
+                .map(|marker| Snapshot {
+                    size_bytes: marker.bytes as i64,
+                    snapshot_id: snapshot_id.to_string(),
+                    source_volume_id: marker.source_volume_id,
+                    creation_time: Some(prost_types::Timestamp {
+                        seconds: marker.created_at as i64,
+                        nanos: 0,
+                    }),
+                    ready_to_use: true,
+                    group_snapshot_id: String::new(),
+                }));
+        };
+        if !self.exports.is_configured() {
+            return Ok(None);
+        }
+        let manifest = self.exports.manifest(&location).await.map_err(|e| {
+            error!("Failed to read export {}: {:#}", snapshot_id, e);
+            Status::internal(format!("Failed to read export: {:#}", e))
+        })?;
+        Ok(manifest.map(|manifest| Snapshot {
+            size_bytes: manifest.bytes as i64,
+            snapshot_id: snapshot_id.to_string(),
+            source_volume_id: manifest.source_volume_id,
+            creation_time: Some(prost_types::Timestamp {
+                seconds: manifest.created_at as i64,
+                nanos: 0,
+            }),
+            ready_to_use: true,
+            group_snapshot_id: String::new(),
+        }))
+    }
+
+    /// Start or check the copy that fills a snapshot.
+    ///
+    /// A failed copy is forgotten, so the snapshotter's retry starts over.
+    pub(crate) async fn snapshot_progress(
+        &self,
+        snapshot_id: &str,
+        source: &VolumeSource,
+        record: &SnapshotRecord,
+    ) -> Result<Snapshot, Status> {
+        match self.copies.status(snapshot_id).await {
+            None => {
+                let snapshot_dir = VolumeSource::parse(snapshot_id)
+                    .map_err(|e| Status::internal(format!("Invalid snapshot ID: {:#}", e)))?;
+                self.copies
+                    .start(source, &snapshot_dir, CopyMode::Copy)
+                    .await
+                    .map_err(|e| {
+                        error!("Failed to start copy into {}: {:#}", snapshot_id, e);
+                        Status::internal(format!("Failed to start snapshot copy: {:#}", e))
+                    })?;
+            }
+            Some(job) if job.status == JobStatus::Failed => {
+                if let Err(e) = self.copies.forget(snapshot_id).await {
+                    warn!("Failed to drop copy record of {}: {:#}", snapshot_id, e);
+                }
+                return Err(Status::internal(job.describe()));
+            }
+            Some(_) => {}
+        }
+
+        let snapshot = self
+            .snapshot(snapshot_id, record, &self.jobs.snapshot().await)
+            .await;
+        if snapshot.ready_to_use {
+            info!("Snapshot {} is ready", snapshot_id);
+        }
+        Ok(snapshot)
+    }
+
+    /// Delete a snapshot's directory and records; IDs this driver cannot
+    /// have handed out are ignored
+    pub(crate) async fn remove_snapshot(&self, snapshot_id: &str) -> Result<(), Status> {
+        if S3Location::is_location(snapshot_id) {
+            return self.remove_export(snapshot_id).await;
+        }
+        let snapshot_dir = match VolumeSource::parse(snapshot_id) {
+            Ok(snapshot_dir) if snapshot_dir.is_snapshot() => snapshot_dir,
+            _ => {
+                warn!("Snapshot {} was not made by this driver", snapshot_id);
+                return Ok(());
+            }
+        };
+        if self.copies.is_busy(snapshot_id) {
+            return Err(Status::aborted(format!(
+                "Snapshot {} is being copied",
+                snapshot_id
+            )));
+        }
+
+        self.provisioner
+            .delete_snapshot(&snapshot_dir)
+            .await
+            .map_err(|e| {
+                error!("Failed to delete snapshot {}: {:#}", snapshot_id, e);
+                Status::internal(format!("Failed to delete snapshot: {:#}", e))
+            })?;
+        self.jobs
+            .update(|state| {
+                state.snapshots.remove(snapshot_id);
+                state.copies.remove(snapshot_id);
+            })
+            .await
+            .map_err(|e| {
+                error!("Failed to drop snapshot {}: {:#}", snapshot_id, e);
+                Status::internal(format!("Failed to drop snapshot record: {:#}", e))
+            })?;
+
+        info!("Deleted snapshot {}", snapshot_id);
+        Ok(())
+    }
+
+    async fn remove_export(&self, snapshot_id: &str) -> Result<(), Status> {
+        let location = match S3Location::parse(snapshot_id) {
+            Ok(location) => location,
+            Err(e) => {
+                warn!(
+                    "Snapshot {} was not made by this driver: {:#}",
+                    snapshot_id, e
+                );
+                return Ok(());
+            }
+        };
+        if self.exports.is_busy(snapshot_id) {
+            return Err(Status::aborted(format!(
+                "Snapshot {} is being exported or imported",
+                snapshot_id
+            )));
+        }
+
+        self.exports.delete(&location).await.map_err(|e| {
+            error!("Failed to delete export {}: {:#}", snapshot_id, e);
+            Status::internal(format!("Failed to delete export: {:#}", e))
+        })?;
+        self.jobs
+            .update(|state| state.snapshots.remove(snapshot_id))
+            .await
+            .map_err(|e| {
+                error!("Failed to drop snapshot {}: {:#}", snapshot_id, e);
+                Status::internal(format!("Failed to drop snapshot record: {:#}", e))
+            })?;
+
+        info!("Deleted snapshot {}", snapshot_id);
+        Ok(())
+    }
+
+    /// Serialize operations on a volume, rejecting overlapping calls
+    pub(crate) fn lock(&self, operation: &str, volume: &str) -> Result<OperationGuard, Status> {
+        self.locks
+            .try_acquire(operation, &[volume_key(volume)])
+            .map_err(|(key, holder)| {
+                warn!("{} rejected: {} is locked by {}", operation, key, holder);
+                Status::aborted(format!(
+                    "An operation is already in progress for {} ({})",
+                    key, holder
+                ))
+            })
+    }
+}
+
+/// Directory of the job store: the configured one on a Lustre filesystem,
+/// so jobs follow the controller to any node, or else the plugin directory
+async fn job_store_dir(config: &Config, provisioner: &Provisioner) -> anyhow::Result<PathBuf> {
+    let Some(store) = &config.provisioning.job_store else {
+        warn!(
+            "No jobStore configured: controller jobs are kept in {} on this node and lost if \
+             the controller moves to another one",
+            config.driver.plugin_dir
+        );
+        return Ok(PathBuf::from(&config.driver.plugin_dir));
+    };
+    let mgs = config
+        .lustre
+        .filesystem_mapping
+        .get(&store.filesystem)
+        .with_context(|| {
+            format!(
+                "Job store filesystem {} is not in the filesystems catalog",
+                store.filesystem
+            )
+        })?;
+    let dir = VolumeSource::parse(&format!("{}:/{}/{}", mgs, store.filesystem, store.path))?;
+    info!("Controller jobs are kept in {}", dir);
+    provisioner.host_volume_path(&dir).await
+}
+
+/// CSI view of a snapshot: an export is ready once `state` records it
+/// completed, a copy once its directory carries `marker`
+fn snapshot(
+    snapshot_id: &str,
+    record: &SnapshotRecord,
+    state: &JobsState,
+    marker: Option<&SnapshotMarker>,
+) -> Snapshot {
+    let (ready, bytes) = match state.exports.get(snapshot_id) {
+        Some(job) => (job.status == JobStatus::Completed, job.bytes),
+        None => (marker.is_some(), marker.map(|marker| marker.bytes)),
+    };
+    Snapshot {
+        size_bytes: bytes.unwrap_or_default() as i64,
+        snapshot_id: snapshot_id.to_string(),
+        source_volume_id: record.source_volume_id.clone(),
+        creation_time: Some(prost_types::Timestamp {
+            seconds: record.created_at as i64,
+            nanos: 0,
+        }),
+        ready_to_use: ready,
+        group_snapshot_id: record.group_snapshot_id.clone().unwrap_or_default(),
+    }
+}
+
+/// Snapshot records in `state` matching the ListSnapshots filters, in ID
+/// order
+fn matching_snapshots<'a>(
+    state: &'a JobsState,
+    snapshot_id: &'a str,
+    source_volume_id: &'a str,
+) -> impl Iterator<Item = (&'a String, &'a SnapshotRecord)> {
+    state
+        .snapshots
+        .iter()
+        .filter(move |(id, _)| snapshot_id.is_empty() || *id == snapshot_id)
+        .filter(move |(_, record)| {
+            source_volume_id.is_empty() || record.source_volume_id == source_volume_id
+        })
+}
+
+/// Start the job filling a new `volume` from `source`, or check on the one
+/// already started.
+///
+/// Fails with UNAVAILABLE until the job has completed, so the
+/// external-provisioner keeps retrying CreateVolume; a failed job is
+/// forgotten and starts over on the next retry. `start` only runs if there
+/// is no job yet.
+async fn fill_volume<J: FillJobs>(
+    jobs: &J,
+    source: &str,
+    volume: &VolumeSource,
+    start: impl Future<Output = anyhow::Result<()>>,
+) -> Result<J::Job, Status> {
+    let volume_id = volume.to_string();
+    let Some(job) = jobs.status(&volume_id).await else {
+        start.await.map_err(|e| {
+            error!("Failed to start {} into {}: {:#}", J::KIND, volume_id, e);
+            Status::internal(format!("Failed to start {}: {:#}", J::KIND, e))
+        })?;
+        return Err(Status::unavailable(format!(
+            "Started {} of {} into {}",
+            J::KIND,
+            source,
+            volume_id
+        )));
+    };
+
+    if job.source() != source {
+        return Err(Status::already_exists(format!(
+            "Volume {} was created from {}",
+            volume_id,
+            job.source()
+        )));
+    }
+    match job.status() {
+        JobStatus::Completed => Ok(job),
+        JobStatus::Running => Err(Status::unavailable(job.describe())),
+        JobStatus::Failed => {
+            if let Err(e) = jobs.forget(&volume_id).await {
+                warn!(
+                    "Failed to drop {} record of {}: {:#}",
+                    J::KIND,
+                    volume_id,
+                    e
+                );
+            }
+            Err(Status::internal(job.describe()))
+        }
+    }
+}
+
+/// Reject changing a volume a running job of `jobs` uses
+fn check_idle<J: FillJobs>(jobs: &J, volume_id: &str) -> Result<(), Status> {
+    if jobs.is_busy(volume_id) {
+        return Err(Status::aborted(format!(
+            "Volume {} is being {}",
+            volume_id,
+            J::BUSY
+        )));
+    }
+    Ok(())
+}
+
+/// What a CreateVolume request asks for, in a stable form recorded with the
+/// volume: its parameters, capacity and content source
+fn create_request(req: &CreateVolumeRequest) -> String {
+    let mut request: BTreeMap<String, String> = req
+        .parameters
+        .iter()
+        .map(|(key, value)| (format!("parameters.{}", key), value.clone()))
+        .chain(
+            req.mutable_parameters
+                .iter()
+                .map(|(key, value)| (format!("mutableParameters.{}", key), value.clone())),
+        )
+        .collect();
+    if let Some(range) = &req.capacity_range {
+        request.insert(
+            "requiredBytes".to_string(),
+            range.required_bytes.to_string(),
+        );
+        request.insert("limitBytes".to_string(), range.limit_bytes.to_string());
+    }
+    let content_source = req
+        .volume_content_source
+        .as_ref()
+        .and_then(|source| source.r#type.as_ref());
+    match content_source {
+        Some(volume_content_source::Type::Volume(source)) => {
+            request.insert("sourceVolume".to_string(), source.volume_id.clone());
+        }
+        Some(volume_content_source::Type::Snapshot(snapshot)) => {
+            request.insert("sourceSnapshot".to_string(), snapshot.snapshot_id.clone());
+        }
+        None => {}
+    }
+    serde_json::to_string(&request).unwrap_or_default()
+}
+
+/// Volume context and capacity CreateVolume returned for a volume, rebuilt
+/// from the request recorded with it by [`create_request`]
+fn recorded_volume(
+    volume: &VolumeSource,
+    request: &str,
+    layout_templates: &BTreeMap<String, String>,
+) -> anyhow::Result<(HashMap<String, String>, i64)> {
+    let request: BTreeMap<String, String> = serde_json::from_str(request)?;
+    let with_prefix = |prefix: &str| -> HashMap<String, String> {
+        request
+            .iter()
+            .filter_map(|(key, value)| Some((key.strip_prefix(prefix)?.to_string(), value.clone())))
+            .collect()
+    };
+    let parameters = with_prefix("parameters.");
+    let mut merged = parameters.clone();
+    merged.extend(with_prefix("mutableParameters."));
+    let options = VolumeOptions::from_parameters(&merged, layout_templates)?;
+
+    let capacity_bytes = match request.get("requiredBytes") {
+        Some(bytes) => bytes.parse()?,
+        None => 0,
+    };
+    Ok((
+        volume_context(volume, &parameters, &options),
+        capacity_bytes,
+    ))
+}
+
+/// Volume context of a new volume: its source, the StorageClass parameters
+/// the node plugin acts on and the normalized layout options
+fn volume_context(
+    volume: &VolumeSource,
+    parameters: &HashMap<String, String>,
+    options: &VolumeOptions,
+) -> HashMap<String, String> {
+    let mut context: HashMap<String, String> = parameters
+        .iter()
+        .filter(|(key, _)| !key.starts_with(RESERVED_PARAMETER_PREFIX))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    context.insert("source".to_string(), volume.to_string());
+    options.to_volume_context(&mut context);
+    context
 }
 
 #[tonic::async_trait]
 impl Controller for ControllerService {
-    #[instrument(skip(self))]
+    #[instrument(skip(self, request))]
     async fn create_volume(
         &self,
-        _request: Request<CreateVolumeRequest>,
+        request: Request<CreateVolumeRequest>,
     ) -> Result<Response<CreateVolumeResponse>, Status> {
-        debug!("CreateVolume called");
-        Err(Status::unimplemented("CreateVolume not yet implemented"))
+        let req = request.into_inner();
+        info!("CreateVolume called for volume: {}", req.name);
+
+        if req.name.is_empty() {
+            return Err(Status::invalid_argument("name is required"));
+        }
+        if req.volume_capabilities.is_empty() {
+            return Err(Status::invalid_argument("volume_capabilities are required"));
+        }
+
+        // The StorageClass source is the directory volumes are created in
+        let parent = req
+            .parameters
+            .get("source")
+            .ok_or_else(|| Status::invalid_argument("source not found in parameters"))?;
+        let parent = VolumeSource::parse(parent)
+            .map_err(|e| Status::invalid_argument(format!("Invalid Lustre source: {}", e)))?;
+        let populate = PopulateSource::from_parameters(&req.parameters).map_err(|e| {
+            Status::invalid_argument(format!("Invalid populate parameters: {:#}", e))
+        })?;
+        // Checked here too so a bad template fails the claim, not every pod
+        SubPathTemplate::from_volume_context(&req.parameters)
+            .map_err(|e| Status::invalid_argument(format!("Invalid sub path template: {:#}", e)))?;
+        let content_source = match &populate {
+            None => Self::content_source(req.volume_content_source.as_ref(), &parent)?,
+            Some(_) if req.volume_content_source.is_some() => {
+                return Err(Status::invalid_argument(
+                    "populate parameters cannot be combined with a volume content source",
+                ));
+            }
+            Some(populate) => Some(self.populate_source(populate, &parent)?),
+        };
+
+        // A VolumeAttributesClass given at creation applies on top of the
+        // StorageClass, but may only hold what ModifyVolume could change
+        VolumeChanges::from_parameters(&req.mutable_parameters, &self.layout_templates).map_err(
+            |e| Status::invalid_argument(format!("Invalid mutable parameters: {:#}", e)),
+        )?;
+        let mut parameters = req.parameters.clone();
+        parameters.extend(req.mutable_parameters.clone());
+        let options = VolumeOptions::from_parameters(&parameters, &self.layout_templates)
+            .map_err(|e| Status::invalid_argument(format!("Invalid layout: {:#}", e)))?;
+
+        let _guard = self.lock("CreateVolume", &req.name)?;
+
+        match &content_source {
+            Some(ContentSource::Copy(source)) => {
+                self.check_volume_exists(source).await?;
+                if source.is_snapshot() {
+                    self.check_snapshot_ready(source).await?;
+                }
+            }
+            Some(ContentSource::Populate(source, _) | ContentSource::Unpack(source, _)) => {
+                self.check_populate_source(source).await?
+            }
+            Some(ContentSource::Import(location)) => self.check_export_ready(location).await?,
+            None => {}
+        }
+
+        self.check_client_version(options.min_client_version())
+            .await?;
+
+        let topology = if options.needs_topology() {
+            let topology = self.topology(&parent).await?;
+            options
+                .validate(&topology)
+                .map_err(|e| Status::invalid_argument(format!("Invalid layout: {}", e)))?;
+            Some(topology)
+        } else {
+            None
+        };
+
+        let volume = self
+            .provisioner
+            .create_volume(
+                &parent,
+                &req.name,
+                &options,
+                topology.as_ref(),
+                &create_request(&req),
+            )
+            .await
+            .map_err(|e| {
+                if let Some(conflict) = e.downcast_ref::<VolumeConflict>() {
+                    return Status::already_exists(conflict.to_string());
+                }
+                error!("Failed to create volume {}: {:#}", req.name, e);
+                Status::internal(format!("Failed to create volume: {:#}", e))
+            })?;
+
+        let populated_sha256 = match &content_source {
+            Some(ContentSource::Copy(source)) => {
+                self.clone_volume(source, &volume, CopyMode::Copy).await?;
+                None
+            }
+            Some(ContentSource::Populate(source, mode)) => {
+                self.clone_volume(source, &volume, *mode).await?;
+                None
+            }
+            Some(ContentSource::Import(location)) => {
+                self.import_volume(location, &volume).await?;
+                None
+            }
+            Some(ContentSource::Unpack(archive, sha256)) => Some(
+                self.unpack_volume(archive, sha256.as_ref(), &volume)
+                    .await?,
+            ),
+            None => None,
+        };
+
+        // Lustre directories have no size of their own, so the request is
+        // echoed back as the capacity
+        let capacity_bytes = req
+            .capacity_range
+            .map(|range| range.required_bytes)
+            .unwrap_or_default();
+
+        let mut volume_context = volume_context(&volume, &req.parameters, &options);
+        if let Some(sha256) = populated_sha256 {
+            volume_context.insert(POPULATED_SHA256_KEY.to_string(), sha256);
+        }
+
+        info!("Successfully created volume {}", volume);
+        Ok(Response::new(CreateVolumeResponse {
+            volume: Some(Volume {
+                capacity_bytes,
+                volume_id: volume.to_string(),
+                volume_context,
+                content_source: req.volume_content_source,
+                accessible_topology: Vec::new(),
+            }),
+        }))
     }
 
-    #[instrument(skip(self))]
+    #[instrument(skip(self, request))]
     async fn delete_volume(
         &self,
-        _request: Request<DeleteVolumeRequest>,
+        request: Request<DeleteVolumeRequest>,
     ) -> Result<Response<DeleteVolumeResponse>, Status> {
-        debug!("DeleteVolume called");
-        Err(Status::unimplemented("DeleteVolume not yet implemented"))
+        let req = request.into_inner();
+        info!("DeleteVolume called for volume: {}", req.volume_id);
+
+        if req.volume_id.is_empty() {
+            return Err(Status::invalid_argument("volume_id is required"));
+        }
+
+        // An ID this driver cannot have handed out names no volume of ours
+        let volume = match VolumeSource::parse(&req.volume_id) {
+            Ok(volume) if !volume.subdir.is_empty() && !volume.is_snapshot() => volume,
+            _ => {
+                warn!(
+                    "Volume {} was not provisioned by this driver",
+                    req.volume_id
+                );
+                return Ok(Response::new(DeleteVolumeResponse {}));
+            }
+        };
+
+        let _guard = self.lock("DeleteVolume", &req.volume_id)?;
+        if self.migrations.is_running(&req.volume_id) {
+            return Err(Status::aborted(format!(
+                "Volume {} is being migrated",
+                req.volume_id
+            )));
+        }
+        self.check_volume_idle(&req.volume_id)?;
+
+        match self.provisioner.delete_volume(&volume).await {
+            Ok(DeleteOutcome::Deleted) => {}
+            Ok(DeleteOutcome::Released { files }) => {
+                info!(
+                    "Kept volume {} with {} released file(s) in the archive",
+                    req.volume_id, files
+                );
+            }
+            // The external-provisioner retries with backoff until the
+            // copytool has caught up
+            Ok(DeleteOutcome::Archiving { pending }) => {
+                return Err(Status::unavailable(format!(
+                    "Waiting for {} file(s) of {} to be archived",
+                    pending, req.volume_id
+                )));
+            }
+            Err(e) if e.downcast_ref::<ArchiveBlocked>().is_some() => {
+                error!("Cannot archive volume {}: {:#}", req.volume_id, e);
+                return Err(Status::failed_precondition(e.to_string()));
+            }
+            Err(e) => {
+                error!("Failed to delete volume {}: {:#}", req.volume_id, e);
+                return Err(Status::internal(format!(
+                    "Failed to delete volume: {:#}",
+                    e
+                )));
+            }
+        }
+
+        if let Err(e) = self.migrations.forget(&req.volume_id).await {
+            warn!(
+                "Failed to drop migration record of {}: {:#}",
+                req.volume_id, e
+            );
+        }
+        if let Err(e) = self.copies.forget(&req.volume_id).await {
+            warn!("Failed to drop copy record of {}: {:#}", req.volume_id, e);
+        }
+        if let Err(e) = self.unpacks.forget(&req.volume_id).await {
+            warn!("Failed to drop unpack record of {}: {:#}", req.volume_id, e);
+        }
+        if let Err(e) = self.exports.forget_import(&req.volume_id).await {
+            warn!("Failed to drop import record of {}: {:#}", req.volume_id, e);
+        }
+
+        info!("Successfully deleted volume {}", req.volume_id);
+        Ok(Response::new(DeleteVolumeResponse {}))
     }
 
     #[instrument(skip(self))]
@@ -113,44 +1218,187 @@ impl Controller for ControllerService {
         debug!("ControllerGetCapabilities called");
 
         Ok(Response::new(ControllerGetCapabilitiesResponse {
-            capabilities: vec![], // Empty - we don't dynamically provision
+            capabilities: [
+                controller_service_capability::rpc::Type::CreateDeleteVolume,
+                controller_service_capability::rpc::Type::CloneVolume,
+                controller_service_capability::rpc::Type::CreateDeleteSnapshot,
+                controller_service_capability::rpc::Type::ListSnapshots,
+                controller_service_capability::rpc::Type::GetSnapshot,
+                controller_service_capability::rpc::Type::ModifyVolume,
+                controller_service_capability::rpc::Type::GetVolume,
+                controller_service_capability::rpc::Type::VolumeCondition,
+            ]
+            .into_iter()
+            .map(|rpc| ControllerServiceCapability {
+                r#type: Some(controller_service_capability::Type::Rpc(
+                    controller_service_capability::Rpc { r#type: rpc as i32 },
+                )),
+            })
+            .collect(),
         }))
     }
 
-    #[instrument(skip(self))]
+    /// Snapshots are copies of the volume directory. The snapshotter calls
+    /// again until `ready_to_use` is set, which is when the copy completed.
+    #[instrument(skip(self, request))]
     async fn create_snapshot(
         &self,
-        _request: Request<CreateSnapshotRequest>,
+        request: Request<CreateSnapshotRequest>,
     ) -> Result<Response<CreateSnapshotResponse>, Status> {
-        debug!("CreateSnapshot called");
-        Err(Status::unimplemented("CreateSnapshot not yet implemented"))
+        let req = request.into_inner();
+        info!(
+            "CreateSnapshot called for snapshot {} of volume {}",
+            req.name, req.source_volume_id
+        );
+
+        if req.name.is_empty() {
+            return Err(Status::invalid_argument("name is required"));
+        }
+        if req.source_volume_id.is_empty() {
+            return Err(Status::invalid_argument("source_volume_id is required"));
+        }
+        let source = Self::parse_volume_id(&req.source_volume_id)?;
+        let export = match req
+            .parameters
+            .get(DESTINATION_PARAMETER)
+            .map(String::as_str)
+        {
+            None | Some("lustre") => false,
+            Some("s3") => true,
+            Some(other) => {
+                return Err(Status::invalid_argument(format!(
+                    "{} must be lustre or s3, got {:?}",
+                    DESTINATION_PARAMETER, other
+                )));
+            }
+        };
+
+        let _guard = self.lock("CreateSnapshot", &req.name)?;
+
+        let snapshot = if export {
+            let (snapshot_id, record) = self.prepare_export(&req.name, &source).await?;
+            self.export_progress(&snapshot_id, &record).await?
+        } else {
+            let (snapshot_id, record, _) = self.prepare_snapshot(&req.name, &source, None).await?;
+            self.snapshot_progress(&snapshot_id, &source, &record)
+                .await?
+        };
+        Ok(Response::new(CreateSnapshotResponse {
+            snapshot: Some(snapshot),
+        }))
     }
 
-    #[instrument(skip(self))]
+    #[instrument(skip(self, request))]
     async fn delete_snapshot(
         &self,
-        _request: Request<DeleteSnapshotRequest>,
+        request: Request<DeleteSnapshotRequest>,
     ) -> Result<Response<DeleteSnapshotResponse>, Status> {
-        debug!("DeleteSnapshot called");
-        Err(Status::unimplemented("DeleteSnapshot not yet implemented"))
+        let req = request.into_inner();
+        info!("DeleteSnapshot called for snapshot: {}", req.snapshot_id);
+
+        if req.snapshot_id.is_empty() {
+            return Err(Status::invalid_argument("snapshot_id is required"));
+        }
+
+        let _guard = self.lock("DeleteSnapshot", &req.snapshot_id)?;
+
+        // Members of a group snapshot go with their group
+        if let Some(group) = self
+            .jobs
+            .snapshot()
+            .await
+            .snapshots
+            .get(&req.snapshot_id)
+            .and_then(|record| record.group_snapshot_id.clone())
+        {
+            return Err(Status::failed_precondition(format!(
+                "Snapshot {} belongs to group snapshot {}",
+                req.snapshot_id, group
+            )));
+        }
+
+        self.remove_snapshot(&req.snapshot_id).await?;
+        Ok(Response::new(DeleteSnapshotResponse {}))
     }
 
-    #[instrument(skip(self))]
+    /// Pages through the recorded snapshots in ID order; the token is the ID
+    /// of the first snapshot of the next page
+    #[instrument(skip(self, request))]
     async fn list_snapshots(
         &self,
-        _request: Request<ListSnapshotsRequest>,
+        request: Request<ListSnapshotsRequest>,
     ) -> Result<Response<ListSnapshotsResponse>, Status> {
+        let req = request.into_inner();
         debug!("ListSnapshots called");
-        Err(Status::unimplemented("ListSnapshots not yet implemented"))
+
+        if req.max_entries < 0 {
+            return Err(Status::invalid_argument("max_entries must not be negative"));
+        }
+
+        let mut snapshots = self
+            .list_snapshots(&req.snapshot_id, &req.source_volume_id)
+            .await;
+        if snapshots.is_empty()
+            && let Some(snapshot) = self.unrecorded_snapshot(&req.snapshot_id).await?
+            && (req.source_volume_id.is_empty()
+                || snapshot.source_volume_id == req.source_volume_id)
+        {
+            snapshots.push(snapshot);
+        }
+        let start = if req.starting_token.is_empty() {
+            0
+        } else {
+            snapshots
+                .iter()
+                .position(|snapshot| snapshot.snapshot_id == req.starting_token)
+                .ok_or_else(|| {
+                    Status::aborted(format!("Invalid starting_token {}", req.starting_token))
+                })?
+        };
+        let end = match req.max_entries {
+            0 => snapshots.len(),
+            max => snapshots.len().min(start + max as usize),
+        };
+        let next_token = snapshots
+            .get(end)
+            .map(|snapshot| snapshot.snapshot_id.clone())
+            .unwrap_or_default();
+
+        Ok(Response::new(ListSnapshotsResponse {
+            entries: snapshots[start..end]
+                .iter()
+                .map(|snapshot| list_snapshots_response::Entry {
+                    snapshot: Some(snapshot.clone()),
+                })
+                .collect(),
+            next_token,
+        }))
     }
 
-    #[instrument(skip(self))]
+    #[instrument(skip(self, request))]
     async fn get_snapshot(
         &self,
-        _request: Request<GetSnapshotRequest>,
+        request: Request<GetSnapshotRequest>,
     ) -> Result<Response<GetSnapshotResponse>, Status> {
-        debug!("GetSnapshot called");
-        Err(Status::unimplemented("GetSnapshot not yet implemented"))
+        let req = request.into_inner();
+        debug!("GetSnapshot called for snapshot: {}", req.snapshot_id);
+
+        if req.snapshot_id.is_empty() {
+            return Err(Status::invalid_argument("snapshot_id is required"));
+        }
+
+        let snapshot = match self.list_snapshots(&req.snapshot_id, "").await.pop() {
+            Some(snapshot) => snapshot,
+            None => self
+                .unrecorded_snapshot(&req.snapshot_id)
+                .await?
+                .ok_or_else(|| {
+                    Status::not_found(format!("Snapshot {} does not exist", req.snapshot_id))
+                })?,
+        };
+        Ok(Response::new(GetSnapshotResponse {
+            snapshot: Some(snapshot),
+        }))
     }
 
     #[instrument(skip(self))]
@@ -164,25 +1412,352 @@ impl Controller for ControllerService {
         ))
     }
 
-    #[instrument(skip(self))]
+    #[instrument(skip(self, request))]
     async fn controller_get_volume(
         &self,
-        _request: Request<ControllerGetVolumeRequest>,
+        request: Request<ControllerGetVolumeRequest>,
     ) -> Result<Response<ControllerGetVolumeResponse>, Status> {
-        debug!("ControllerGetVolume called");
-        Err(Status::unimplemented(
-            "ControllerGetVolume not yet implemented",
-        ))
+        let req = request.into_inner();
+        debug!("ControllerGetVolume called for volume: {}", req.volume_id);
+
+        if req.volume_id.is_empty() {
+            return Err(Status::invalid_argument("volume_id is required"));
+        }
+        let volume = Self::parse_volume_id(&req.volume_id)?;
+        self.check_volume_exists(&volume).await?;
+
+        // A migration that lost files is reported as abnormal until the
+        // volume is migrated again
+        let volume_condition = match self.migrations.status(&req.volume_id).await {
+            Some(job) => VolumeCondition {
+                abnormal: job.status == JobStatus::Failed || job.failed > 0,
+                message: job.describe(),
+            },
+            None => VolumeCondition {
+                abnormal: false,
+                message: "Volume directory is present".to_string(),
+            },
+        };
+
+        let recorded = self.provisioner.created_for(&volume).await.map_err(|e| {
+            error!("Failed to look up volume {}: {:#}", volume, e);
+            Status::internal(format!("Failed to look up volume: {:#}", e))
+        })?;
+        // Volumes created before the request was recorded only have a source
+        let (volume_context, mut capacity_bytes) = recorded
+            .and_then(|request| {
+                recorded_volume(&volume, &request, &self.layout_templates)
+                    .inspect_err(|e| warn!("Unreadable create request of {}: {:#}", volume, e))
+                    .ok()
+            })
+            .unwrap_or_else(|| {
+                let context = HashMap::from([("source".to_string(), volume.to_string())]);
+                (context, 0)
+            });
+        // A block limit is what the volume can actually hold, and follows
+        // ModifyVolume
+        match self.provisioner.volume_quota(&volume).await {
+            Ok(Some(usage)) if usage.bytes_limit > 0 => {
+                capacity_bytes = usage.bytes_limit as i64;
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Failed to read the quota of {}: {:#}", volume, e),
+        }
+
+        Ok(Response::new(ControllerGetVolumeResponse {
+            volume: Some(Volume {
+                capacity_bytes,
+                volume_id: req.volume_id,
+                volume_context,
+                content_source: None,
+                accessible_topology: Vec::new(),
+            }),
+            status: Some(controller_get_volume_response::VolumeStatus {
+                published_node_ids: Vec::new(),
+                volume_condition: Some(volume_condition),
+            }),
+        }))
     }
 
-    #[instrument(skip(self))]
+    #[instrument(skip(self, request))]
     async fn controller_modify_volume(
         &self,
-        _request: Request<ControllerModifyVolumeRequest>,
+        request: Request<ControllerModifyVolumeRequest>,
     ) -> Result<Response<ControllerModifyVolumeResponse>, Status> {
-        debug!("ControllerModifyVolume called");
-        Err(Status::unimplemented(
-            "ControllerModifyVolume not yet implemented",
-        ))
+        let req = request.into_inner();
+        info!(
+            "ControllerModifyVolume called for volume: {}",
+            req.volume_id
+        );
+
+        if req.volume_id.is_empty() {
+            return Err(Status::invalid_argument("volume_id is required"));
+        }
+        let volume = Self::parse_volume_id(&req.volume_id)?;
+        let changes =
+            VolumeChanges::from_parameters(&req.mutable_parameters, &self.layout_templates)
+                .map_err(|e| {
+                    Status::invalid_argument(format!("Invalid mutable parameters: {:#}", e))
+                })?;
+
+        if changes.is_empty() {
+            debug!("Nothing to change on volume {}", req.volume_id);
+            return Ok(Response::new(ControllerModifyVolumeResponse {}));
+        }
+
+        let _guard = self.lock("ControllerModifyVolume", &req.volume_id)?;
+
+        self.check_volume_idle(&req.volume_id)?;
+        if changes.migrate && self.migrations.is_running(&req.volume_id) {
+            return Err(Status::aborted(format!(
+                "A migration of {} is still running",
+                req.volume_id
+            )));
+        }
+
+        self.check_volume_exists(&volume).await?;
+
+        if let Some(layout) = &changes.layout {
+            self.check_client_version(layout.min_client_version())
+                .await?;
+            if !layout.is_empty() {
+                layout
+                    .validate(&self.topology(&volume).await?)
+                    .map_err(|e| Status::invalid_argument(format!("Invalid layout: {}", e)))?;
+            }
+        }
+
+        self.provisioner
+            .modify_volume(&volume, &changes)
+            .await
+            .map_err(|e| {
+                error!("Failed to modify volume {}: {:#}", req.volume_id, e);
+                Status::internal(format!("Failed to modify volume: {:#}", e))
+            })?;
+
+        if changes.migrate
+            && let Some(layout) = &changes.layout
+        {
+            self.migrations.start(&volume, layout).await.map_err(|e| {
+                error!("Failed to start migration of {}: {:#}", req.volume_id, e);
+                Status::internal(format!("Failed to start migration: {:#}", e))
+            })?;
+        }
+
+        info!("Successfully modified volume {}", req.volume_id);
+        Ok(Response::new(ControllerModifyVolumeResponse {}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csi_types::{CapacityRange, VolumeCapability};
+    use crate::jobs::store::CopyJob;
+    use crate::utils::testing::{config, parameters};
+    use tonic::Code;
+
+    const PARENT: &str = "10.0.0.1@tcp:/lustre/k8s";
+
+    async fn service(dir: &tempfile::TempDir) -> ControllerService {
+        ControllerService::new(&config(dir.path(), "controller"))
+            .await
+            .unwrap()
+    }
+
+    fn request(name: &str, pairs: &[(&str, &str)]) -> CreateVolumeRequest {
+        CreateVolumeRequest {
+            name: name.to_string(),
+            volume_capabilities: vec![VolumeCapability::default()],
+            parameters: parameters(pairs),
+            ..Default::default()
+        }
+    }
+
+    fn clone_of(volume_id: &str) -> Option<VolumeContentSource> {
+        Some(VolumeContentSource {
+            r#type: Some(volume_content_source::Type::Volume(
+                volume_content_source::VolumeSource {
+                    volume_id: volume_id.to_string(),
+                },
+            )),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_create_volume_rejects_invalid_requests() {
+        let dir = tempfile::tempdir().unwrap();
+        let controller = service(&dir).await;
+
+        let mut cases = vec![
+            request("", &[("source", PARENT)]),
+            CreateVolumeRequest {
+                volume_capabilities: Vec::new(),
+                ..request("pvc-1", &[("source", PARENT)])
+            },
+            request("pvc-1", &[]),
+            request("pvc-1", &[("source", "lustre")]),
+            request("pvc-1", &[("source", PARENT), ("populateMode", "copy")]),
+            request(
+                "pvc-1",
+                &[("source", PARENT), ("subPathTemplate", "${pod.label}")],
+            ),
+            request("pvc-1", &[("source", PARENT), ("stripeCount", "many")]),
+            CreateVolumeRequest {
+                volume_content_source: clone_of("10.0.0.9@tcp:/other/k8s/pvc-0"),
+                ..request("pvc-1", &[("source", PARENT)])
+            },
+            CreateVolumeRequest {
+                volume_content_source: clone_of("10.0.0.1@tcp:/lustre/k8s/pvc-0"),
+                ..request(
+                    "pvc-1",
+                    &[("source", PARENT), ("populateFrom", "lustre:/data")],
+                )
+            },
+        ];
+        for (i, req) in cases.drain(..).enumerate() {
+            let status = controller
+                .create_volume(Request::new(req))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument, "case {}", i);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_volume_in_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        let controller = service(&dir).await;
+
+        let _guard = controller
+            .locks
+            .try_acquire("CreateVolume", &[volume_key("pvc-1")])
+            .unwrap();
+        let status = controller
+            .create_volume(Request::new(request("pvc-1", &[("source", PARENT)])))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Aborted);
+    }
+
+    #[test]
+    fn test_create_request_identity() {
+        let req = request("pvc-1", &[("source", PARENT), ("stripeCount", "2")]);
+        assert_eq!(create_request(&req), create_request(&req.clone()));
+
+        let other = request("pvc-1", &[("source", PARENT), ("stripeCount", "4")]);
+        assert_ne!(create_request(&req), create_request(&other));
+
+        let cloned = CreateVolumeRequest {
+            volume_content_source: clone_of("10.0.0.1@tcp:/lustre/k8s/pvc-0"),
+            ..req.clone()
+        };
+        assert_ne!(create_request(&req), create_request(&cloned));
+    }
+
+    #[test]
+    fn test_recorded_volume() {
+        let req = CreateVolumeRequest {
+            mutable_parameters: parameters(&[("quotaBytes", "1G")]),
+            capacity_range: Some(CapacityRange {
+                required_bytes: 1 << 30,
+                limit_bytes: 0,
+            }),
+            ..request(
+                "pvc-1",
+                &[
+                    ("source", PARENT),
+                    ("stripeCount", "2"),
+                    ("csi.storage.k8s.io/pvc/name", "data"),
+                ],
+            )
+        };
+        let volume = VolumeSource::parse(PARENT).unwrap().child("pvc-1").unwrap();
+        let (context, capacity_bytes) =
+            recorded_volume(&volume, &create_request(&req), &BTreeMap::new()).unwrap();
+        assert_eq!(capacity_bytes, 1 << 30);
+        assert_eq!(context["source"], volume.to_string());
+        assert_eq!(context["stripeCount"], "2");
+        assert_eq!(context["quotaBytes"], (1u64 << 30).to_string());
+        assert!(!context.contains_key("csi.storage.k8s.io/pvc/name"));
+
+        assert!(recorded_volume(&volume, "not json", &BTreeMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_ready_from_marker() {
+        let snapshot_id = format!("{}/.klustre-snapshots/snapshot-1", PARENT);
+        let record = SnapshotRecord {
+            name: "snapshot-1".to_string(),
+            source_volume_id: format!("{}/pvc-1", PARENT),
+            created_at: 100,
+            group_snapshot_id: None,
+        };
+        let mut state = JobsState::default();
+        let mut job = CopyJob::new(record.source_volume_id.clone(), CopyMode::Copy);
+        job.status = JobStatus::Completed;
+        job.bytes = Some(42);
+        state.copies.insert(snapshot_id.clone(), job);
+
+        // A completed copy record alone does not make the snapshot ready
+        let pending = snapshot(&snapshot_id, &record, &state, None);
+        assert!(!pending.ready_to_use);
+
+        let marker = SnapshotMarker {
+            source_volume_id: record.source_volume_id.clone(),
+            created_at: 100,
+            bytes: 42,
+        };
+        let ready = snapshot(&snapshot_id, &record, &JobsState::default(), Some(&marker));
+        assert!(ready.ready_to_use);
+        assert_eq!(ready.size_bytes, 42);
+        assert_eq!(ready.creation_time.unwrap().seconds, 100);
+    }
+
+    #[tokio::test]
+    async fn test_delete_volume_of_other_drivers() {
+        let dir = tempfile::tempdir().unwrap();
+        let controller = service(&dir).await;
+
+        let status = controller
+            .delete_volume(Request::new(DeleteVolumeRequest::default()))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        // Nothing this driver provisioned, so there is nothing to delete
+        for volume_id in [
+            "pv-static-1",
+            "10.0.0.1@tcp:/lustre",
+            "10.0.0.1@tcp:/lustre/k8s/.klustre-snapshots/snapshot-1",
+        ] {
+            controller
+                .delete_volume(Request::new(DeleteVolumeRequest {
+                    volume_id: volume_id.to_string(),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_volume_in_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        let controller = service(&dir).await;
+        let volume_id = "10.0.0.1@tcp:/lustre/k8s/pvc-1";
+
+        let _guard = controller
+            .locks
+            .try_acquire("CreateVolume", &[volume_key(volume_id)])
+            .unwrap();
+        let status = controller
+            .delete_volume(Request::new(DeleteVolumeRequest {
+                volume_id: volume_id.to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Aborted);
     }
 }
@@ -0,0 +1,349 @@
+use crate::csi_types::{
+    CreateVolumeGroupSnapshotRequest, CreateVolumeGroupSnapshotResponse,
+    DeleteVolumeGroupSnapshotRequest, DeleteVolumeGroupSnapshotResponse,
+    GetVolumeGroupSnapshotRequest, GetVolumeGroupSnapshotResponse,
+    GroupControllerGetCapabilitiesRequest, GroupControllerGetCapabilitiesResponse,
+    GroupControllerServiceCapability, VolumeGroupSnapshot,
+    group_controller_server::GroupController, group_controller_service_capability,
+};
+use crate::jobs::store::{GroupSnapshotRecord, JobsState, now};
+use crate::provision::VolumeSource;
+use crate::services::controller::ControllerService;
+use tonic::{Request, Response, Status};
+use tracing::{debug, error, info, instrument, warn};
+
+/// Group snapshots on top of the controller's copy-based snapshots.
+///
+/// The group snapshot ID is the name the CO asked for, and member `i` is the
+/// snapshot `<name>-<i>` of the `i`-th source volume in sorted order, so a
+/// retried request finds the members of the first one.
+#[derive(Debug, Clone)]
+pub struct GroupControllerService {
+    controller: ControllerService,
+}
+
+impl GroupControllerService {
+    pub fn new(controller: ControllerService) -> Self {
+        info!("Creating GroupController service");
+        Self { controller }
+    }
+
+    /// Undo the members a failed CreateVolumeGroupSnapshot created; returns
+    /// whether all of them are gone
+    async fn remove_members(&self, snapshot_ids: &[String]) -> bool {
+        let mut removed = true;
+        for snapshot_id in snapshot_ids {
+            if let Err(e) = self.controller.remove_snapshot(snapshot_id).await {
+                error!(
+                    "Failed to clean up snapshot {}: {}",
+                    snapshot_id,
+                    e.message()
+                );
+                removed = false;
+            }
+        }
+        removed
+    }
+
+    /// CSI view of a group snapshot, ready once all of its members are
+    async fn group_snapshot(
+        &self,
+        state: &JobsState,
+        group_snapshot_id: &str,
+        record: &GroupSnapshotRecord,
+    ) -> VolumeGroupSnapshot {
+        let mut snapshots = Vec::new();
+        for id in &record.snapshot_ids {
+            if let Some(member) = state.snapshots.get(id) {
+                snapshots.push(self.controller.snapshot(id, member, state).await);
+            }
+        }
+        VolumeGroupSnapshot {
+            group_snapshot_id: group_snapshot_id.to_string(),
+            ready_to_use: snapshots.len() == record.snapshot_ids.len()
+                && snapshots.iter().all(|snapshot| snapshot.ready_to_use),
+            snapshots,
+            creation_time: Some(prost_types::Timestamp {
+                seconds: record.created_at as i64,
+                nanos: 0,
+            }),
+        }
+    }
+}
+
+/// Reject a request whose `snapshot_ids` are not the group's members
+fn check_members(record: &GroupSnapshotRecord, snapshot_ids: &[String]) -> Result<(), Status> {
+    let mut requested = snapshot_ids.to_vec();
+    requested.sort_unstable();
+    let mut members = record.snapshot_ids.clone();
+    members.sort_unstable();
+    if !requested.is_empty() && requested != members {
+        return Err(Status::invalid_argument(
+            "snapshot_ids do not match the members of the group snapshot",
+        ));
+    }
+    Ok(())
+}
+
+#[tonic::async_trait]
+impl GroupController for GroupControllerService {
+    async fn group_controller_get_capabilities(
+        &self,
+        _request: Request<GroupControllerGetCapabilitiesRequest>,
+    ) -> Result<Response<GroupControllerGetCapabilitiesResponse>, Status> {
+        debug!("GroupControllerGetCapabilities called");
+
+        let rpc =
+            group_controller_service_capability::rpc::Type::CreateDeleteGetVolumeGroupSnapshot;
+        Ok(Response::new(GroupControllerGetCapabilitiesResponse {
+            capabilities: vec![GroupControllerServiceCapability {
+                r#type: Some(group_controller_service_capability::Type::Rpc(
+                    group_controller_service_capability::Rpc { r#type: rpc as i32 },
+                )),
+            }],
+        }))
+    }
+
+    /// Members are created all together, or not at all; their copies are
+    /// then started one by one and the group is ready once every one
+    /// completed. The members are not crash-consistent: each is a copy of
+    /// a live volume, taken at its own pace.
+    #[instrument(skip(self, request))]
+    async fn create_volume_group_snapshot(
+        &self,
+        request: Request<CreateVolumeGroupSnapshotRequest>,
+    ) -> Result<Response<CreateVolumeGroupSnapshotResponse>, Status> {
+        let req = request.into_inner();
+        info!(
+            "CreateVolumeGroupSnapshot called for {} of {} volume(s)",
+            req.name,
+            req.source_volume_ids.len()
+        );
+
+        if req.name.is_empty() {
+            return Err(Status::invalid_argument("name is required"));
+        }
+        if req.source_volume_ids.is_empty() {
+            return Err(Status::invalid_argument("source_volume_ids are required"));
+        }
+        let mut sources = req
+            .source_volume_ids
+            .iter()
+            .map(|id| ControllerService::parse_volume_id(id))
+            .collect::<Result<Vec<VolumeSource>, Status>>()?;
+        sources.sort_unstable_by_key(|source| source.to_string());
+        sources.dedup();
+
+        let _guard = self
+            .controller
+            .lock("CreateVolumeGroupSnapshot", &req.name)?;
+
+        let names: Vec<String> = (0..sources.len())
+            .map(|index| format!("{}-{}", req.name, index))
+            .collect();
+        let existing = self
+            .controller
+            .jobs()
+            .snapshot()
+            .await
+            .group_snapshots
+            .remove(&req.name);
+        if let Some(record) = &existing {
+            let same_members = sources.iter().zip(&names).all(|(source, name)| {
+                source
+                    .snapshot(name)
+                    .is_ok_and(|snapshot| record.snapshot_ids.contains(&snapshot.to_string()))
+            });
+            if !same_members || record.snapshot_ids.len() != sources.len() {
+                return Err(Status::already_exists(format!(
+                    "Group snapshot {} was taken of other volumes",
+                    req.name
+                )));
+            }
+        }
+
+        let mut members = Vec::new();
+        let mut created = Vec::new();
+        for (source, name) in sources.iter().zip(&names) {
+            match self
+                .controller
+                .prepare_snapshot(name, source, Some(&req.name))
+                .await
+            {
+                Ok((snapshot_id, record, is_new)) => {
+                    if is_new {
+                        created.push(snapshot_id.clone());
+                    }
+                    members.push((snapshot_id, record));
+                }
+                Err(status) => {
+                    warn!(
+                        "Group snapshot {} failed on volume {}: {}",
+                        req.name,
+                        source,
+                        status.message()
+                    );
+                    self.remove_members(&created).await;
+                    return Err(status);
+                }
+            }
+        }
+
+        if existing.is_none() {
+            let record = GroupSnapshotRecord {
+                snapshot_ids: members.iter().map(|(id, _)| id.clone()).collect(),
+                created_at: now(),
+            };
+            let recorded = self
+                .controller
+                .jobs()
+                .update(|state| state.group_snapshots.insert(req.name.clone(), record))
+                .await;
+            if let Err(e) = recorded {
+                error!("Failed to record group snapshot {}: {:#}", req.name, e);
+                self.remove_members(&created).await;
+                return Err(Status::internal(format!(
+                    "Failed to record group snapshot: {:#}",
+                    e
+                )));
+            }
+        }
+
+        // A group with a failed member is of no use, so all members go and
+        // the snapshotter's retry starts over. Copies still running cannot
+        // be removed; the group is then kept for the retry to pick up.
+        for ((snapshot_id, record), source) in members.iter().zip(&sources) {
+            if let Err(status) = self
+                .controller
+                .snapshot_progress(snapshot_id, source, record)
+                .await
+            {
+                warn!(
+                    "Group snapshot {} failed on volume {}: {}",
+                    req.name,
+                    source,
+                    status.message()
+                );
+                let snapshot_ids: Vec<String> = members.iter().map(|(id, _)| id.clone()).collect();
+                if self.remove_members(&snapshot_ids).await {
+                    let forgotten = self
+                        .controller
+                        .jobs()
+                        .update(|state| state.group_snapshots.remove(&req.name))
+                        .await;
+                    if let Err(e) = forgotten {
+                        warn!("Failed to drop group snapshot {}: {:#}", req.name, e);
+                    }
+                }
+                return Err(status);
+            }
+        }
+
+        let state = self.controller.jobs().snapshot().await;
+        let record = state
+            .group_snapshots
+            .get(&req.name)
+            .ok_or_else(|| Status::internal("Group snapshot record is missing"))?;
+        let group_snapshot = self.group_snapshot(&state, &req.name, record).await;
+        if group_snapshot.ready_to_use {
+            info!("Group snapshot {} is ready", req.name);
+        }
+        // Ready means every member copy completed, not that the members
+        // share a point in time; see the method's documentation
+        Ok(Response::new(CreateVolumeGroupSnapshotResponse {
+            group_snapshot: Some(group_snapshot),
+        }))
+    }
+
+    #[instrument(skip(self, request))]
+    async fn delete_volume_group_snapshot(
+        &self,
+        request: Request<DeleteVolumeGroupSnapshotRequest>,
+    ) -> Result<Response<DeleteVolumeGroupSnapshotResponse>, Status> {
+        let req = request.into_inner();
+        info!(
+            "DeleteVolumeGroupSnapshot called for group snapshot: {}",
+            req.group_snapshot_id
+        );
+
+        if req.group_snapshot_id.is_empty() {
+            return Err(Status::invalid_argument("group_snapshot_id is required"));
+        }
+
+        let _guard = self
+            .controller
+            .lock("DeleteVolumeGroupSnapshot", &req.group_snapshot_id)?;
+
+        // Without a record, the members the CO knows of are still removed
+        let snapshot_ids = match self
+            .controller
+            .jobs()
+            .snapshot()
+            .await
+            .group_snapshots
+            .remove(&req.group_snapshot_id)
+        {
+            Some(record) => {
+                check_members(&record, &req.snapshot_ids)?;
+                record.snapshot_ids
+            }
+            None => req.snapshot_ids.clone(),
+        };
+
+        for snapshot_id in &snapshot_ids {
+            self.controller.remove_snapshot(snapshot_id).await?;
+        }
+        self.controller
+            .jobs()
+            .update(|state| state.group_snapshots.remove(&req.group_snapshot_id))
+            .await
+            .map_err(|e| {
+                error!(
+                    "Failed to drop group snapshot {}: {:#}",
+                    req.group_snapshot_id, e
+                );
+                Status::internal(format!("Failed to drop group snapshot record: {:#}", e))
+            })?;
+
+        info!(
+            "Successfully deleted group snapshot {}",
+            req.group_snapshot_id
+        );
+        Ok(Response::new(DeleteVolumeGroupSnapshotResponse {}))
+    }
+
+    #[instrument(skip(self, request))]
+    async fn get_volume_group_snapshot(
+        &self,
+        request: Request<GetVolumeGroupSnapshotRequest>,
+    ) -> Result<Response<GetVolumeGroupSnapshotResponse>, Status> {
+        let req = request.into_inner();
+        debug!(
+            "GetVolumeGroupSnapshot called for group snapshot: {}",
+            req.group_snapshot_id
+        );
+
+        if req.group_snapshot_id.is_empty() {
+            return Err(Status::invalid_argument("group_snapshot_id is required"));
+        }
+
+        let state = self.controller.jobs().snapshot().await;
+        let record = state
+            .group_snapshots
+            .get(&req.group_snapshot_id)
+            .ok_or_else(|| {
+                Status::not_found(format!(
+                    "Group snapshot {} does not exist",
+                    req.group_snapshot_id
+                ))
+            })?;
+        check_members(record, &req.snapshot_ids)?;
+
+        Ok(Response::new(GetVolumeGroupSnapshotResponse {
+            group_snapshot: Some(
+                self.group_snapshot(&state, &req.group_snapshot_id, record)
+                    .await,
+            ),
+        }))
+    }
+}
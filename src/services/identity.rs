@@ -11,14 +11,17 @@ use tracing::{debug, info, instrument};
 pub struct IdentityService {
     driver_name: String,
     driver_version: String,
+    /// Whether this instance also serves the Controller service
+    controller: bool,
 }
 
 impl IdentityService {
-    pub fn new(driver_name: String, driver_version: String) -> Self {
+    pub fn new(driver_name: String, driver_version: String, controller: bool) -> Self {
         info!("Creating Identity service for driver: {}", driver_name);
         Self {
             driver_name,
             driver_version,
+            controller,
         }
     }
 }
@@ -52,17 +55,26 @@ impl Identity for IdentityService {
     ) -> Result<Response<GetPluginCapabilitiesResponse>, Status> {
         debug!("Handling GetPluginCapabilities request");
 
+        let mut services = vec![
+            // Advertise that we support volume accessibility constraints
+            Type::VolumeAccessibilityConstraints,
+        ];
+        if self.controller {
+            services.push(Type::ControllerService);
+            services.push(Type::GroupControllerService);
+        }
+
         let response = GetPluginCapabilitiesResponse {
-            capabilities: vec![
-                // Advertise that we support volume accessibility constraints
-                PluginCapability {
+            capabilities: services
+                .into_iter()
+                .map(|service| PluginCapability {
                     r#type: Some(plugin_capability::Type::Service(
                         plugin_capability::Service {
-                            r#type: Type::VolumeAccessibilityConstraints as i32,
+                            r#type: service as i32,
                         },
                     )),
-                },
-            ],
+                })
+                .collect(),
         };
 
         Ok(Response::new(response))
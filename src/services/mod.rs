@@ -1,8 +1,10 @@
 pub mod controller;
+pub mod group_controller;
 pub mod identity;
 pub mod node;
 
 // Re-export
 pub use controller::ControllerService;
+pub use group_controller::GroupControllerService;
 pub use identity::IdentityService;
 pub use node::NodeService;
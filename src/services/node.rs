@@ -1,25 +1,61 @@
+use crate::config::{Config, PccBackend};
 use crate::csi_types::{
     NodeExpandVolumeRequest, NodeExpandVolumeResponse, NodeGetCapabilitiesRequest,
     NodeGetCapabilitiesResponse, NodeGetInfoRequest, NodeGetInfoResponse,
     NodeGetVolumeStatsRequest, NodeGetVolumeStatsResponse, NodePublishVolumeRequest,
     NodePublishVolumeResponse, NodeServiceCapability, NodeStageVolumeRequest,
     NodeStageVolumeResponse, NodeUnpublishVolumeRequest, NodeUnpublishVolumeResponse,
-    NodeUnstageVolumeRequest, NodeUnstageVolumeResponse, node_server::Node,
-    node_service_capability,
+    NodeUnstageVolumeRequest, NodeUnstageVolumeResponse, VolumeCondition, VolumeUsage,
+    node_server::Node, node_service_capability, volume_usage,
 };
-use crate::lustre::{LustreClient, MountManager};
+use crate::lustre::health::{FsUsage, MountHealth, statvfs};
+use crate::lustre::hsm::HsmRestore;
+use crate::lustre::mountinfo::same_lustre_source;
+use crate::lustre::prefetch::PrefetchHints;
+use crate::lustre::{ClientTuning, LustreClient, MountConflict, MountManager};
+use crate::provision::{ScratchSpace, VolumeSource};
+use crate::state::{
+    OperationKind, OperationRecord, PublishRecord, StageRecord, StateStore, reconcile,
+};
+use crate::tasks::{HealthMonitor, OrphanCollector, PccAttaches, PublishCleanup};
+use crate::utils::locks::{OperationGuard, OperationLocks, path_key, volume_key};
+use crate::utils::pod::{PodInfo, SubPathTemplate};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 use tonic::{Request, Response, Status};
-use tracing::{debug, error, info, instrument, warn};
+use tracing::field::Empty;
+use tracing::{Span, debug, error, info, instrument, warn};
 
 #[derive(Debug, Clone)]
 pub struct NodeService {
     node_id: String,
     mount_manager: MountManager,
     lustre_client: LustreClient,
+    locks: OperationLocks,
+    state: StateStore,
+    orphan_collector: OrphanCollector,
+    health_monitor: HealthMonitor,
+    cleanup: PublishCleanup,
+    pcc_backends: BTreeMap<String, PccBackend>,
+    /// PCC attach tasks still running, by target path
+    pcc_attaches: PccAttaches,
+    /// Prefetch hints in flight, shared by all volumes on the node
+    prefetch_permits: Arc<Semaphore>,
+    /// Where ephemeral volumes get their scratch directories, if anywhere
+    scratch: Option<ScratchSpace>,
 }
 
 impl NodeService {
-    pub fn new(node_id: String) -> Self {
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        Self::with_mount_manager(config, MountManager::new(&config.lustre))
+    }
+
+    /// Node service mounting through `mount_manager`, e.g. a fake host in
+    /// tests
+    fn with_mount_manager(config: &Config, mount_manager: MountManager) -> anyhow::Result<Self> {
+        let node_id = config.driver.node_id.clone();
         info!("Creating Node service for node: {}", node_id);
 
         let lustre_client = LustreClient::new();
@@ -33,41 +69,589 @@ impl NodeService {
             info!("Lustre version: {}", version);
         }
 
-        Self {
+        let locks = OperationLocks::new();
+        let state = StateStore::open(&config.driver.plugin_dir)?;
+        let scratch = ScratchSpace::new(config, mount_manager.clone())?;
+        let health_monitor = HealthMonitor::new(
+            config.health.clone(),
+            lustre_client.clone(),
+            mount_manager.clone(),
+            state.clone(),
+            locks.clone(),
+        );
+        let pcc_attaches = PccAttaches::default();
+        let cleanup = PublishCleanup::new(
+            lustre_client.clone(),
+            state.clone(),
+            config.pcc.backends.clone(),
+            pcc_attaches.clone(),
+            scratch.clone(),
+            health_monitor.timeout(),
+        );
+        let orphan_collector = OrphanCollector::new(
+            config.orphan_gc.clone(),
+            config.driver.name.clone(),
+            mount_manager.clone(),
+            state.clone(),
+            locks.clone(),
+            cleanup.clone(),
+        );
+
+        Ok(Self {
             node_id,
-            mount_manager: MountManager::new(),
+            mount_manager,
             lustre_client,
+            locks,
+            state,
+            orphan_collector,
+            health_monitor,
+            cleanup,
+            pcc_backends: config.pcc.backends.clone(),
+            pcc_attaches,
+            prefetch_permits: Arc::new(Semaphore::new(PREFETCH_CONCURRENCY)),
+            scratch,
+        })
+    }
+
+    /// Start periodic node maintenance tasks
+    pub fn spawn_background_tasks(&self) {
+        self.orphan_collector.clone().spawn();
+        self.health_monitor.clone().spawn();
+
+        // Deletions whose grace period ran on while the plugin was down
+        let node = self.clone();
+        tokio::spawn(async move {
+            for (source, due) in node.state.snapshot().await.scratch_deletions {
+                node.cleanup.spawn_scratch_deletion(source, due);
+            }
+        });
+    }
+
+    /// Finish or roll back operations interrupted by a restart
+    pub async fn reconcile(&self) -> anyhow::Result<()> {
+        reconcile::reconcile(
+            &self.state,
+            &self.mount_manager,
+            self.cleanup.scratch_grace(),
+        )
+        .await
+    }
+
+    /// Serialize operations on a volume and path, rejecting overlapping calls
+    fn lock(&self, operation: &str, volume_id: &str, path: &str) -> Result<OperationGuard, Status> {
+        self.lock_keys(operation, &[volume_key(volume_id), path_key(path)], &[])
+    }
+
+    /// Lock a target path for publishing or unpublishing; the volume is held
+    /// shared, so pods sharing it publish in parallel but not while it is
+    /// staged or unstaged
+    fn lock_target(
+        &self,
+        operation: &str,
+        volume_id: &str,
+        target_path: &str,
+    ) -> Result<OperationGuard, Status> {
+        self.lock_keys(
+            operation,
+            &[path_key(target_path)],
+            &[volume_key(volume_id)],
+        )
+    }
+
+    fn lock_keys(
+        &self,
+        operation: &str,
+        keys: &[String],
+        shared: &[String],
+    ) -> Result<OperationGuard, Status> {
+        self.locks
+            .try_acquire_shared(operation, keys, shared)
+            .map_err(|(key, holder)| {
+                warn!("{} rejected: {} is locked by {}", operation, key, holder);
+                Status::aborted(format!(
+                    "An operation is already in progress for {} ({})",
+                    key, holder
+                ))
+            })
+    }
+
+    /// PCC backend a volume context asks for, by name
+    fn pcc_backend(
+        &self,
+        volume_context: &HashMap<String, String>,
+    ) -> Result<Option<(String, PccBackend)>, Status> {
+        let Some(name) = volume_context.get("pccBackend") else {
+            return Ok(None);
+        };
+        let backend = self.pcc_backends.get(name).ok_or_else(|| {
+            Status::invalid_argument(format!("Unknown PCC backend {:?} on this node", name))
+        })?;
+        Ok(Some((name.clone(), backend.clone())))
+    }
+
+    /// Attach the files of a published volume to PCC in the background;
+    /// copying them into the cache can take far longer than a publish may
+    fn spawn_pcc_attach(&self, target_path: &str, backend: PccBackend) {
+        let lustre_client = self.lustre_client.clone();
+        let attaches = self.pcc_attaches.clone();
+        let target = target_path.to_string();
+
+        // Held until the handle is in, so a task that finishes at once still
+        // finds its own handle to remove
+        let mut running = self.pcc_attaches.lock().unwrap();
+        let task = tokio::spawn(async move {
+            match lustre_client
+                .pcc_attach_dir(&target, &backend, PCC_ATTACH_TIMEOUT)
+                .await
+            {
+                Ok(attached) => info!("Attached {} file(s) below {} to PCC", attached, target),
+                Err(e) => warn!("Failed to attach files below {} to PCC: {:#}", target, e),
+            }
+            let mut attaches = attaches.lock().unwrap();
+            if attaches
+                .get(&target)
+                .is_some_and(|handle| handle.id() == tokio::task::id())
+            {
+                attaches.remove(&target);
+            }
+        });
+        if let Some(previous) = running.insert(target_path.to_string(), task.abort_handle()) {
+            previous.abort();
         }
     }
+
+    /// Send willread hints for the files a volume asks to prefetch in the
+    /// background; failures are logged only
+    fn spawn_prefetch(&self, volume_id: &str, target_path: &str, hints: PrefetchHints) {
+        let lustre_client = self.lustre_client.clone();
+        let permits = self.prefetch_permits.clone();
+        let volume_id = volume_id.to_string();
+        let root = self.mount_manager.host_path(target_path);
+        tokio::spawn(async move {
+            let files = match hints.resolve(&root) {
+                Ok(files) => files,
+                Err(e) => {
+                    warn!(
+                        "Failed to list files to prefetch for {}: {:#}",
+                        volume_id, e
+                    );
+                    return;
+                }
+            };
+
+            let mut tasks = JoinSet::new();
+            for file in files {
+                let Ok(permit) = permits.clone().acquire_owned().await else {
+                    return;
+                };
+                let lustre_client = lustre_client.clone();
+                tasks.spawn(async move {
+                    let result = lustre_client
+                        .ladvise_willread(&file, PREFETCH_TIMEOUT)
+                        .await;
+                    drop(permit);
+                    (file, result)
+                });
+            }
+
+            let (mut hinted, mut failed) = (0, 0);
+            while let Some(joined) = tasks.join_next().await {
+                match joined {
+                    Ok((file, Ok(()))) => {
+                        debug!("Sent willread hint for {}", file);
+                        hinted += 1;
+                    }
+                    Ok((file, Err(e))) => {
+                        warn!("Failed to prefetch {}: {:#}", file, e);
+                        failed += 1;
+                    }
+                    Err(e) => {
+                        warn!("Prefetch task of {} panicked: {}", volume_id, e);
+                        failed += 1;
+                    }
+                }
+            }
+            info!(
+                "Sent prefetch hints for {} file(s) of {}, {} failed",
+                hinted, volume_id, failed
+            );
+        });
+    }
+
+    /// Take back a publish whose mount was made but that failed afterwards,
+    /// so kubelet's retry starts from an unmounted target
+    async fn withdraw_publish(&self, volume_id: &str, target_path: &str) {
+        self.state
+            .begin(
+                target_path,
+                OperationRecord::new(OperationKind::Unpublish, volume_id),
+            )
+            .await;
+        let result = self.mount_manager.unmount(target_path).await;
+        self.state
+            .finish(target_path, |state| {
+                if result.is_ok() {
+                    state.publishes.remove(target_path);
+                    state.recount();
+                }
+            })
+            .await;
+        if let Err(e) = result {
+            warn!(
+                "Failed to unmount {} after a failed publish: {:#}",
+                target_path, e
+            );
+        }
+    }
+
+    /// Create the scratch directory of an ephemeral volume and return its
+    /// Lustre source; pods cannot choose a source of their own
+    async fn scratch_source(
+        &self,
+        volume_id: &str,
+        volume_context: &HashMap<String, String>,
+        pod: &PodInfo,
+    ) -> Result<String, Status> {
+        let scratch = self.scratch.as_ref().ok_or_else(|| {
+            Status::failed_precondition("No scratch space for ephemeral volumes is configured")
+        })?;
+        if volume_context.contains_key("source") {
+            return Err(Status::invalid_argument(
+                "Ephemeral volumes get a scratch directory and take no source",
+            ));
+        }
+
+        let dir = scratch.create(volume_id).await.map_err(|e| {
+            error!(
+                "Failed to create scratch directory for {}: {:#}",
+                volume_id, e
+            );
+            Status::internal(format!("Failed to create scratch directory: {:#}", e))
+        })?;
+        let source = dir.to_string();
+        // A publish within the grace period takes the directory back
+        let kept = self
+            .state
+            .update(|state| state.scratch_deletions.remove(&source))
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update node state: {:#}", e)))?;
+        info!(
+            "{} scratch directory {} for pod {}",
+            if kept.is_some() { "Reusing" } else { "Created" },
+            source,
+            pod
+        );
+        Ok(source)
+    }
+
+    /// Extract and validate the Lustre source from a volume context
+    fn lustre_source<'a>(
+        &self,
+        volume_context: &'a HashMap<String, String>,
+    ) -> Result<&'a str, Status> {
+        let source = volume_context
+            .get("source")
+            .ok_or_else(|| Status::invalid_argument("source not found in volume_context"))?;
+
+        // Validate source format
+        if let Err(e) = self.lustre_client.validate_source(source) {
+            return Err(Status::invalid_argument(format!(
+                "Invalid Lustre source: {}",
+                e
+            )));
+        }
+
+        Ok(source)
+    }
+}
+
+/// Volume context key kubelet sets on ephemeral inline volumes
+const EPHEMERAL_KEY: &str = "csi.storage.k8s.io/ephemeral";
+
+/// Attach the pod a volume is published for to the current span
+fn record_pod(pod: &PodInfo) {
+    let span = Span::current();
+    span.record("pod", tracing::field::display(pod));
+    if let Some(uid) = &pod.uid {
+        span.record("pod_uid", uid.as_str());
+    }
+    if let Some(account) = &pod.service_account {
+        span.record("service_account", account.as_str());
+    }
+}
+
+/// Create a pod's directory below a staged volume, writable by any user
+/// like the scratch directories of ephemeral volumes
+async fn create_sub_path(mount_manager: &MountManager, source: &str) -> anyhow::Result<()> {
+    use anyhow::Context;
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = mount_manager.host_path(source);
+    if tokio::fs::metadata(&path).await.is_err() {
+        tokio::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o1777)
+            .create(&path)
+            .await
+            .with_context(|| format!("Failed to create {}", source))?;
+        // The mode given is narrowed by the umask
+        tokio::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o1777))
+            .await
+            .with_context(|| format!("Failed to open up {}", source))?;
+        info!("Created pod directory {}", source);
+    }
+    Ok(())
+}
+
+/// Upper bound for attaching or detaching the files of one volume, which
+/// copies their data
+const PCC_ATTACH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3600);
+
+/// Willread hints sent at once across all volumes on the node
+const PREFETCH_CONCURRENCY: usize = 8;
+
+/// Upper bound for one `lfs ladvise` call
+const PREFETCH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Map a mount failure to a gRPC status
+fn mount_error(e: anyhow::Error) -> Status {
+    // CSI expects ALREADY_EXISTS when the path is mounted incompatibly
+    if let Some(conflict) = e.downcast_ref::<MountConflict>() {
+        return Status::already_exists(conflict.to_string());
+    }
+    Status::internal(format!("Mount failed: {}", e))
+}
+
+/// Byte and inode usage in CSI form
+fn volume_usage(usage: &FsUsage) -> Vec<VolumeUsage> {
+    vec![
+        VolumeUsage {
+            available: usage.available_bytes as i64,
+            total: usage.total_bytes as i64,
+            used: usage.used_bytes as i64,
+            unit: volume_usage::Unit::Bytes as i32,
+        },
+        VolumeUsage {
+            available: usage.free_inodes as i64,
+            total: usage.total_inodes as i64,
+            used: usage.used_inodes as i64,
+            unit: volume_usage::Unit::Inodes as i32,
+        },
+    ]
+}
+
+/// The filesystem a Lustre source is on, `mgs@net:/fsname`
+fn lustre_filesystem(source: &str) -> String {
+    VolumeSource::parse(source)
+        .map(|volume| volume.filesystem())
+        .unwrap_or_else(|_| source.to_string())
+}
+
+/// Mount options from the volume context, or the driver defaults
+fn mount_options(volume_context: &HashMap<String, String>) -> Vec<String> {
+    volume_context
+        .get("mountOptions")
+        .map(|s| s.split(',').map(String::from).collect())
+        .unwrap_or_else(|| vec!["flock".to_string(), "user_xattr".to_string()])
 }
 
 #[tonic::async_trait]
 impl Node for NodeService {
-    #[instrument(skip(self))]
+    #[instrument(skip(self, request))]
     async fn node_stage_volume(
         &self,
-        _request: Request<NodeStageVolumeRequest>,
+        request: Request<NodeStageVolumeRequest>,
     ) -> Result<Response<NodeStageVolumeResponse>, Status> {
-        debug!("NodeStageVolume called");
-        // For Lustre, we don't need staging
+        let req = request.into_inner();
+
+        info!("NodeStageVolume called for volume: {}", req.volume_id);
+        debug!("Staging target path: {}", req.staging_target_path);
+
+        // Validate request
+        if req.volume_id.is_empty() {
+            return Err(Status::invalid_argument("volume_id is required"));
+        }
+        if req.staging_target_path.is_empty() {
+            return Err(Status::invalid_argument("staging_target_path is required"));
+        }
+
+        let _guard = self.lock("NodeStageVolume", &req.volume_id, &req.staging_target_path)?;
+
+        let source = self.lustre_source(&req.volume_context)?;
+        let mount_options = mount_options(&req.volume_context);
+        let tuning = ClientTuning::from_volume_context(&req.volume_context)
+            .map_err(|e| Status::invalid_argument(format!("Invalid client tuning: {}", e)))?;
+        let staging_path = req.staging_target_path.as_str();
+
+        // Tuning belongs to the client mount, which every publish of this
+        // staging path shares; a different request cannot be honoured.
+        // Mounts of other volumes on the same filesystem must agree on the
+        // settings both of them make.
+        let filesystem = lustre_filesystem(source);
+        let conflicts: Vec<(String, Vec<String>)> = self
+            .state
+            .snapshot()
+            .await
+            .stages
+            .iter()
+            .filter_map(|(path, stage)| {
+                let staged = ClientTuning::from_settings(stage.tuning.clone());
+                let conflicts = if path == staging_path {
+                    staged.conflicts_with(&tuning)
+                } else if same_lustre_source(&lustre_filesystem(&stage.source), &filesystem) {
+                    staged.conflicts_in_common(&tuning)
+                } else {
+                    return None;
+                };
+                (!conflicts.is_empty()).then(|| (path.clone(), conflicts))
+            })
+            .collect();
+        if let Some((path, conflicts)) = conflicts.first() {
+            warn!(
+                "Volume {} conflicts with the client tuning of {}: {}",
+                req.volume_id,
+                path,
+                conflicts.join(", ")
+            );
+            return Err(Status::already_exists(format!(
+                "{} is staged with conflicting client tuning ({})",
+                path,
+                conflicts.join(", ")
+            )));
+        }
+
+        self.state
+            .begin(
+                staging_path,
+                OperationRecord {
+                    source: source.to_string(),
+                    mount_options: mount_options.clone(),
+                    tuning: tuning.settings().clone(),
+                    ..OperationRecord::new(OperationKind::Stage, &req.volume_id)
+                },
+            )
+            .await;
+
+        // The Lustre client mount happens once per volume here; every
+        // publish is then a cheap bind mount of the staging path.
+        let result = self
+            .mount_manager
+            .mount(source, staging_path, &mount_options)
+            .await;
+
+        let tuning_result = match &result {
+            Ok(()) => {
+                self.lustre_client
+                    .apply_tuning(staging_path, &tuning, self.health_monitor.timeout())
+                    .await
+            }
+            Err(_) => Ok(()),
+        };
+
+        self.state
+            .finish(staging_path, |state| {
+                if result.is_ok() {
+                    let refcount = state.publishes_from(staging_path);
+                    state.stages.insert(
+                        staging_path.to_string(),
+                        StageRecord {
+                            volume_id: req.volume_id.clone(),
+                            source: source.to_string(),
+                            mount_options: mount_options.clone(),
+                            tuning: tuning.settings().clone(),
+                            refcount,
+                        },
+                    );
+                }
+            })
+            .await;
+
+        if let Err(e) = result {
+            error!("Failed to stage volume: {}", e);
+            return Err(mount_error(e));
+        }
+
+        // The mount stays staged; a retry applies the tuning again
+        if let Err(e) = tuning_result {
+            error!("Failed to apply client tuning to {}: {:#}", staging_path, e);
+            return Err(Status::internal(format!(
+                "Failed to apply client tuning: {:#}",
+                e
+            )));
+        }
+
+        info!("Successfully staged volume {}", req.volume_id);
         Ok(Response::new(NodeStageVolumeResponse {}))
     }
 
-    #[instrument(skip(self))]
+    #[instrument(skip(self, request))]
     async fn node_unstage_volume(
         &self,
-        _request: Request<NodeUnstageVolumeRequest>,
+        request: Request<NodeUnstageVolumeRequest>,
     ) -> Result<Response<NodeUnstageVolumeResponse>, Status> {
-        debug!("NodeUnstageVolume called");
+        let req = request.into_inner();
+
+        info!("NodeUnstageVolume called for volume: {}", req.volume_id);
+        debug!("Staging target path: {}", req.staging_target_path);
+
+        // Validate request
+        if req.volume_id.is_empty() {
+            return Err(Status::invalid_argument("volume_id is required"));
+        }
+        if req.staging_target_path.is_empty() {
+            return Err(Status::invalid_argument("staging_target_path is required"));
+        }
+
+        let _guard = self.lock(
+            "NodeUnstageVolume",
+            &req.volume_id,
+            &req.staging_target_path,
+        )?;
+
+        let staging_path = req.staging_target_path.as_str();
+
+        let refcount = self.state.snapshot().await.publishes_from(staging_path);
+        if refcount > 0 {
+            warn!(
+                "Unstaging {} while {} publishes still reference it",
+                staging_path, refcount
+            );
+        }
+
+        self.state
+            .begin(
+                staging_path,
+                OperationRecord::new(OperationKind::Unstage, &req.volume_id),
+            )
+            .await;
+
+        let result = self.mount_manager.unmount(staging_path).await;
+
+        self.state
+            .finish(staging_path, |state| {
+                if result.is_ok() {
+                    state.stages.remove(staging_path);
+                }
+            })
+            .await;
+
+        if let Err(e) = result {
+            error!("Failed to unstage volume: {}", e);
+            return Err(Status::internal(format!("Unmount failed: {}", e)));
+        }
+
+        info!("Successfully unstaged volume {}", req.volume_id);
         Ok(Response::new(NodeUnstageVolumeResponse {}))
     }
 
-    #[instrument(skip(self, request))]
+    #[instrument(skip(self, request), fields(pod = Empty, pod_uid = Empty, service_account = Empty))]
     async fn node_publish_volume(
         &self,
         request: Request<NodePublishVolumeRequest>,
     ) -> Result<Response<NodePublishVolumeResponse>, Status> {
         let req = request.into_inner();
+        let pod = PodInfo::from_volume_context(&req.volume_context);
+        record_pod(&pod);
 
         info!("NodePublishVolume called for volume: {}", req.volume_id);
         debug!("Target path: {}", req.target_path);
@@ -80,42 +664,182 @@ impl Node for NodeService {
             return Err(Status::invalid_argument("target_path is required"));
         }
 
+        let _guard = self.lock_target("NodePublishVolume", &req.volume_id, &req.target_path)?;
+
         // Get volume context (contains Lustre-specific info)
         let volume_context = req.volume_context;
-        let source = volume_context
-            .get("source")
-            .ok_or_else(|| Status::invalid_argument("source not found in volume_context"))?;
-
-        // Validate source format
-        if let Err(e) = self.lustre_client.validate_source(source) {
-            return Err(Status::invalid_argument(format!(
-                "Invalid Lustre source: {}",
-                e
-            )));
+        let ephemeral = volume_context
+            .get(EPHEMERAL_KEY)
+            .is_some_and(|v| v == "true");
+        let source = if ephemeral {
+            self.scratch_source(&req.volume_id, &volume_context, &pod)
+                .await?
+        } else {
+            self.lustre_source(&volume_context)?.to_string()
+        };
+        let source = source.as_str();
+        // A scratch directory starts out empty: nothing to restore from HSM,
+        // cache or prefetch
+        let (restore, pcc, prefetch) = if ephemeral {
+            (None, None, None)
+        } else {
+            (
+                HsmRestore::from_volume_context(&volume_context)
+                    .map_err(|e| Status::invalid_argument(format!("Invalid HSM restore: {}", e)))?,
+                self.pcc_backend(&volume_context)?,
+                PrefetchHints::from_volume_context(&volume_context).map_err(|e| {
+                    Status::invalid_argument(format!("Invalid prefetch hints: {}", e))
+                })?,
+            )
+        };
+        let pcc_backend = pcc.as_ref().map(|(name, _)| name.clone());
+        let staging_path = Some(req.staging_target_path.clone()).filter(|p| !p.is_empty());
+        let sub_path = SubPathTemplate::from_volume_context(&volume_context)
+            .and_then(|template| template.map(|t| t.render(&pod)).transpose())
+            .map_err(|e| Status::invalid_argument(format!("Invalid sub path: {:#}", e)))?;
+        if sub_path.is_some() && staging_path.is_none() {
+            return Err(Status::invalid_argument(
+                "subPathTemplate needs a staged volume",
+            ));
         }
-        // Get mount options
-        let mount_options = volume_context
-            .get("mountOptions")
-            .map(|s| s.split(',').map(String::from).collect())
-            .unwrap_or_else(|| vec!["flock".to_string(), "user_xattr".to_string()]);
+        let pod = Some(pod).filter(|pod| !pod.is_empty());
+
+        self.state
+            .begin(
+                &req.target_path,
+                OperationRecord {
+                    source: source.to_string(),
+                    staging_path: staging_path.clone(),
+                    read_only: req.readonly,
+                    pcc_backend: pcc_backend.clone(),
+                    ephemeral,
+                    sub_path: sub_path.clone(),
+                    pod: pod.clone(),
+                    ..OperationRecord::new(OperationKind::Publish, &req.volume_id)
+                },
+            )
+            .await;
+
+        let record = PublishRecord {
+            volume_id: req.volume_id.clone(),
+            source: source.to_string(),
+            staging_path: staging_path.clone(),
+            read_only: req.readonly,
+            pcc_backend,
+            ephemeral,
+            sub_path,
+            pod: pod.clone(),
+        };
+        let result = match record.bind_source() {
+            None => {
+                // Get mount options
+                let mount_options = mount_options(&volume_context);
+
+                info!("Mounting Lustre source: {} to {}", source, req.target_path);
+                self.mount_manager
+                    .mount(source, &req.target_path, &mount_options)
+                    .await
+            }
+            Some(bind_source) => {
+                let created = match &record.sub_path {
+                    Some(_) => create_sub_path(&self.mount_manager, &bind_source).await,
+                    None => Ok(()),
+                };
+                match created {
+                    Ok(()) => {
+                        info!(
+                            "Bind mounting staged volume: {} to {}",
+                            bind_source, req.target_path
+                        );
+                        self.mount_manager
+                            .bind_mount(&bind_source, &req.target_path, req.readonly)
+                            .await
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+        };
 
-        info!("Mounting Lustre source: {} to {}", source, req.target_path);
+        self.state
+            .finish(&req.target_path, |state| {
+                if result.is_ok() {
+                    state.publishes.insert(req.target_path.clone(), record);
+                    state.recount();
+                }
+            })
+            .await;
 
         // Perform the mount
-        if let Err(e) = self
-            .mount_manager
-            .mount(source, &req.target_path, &mount_options)
-            .await
-        {
+        if let Err(e) = result {
             error!("Failed to mount volume: {}", e);
-            return Err(Status::internal(format!("Mount failed: {}", e)));
+            return Err(mount_error(e));
+        }
+
+        // The target is unmounted again so the pod never starts on released
+        // files; a retry mounts and checks them again
+        if let Some(restore) = restore {
+            let online = self
+                .lustre_client
+                .restore_online(&req.target_path, restore.files.as_deref(), restore.timeout)
+                .await;
+            let status = match online {
+                Ok(true) => None,
+                Ok(false) => Some(Status::deadline_exceeded(format!(
+                    "Files of {} are still being restored from HSM",
+                    req.volume_id
+                ))),
+                Err(e) => {
+                    error!("Failed to restore files of {}: {:#}", req.volume_id, e);
+                    Some(Status::internal(format!(
+                        "Failed to restore files from HSM: {:#}",
+                        e
+                    )))
+                }
+            };
+            if let Some(status) = status {
+                self.withdraw_publish(&req.volume_id, &req.target_path)
+                    .await;
+                return Err(status);
+            }
         }
 
-        info!("Successfully published volume {}", req.volume_id);
+        // The backend belongs to the client mount, the staging path if any
+        if let Some((name, backend)) = pcc {
+            let mount_point = staging_path.as_deref().unwrap_or(&req.target_path);
+            if let Err(e) = self
+                .lustre_client
+                .pcc_add(mount_point, &backend, self.health_monitor.timeout())
+                .await
+            {
+                error!(
+                    "Failed to add PCC backend {} to {}: {:#}",
+                    name, mount_point, e
+                );
+                self.withdraw_publish(&req.volume_id, &req.target_path)
+                    .await;
+                return Err(Status::internal(format!(
+                    "Failed to add PCC backend {}: {:#}",
+                    name, e
+                )));
+            }
+            self.spawn_pcc_attach(&req.target_path, backend);
+        }
+
+        if let Some(hints) = prefetch {
+            self.spawn_prefetch(&req.volume_id, &req.target_path, hints);
+        }
+
+        match &pod {
+            Some(pod) => info!(
+                "Successfully published volume {} for pod {}",
+                req.volume_id, pod
+            ),
+            None => info!("Successfully published volume {}", req.volume_id),
+        }
         Ok(Response::new(NodePublishVolumeResponse {}))
     }
 
-    #[instrument(skip(self, request))]
+    #[instrument(skip(self, request), fields(pod = Empty, pod_uid = Empty, service_account = Empty))]
     async fn node_unpublish_volume(
         &self,
         request: Request<NodeUnpublishVolumeRequest>,
@@ -133,28 +857,130 @@ impl Node for NodeService {
             return Err(Status::invalid_argument("target_path is required"));
         }
 
-        // Perform the unmount
-        if let Err(e) = self.mount_manager.unmount(&req.target_path).await {
-            warn!(
-                "Failed to unmount volume (might already be unmounted): {}",
+        let _guard = self.lock_target("NodeUnpublishVolume", &req.volume_id, &req.target_path)?;
+
+        let record = self
+            .state
+            .snapshot()
+            .await
+            .publishes
+            .remove(&req.target_path);
+        if let Some(record) = &record {
+            if let Some(pod) = &record.pod {
+                record_pod(pod);
+            }
+            self.cleanup.release_pcc(&req.target_path, record).await;
+        }
+
+        self.state
+            .begin(
+                &req.target_path,
+                OperationRecord::new(OperationKind::Unpublish, &req.volume_id),
+            )
+            .await;
+
+        // Perform the unmount; a mount left behind is reported so kubelet retries
+        let result = self.mount_manager.unmount(&req.target_path).await;
+
+        self.state
+            .finish(&req.target_path, |state| {
+                if result.is_ok() {
+                    state.publishes.remove(&req.target_path);
+                    state.recount();
+                }
+            })
+            .await;
+
+        if let Err(e) = result {
+            error!("Failed to unmount volume: {:#}", e);
+            return Err(Status::internal(format!("Unmount failed: {:#}", e)));
+        }
+
+        // The target path is ours to delete once it is unmounted
+        if let Err(e) = self
+            .mount_manager
+            .remove_mount_point(&req.target_path)
+            .await
+        {
+            error!("Failed to remove target path: {:#}", e);
+            return Err(Status::internal(format!(
+                "Failed to remove target path: {:#}",
                 e
-            );
-            // Don't fail - volume might already be unmounted
+            )));
+        }
+
+        if let Some(record) = record.filter(|record| record.ephemeral) {
+            self.cleanup.schedule_scratch_deletion(&record.source).await;
         }
 
         info!("Successfully unpublished volume {}", req.volume_id);
         Ok(Response::new(NodeUnpublishVolumeResponse {}))
     }
 
-    #[instrument(skip(self))]
+    #[instrument(skip(self, request))]
     async fn node_get_volume_stats(
         &self,
-        _request: Request<NodeGetVolumeStatsRequest>,
+        request: Request<NodeGetVolumeStatsRequest>,
     ) -> Result<Response<NodeGetVolumeStatsResponse>, Status> {
-        debug!("NodeGetVolumeStats called");
-        Err(Status::unimplemented(
-            "NodeGetVolumeStats not yet implemented",
-        ))
+        let req = request.into_inner();
+        debug!("NodeGetVolumeStats called for volume: {}", req.volume_id);
+
+        // Validate request
+        if req.volume_id.is_empty() {
+            return Err(Status::invalid_argument("volume_id is required"));
+        }
+        if req.volume_path.is_empty() {
+            return Err(Status::invalid_argument("volume_path is required"));
+        }
+
+        let mounted = self
+            .mount_manager
+            .is_mounted(&req.volume_path)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to read mount table: {}", e)))?;
+        if !mounted {
+            return Err(Status::not_found(format!(
+                "{} is not mounted",
+                req.volume_path
+            )));
+        }
+
+        // Prefer the monitor's latest result; check now if there is none yet
+        let health = match self.health_monitor.health_of(&req.volume_path).await {
+            Some(health) => health,
+            None => {
+                self.lustre_client
+                    .check_mount_health(&req.volume_path, self.health_monitor.timeout())
+                    .await
+            }
+        };
+
+        // A hung mount would hang statvfs as well
+        let usage = if matches!(health, MountHealth::Hung) {
+            Vec::new()
+        } else {
+            let path = self.mount_manager.host_path(&req.volume_path);
+            let stat = tokio::time::timeout(
+                self.health_monitor.timeout(),
+                tokio::task::spawn_blocking(move || statvfs(&path)),
+            )
+            .await;
+            match stat {
+                Ok(Ok(Ok(usage))) => volume_usage(&usage),
+                _ => {
+                    warn!("Failed to get usage of {}", req.volume_path);
+                    Vec::new()
+                }
+            }
+        };
+
+        Ok(Response::new(NodeGetVolumeStatsResponse {
+            usage,
+            volume_condition: Some(VolumeCondition {
+                abnormal: !health.is_healthy(),
+                message: health.message(),
+            }),
+        }))
     }
 
     #[instrument(skip(self))]
@@ -176,13 +1002,18 @@ impl Node for NodeService {
         debug!("NodeGetCapabilities called");
 
         let response = NodeGetCapabilitiesResponse {
-            capabilities: vec![NodeServiceCapability {
+            capabilities: [
+                node_service_capability::rpc::Type::StageUnstageVolume,
+                node_service_capability::rpc::Type::GetVolumeStats,
+                node_service_capability::rpc::Type::VolumeCondition,
+            ]
+            .into_iter()
+            .map(|rpc| NodeServiceCapability {
                 r#type: Some(node_service_capability::Type::Rpc(
-                    node_service_capability::Rpc {
-                        r#type: node_service_capability::rpc::Type::StageUnstageVolume as i32,
-                    },
+                    node_service_capability::Rpc { r#type: rpc as i32 },
                 )),
-            }],
+            })
+            .collect(),
         };
 
         Ok(Response::new(response))
@@ -205,3 +1036,257 @@ impl Node for NodeService {
         Ok(Response::new(response))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ScratchConfig;
+    use crate::lustre::mountinfo::MountEntry;
+    use crate::utils::testing::{config, parameters};
+    use tonic::Code;
+
+    const SOURCE: &str = "10.0.0.1@tcp:/lustre/k8s/pvc-1";
+
+    fn service(dir: &tempfile::TempDir) -> NodeService {
+        service_with(dir, |_| {})
+    }
+
+    /// Node service whose mounts go to a fake host below `dir`
+    fn service_with(dir: &tempfile::TempDir, configure: impl FnOnce(&mut Config)) -> NodeService {
+        let mut config = config(&dir.path().join("plugin"), "node-1");
+        configure(&mut config);
+        let host = dir.path().join("host");
+        std::fs::create_dir(&host).unwrap();
+        NodeService::with_mount_manager(&config, MountManager::fake(&host)).unwrap()
+    }
+
+    fn request(pairs: &[(&str, &str)]) -> NodePublishVolumeRequest {
+        NodePublishVolumeRequest {
+            volume_id: SOURCE.to_string(),
+            target_path: "/pods/a/volumes/pvc-1/mount".to_string(),
+            volume_context: parameters(pairs),
+            ..Default::default()
+        }
+    }
+
+    async fn publish_error(node: &NodeService, req: NodePublishVolumeRequest) -> Code {
+        node.node_publish_volume(Request::new(req))
+            .await
+            .unwrap_err()
+            .code()
+    }
+
+    #[tokio::test]
+    async fn test_publish_rejects_invalid_requests() {
+        let dir = tempfile::tempdir().unwrap();
+        let node = service(&dir);
+
+        let cases = [
+            NodePublishVolumeRequest {
+                volume_id: String::new(),
+                ..request(&[("source", SOURCE)])
+            },
+            NodePublishVolumeRequest {
+                target_path: String::new(),
+                ..request(&[("source", SOURCE)])
+            },
+            request(&[]),
+            request(&[("source", "lustre")]),
+            request(&[("source", SOURCE), ("pccBackend", "nvme")]),
+            request(&[("source", SOURCE), ("prefetchFiles", "../etc/passwd")]),
+            // Pod directories are made below the staged volume
+            request(&[
+                ("source", SOURCE),
+                ("subPathTemplate", "${pod.namespace}/${pod.name}"),
+                ("csi.storage.k8s.io/pod.name", "trainer-0"),
+                ("csi.storage.k8s.io/pod.namespace", "ml"),
+            ]),
+            NodePublishVolumeRequest {
+                staging_target_path: "/staging/pvc-1".to_string(),
+                ..request(&[("source", SOURCE), ("subPathTemplate", "${pod.name}")])
+            },
+        ];
+        for (i, req) in cases.into_iter().enumerate() {
+            assert_eq!(
+                publish_error(&node, req).await,
+                Code::InvalidArgument,
+                "case {}",
+                i
+            );
+        }
+        assert!(node.state.snapshot().await.operations.is_empty());
+    }
+
+    async fn mounted(node: &NodeService, path: &str) -> Option<MountEntry> {
+        node.mount_manager.mount_table().lookup(path).await.unwrap()
+    }
+
+    fn mode(path: &std::path::Path) -> u32 {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::metadata(path).unwrap().permissions().mode() & 0o7777
+    }
+
+    #[tokio::test]
+    async fn test_publish_pcc_failure_withdraws() {
+        let dir = tempfile::tempdir().unwrap();
+        let node = service_with(&dir, |config| {
+            config.pcc.backends.insert(
+                "nvme".to_string(),
+                PccBackend {
+                    path: "/nvme/pcc".to_string(),
+                    archive_id: 2,
+                    read_only: false,
+                },
+            );
+        });
+
+        // Without lctl the backend cannot be added, after the mount was made
+        let req = request(&[("source", SOURCE), ("pccBackend", "nvme")]);
+        let target = req.target_path.clone();
+        assert_eq!(publish_error(&node, req).await, Code::Internal);
+
+        assert!(mounted(&node, &target).await.is_none());
+        let state = node.state.snapshot().await;
+        assert!(state.publishes.is_empty());
+        assert!(state.operations.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_publish_sub_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let node = service(&dir);
+        node.mount_manager
+            .mount(SOURCE, "/staging/pvc-1", &[])
+            .await
+            .unwrap();
+
+        let req = NodePublishVolumeRequest {
+            staging_target_path: "/staging/pvc-1".to_string(),
+            ..request(&[
+                ("source", SOURCE),
+                ("subPathTemplate", "${pod.namespace}/${pod.name}"),
+                ("csi.storage.k8s.io/pod.name", "trainer-0"),
+                ("csi.storage.k8s.io/pod.namespace", "ml"),
+            ])
+        };
+        let target = req.target_path.clone();
+        node.node_publish_volume(Request::new(req)).await.unwrap();
+
+        let record = node.state.snapshot().await.publishes[&target].clone();
+        assert_eq!(record.sub_path.as_deref(), Some("ml/trainer-0"));
+        assert_eq!(
+            record.bind_source().as_deref(),
+            Some("/staging/pvc-1/ml/trainer-0")
+        );
+        let staged = mounted(&node, "/staging/pvc-1").await.unwrap();
+        let published = mounted(&node, &target).await.unwrap();
+        assert_eq!(published.device, staged.device);
+        assert_eq!(published.root, "/ml/trainer-0");
+        let pod_dir = node.mount_manager.host_path("/staging/pvc-1/ml/trainer-0");
+        assert_eq!(mode(&pod_dir), 0o1777);
+
+        // The pod's directory outlives the publish
+        node.node_unpublish_volume(Request::new(NodeUnpublishVolumeRequest {
+            volume_id: SOURCE.to_string(),
+            target_path: target.clone(),
+        }))
+        .await
+        .unwrap();
+        assert!(mounted(&node, &target).await.is_none());
+        assert!(node.state.snapshot().await.publishes.is_empty());
+        assert!(pod_dir.is_dir());
+    }
+
+    #[tokio::test]
+    async fn test_ephemeral_scratch_cleanup() {
+        let dir = tempfile::tempdir().unwrap();
+        let node = service_with(&dir, |config| {
+            config
+                .lustre
+                .filesystem_mapping
+                .insert("scratch".to_string(), "10.0.0.9@tcp".to_string());
+            config.scratch = Some(ScratchConfig {
+                filesystem: "scratch".to_string(),
+                path: "k8s/ephemeral".to_string(),
+                grace_period_secs: 0,
+            });
+        });
+
+        let req = NodePublishVolumeRequest {
+            volume_id: "csi-4f2a".to_string(),
+            ..request(&[
+                (EPHEMERAL_KEY, "true"),
+                ("csi.storage.k8s.io/pod.name", "trainer-0"),
+                ("csi.storage.k8s.io/pod.namespace", "ml"),
+            ])
+        };
+        let target = req.target_path.clone();
+        node.node_publish_volume(Request::new(req)).await.unwrap();
+
+        let record = node.state.snapshot().await.publishes[&target].clone();
+        assert!(record.ephemeral);
+        assert_eq!(
+            record.source,
+            "10.0.0.9@tcp:/scratch/k8s/ephemeral/csi-4f2a"
+        );
+        let table = node.mount_manager.mount_table().get().await.unwrap();
+        let scratch_root = table
+            .entries()
+            .iter()
+            .find(|entry| entry.source == "10.0.0.9@tcp:/scratch")
+            .unwrap();
+        let scratch_dir = node
+            .mount_manager
+            .host_path(&scratch_root.mount_point)
+            .join("k8s/ephemeral/csi-4f2a");
+        assert_eq!(mode(&scratch_dir), 0o1777);
+
+        node.node_unpublish_volume(Request::new(NodeUnpublishVolumeRequest {
+            volume_id: "csi-4f2a".to_string(),
+            target_path: target.clone(),
+        }))
+        .await
+        .unwrap();
+        assert!(mounted(&node, &target).await.is_none());
+
+        // Deleted in the background once the grace period is over
+        for _ in 0..100 {
+            if !scratch_dir.exists() && node.state.snapshot().await.scratch_deletions.is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert!(!scratch_dir.exists());
+        assert!(node.state.snapshot().await.scratch_deletions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_publish_ephemeral_without_scratch() {
+        let dir = tempfile::tempdir().unwrap();
+        let node = service(&dir);
+
+        let req = request(&[(EPHEMERAL_KEY, "true")]);
+        assert_eq!(publish_error(&node, req).await, Code::FailedPrecondition);
+    }
+
+    #[tokio::test]
+    async fn test_publish_locks() {
+        let dir = tempfile::tempdir().unwrap();
+        let node = service(&dir);
+
+        // Another pod publishing the volume does not get in the way
+        let _other = node
+            .lock_target("NodePublishVolume", SOURCE, "/pods/b/volumes/pvc-1/mount")
+            .unwrap();
+        assert!(
+            node.lock("NodeStageVolume", SOURCE, "/staging/pvc-1")
+                .is_err()
+        );
+
+        let req = request(&[("source", SOURCE)]);
+        let _same = node
+            .lock_target("NodeUnpublishVolume", SOURCE, &req.target_path)
+            .unwrap();
+        assert_eq!(publish_error(&node, req).await, Code::Aborted);
+    }
+}
@@ -0,0 +1,5 @@
+pub mod reconcile;
+pub mod store;
+
+// Re-export
+pub use store::{OperationKind, OperationRecord, PublishRecord, StageRecord, StateStore};
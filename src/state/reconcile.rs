@@ -0,0 +1,371 @@
+use anyhow::Result;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use super::store::{
+    NodeState, OperationKind, OperationRecord, PublishRecord, StageRecord, StateStore,
+};
+use crate::jobs::store::now;
+use crate::lustre::MountManager;
+use crate::lustre::mountinfo::{MountEntry, MountTable};
+
+/// Result of comparing the stored state with the host mount table
+#[derive(Debug, Default)]
+pub struct Plan {
+    /// State to store once the pending unmounts were attempted
+    pub state: NodeState,
+    /// Mounts left behind by interrupted unpublish/unstage calls
+    pub unmounts: Vec<PendingUnmount>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingUnmount {
+    pub path: String,
+    /// Publish targets are removed and their records dropped as well;
+    /// staging paths belong to kubelet
+    pub remove_dir: bool,
+    /// Scratch directory of an ephemeral volume to delete once unmounted
+    pub scratch_dir: Option<String>,
+}
+
+/// Bring the stored state in line with what is actually mounted on the host.
+///
+/// Runs once at startup, before the gRPC server accepts requests and before
+/// scheduled scratch deletions are started. Scratch directories are deleted
+/// after `scratch_grace`, or kept if the node has no scratch space.
+pub async fn reconcile(
+    store: &StateStore,
+    mount_manager: &MountManager,
+    scratch_grace: Option<Duration>,
+) -> Result<()> {
+    let stored = store.snapshot().await;
+    let table = mount_manager.mount_table().get().await?;
+    let mut plan = plan(&stored, &table);
+
+    for pending in &plan.unmounts {
+        info!("Finishing interrupted unmount of {}", pending.path);
+
+        if let Err(e) = mount_manager.unmount(&pending.path).await {
+            // kubelet still believes it is mounted and will retry the call
+            warn!("Failed to unmount {}: {:#}", pending.path, e);
+            continue;
+        }
+
+        if pending.remove_dir {
+            plan.state.publishes.remove(&pending.path);
+            if let Err(e) = mount_manager.remove_mount_point(&pending.path).await {
+                warn!("Failed to remove {}: {:#}", pending.path, e);
+            }
+        }
+
+        if let Some(dir) = &pending.scratch_dir {
+            match scratch_grace {
+                Some(grace) => {
+                    info!("Scheduling deletion of scratch directory {}", dir);
+                    plan.state
+                        .scratch_deletions
+                        .insert(dir.clone(), now() + grace.as_secs());
+                }
+                None => warn!(
+                    "Keeping scratch directory {}: no scratch space is configured",
+                    dir
+                ),
+            }
+        }
+    }
+    plan.state.recount();
+
+    info!(
+        "Reconciled node state: {} staged, {} published, {} interrupted operations resolved",
+        plan.state.stages.len(),
+        plan.state.publishes.len(),
+        stored.operations.len()
+    );
+
+    store.update(move |state| *state = plan.state).await
+}
+
+/// Decide how to finish or roll back each recorded operation
+pub fn plan(stored: &NodeState, table: &MountTable) -> Plan {
+    let mut state = NodeState {
+        stages: stored.stages.clone(),
+        publishes: stored.publishes.clone(),
+        operations: Default::default(),
+        scratch_deletions: stored.scratch_deletions.clone(),
+    };
+    let mut unmounts = Vec::new();
+
+    for (path, op) in &stored.operations {
+        let mounted = table.find(path);
+
+        match op.kind {
+            OperationKind::Stage => match mounted {
+                Some(entry) if entry.matches_source(&op.source) => {
+                    info!(
+                        "Completing interrupted stage of {} at {}",
+                        op.volume_id, path
+                    );
+                    state.stages.insert(
+                        path.clone(),
+                        StageRecord {
+                            volume_id: op.volume_id.clone(),
+                            source: op.source.clone(),
+                            mount_options: op.mount_options.clone(),
+                            tuning: op.tuning.clone(),
+                            refcount: 0,
+                        },
+                    );
+                }
+                Some(entry) => warn!(
+                    "Interrupted stage of {} at {}: mounted from {} instead of {}, leaving it alone",
+                    op.volume_id, path, entry.source, op.source
+                ),
+                None => info!(
+                    "Rolled back interrupted stage of {} at {}",
+                    op.volume_id, path
+                ),
+            },
+            OperationKind::Publish => {
+                let publish = publish_record(op);
+                match mounted {
+                    Some(entry) if is_mounted_from(entry, &publish, table) => {
+                        info!(
+                            "Completing interrupted publish of {} at {}",
+                            op.volume_id, path
+                        );
+                        state.publishes.insert(path.clone(), publish);
+                    }
+                    _ => {
+                        if let Some(entry) = mounted {
+                            warn!(
+                                "Interrupted publish of {} at {}: mounted from {} ({}) instead of {}",
+                                op.volume_id,
+                                path,
+                                entry.source,
+                                entry.root,
+                                publish.bind_source().unwrap_or(publish.source.clone())
+                            );
+                        }
+                        info!(
+                            "Rolled back interrupted publish of {} at {}",
+                            op.volume_id, path
+                        );
+                        unmounts.push(PendingUnmount {
+                            path: path.clone(),
+                            remove_dir: true,
+                            scratch_dir: op.ephemeral.then(|| op.source.clone()),
+                        });
+                    }
+                }
+            }
+            OperationKind::Unpublish => {
+                // The record goes once the unmount is confirmed
+                let publish = stored.publishes.get(path);
+                unmounts.push(PendingUnmount {
+                    path: path.clone(),
+                    remove_dir: true,
+                    scratch_dir: publish
+                        .filter(|publish| publish.ephemeral)
+                        .map(|publish| publish.source.clone()),
+                });
+            }
+            OperationKind::Unstage => {
+                if stored.publishes_from(path) > 0 {
+                    warn!(
+                        "Interrupted unstage of {} at {} still has publishes, keeping it",
+                        op.volume_id, path
+                    );
+                    continue;
+                }
+                state.stages.remove(path);
+                if mounted.is_some() {
+                    unmounts.push(PendingUnmount {
+                        path: path.clone(),
+                        remove_dir: false,
+                        scratch_dir: None,
+                    });
+                }
+            }
+        }
+    }
+
+    // Records whose mounts are gone, e.g. after a node reboot
+    state.stages.retain(|path, stage| {
+        let mounted = table.find(path).is_some();
+        if !mounted {
+            info!(
+                "Staged volume {} at {} is no longer mounted",
+                stage.volume_id, path
+            );
+        }
+        mounted
+    });
+    state.publishes.retain(|path, publish| {
+        let mounted = table.find(path).is_some();
+        if !mounted {
+            info!(
+                "Published volume {} at {} is no longer mounted",
+                publish.volume_id, path
+            );
+        }
+        mounted
+    });
+
+    state.recount();
+
+    Plan { state, unmounts }
+}
+
+/// The record an interrupted publish would have stored
+fn publish_record(op: &OperationRecord) -> PublishRecord {
+    PublishRecord {
+        volume_id: op.volume_id.clone(),
+        source: op.source.clone(),
+        staging_path: op.staging_path.clone(),
+        read_only: op.read_only,
+        pcc_backend: op.pcc_backend.clone(),
+        ephemeral: op.ephemeral,
+        sub_path: op.sub_path.clone(),
+        pod: op.pod.clone(),
+    }
+}
+
+/// Whether `entry` is the mount `publish` makes: a bind mount of the same
+/// device and root as its staging path, or a mount of its Lustre source
+fn is_mounted_from(entry: &MountEntry, publish: &PublishRecord, table: &MountTable) -> bool {
+    match publish.bind_source() {
+        Some(bind_source) => table
+            .containing(&bind_source)
+            .is_some_and(|expected| expected.device == entry.device && expected.root == entry.root),
+        None => entry.matches_source(&publish.source),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::store::OperationRecord;
+
+    const SOURCE: &str = "10.0.0.1@tcp:/lustre-fs";
+    const SCRATCH: &str = "10.0.0.9@tcp:/scratch/k8s/ephemeral/csi-e";
+
+    fn record(kind: OperationKind, staging_path: Option<&str>) -> OperationRecord {
+        OperationRecord {
+            source: SOURCE.to_string(),
+            staging_path: staging_path.map(String::from),
+            ..OperationRecord::new(kind, "vol-1")
+        }
+    }
+
+    #[test]
+    fn test_plan_after_restart() {
+        let table = MountTable::parse(
+            "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+318 22 0:57 / /stage rw - lustre 10.0.0.1@tcp:/lustre-fs rw,flock
+402 22 0:57 / /pods/a rw - lustre 10.0.0.1@tcp:/lustre-fs rw,flock
+403 22 0:57 / /pods/b rw - lustre 10.0.0.1@tcp:/lustre-fs rw,flock
+404 22 0:58 / /pods/d rw - lustre 10.0.0.2@tcp:/other-fs rw,flock
+",
+        )
+        .unwrap();
+
+        let mut stored = NodeState::default();
+        stored
+            .operations
+            .insert("/stage".into(), record(OperationKind::Stage, None));
+        stored.operations.insert(
+            "/pods/a".into(),
+            record(OperationKind::Publish, Some("/stage")),
+        );
+        stored.operations.insert(
+            "/pods/b".into(),
+            record(OperationKind::Unpublish, Some("/stage")),
+        );
+        stored.operations.insert(
+            "/pods/c".into(),
+            record(OperationKind::Publish, Some("/stage")),
+        );
+        stored.operations.insert(
+            "/pods/d".into(),
+            record(OperationKind::Publish, Some("/stage")),
+        );
+        stored.operations.insert(
+            "/pods/e".into(),
+            OperationRecord {
+                source: SCRATCH.to_string(),
+                ephemeral: true,
+                ..OperationRecord::new(OperationKind::Publish, "csi-e")
+            },
+        );
+        stored.operations.insert(
+            "/pods/f".into(),
+            OperationRecord::new(OperationKind::Unpublish, "csi-f"),
+        );
+        stored.publishes.insert(
+            "/pods/f".into(),
+            PublishRecord {
+                volume_id: "csi-f".into(),
+                source: format!("{}-f", SCRATCH),
+                staging_path: None,
+                read_only: false,
+                pcc_backend: None,
+                ephemeral: true,
+                sub_path: None,
+                pod: None,
+            },
+        );
+        stored.publishes.insert(
+            "/pods/b".into(),
+            PublishRecord {
+                volume_id: "vol-1".into(),
+                source: SOURCE.into(),
+                staging_path: Some("/stage".into()),
+                read_only: false,
+                pcc_backend: None,
+                ephemeral: false,
+                sub_path: None,
+                pod: None,
+            },
+        );
+        stored.publishes.insert(
+            "/pods/gone".into(),
+            PublishRecord {
+                volume_id: "vol-2".into(),
+                source: SOURCE.into(),
+                staging_path: None,
+                read_only: false,
+                pcc_backend: None,
+                ephemeral: false,
+                sub_path: None,
+                pod: None,
+            },
+        );
+
+        let plan = plan(&stored, &table);
+
+        // The interrupted unpublish of /pods/b keeps its record until the
+        // unmount is confirmed; /pods/d is mounted from something else
+        assert!(plan.state.operations.is_empty());
+        assert_eq!(plan.state.stages["/stage"].refcount, 2);
+        assert_eq!(
+            plan.state.publishes.keys().collect::<Vec<_>>(),
+            vec!["/pods/a", "/pods/b"]
+        );
+        let unmount = |path: &str, scratch_dir: Option<String>| PendingUnmount {
+            path: path.into(),
+            remove_dir: true,
+            scratch_dir,
+        };
+        assert_eq!(
+            plan.unmounts,
+            vec![
+                unmount("/pods/b", None),
+                unmount("/pods/c", None),
+                unmount("/pods/d", None),
+                unmount("/pods/e", Some(SCRATCH.into())),
+                unmount("/pods/f", Some(format!("{}-f", SCRATCH))),
+            ]
+        );
+    }
+}
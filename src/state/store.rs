@@ -0,0 +1,289 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+use crate::utils::pod::PodInfo;
+
+/// File name of the state store inside the plugin directory
+pub const STATE_FILE: &str = "node-state.json";
+
+/// Everything the node plugin has mounted or is in the middle of mounting
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NodeState {
+    /// Staged Lustre mounts, keyed by staging path
+    pub stages: BTreeMap<String, StageRecord>,
+
+    /// Published targets, keyed by target path
+    pub publishes: BTreeMap<String, PublishRecord>,
+
+    /// Operations that started but did not finish, keyed by path
+    pub operations: BTreeMap<String, OperationRecord>,
+
+    /// Scratch directories of unpublished ephemeral volumes, keyed by Lustre
+    /// source, with the time they are deleted at in seconds since the Unix
+    /// epoch
+    pub scratch_deletions: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StageRecord {
+    pub volume_id: String,
+    pub source: String,
+    pub mount_options: Vec<String>,
+    /// Client tuning applied to the mount, by volume context key
+    #[serde(default)]
+    pub tuning: BTreeMap<String, String>,
+    /// Number of targets bind mounted from this staging path
+    pub refcount: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PublishRecord {
+    pub volume_id: String,
+    pub source: String,
+    /// Staging path the target is bind mounted from, if any
+    pub staging_path: Option<String>,
+    pub read_only: bool,
+    /// PCC backend the target's files were attached to
+    #[serde(default)]
+    pub pcc_backend: Option<String>,
+    /// The source is a scratch directory made for an ephemeral volume
+    #[serde(default)]
+    pub ephemeral: bool,
+    /// Directory below the staging path the target is bind mounted from
+    #[serde(default)]
+    pub sub_path: Option<String>,
+    /// Pod the volume was published for
+    #[serde(default)]
+    pub pod: Option<PodInfo>,
+}
+
+impl PublishRecord {
+    /// Path the target is bind mounted from: the staging path, or the
+    /// pod's directory below it. `None` if the target is mounted directly.
+    pub fn bind_source(&self) -> Option<String> {
+        let staging_path = self.staging_path.as_deref()?;
+        Some(match &self.sub_path {
+            Some(sub_path) => format!("{}/{}", staging_path, sub_path),
+            None => staging_path.to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OperationKind {
+    Stage,
+    Unstage,
+    Publish,
+    Unpublish,
+}
+
+/// Intent recorded before an operation touches the host
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OperationRecord {
+    pub kind: OperationKind,
+    pub volume_id: String,
+    #[serde(default)]
+    pub source: String,
+    #[serde(default)]
+    pub staging_path: Option<String>,
+    #[serde(default)]
+    pub mount_options: Vec<String>,
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub tuning: BTreeMap<String, String>,
+    #[serde(default)]
+    pub pcc_backend: Option<String>,
+    #[serde(default)]
+    pub ephemeral: bool,
+    #[serde(default)]
+    pub sub_path: Option<String>,
+    #[serde(default)]
+    pub pod: Option<PodInfo>,
+    /// Seconds since the Unix epoch
+    pub started_at: u64,
+}
+
+impl OperationRecord {
+    pub fn new(kind: OperationKind, volume_id: &str) -> Self {
+        Self {
+            kind,
+            volume_id: volume_id.to_string(),
+            source: String::new(),
+            staging_path: None,
+            mount_options: Vec::new(),
+            read_only: false,
+            tuning: BTreeMap::new(),
+            pcc_backend: None,
+            ephemeral: false,
+            sub_path: None,
+            pod: None,
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl NodeState {
+    /// Number of recorded publishes of `volume_id`
+    pub fn publishes_of(&self, volume_id: &str) -> usize {
+        self.publishes
+            .values()
+            .filter(|p| p.volume_id == volume_id)
+            .count()
+    }
+
+    /// Number of recorded publishes bind mounted from `staging_path`
+    pub fn publishes_from(&self, staging_path: &str) -> u32 {
+        self.publishes
+            .values()
+            .filter(|p| p.staging_path.as_deref() == Some(staging_path))
+            .count() as u32
+    }
+
+    /// Recompute stage refcounts from the recorded publishes
+    pub fn recount(&mut self) {
+        let counts: BTreeMap<String, u32> = self
+            .stages
+            .keys()
+            .map(|path| (path.clone(), self.publishes_from(path)))
+            .collect();
+
+        for (path, stage) in self.stages.iter_mut() {
+            let count = counts[path];
+            if stage.refcount != count {
+                debug!(
+                    "Refcount of {} corrected from {} to {}",
+                    path, stage.refcount, count
+                );
+                stage.refcount = count;
+            }
+        }
+    }
+}
+
+/// JSON-backed store for [`NodeState`] under the plugin directory.
+///
+/// Every change is written to a temporary file, synced and renamed into
+/// place, so a crash leaves either the old or the new state behind, never a
+/// torn file.
+#[derive(Debug, Clone)]
+pub struct StateStore {
+    path: PathBuf,
+    state: Arc<Mutex<NodeState>>,
+}
+
+impl StateStore {
+    /// Load the store from `dir`, starting empty if there is no state yet
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create state directory {}", dir.display()))?;
+
+        let path = dir.join(STATE_FILE);
+        let state = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .with_context(|| format!("Failed to parse {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => NodeState::default(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+
+        info!("Loaded node state from {}", path.display());
+        Ok(Self {
+            path,
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+
+    /// Copy of the current state
+    pub async fn snapshot(&self) -> NodeState {
+        self.state.lock().await.clone()
+    }
+
+    /// Apply `f` to the state and persist the result
+    pub async fn update<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut NodeState) -> T,
+    {
+        let mut state = self.state.lock().await;
+        let result = f(&mut state);
+        self.persist(&state).await?;
+        Ok(result)
+    }
+
+    /// Record that an operation on `path` is starting
+    pub async fn begin(&self, path: &str, operation: OperationRecord) {
+        let path = path.to_string();
+        self.update_or_warn(move |state| {
+            state.operations.insert(path, operation);
+        })
+        .await;
+    }
+
+    /// Clear the in-flight record for `path` and apply the operation's outcome
+    pub async fn finish<F>(&self, path: &str, f: F)
+    where
+        F: FnOnce(&mut NodeState),
+    {
+        let path = path.to_string();
+        self.update_or_warn(move |state| {
+            state.operations.remove(&path);
+            f(state);
+        })
+        .await;
+    }
+
+    /// Like [`update`](Self::update), but only logs when persisting fails.
+    ///
+    /// The state store is a recovery aid; losing a write must not fail the
+    /// mount operation that is being recorded.
+    async fn update_or_warn<F>(&self, f: F)
+    where
+        F: FnOnce(&mut NodeState),
+    {
+        if let Err(e) = self.update(f).await {
+            warn!("Failed to persist node state: {:#}", e);
+        }
+    }
+
+    async fn persist(&self, state: &NodeState) -> Result<()> {
+        let bytes = serde_json::to_vec_pretty(state).context("Failed to serialize node state")?;
+        replace_file(&self.path, bytes).await
+    }
+}
+
+/// Replace `path` with `bytes` through a synced temporary file, then sync
+/// the directory so the rename itself survives a crash
+pub async fn replace_file(path: &Path, bytes: Vec<u8>) -> Result<()> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let tmp = path.with_extension("json.tmp");
+        let mut file = std::fs::File::create(&tmp)
+            .with_context(|| format!("Failed to create {}", tmp.display()))?;
+        file.write_all(&bytes)
+            .and_then(|()| file.sync_all())
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &path)
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
+
+        let dir = path.parent().unwrap_or(Path::new("."));
+        std::fs::File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .with_context(|| format!("Failed to sync {}", dir.display()))
+    })
+    .await
+    .context("State writer panicked")?
+}
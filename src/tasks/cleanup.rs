@@ -0,0 +1,167 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::AbortHandle;
+use tracing::warn;
+
+use crate::config::PccBackend;
+use crate::jobs::store::now;
+use crate::lustre::LustreClient;
+use crate::provision::{ScratchSpace, VolumeSource};
+use crate::state::{PublishRecord, StateStore};
+
+/// Wait before trying again to delete a scratch directory
+const SCRATCH_DELETE_RETRY: Duration = Duration::from_secs(60);
+
+/// PCC attach tasks still running, by target path
+pub type PccAttaches = Arc<Mutex<HashMap<String, AbortHandle>>>;
+
+/// Undoes what a publish set up next to its mount: the PCC attachment of
+/// the volume's files and the scratch directory of an ephemeral volume.
+///
+/// Shared by NodeUnpublishVolume and the orphaned mount collector, which
+/// unmounts targets kubelet never unpublished.
+#[derive(Debug, Clone)]
+pub struct PublishCleanup {
+    lustre_client: LustreClient,
+    state: StateStore,
+    pcc_backends: BTreeMap<String, PccBackend>,
+    pcc_attaches: PccAttaches,
+    scratch: Option<ScratchSpace>,
+    /// Bound for detaching files from PCC
+    timeout: Duration,
+}
+
+impl PublishCleanup {
+    pub fn new(
+        lustre_client: LustreClient,
+        state: StateStore,
+        pcc_backends: BTreeMap<String, PccBackend>,
+        pcc_attaches: PccAttaches,
+        scratch: Option<ScratchSpace>,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            lustre_client,
+            state,
+            pcc_backends,
+            pcc_attaches,
+            scratch,
+            timeout,
+        }
+    }
+
+    /// How long scratch directories are kept, `None` without scratch space
+    pub fn scratch_grace(&self) -> Option<Duration> {
+        self.scratch.as_ref().map(|scratch| scratch.grace_period)
+    }
+
+    /// Detach a volume's files from PCC and drop the backend from its client
+    /// mount, once `record` is the last publish of the volume on this node.
+    ///
+    /// Failures are logged only; they must not keep the target mounted.
+    pub async fn release_pcc(&self, target_path: &str, record: &PublishRecord) {
+        if let Some(attach) = self.pcc_attaches.lock().unwrap().remove(target_path) {
+            attach.abort();
+        }
+
+        let Some(backend) = record
+            .pcc_backend
+            .as_ref()
+            .and_then(|name| self.pcc_backends.get(name))
+        else {
+            return;
+        };
+        if self.state.snapshot().await.publishes_of(&record.volume_id) > 1 {
+            return;
+        }
+
+        // Bounded like any other unpublish step; files left attached are
+        // detached when the backend is removed
+        if let Err(e) = self
+            .lustre_client
+            .pcc_detach_dir(target_path, self.timeout)
+            .await
+        {
+            warn!(
+                "Failed to detach files below {} from PCC: {:#}",
+                target_path, e
+            );
+        }
+        let mount_point = record.staging_path.as_deref().unwrap_or(target_path);
+        if let Err(e) = self
+            .lustre_client
+            .pcc_del(mount_point, backend, self.timeout)
+            .await
+        {
+            warn!("Failed to remove PCC backend from {}: {:#}", mount_point, e);
+        }
+    }
+
+    /// Delete the scratch directory of an unpublished ephemeral volume once
+    /// the grace period is over
+    pub async fn schedule_scratch_deletion(&self, source: &str) {
+        let Some(scratch) = &self.scratch else {
+            warn!(
+                "Keeping scratch directory {}: no scratch space is configured",
+                source
+            );
+            return;
+        };
+        let due = now() + scratch.grace_period.as_secs();
+        if let Err(e) = self
+            .state
+            .update(|state| state.scratch_deletions.insert(source.to_string(), due))
+            .await
+        {
+            warn!("Failed to record deletion of {}: {:#}", source, e);
+        }
+        self.spawn_scratch_deletion(source.to_string(), due);
+    }
+
+    /// Delete a scratch directory at `due`, retrying until it is gone unless
+    /// a publish took it back meanwhile
+    pub fn spawn_scratch_deletion(&self, source: String, mut due: u64) {
+        let Some(scratch) = self.scratch.clone() else {
+            return;
+        };
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            loop {
+                let wait = due.saturating_sub(now());
+                tokio::time::sleep(Duration::from_secs(wait)).await;
+                if state.snapshot().await.scratch_deletions.get(&source) != Some(&due) {
+                    return;
+                }
+
+                let result = match VolumeSource::parse(&source) {
+                    Ok(dir) => scratch.delete(&dir).await,
+                    Err(e) => Err(e),
+                };
+                let retry = match result {
+                    Ok(()) => None,
+                    Err(e) => {
+                        warn!(
+                            "Failed to delete scratch directory {}, retrying: {:#}",
+                            source, e
+                        );
+                        Some(now() + SCRATCH_DELETE_RETRY.as_secs())
+                    }
+                };
+                let recorded = state
+                    .update(|state| match retry {
+                        Some(retry) => state.scratch_deletions.insert(source.clone(), retry),
+                        None => state.scratch_deletions.remove(&source),
+                    })
+                    .await;
+                if let Err(e) = recorded {
+                    warn!("Failed to record deletion of {}: {:#}", source, e);
+                }
+                match retry {
+                    Some(retry) => due = retry,
+                    None => return,
+                }
+            }
+        });
+    }
+}
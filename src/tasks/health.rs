@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::config::HealthConfig;
+use crate::lustre::health::MountHealth;
+use crate::lustre::{ClientTuning, LustreClient, MountManager};
+use crate::state::{StageRecord, StateStore};
+use crate::utils::locks::{OperationLocks, path_key};
+
+/// Periodically checks every Lustre client mount this node plugin owns.
+///
+/// Publishes are bind mounts of a staging mount and share its Lustre client,
+/// so the staging mount is what gets probed; results are looked up for a
+/// target path through the state store.
+#[derive(Debug, Clone)]
+pub struct HealthMonitor {
+    config: HealthConfig,
+    lustre_client: LustreClient,
+    mount_manager: MountManager,
+    state: StateStore,
+    locks: OperationLocks,
+    /// Latest result per client mount path
+    results: Arc<RwLock<HashMap<String, MountHealth>>>,
+}
+
+impl HealthMonitor {
+    pub fn new(
+        config: HealthConfig,
+        lustre_client: LustreClient,
+        mount_manager: MountManager,
+        state: StateStore,
+        locks: OperationLocks,
+    ) -> Self {
+        Self {
+            config,
+            lustre_client,
+            mount_manager,
+            state,
+            locks,
+            results: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.config.timeout_secs.max(1))
+    }
+
+    /// Run a pass every configured interval until the process exits
+    pub fn spawn(self) {
+        if self.config.interval_secs == 0 {
+            info!("Mount health monitor disabled");
+            return;
+        }
+
+        info!(
+            "Starting mount health monitor (every {}s, timeout {}s, remount {})",
+            self.config.interval_secs, self.config.timeout_secs, self.config.remount
+        );
+
+        tokio::spawn(async move {
+            let period = Duration::from_secs(self.config.interval_secs);
+            loop {
+                tokio::time::sleep(period).await;
+                self.run_once().await;
+            }
+        });
+    }
+
+    /// Check all client mounts once
+    pub async fn run_once(&self) {
+        let snapshot = self.state.snapshot().await;
+
+        // Direct publishes (no staging path) are their own client mount
+        let mut mounts: Vec<String> = snapshot.stages.keys().cloned().collect();
+        mounts.extend(
+            snapshot
+                .publishes
+                .iter()
+                .filter(|(_, p)| p.staging_path.is_none())
+                .map(|(path, _)| path.clone()),
+        );
+
+        let mut results = HashMap::new();
+        for path in mounts {
+            let health = self
+                .lustre_client
+                .check_mount_health(&path, self.timeout())
+                .await;
+
+            if !health.is_healthy() {
+                warn!("Unhealthy Lustre mount {}: {}", path, health.message());
+
+                if self.config.remount
+                    && matches!(health, MountHealth::Stale(_))
+                    && let Some(stage) = snapshot.stages.get(&path)
+                {
+                    self.remount_stage(&path, stage).await;
+                }
+            }
+
+            results.insert(path, health);
+        }
+
+        *self.results.write().unwrap() = results;
+    }
+
+    /// Latest health of the client mount behind a target or staging path
+    pub async fn health_of(&self, path: &str) -> Option<MountHealth> {
+        let client_mount = self
+            .state
+            .snapshot()
+            .await
+            .publishes
+            .get(path)
+            .and_then(|p| p.staging_path.clone())
+            .unwrap_or_else(|| path.to_string());
+
+        self.results.read().unwrap().get(&client_mount).cloned()
+    }
+
+    /// Replace a stale staging mount and re-bind its publishes.
+    ///
+    /// Only stale mounts are handled: they fail fast, so detaching them is
+    /// safe, whereas touching a hung mount can block the plugin as well.
+    /// Containers see the new mount only with HostToContainer propagation.
+    async fn remount_stage(&self, staging_path: &str, stage: &StageRecord) {
+        let snapshot = self.state.snapshot().await;
+        let targets: Vec<(String, String, bool)> = snapshot
+            .publishes
+            .iter()
+            .filter(|(_, p)| p.staging_path.as_deref() == Some(staging_path))
+            .filter_map(|(target, p)| Some((target.clone(), p.bind_source()?, p.read_only)))
+            .collect();
+
+        let mut keys = vec![path_key(staging_path)];
+        keys.extend(targets.iter().map(|(target, _, _)| path_key(target)));
+        let Ok(_guard) = self.locks.try_acquire("HealthMonitor", &keys) else {
+            info!(
+                "Skipping remount of {}: an operation is in progress",
+                staging_path
+            );
+            return;
+        };
+
+        info!("Remounting stale staging mount {}", staging_path);
+        if let Err(e) = self.mount_manager.detach(staging_path).await {
+            warn!("Failed to detach {}: {:#}", staging_path, e);
+            return;
+        }
+        if let Err(e) = self
+            .mount_manager
+            .mount(&stage.source, staging_path, &stage.mount_options)
+            .await
+        {
+            warn!("Failed to remount {}: {:#}", staging_path, e);
+            return;
+        }
+
+        let tuning = ClientTuning::from_settings(stage.tuning.clone());
+        if let Err(e) = self
+            .lustre_client
+            .apply_tuning(staging_path, &tuning, self.timeout())
+            .await
+        {
+            warn!(
+                "Failed to reapply client tuning to {}: {:#}",
+                staging_path, e
+            );
+        }
+
+        for (target, source, read_only) in targets {
+            let result = match self.mount_manager.detach(&target).await {
+                Ok(()) => {
+                    self.mount_manager
+                        .bind_mount(&source, &target, read_only)
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => info!("Re-bound {} to remounted {}", target, source),
+                Err(e) => warn!("Failed to re-bind {}: {:#}", target, e),
+            }
+        }
+    }
+}
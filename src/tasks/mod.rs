@@ -0,0 +1,8 @@
+pub mod cleanup;
+pub mod health;
+pub mod orphans;
+
+// Re-export
+pub use cleanup::{PccAttaches, PublishCleanup};
+pub use health::HealthMonitor;
+pub use orphans::OrphanCollector;
@@ -0,0 +1,306 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use super::PublishCleanup;
+use crate::config::{GcMode, OrphanGcConfig};
+use crate::lustre::MountManager;
+use crate::lustre::health::host_path;
+use crate::lustre::mountinfo::MountEntry;
+use crate::state::StateStore;
+use crate::utils::locks::{OperationLocks, path_key};
+
+/// Directory kubelet keeps CSI volume mounts in, relative to a pod directory
+const CSI_VOLUMES_DIR: &str = "volumes/kubernetes.io~csi";
+
+/// Running totals, logged after every pass
+#[derive(Debug, Default)]
+pub struct GcStats {
+    pub passes: AtomicU64,
+    pub orphans_found: AtomicU64,
+    pub unmounted: AtomicU64,
+    pub failures: AtomicU64,
+}
+
+/// Why a mount was considered orphaned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrphanReason {
+    /// The pod directory is gone
+    PodDeleted,
+    /// The pod exists, but kubelet no longer has metadata for the volume
+    Untracked,
+}
+
+#[derive(Debug, Clone)]
+pub struct Orphan {
+    pub target: String,
+    pub pod_uid: String,
+    pub volume: String,
+    pub reason: OrphanReason,
+}
+
+/// Subset of the `vol_data.json` kubelet writes next to each CSI mount
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VolumeData {
+    driver_name: String,
+}
+
+/// Finds and removes Lustre mounts left behind under kubelet's pod directory.
+///
+/// Force-deleted pods or a kubelet that lost its state never call
+/// NodeUnpublishVolume, and each such mount pins a Lustre client import.
+#[derive(Debug, Clone)]
+pub struct OrphanCollector {
+    config: OrphanGcConfig,
+    driver_name: String,
+    mount_manager: MountManager,
+    state: StateStore,
+    locks: OperationLocks,
+    cleanup: PublishCleanup,
+    stats: Arc<GcStats>,
+}
+
+impl OrphanCollector {
+    pub fn new(
+        config: OrphanGcConfig,
+        driver_name: String,
+        mount_manager: MountManager,
+        state: StateStore,
+        locks: OperationLocks,
+        cleanup: PublishCleanup,
+    ) -> Self {
+        Self {
+            config,
+            driver_name,
+            mount_manager,
+            state,
+            locks,
+            cleanup,
+            stats: Arc::new(GcStats::default()),
+        }
+    }
+
+    /// Run a pass every configured interval until the process exits
+    pub fn spawn(self) {
+        if self.config.mode == GcMode::Disabled {
+            info!("Orphaned mount collector disabled");
+            return;
+        }
+
+        info!(
+            "Starting orphaned mount collector ({:?}, every {}s)",
+            self.config.mode, self.config.interval_secs
+        );
+
+        tokio::spawn(async move {
+            let period = Duration::from_secs(self.config.interval_secs.max(1));
+            loop {
+                tokio::time::sleep(period).await;
+                if let Err(e) = self.run_once().await {
+                    warn!("Orphaned mount collection failed: {:#}", e);
+                }
+            }
+        });
+    }
+
+    /// Scan the mount table once and clean up (or report) orphans
+    pub async fn run_once(&self) -> Result<Vec<Orphan>> {
+        self.stats.passes.fetch_add(1, Ordering::Relaxed);
+
+        let table = self.mount_manager.mount_table().get().await?;
+        let mut orphans = Vec::new();
+        for entry in table.entries() {
+            if let Some(orphan) = self.classify(entry).await {
+                orphans.push(orphan);
+            }
+        }
+
+        for orphan in &orphans {
+            self.stats.orphans_found.fetch_add(1, Ordering::Relaxed);
+
+            if self.config.mode == GcMode::DryRun {
+                info!(
+                    "Orphaned mount (dry run, not removed): {} (pod {}, volume {}, {:?})",
+                    orphan.target, orphan.pod_uid, orphan.volume, orphan.reason
+                );
+                continue;
+            }
+
+            self.remove(orphan).await;
+        }
+
+        info!(
+            "Orphaned mount pass: {} found; totals: {} passes, {} found, {} unmounted, {} failures",
+            orphans.len(),
+            self.stats.passes.load(Ordering::Relaxed),
+            self.stats.orphans_found.load(Ordering::Relaxed),
+            self.stats.unmounted.load(Ordering::Relaxed),
+            self.stats.failures.load(Ordering::Relaxed)
+        );
+
+        Ok(orphans)
+    }
+
+    async fn remove(&self, orphan: &Orphan) {
+        // Never race a NodePublish/NodeUnpublish on the same path
+        let Ok(_guard) = self
+            .locks
+            .try_acquire("OrphanCollector", &[path_key(&orphan.target)])
+        else {
+            debug!("Skipping {}: an operation is in progress", orphan.target);
+            return;
+        };
+
+        info!(
+            "Removing orphaned mount {} (pod {}, volume {}, {:?})",
+            orphan.target, orphan.pod_uid, orphan.volume, orphan.reason
+        );
+
+        // Undo the rest of the publish like NodeUnpublishVolume would
+        let record = self.state.snapshot().await.publishes.remove(&orphan.target);
+        if let Some(record) = &record {
+            self.cleanup.release_pcc(&orphan.target, record).await;
+        }
+
+        let result = match self.mount_manager.unmount(&orphan.target).await {
+            Ok(()) => self.mount_manager.remove_mount_point(&orphan.target).await,
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(()) => {
+                self.stats.unmounted.fetch_add(1, Ordering::Relaxed);
+                self.state
+                    .finish(&orphan.target, |state| {
+                        state.publishes.remove(&orphan.target);
+                        state.recount();
+                    })
+                    .await;
+                if let Some(record) = record.filter(|record| record.ephemeral) {
+                    self.cleanup.schedule_scratch_deletion(&record.source).await;
+                }
+            }
+            Err(e) => {
+                self.stats.failures.fetch_add(1, Ordering::Relaxed);
+                warn!("Failed to remove orphaned mount {}: {:#}", orphan.target, e);
+            }
+        }
+    }
+
+    /// Decide whether a mount table entry is an orphaned mount of this driver.
+    ///
+    /// Mount points are host paths, and so is the pods directory they are
+    /// matched against; files below them are read through the host root.
+    async fn classify(&self, entry: &MountEntry) -> Option<Orphan> {
+        if entry.fs_type != "lustre" {
+            return None;
+        }
+
+        let (pod_uid, volume) = parse_pod_volume_path(&self.config.pods_dir, &entry.mount_point)?;
+        let pod_dir = host_path(&format!("{}/{}", self.config.pods_dir, pod_uid));
+        let vol_data = host_path(&entry.mount_point)
+            .parent()?
+            .join("vol_data.json");
+
+        let volume_data = read_volume_data(&vol_data).await;
+        let reason = if !exists(&pod_dir).await {
+            OrphanReason::PodDeleted
+        } else if volume_data.is_none() {
+            OrphanReason::Untracked
+        } else {
+            return None;
+        };
+
+        // Only claim mounts kubelet attributed to this driver, or that this
+        // driver recorded itself; other Lustre drivers may share the node.
+        let ours = match &volume_data {
+            Some(data) => data.driver_name == self.driver_name,
+            None => self
+                .state
+                .snapshot()
+                .await
+                .publishes
+                .contains_key(&entry.mount_point),
+        };
+        if !ours {
+            debug!(
+                "Orphaned Lustre mount {} does not belong to {}",
+                entry.mount_point, self.driver_name
+            );
+            return None;
+        }
+
+        Some(Orphan {
+            target: entry.mount_point.clone(),
+            pod_uid,
+            volume,
+            reason,
+        })
+    }
+}
+
+/// Split `<pods_dir>/<uid>/volumes/kubernetes.io~csi/<volume>/mount`
+fn parse_pod_volume_path(pods_dir: &str, mount_point: &str) -> Option<(String, String)> {
+    let rest = Path::new(mount_point)
+        .strip_prefix(pods_dir)
+        .ok()?
+        .to_str()?;
+
+    let (pod_uid, rest) = rest.split_once('/')?;
+    let volume = rest
+        .strip_prefix(CSI_VOLUMES_DIR)?
+        .strip_prefix('/')?
+        .strip_suffix("/mount")?;
+
+    if pod_uid.is_empty() || volume.is_empty() || volume.contains('/') {
+        return None;
+    }
+
+    Some((pod_uid.to_string(), volume.to_string()))
+}
+
+async fn exists(path: &Path) -> bool {
+    // Errors other than "not found" (e.g. EACCES) count as present, to be safe
+    tokio::fs::try_exists(path).await.unwrap_or(true)
+}
+
+async fn read_volume_data(path: &Path) -> Option<VolumeData> {
+    let bytes = tokio::fs::read(path).await.ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pod_volume_path() {
+        let pods = "/var/lib/kubelet/pods";
+        assert_eq!(
+            parse_pod_volume_path(
+                pods,
+                "/var/lib/kubelet/pods/1f2e/volumes/kubernetes.io~csi/pvc-9/mount"
+            ),
+            Some(("1f2e".to_string(), "pvc-9".to_string()))
+        );
+        assert_eq!(
+            parse_pod_volume_path(
+                pods,
+                "/var/lib/kubelet/pods/1f2e/volumes/kubernetes.io~nfs/pvc-9/mount"
+            ),
+            None
+        );
+        assert_eq!(
+            parse_pod_volume_path(
+                pods,
+                "/var/lib/kubelet/plugins/kubernetes.io/csi/x/globalmount"
+            ),
+            None
+        );
+    }
+}
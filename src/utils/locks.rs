@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tracing::debug;
+
+/// In-process locks keyed by volume ID and target path.
+///
+/// kubelet retries NodePublish/NodeUnpublish aggressively, so two calls for
+/// the same volume or path can overlap. Instead of queueing, a second caller
+/// is turned away and is expected to retry, as the CSI spec recommends.
+/// Keys can also be held shared, e.g. a volume by the publishes of several
+/// pods, which only an exclusive holder is turned away from.
+#[derive(Debug, Clone, Default)]
+pub struct OperationLocks {
+    held: Arc<Mutex<HashMap<String, Hold>>>,
+}
+
+/// Who holds a key
+#[derive(Debug)]
+enum Hold {
+    Exclusive(String),
+    /// Operations holding the key shared, one entry per guard
+    Shared(Vec<String>),
+}
+
+impl Hold {
+    fn holder(&self) -> String {
+        match self {
+            Self::Exclusive(operation) => operation.clone(),
+            Self::Shared(operations) => operations.join(", "),
+        }
+    }
+}
+
+impl OperationLocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lock all `keys` for `operation`, or none of them.
+    ///
+    /// On contention, returns the key and the operation currently holding it.
+    pub fn try_acquire(
+        &self,
+        operation: &str,
+        keys: &[String],
+    ) -> Result<OperationGuard, (String, String)> {
+        self.try_acquire_shared(operation, keys, &[])
+    }
+
+    /// Lock `keys` exclusively and `shared` shared for `operation`, or none
+    /// of them
+    pub fn try_acquire_shared(
+        &self,
+        operation: &str,
+        keys: &[String],
+        shared: &[String],
+    ) -> Result<OperationGuard, (String, String)> {
+        let mut held = self.held.lock().unwrap();
+
+        let busy = keys
+            .iter()
+            .find_map(|k| held.get(k).map(|h| (k.clone(), h.holder())))
+            .or_else(|| {
+                shared.iter().find_map(|k| match held.get(k) {
+                    Some(hold @ Hold::Exclusive(_)) => Some((k.clone(), hold.holder())),
+                    _ => None,
+                })
+            });
+        if let Some((key, holder)) = busy {
+            debug!(
+                "Lock {} busy: held by {}, requested by {}",
+                key, holder, operation
+            );
+            return Err((key, holder));
+        }
+
+        for key in keys {
+            held.insert(key.clone(), Hold::Exclusive(operation.to_string()));
+        }
+        for key in shared {
+            match held
+                .entry(key.clone())
+                .or_insert_with(|| Hold::Shared(Vec::new()))
+            {
+                Hold::Shared(operations) => operations.push(operation.to_string()),
+                Hold::Exclusive(_) => unreachable!("checked above"),
+            }
+        }
+        debug!(
+            "Locks {:?} (shared {:?}) acquired by {}",
+            keys, shared, operation
+        );
+
+        Ok(OperationGuard {
+            held: self.held.clone(),
+            operation: operation.to_string(),
+            keys: keys.to_vec(),
+            shared: shared.to_vec(),
+        })
+    }
+}
+
+/// Releases its keys when dropped
+#[derive(Debug)]
+pub struct OperationGuard {
+    held: Arc<Mutex<HashMap<String, Hold>>>,
+    operation: String,
+    keys: Vec<String>,
+    shared: Vec<String>,
+}
+
+impl Drop for OperationGuard {
+    fn drop(&mut self) {
+        let mut held = self.held.lock().unwrap();
+        for key in &self.keys {
+            held.remove(key);
+        }
+        for key in &self.shared {
+            if let Some(Hold::Shared(operations)) = held.get_mut(key) {
+                if let Some(i) = operations.iter().position(|o| *o == self.operation) {
+                    operations.swap_remove(i);
+                }
+                if operations.is_empty() {
+                    held.remove(key);
+                }
+            }
+        }
+        debug!(
+            "Locks {:?} (shared {:?}) released by {}",
+            self.keys, self.shared, self.operation
+        );
+    }
+}
+
+/// Lock key for a volume ID
+pub fn volume_key(volume_id: &str) -> String {
+    format!("volume:{}", volume_id)
+}
+
+/// Lock key for a staging or target path
+pub fn path_key(path: &str) -> String {
+    format!("path:{}", path.trim_end_matches('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_operation_locks() {
+        let locks = OperationLocks::new();
+        let keys = vec![volume_key("vol-1"), path_key("/target/a")];
+
+        let guard = locks.try_acquire("NodePublishVolume", &keys).unwrap();
+
+        // Any overlapping key is refused, and nothing is partially locked
+        let err = locks
+            .try_acquire(
+                "NodeUnpublishVolume",
+                &[path_key("/target/b"), path_key("/target/a/")],
+            )
+            .unwrap_err();
+        assert_eq!(
+            err,
+            (path_key("/target/a"), "NodePublishVolume".to_string())
+        );
+        assert!(
+            locks
+                .try_acquire("NodePublishVolume", &[path_key("/target/b")])
+                .is_ok()
+        );
+
+        drop(guard);
+        assert!(locks.try_acquire("NodeUnpublishVolume", &keys).is_ok());
+    }
+
+    #[test]
+    fn test_shared_locks() {
+        let locks = OperationLocks::new();
+        let volume = [volume_key("vol-1")];
+
+        // Pods sharing a volume publish it at the same time
+        let a = locks
+            .try_acquire_shared("NodePublishVolume", &[path_key("/target/a")], &volume)
+            .unwrap();
+        let b = locks
+            .try_acquire_shared("NodePublishVolume", &[path_key("/target/b")], &volume)
+            .unwrap();
+        assert!(
+            locks
+                .try_acquire_shared("NodePublishVolume", &[path_key("/target/a")], &volume)
+                .is_err()
+        );
+        assert!(locks.try_acquire("NodeStageVolume", &volume).is_err());
+
+        drop(a);
+        assert!(locks.try_acquire("NodeStageVolume", &volume).is_err());
+        drop(b);
+        let stage = locks.try_acquire("NodeStageVolume", &volume).unwrap();
+        assert!(
+            locks
+                .try_acquire_shared("NodePublishVolume", &[path_key("/target/a")], &volume)
+                .is_err()
+        );
+        drop(stage);
+    }
+}
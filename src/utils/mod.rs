@@ -1 +1,6 @@
+pub mod locks;
 pub mod path;
+pub mod pod;
+#[cfg(test)]
+pub(crate) mod testing;
+pub mod xattr;
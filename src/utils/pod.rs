@@ -0,0 +1,201 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+const POD_NAME_KEY: &str = "csi.storage.k8s.io/pod.name";
+const POD_NAMESPACE_KEY: &str = "csi.storage.k8s.io/pod.namespace";
+const POD_UID_KEY: &str = "csi.storage.k8s.io/pod.uid";
+const SERVICE_ACCOUNT_KEY: &str = "csi.storage.k8s.io/serviceAccount.name";
+
+const SUB_PATH_TEMPLATE_KEY: &str = "subPathTemplate";
+
+/// The pod a volume is published for, as kubelet passes it in the volume
+/// context with `podInfoOnMount`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PodInfo {
+    pub name: Option<String>,
+    pub namespace: Option<String>,
+    pub uid: Option<String>,
+    pub service_account: Option<String>,
+}
+
+impl PodInfo {
+    pub fn from_volume_context(volume_context: &HashMap<String, String>) -> Self {
+        let get = |key| volume_context.get(key).filter(|v| !v.is_empty()).cloned();
+        Self {
+            name: get(POD_NAME_KEY),
+            namespace: get(POD_NAMESPACE_KEY),
+            uid: get(POD_UID_KEY),
+            service_account: get(SERVICE_ACCOUNT_KEY),
+        }
+    }
+
+    /// Whether kubelet passed no pod information at all
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn variable(&self, name: &str) -> Option<&Option<String>> {
+        match name {
+            "pod.name" => Some(&self.name),
+            "pod.namespace" => Some(&self.namespace),
+            "pod.uid" => Some(&self.uid),
+            "serviceAccount.name" => Some(&self.service_account),
+            _ => None,
+        }
+    }
+}
+
+/// `namespace/name`, with `?` for what is unknown
+impl fmt::Display for PodInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}",
+            self.namespace.as_deref().unwrap_or("?"),
+            self.name.as_deref().unwrap_or("?")
+        )
+    }
+}
+
+/// Directory below the volume each pod gets for itself, written with
+/// `${pod.name}`, `${pod.namespace}`, `${pod.uid}` and
+/// `${serviceAccount.name}`, e.g. `${pod.namespace}/${pod.name}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubPathTemplate(String);
+
+impl SubPathTemplate {
+    /// Read and check `subPathTemplate`
+    pub fn from_volume_context(volume_context: &HashMap<String, String>) -> Result<Option<Self>> {
+        let Some(template) = volume_context.get(SUB_PATH_TEMPLATE_KEY) else {
+            return Ok(None);
+        };
+        let template = Self(template.trim().to_string());
+        // Every variable set, to catch unknown ones and bad literal parts
+        let example = PodInfo {
+            name: Some("name".to_string()),
+            namespace: Some("namespace".to_string()),
+            uid: Some("uid".to_string()),
+            service_account: Some("account".to_string()),
+        };
+        template.render(&example)?;
+        Ok(Some(template))
+    }
+
+    /// The relative directory for `pod`
+    pub fn render(&self, pod: &PodInfo) -> Result<String> {
+        let mut rendered = String::new();
+        let mut rest = self.0.as_str();
+        while let Some(start) = rest.find("${") {
+            rendered.push_str(&rest[..start]);
+            let Some((name, after)) = rest[start + 2..].split_once('}') else {
+                anyhow::bail!(
+                    "{} has an unclosed variable: {:?}",
+                    SUB_PATH_TEMPLATE_KEY,
+                    self.0
+                );
+            };
+            let value = self.variable(pod, name)?;
+            if value.contains('/') {
+                anyhow::bail!("${{{}}} cannot be used in a path: {:?}", name, value);
+            }
+            rendered.push_str(value);
+            rest = after;
+        }
+        rendered.push_str(rest);
+
+        let components: Vec<&str> = rendered.split('/').collect();
+        if components
+            .iter()
+            .any(|c| c.is_empty() || *c == "." || *c == "..")
+        {
+            anyhow::bail!(
+                "{} must give a relative path without empty, . or .. parts, got {:?} from {:?}",
+                SUB_PATH_TEMPLATE_KEY,
+                rendered,
+                self.0
+            );
+        }
+        Ok(rendered)
+    }
+
+    fn variable<'a>(&self, pod: &'a PodInfo, name: &str) -> Result<&'a str> {
+        match pod.variable(name) {
+            Some(Some(value)) => Ok(value),
+            Some(None) => anyhow::bail!(
+                "{} uses ${{{}}}, which kubelet did not pass; is podInfoOnMount enabled?",
+                SUB_PATH_TEMPLATE_KEY,
+                name
+            ),
+            None => anyhow::bail!(
+                "{} uses unknown variable ${{{}}}: {:?}",
+                SUB_PATH_TEMPLATE_KEY,
+                name,
+                self.0
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::parameters;
+
+    fn pod() -> PodInfo {
+        PodInfo::from_volume_context(&parameters(&[
+            (POD_NAME_KEY, "trainer-0"),
+            (POD_NAMESPACE_KEY, "ml"),
+            (POD_UID_KEY, "6a1f"),
+        ]))
+    }
+
+    #[test]
+    fn test_pod_info() {
+        let pod = pod();
+        assert_eq!(pod.to_string(), "ml/trainer-0");
+        assert_eq!(pod.service_account, None);
+        assert!(PodInfo::from_volume_context(&HashMap::new()).is_empty());
+    }
+
+    #[test]
+    fn test_render_sub_path() {
+        let context = parameters(&[(
+            SUB_PATH_TEMPLATE_KEY,
+            "${pod.namespace}/${pod.name}-${pod.uid}",
+        )]);
+        let template = SubPathTemplate::from_volume_context(&context)
+            .unwrap()
+            .unwrap();
+        assert_eq!(template.render(&pod()).unwrap(), "ml/trainer-0-6a1f");
+
+        // Without podInfoOnMount, or a service account, there is no value
+        let account = SubPathTemplate("${serviceAccount.name}".to_string());
+        assert!(account.render(&pod()).is_err());
+    }
+
+    #[test]
+    fn test_invalid_sub_path_template() {
+        for bad in [
+            "${pod.label}",
+            "${pod.name",
+            "/${pod.name}",
+            "${pod.name}/../x",
+            "a//${pod.name}",
+            "",
+        ] {
+            let context = parameters(&[(SUB_PATH_TEMPLATE_KEY, bad)]);
+            assert!(
+                SubPathTemplate::from_volume_context(&context).is_err(),
+                "{:?}",
+                bad
+            );
+        }
+        assert_eq!(
+            SubPathTemplate::from_volume_context(&HashMap::new()).unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,21 @@
+//! Fixtures shared by unit tests.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::config::Config;
+
+/// Parameters or a volume context from key/value pairs
+pub fn parameters(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+    pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+/// Driver config of `node_id` keeping its state below `dir`
+pub fn config(dir: &Path, node_id: &str) -> Config {
+    let mut config = Config::new("lustre.csi.klustrefs.io".into(), node_id.into());
+    config.driver.plugin_dir = dir.to_string_lossy().into_owned();
+    config
+}
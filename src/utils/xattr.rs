@@ -0,0 +1,93 @@
+use anyhow::{Context, Result};
+use std::ffi::CString;
+use std::path::Path;
+
+/// Names of the extended attributes of `path`, without following a final
+/// symlink
+pub fn list(path: &Path) -> Result<Vec<String>> {
+    let path = cstring(path.as_os_str().as_encoded_bytes())?;
+    let mut names = vec![0u8; 1024];
+    loop {
+        let len =
+            unsafe { libc::llistxattr(path.as_ptr(), names.as_mut_ptr().cast(), names.len()) };
+        if len >= 0 {
+            names.truncate(len as usize);
+            break;
+        }
+        let e = std::io::Error::last_os_error();
+        if e.raw_os_error() != Some(libc::ERANGE) {
+            return Err(e.into());
+        }
+        names.resize(names.len() * 4, 0);
+    }
+
+    Ok(names
+        .split(|b| *b == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| String::from_utf8_lossy(name).into_owned())
+        .collect())
+}
+
+/// Value of one extended attribute, `None` if `path` does not have it or
+/// its filesystem does not know the namespace
+pub fn get(path: &Path, name: &str) -> Result<Option<Vec<u8>>> {
+    let path = cstring(path.as_os_str().as_encoded_bytes())?;
+    let name = cstring(name.as_bytes())?;
+    let mut value = vec![0u8; 256];
+    loop {
+        let len = unsafe {
+            libc::lgetxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_mut_ptr().cast(),
+                value.len(),
+            )
+        };
+        if len >= 0 {
+            value.truncate(len as usize);
+            return Ok(Some(value));
+        }
+        let e = std::io::Error::last_os_error();
+        match e.raw_os_error() {
+            Some(libc::ENODATA | libc::EOPNOTSUPP) => return Ok(None),
+            Some(libc::ERANGE) => value.resize(value.len() * 16, 0),
+            _ => return Err(e.into()),
+        }
+    }
+}
+
+pub fn set(path: &Path, name: &str, value: &[u8]) -> Result<()> {
+    let path = cstring(path.as_os_str().as_encoded_bytes())?;
+    let name = cstring(name.as_bytes())?;
+    let rc = unsafe {
+        libc::lsetxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        )
+    };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+/// Remove one extended attribute; succeeds if `path` does not have it
+pub fn remove(path: &Path, name: &str) -> Result<()> {
+    let path = cstring(path.as_os_str().as_encoded_bytes())?;
+    let name = cstring(name.as_bytes())?;
+    let rc = unsafe { libc::lremovexattr(path.as_ptr(), name.as_ptr()) };
+    if rc != 0 {
+        let e = std::io::Error::last_os_error();
+        if e.raw_os_error() != Some(libc::ENODATA) {
+            return Err(e.into());
+        }
+    }
+    Ok(())
+}
+
+fn cstring(bytes: &[u8]) -> Result<CString> {
+    CString::new(bytes).context("Path contains a NUL byte")
+}